@@ -1,6 +1,6 @@
 use itertools::Itertools;
 
-use crate::{place_not::PnBlockParseError, Block, PnBlock, Row, RowBuf, Stage};
+use crate::{place_not::PnBlockParseError, Block, PlaceNot, PnBlock, Row, RowBuf, Stage};
 
 use self::class::FullClass;
 
@@ -8,6 +8,16 @@ pub mod class;
 
 /// A standard label name used for denoting the 'lead end' of a method
 pub const LABEL_LEAD_END: &str = "LE";
+/// A standard label name used for denoting the 'half lead' of a symmetric method (see
+/// [`Method::half_lead_idx`])
+pub const LABEL_HALF_LEAD: &str = "HL";
+/// A standard label name used for denoting every 'six end' of a principle like Stedman (see
+/// [`Method::six_ends`])
+pub const LABEL_SIX_END: &str = "SIX";
+/// A standard label name used for denoting the ends of quick sixes
+pub const LABEL_QUICK_SIX: &str = "QUICK";
+/// A standard label name used for denoting the ends of slow sixes
+pub const LABEL_SLOW_SIX: &str = "SLOW";
 
 /// The definition of a 'method' within Change Ringing.  Essentially, a `Method` consists of a
 /// [`Block`] which is intended to be rung as a repeating unit (usually a 'lead'), along with
@@ -206,6 +216,105 @@ impl Method {
             .annots()
             .positions(move |labels| labels.iter().any(|l| l == label))
     }
+
+    ////////////////
+    // HALF LEADS //
+    ////////////////
+
+    /// If this `Method` is symmetric about its half lead (like most Surprise methods), returns the
+    /// sub-lead index of the first [`Row`] after the half-lead change.  Otherwise, returns `None`.
+    ///
+    /// A method is symmetric if the changes before its lead end form a palindrome.  For example,
+    /// Cambridge Surprise Major (`x38x14x1258x36x14x58x16x78,12`) is symmetric about its `78`, so
+    /// its half lead is at index 16.
+    pub fn half_lead_idx(&self) -> Option<usize> {
+        let lead_len = self.lead_len();
+        if lead_len == 0 || !lead_len.is_multiple_of(2) {
+            return None;
+        }
+        let changes = self
+            .first_lead
+            .all_rows()
+            .tuple_windows()
+            .map(|(r1, r2)| PlaceNot::pn_between(r1, r2))
+            .collect::<Option<Vec<_>>>()?;
+        // Ignore the lead end change, which isn't part of the palindrome
+        let changes = &changes[..lead_len - 1];
+        let is_palindrome = changes.iter().eq(changes.iter().rev());
+        is_palindrome.then_some(lead_len / 2)
+    }
+
+    ///////////
+    // SIXES //
+    ///////////
+
+    /// If this `Method` is built from sixes (like Stedman or Erin), returns the sub-lead index of
+    /// the first [`Row`] after every six end, along with the [`SixType`] of the six which ends
+    /// there.  Otherwise, returns `None`.
+    ///
+    /// A method is built from sixes if its lead splits into blocks of six changes where the last
+    /// change of every block (the 'six end') is the same and isn't used anywhere else, and the
+    /// other five changes alternate between two different place notations.  For example, Stedman
+    /// Triples (`3.1.7.3.1.3,1`) has six ends (`7`) after the 3rd and 9th changes.
+    pub fn six_ends(&self) -> Option<Vec<(usize, SixType)>> {
+        let lead_len = self.lead_len();
+        if lead_len == 0 || !lead_len.is_multiple_of(6) {
+            return None;
+        }
+        let changes = self
+            .first_lead
+            .all_rows()
+            .tuple_windows()
+            .map(|(r1, r2)| PlaceNot::pn_between(r1, r2))
+            .collect::<Option<Vec<_>>>()?;
+        let change = |idx: usize| &changes[idx % lead_len];
+
+        // Find the changes which are six ends
+        let six_end_offset = (0..6).find(|&offset| {
+            let six_end = change(offset);
+            changes
+                .iter()
+                .enumerate()
+                .all(|(idx, pn)| (pn == six_end) == (idx % 6 == offset))
+        })?;
+        // Check that the changes between the six ends alternate
+        let mut six_ends = Vec::new();
+        for six_end_idx in (six_end_offset..lead_len).step_by(6) {
+            let six_start_idx = six_end_idx + lead_len - 5;
+            let (first, second) = (change(six_start_idx), change(six_start_idx + 1));
+            let alternates = first != second
+                && (0..5).all(|i| change(six_start_idx + i) == [first, second][i % 2]);
+            if !alternates {
+                return None;
+            }
+            let six_type = match first.contains(0) {
+                true => SixType::Slow,
+                false => SixType::Quick,
+            };
+            six_ends.push(((six_end_idx + 1) % lead_len, six_type));
+        }
+        six_ends.sort_unstable_by_key(|&(idx, _)| idx);
+        Some(six_ends)
+    }
+}
+
+/// The two types of six in principles like Stedman.  A slow six starts by making first place
+/// (e.g. `1.3.1.3.1` in Stedman Triples) and a quick six doesn't (e.g. `3.1.3.1.3`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SixType {
+    Quick,
+    Slow,
+}
+
+impl SixType {
+    /// The label given to the ends of sixes of this type (either [`LABEL_QUICK_SIX`] or
+    /// [`LABEL_SLOW_SIX`])
+    pub fn label(self) -> &'static str {
+        match self {
+            SixType::Quick => LABEL_QUICK_SIX,
+            SixType::Slow => LABEL_SLOW_SIX,
+        }
+    }
 }
 
 /// Generate the (standard) title of a [`Method`] from its parts, according to the Framework's
@@ -251,3 +360,56 @@ impl<'meth> RowAnnot<'meth> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::Stage;
+
+    use super::{Method, SixType};
+
+    #[test]
+    fn six_ends() {
+        #[track_caller]
+        fn check(pn: &str, stage: Stage, exp_six_ends: Option<Vec<(usize, SixType)>>) {
+            let method = Method::from_place_not_string(String::new(), stage, pn).unwrap();
+            assert_eq!(method.six_ends(), exp_six_ends);
+        }
+
+        use SixType::{Quick, Slow};
+        check(
+            "3.1.7.3.1.3,1",
+            Stage::TRIPLES,
+            Some(vec![(3, Slow), (9, Quick)]),
+        );
+        check(
+            "3.1.9.3.1.3,1",
+            Stage::CATERS,
+            Some(vec![(3, Slow), (9, Quick)]),
+        );
+        check(
+            "3.1.5.3.1.3,1",
+            Stage::DOUBLES,
+            Some(vec![(3, Slow), (9, Quick)]),
+        );
+        check("3.1.3.1.3.7", Stage::TRIPLES, Some(vec![(0, Quick)]));
+        // Not built from sixes
+        check("x16x16x16,12", Stage::MINOR, None);
+        check("3,1.7.1.7.1.7.1", Stage::TRIPLES, None);
+        check("x1x1x1", Stage::MINOR, None);
+    }
+
+    #[test]
+    fn half_lead_idx() {
+        #[track_caller]
+        fn check(pn: &str, stage: Stage, exp_half_lead_idx: Option<usize>) {
+            let method = Method::from_place_not_string(String::new(), stage, pn).unwrap();
+            assert_eq!(method.half_lead_idx(), exp_half_lead_idx);
+        }
+
+        check("x38x14x1258x36x14x58x16x78,12", Stage::MAJOR, Some(16));
+        check("x16x16x16,12", Stage::MINOR, Some(6));
+        check("3.1.7.3.1.3,1", Stage::TRIPLES, Some(6));
+        // Asymmetric methods
+        check("x58x16x12x18", Stage::MAJOR, None);
+    }
+}
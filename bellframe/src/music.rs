@@ -289,6 +289,34 @@ impl Pattern {
         runs
     }
 
+    /// Creates a set of `Pattern`s which match runs of a given length which [wrap](Self::num_wraps)
+    /// from one [`Row`] into the next (e.g. `5678xxxx` on Major).  If the run length is longer
+    /// than the stage, then no `Pattern`s are returned.
+    pub fn run_wraps(stage: Stage, len: u8) -> Vec<Self> {
+        let num_bells = stage.num_bells_u8();
+        if num_bells < len {
+            return vec![];
+        }
+
+        let mut runs = Vec::new();
+        // Iterate over every bell which could start a run
+        for i in 0..=num_bells - len {
+            // An iterator that yields the bells forming this run in ascending order
+            let run_iterator = (i..i + len).map(Bell::from_index).map(Elem::Bell);
+            // Fill the rest of the wrap with `x`s
+            let padding = std::iter::repeat_n(Elem::X, (num_bells - len) as usize);
+
+            let ascending_pattern =
+                Self::from_elems(run_iterator.clone().chain(padding.clone()), stage).unwrap();
+            let descending_pattern =
+                Self::from_elems(run_iterator.rev().chain(padding), stage).unwrap();
+
+            runs.push(descending_pattern);
+            runs.push(ascending_pattern);
+        }
+        runs
+    }
+
     /// Creates a set of `Pattern`s which match runs of a given length off the front or back of a
     /// [`Row`].  If the run length is longer than the stage, then no `Pattern`s are returned.
     pub fn runs_front_or_back(stage: Stage, len: u8) -> Vec<Self> {
@@ -396,6 +424,48 @@ impl Pattern {
         })
     }
 
+    /// Returns the number of _wraps_ of this `Pattern` between two consecutive [`Row`]s.  A wrap is
+    /// a sequence of `stage.num_bells()` consecutive [`Bell`]s which starts part-way through `first`
+    /// and finishes part-way through `second`.  For example, rounds (`12345678`) wraps between
+    /// `56781234` and `56781234`.
+    ///
+    /// Only wraps where `self` matches a specific [`Bell`] in both `first` and `second` are counted,
+    /// so e.g. `5678xxxx` only matches if the `5678` is split between the two [`Row`]s.
+    /// `Pattern`s containing `*`s never match wraps.
+    pub fn num_wraps(&self, first: &Row, second: &Row) -> Result<usize, IncompatibleStages> {
+        IncompatibleStages::test_err(self.stage(), first.stage())?;
+        IncompatibleStages::test_err(self.stage(), second.stage())?;
+        if self.elems.iter().any(|elem| elem.is_star()) {
+            return Ok(0);
+        }
+
+        // Without `*`s, every `Elem` corresponds to exactly one `Bell` of the wrap
+        let bell_indices = self
+            .elems
+            .iter()
+            .positions(|elem| elem.is_bell())
+            .collect_vec();
+        let (first_bell_idx, last_bell_idx) = match (bell_indices.first(), bell_indices.last()) {
+            (Some(f), Some(l)) => (*f, *l),
+            _ => return Ok(0), // Patterns like `xxxx` can't match bells in both rows
+        };
+        let num_bells = self.stage.num_bells();
+        let num_wraps = (1..num_bells)
+            // `split` is the number of bells of the wrap which come from `first`
+            .filter(|&split| first_bell_idx < split && last_bell_idx >= split)
+            .filter(|&split| {
+                let wrap_bells = first.slice()[num_bells - split..]
+                    .iter()
+                    .chain(&second.slice()[..num_bells - split]);
+                self.elems
+                    .iter()
+                    .zip_eq(wrap_bells)
+                    .all(|(elem, bell)| elem.bell().is_none_or(|b| b == *bell))
+            })
+            .count();
+        Ok(num_wraps)
+    }
+
     /// Helper function to test a match between a [`Row`] and this `Pattern`, calling some function
     /// every time a [`Bell`] is matched.
     ///
@@ -756,4 +826,24 @@ mod tests {
         check("*1x2x3x4*", "18273645", Some(vec![0, 2, 4, 6]));
         check("*1x2x3x4*", "091E273645T8", Some(vec![2, 4, 6, 8]));
     }
+
+    #[test]
+    fn num_wraps() {
+        #[track_caller]
+        fn check(pattern_str: &str, first_str: &str, second_str: &str, expected_wraps: usize) {
+            let first = RowBuf::parse(first_str).unwrap();
+            let second = RowBuf::parse(second_str).unwrap();
+            let pattern = Pattern::parse(pattern_str, first.stage()).unwrap();
+            assert_eq!(pattern.num_wraps(&first, &second).unwrap(), expected_wraps);
+        }
+
+        check("12345678", "56781234", "56781234", 1);
+        check("12345678", "65871234", "56784321", 1);
+        check("12345678", "12345678", "12345678", 0); // Rounds, but not a wrap
+        check("5678xxxx", "21437856", "78563412", 1);
+        check("5678xxxx", "21435678", "12345678", 0); // The run isn't split between the rows
+        check("xxxx5678", "12345678", "12345678", 0);
+        check("*5678", "12345678", "12345678", 0);
+        check("xxxxxx", "123456", "123456", 0);
+    }
 }
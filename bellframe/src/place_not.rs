@@ -601,6 +601,17 @@ impl PnBlock {
         self.pns.len()
     }
 
+    /// Returns a [`RowBuf`] representing the transposition made by applying every [`PlaceNot`]
+    /// in this `PnBlock` in turn.
+    pub fn transposition(&self) -> RowBuf {
+        let mut row = RowBuf::rounds(self.stage());
+        for pn in &self.pns {
+            // SAFETY: all PlaceNots in `self` have the same stage as `row` (by invariant)
+            unsafe { pn.permute_unchecked(&mut row) };
+        }
+        row
+    }
+
     /// Generates the [`Row`]s which follow from applying `self` to a given [`Row`].  The resulting
     /// [`SameStageVec`] has length one greater than that of `self`, because it starts with
     /// `start_row` and then adds one new [`Row`] per [`PlaceNot`] in `self`.
@@ -643,6 +654,21 @@ impl PnBlock {
     }
 }
 
+impl Display for PnBlock {
+    /// Displays the place notations in order, separating any two non-cross changes with `.`
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let mut last_was_cross = true;
+        for pn in &self.pns {
+            if !pn.is_cross() && !last_was_cross {
+                write!(f, ".")?;
+            }
+            write!(f, "{}", pn)?;
+            last_was_cross = pn.is_cross();
+        }
+        Ok(())
+    }
+}
+
 #[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
 enum CharMeaning {
     Bell(Bell),
@@ -907,4 +933,21 @@ mod tests {
         check_ok("12345678", "13246587", "14");
         check_ok("18247653", "81246753", "3478");
     }
+
+    #[test]
+    fn pn_block_transposition_and_display() {
+        #[track_caller]
+        fn check(stage: Stage, pn: &str, exp_transposition: &str, exp_display: &str) {
+            let block = PnBlock::parse(pn, stage).unwrap();
+            assert_eq!(
+                block.transposition(),
+                RowBuf::parse(exp_transposition).unwrap()
+            );
+            assert_eq!(block.to_string(), exp_display);
+        }
+
+        check(Stage::TRIPLES, "3.123", "2134567", "3.123"); // Grandsire single
+        check(Stage::TRIPLES, "3.1", "2314567", "3.1"); // Grandsire bob
+        check(Stage::MAJOR, "x14x", "42316587", "-14-");
+    }
 }
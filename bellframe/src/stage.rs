@@ -191,6 +191,12 @@ impl Stage {
         (new_num_bells > 0).then_some(Self(new_num_bells))
     }
 
+    /// Returns the number of [`Row`]s in an extent of this `Stage` (i.e. `n!` for `n` bells), or
+    /// `None` if that number doesn't fit in a [`usize`].
+    pub fn extent_length(self) -> Option<usize> {
+        (1..=self.num_bells()).try_fold(1usize, usize::checked_mul)
+    }
+
     /// Returns a [`SameStageVec`] containing one copy of every [`Row`] possible in this [`Stage`]
     /// in some arbitrary order.
     // TODO: Make this an iterator
@@ -587,4 +593,15 @@ mod tests {
             ]
         );
     }
+
+    #[test]
+    fn extent_length() {
+        assert_eq!(Stage::SINGLES.extent_length(), Some(6));
+        assert_eq!(Stage::DOUBLES.extent_length(), Some(120));
+        assert_eq!(Stage::MINOR.extent_length(), Some(720));
+        assert_eq!(
+            Stage::MINIMUS.extent_length(),
+            Some(Stage::MINIMUS.extent().len())
+        );
+    }
 }
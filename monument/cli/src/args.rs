@@ -1,18 +1,24 @@
 use std::path::PathBuf;
 
 use log::LevelFilter;
-use structopt::StructOpt;
+use structopt::{clap::AppSettings, StructOpt};
 
-use crate::DebugOption;
+use crate::{DebugOption, ExportFormat};
 
 /// A struct storing the CLI args taken by Monument.  `StructOpt` will generate the argument
 /// parsing/help code for us.
 #[derive(Debug, Clone, StructOpt)]
-#[structopt(name = "Monument", about = "Fast and flexible composition generator")]
+#[structopt(
+    name = "Monument",
+    about = "Fast and flexible composition generator",
+    setting = AppSettings::ArgsNegateSubcommands
+)]
 pub struct CliArgs {
     /// The name of the specification file for Monument (`*.toml`)
     #[structopt(parse(from_os_str))]
-    pub input_file: PathBuf,
+    pub input_file: Option<PathBuf>,
+    #[structopt(subcommand)]
+    pub command: Option<Command>,
 
     #[structopt(flatten)]
     pub options: Options,
@@ -25,14 +31,35 @@ pub struct CliArgs {
     pub quietness: usize,
 }
 
+/// Subcommands which do something other than running a search
+#[derive(Debug, Clone, StructOpt)]
+pub enum Command {
+    /// Score an existing calling against a specification file, checking that it satisfies the
+    /// file's constraints
+    Evaluate {
+        /// The name of the specification file for Monument (`*.toml`)
+        #[structopt(parse(from_os_str))]
+        input_file: PathBuf,
+        /// The calling to evaluate, in the same format as Monument's output (e.g. `WsWWH` or
+        /// `D[B]BL[W]N[M]`)
+        calling: String,
+
+        #[structopt(flatten)]
+        options: Options,
+    },
+}
+
 // Parameters passed directly into `monument_cli::run`, used to generated the [`monument::Config`]
 // for the search.  This isn't a doc-comment because doc comments override
 // `#[structopt(about = "...")]`.
 #[derive(Default, Debug, Clone, StructOpt)]
 pub struct Options {
-    /// The maximum number of threads that Monument will use.
-    // TODO: Uncomment this once multi-threading is possible
-    // #[structopt(short = "T", long)]
+    /// The maximum number of threads that Monument will use.  Defaults to the number of physical
+    /// CPU cores.  With more than one thread, the threads race each other to find compositions, so
+    /// a search which stops early (e.g. once it's found enough compositions) can output a
+    /// different set of compositions each time it's run.  Use `--num-threads=1` to get the same
+    /// output every time.
+    #[structopt(short = "T", long)]
     pub num_threads: Option<usize>,
     /// The maximum number of chunks in the chunk graph.  Exceeding this during generation will
     /// cause an error.  Defaults to 100K.
@@ -42,6 +69,48 @@ pub struct Options {
     /// Defaults to 80% of what's available.
     #[structopt(short = "M", long, parse(try_from_str = parse_big_int))]
     pub mem_limit: Option<usize>,
+    /// If the search reaches its memory limit, write the lowest-scoring composition prefixes to
+    /// files in this directory instead of throwing them away.  The files are deleted once the
+    /// search finishes.
+    #[structopt(long, parse(from_os_str))]
+    pub spill_dir: Option<PathBuf>,
+    /// Use beam search instead of best-first search, keeping at most this many composition
+    /// prefixes of each length.  This uses much less memory than the default search, but may miss
+    /// good compositions.
+    #[structopt(long, conflicts_with_all = &["depth-first", "exhaustive"])]
+    pub beam_width: Option<usize>,
+    /// Use depth-first search instead of best-first search.  This uses almost no memory, but can
+    /// take a very long time to find good compositions.
+    #[structopt(long, conflicts_with = "exhaustive")]
+    pub depth_first: bool,
+    /// Search every possible composition (skipping any which can't beat the best found so far),
+    /// and report whether the compositions generated are provably the best.  Only practical for
+    /// small searches.
+    #[structopt(long)]
+    pub exhaustive: bool,
+    /// If set, Monument will periodically save the state of the search to this file.  The search
+    /// can then be continued with `--resume`.
+    #[structopt(long, parse(from_os_str))]
+    pub checkpoint: Option<PathBuf>,
+    /// Continue a search from a checkpoint file made with `--checkpoint`.  The input file must
+    /// specify the same search as when the checkpoint was made.  Unless `--checkpoint` is also
+    /// given, the resumed search will keep saving its state to the same checkpoint file.
+    #[structopt(long, parse(from_os_str))]
+    pub resume: Option<PathBuf>,
+
+    /// Also write every composition to its own file in another format: either `microsiril` (a
+    /// MicroSIRIL program which proves the composition) or `complib` (a layout which can be
+    /// imported into CompLib).
+    #[structopt(long)]
+    pub export_format: Option<ExportFormat>,
+    /// The directory where `--export-format` writes its files.  Defaults to the current directory.
+    #[structopt(long, parse(from_os_str), requires = "export-format")]
+    pub export_dir: Option<PathBuf>,
+
+    /// Show how much each kind of weight (calls, splices, course weights, etc.) contributes to the
+    /// score of each composition.
+    #[structopt(long)]
+    pub score_breakdown: bool,
 
     /// Debug options.  `toml`, `params`, `search` and `graph` print the corresponding data
     /// structures.  `no-search` will run as normal but stop just before starting the full search.
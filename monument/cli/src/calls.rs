@@ -1,10 +1,12 @@
-use bellframe::{method::LABEL_LEAD_END, PlaceNot, Stage};
+use bellframe::{method::LABEL_LEAD_END, PnBlock, Stage};
 use itertools::Itertools;
 use monument::parameters::{
     default_calling_positions, BaseCallType, CallId, DEFAULT_MISC_CALL_WEIGHT,
 };
 use serde::Deserialize;
 
+use crate::utils::{OptRangeInclusive, ScheduledWeight};
+
 /// The values of the `base_calls` attribute
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
 #[serde(rename_all = "snake_case")]
@@ -43,8 +45,18 @@ pub struct CustomCall {
     lead_location: Option<CallLabel>,
     // TODO: Make this only allow strings
     calling_positions: Option<CallingPositions>,
+    /// If set, this call can only be used at these calling positions
+    allowed_positions: Option<CallingPositions>,
+    /// This call can't be used at any of these calling positions
+    forbidden_positions: Option<CallingPositions>,
     #[serde(default = "default_misc_call_score")]
     weight: f32,
+    /// Extra weights given to this call in some regions of the composition
+    #[serde(default)]
+    weight_schedule: Vec<ScheduledWeight>,
+    /// Range of how many of this call are allowed
+    #[serde(default)]
+    count: OptRangeInclusive,
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -63,7 +75,7 @@ impl CustomCall {
         id: CallId,
         stage: Stage,
     ) -> anyhow::Result<monument::parameters::Call> {
-        let place_notation = PlaceNot::parse(&self.place_notation, stage).map_err(|e| {
+        let place_notation = PnBlock::parse(&self.place_notation, stage).map_err(|e| {
             anyhow::Error::msg(format!(
                 "Can't parse place notation {:?} for call {:?}: {}",
                 self.place_notation, &self.symbol, e
@@ -85,7 +97,7 @@ impl CustomCall {
         };
         let calling_positions = match &self.calling_positions {
             Some(c) => c.as_vec(),
-            None => default_calling_positions(&place_notation),
+            None => default_calling_positions(place_notation.place_nots().last().unwrap()),
         };
 
         Ok(monument::parameters::Call {
@@ -93,10 +105,14 @@ impl CustomCall {
             used: true,
             symbol: self.symbol.to_owned(),
             calling_positions,
+            allowed_calling_positions: CallingPositions::opt_as_vec(&self.allowed_positions),
+            forbidden_calling_positions: CallingPositions::opt_as_vec(&self.forbidden_positions),
             label_from,
             label_to,
             place_notation,
             weight: self.weight,
+            weight_schedule: crate::utils::weight_schedule(&self.weight_schedule),
+            count_range: self.count.into(),
         })
     }
 }
@@ -104,7 +120,7 @@ impl CustomCall {
 /// The different ways the user can specify a set of calling positions
 #[derive(Debug, Clone, Deserialize)]
 #[serde(untagged, deny_unknown_fields)]
-enum CallingPositions {
+pub enum CallingPositions {
     /// The calling positions should be the `char`s in the given string
     Str(String),
     /// Each calling position is explicitly listed
@@ -120,6 +136,11 @@ impl CallingPositions {
             CallingPositions::List(positions) => positions.clone(),
         }
     }
+
+    /// Same as [`Self::as_vec`], but treating `None` as an empty list of calling positions
+    pub(crate) fn opt_as_vec(positions: &Option<Self>) -> Vec<String> {
+        positions.as_ref().map_or_else(Vec::new, Self::as_vec)
+    }
 }
 
 fn lead_end() -> CallLabel {
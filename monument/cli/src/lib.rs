@@ -22,8 +22,9 @@ use std::{
     time::{Duration, Instant},
 };
 
+use anyhow::Context;
 use log::LevelFilter;
-use monument::{Composition, Search};
+use monument::{Composition, Optimality, Search, Update};
 use ordered_float::OrderedFloat;
 use ringing_utils::PrettyDuration;
 use simple_logger::SimpleLogger;
@@ -81,6 +82,7 @@ pub fn run(
         search.clone(),
         toml_file.print_atw(),
         toml_file.print_duffers(),
+        options.score_breakdown,
         !options.dont_display_comp_numbers,
     );
     let mut update_logger = SingleLineProgressLogger::new(match options.only_display_update_line {
@@ -103,14 +105,19 @@ pub fn run(
 
     // Run the search, collecting the compositions as the search runs
     let mut comps = Vec::<Composition>::new();
-    search.run(
-        |update| {
-            if let Some(comp) = update_logger.log(update) {
-                comps.push(comp);
-            }
-        },
-        &abort_flag,
-    );
+    let mut optimality = None;
+    let update_fn = |update| {
+        if let Update::Optimality(o) = update {
+            optimality = Some(o);
+        }
+        if let Some(comp) = update_logger.log(update) {
+            comps.push(comp);
+        }
+    };
+    match &options.resume {
+        Some(checkpoint_file) => search.resume(checkpoint_file, update_fn, &abort_flag)?,
+        None => search.run(update_fn, &abort_flag),
+    }
 
     // Once the search has completed, sort the compositions and return
     fn rounded_float(f: f32) -> OrderedFloat<f32> {
@@ -125,16 +132,82 @@ pub fn run(
             comp.call_string(),
         )
     });
+    if let Some(format) = options.export_format {
+        export(&comps, format, toml_path, options.export_dir.as_deref())?;
+    }
     Ok(Some(QueryResult {
         comps,
         comp_printer,
         duration: start_time.elapsed(),
         aborted: abort_flag.load(Ordering::SeqCst),
+        optimality,
 
         search,
     }))
 }
 
+/// Evaluate a single `calling` against the query in a TOML file, returning a table summarising the
+/// resulting [`Composition`] (in the same format as the search results).
+pub fn evaluate(
+    toml_path: &Path,
+    calling: &str,
+    options: &args::Options,
+) -> anyhow::Result<String> {
+    let toml_file = TomlFile::new(toml_path)?;
+    let (params, music_displays) = toml_file.to_params(toml_path)?;
+    let search = Arc::new(Search::new(params, toml_file.config(options, false))?);
+    let comp = search.evaluate(calling)?;
+    if let Some(format) = options.export_format {
+        export(
+            std::slice::from_ref(&comp),
+            format,
+            toml_path,
+            options.export_dir.as_deref(),
+        )?;
+    }
+
+    let mut comp_printer = CompositionPrinter::new(
+        music_displays,
+        search,
+        toml_file.print_atw(),
+        toml_file.print_duffers(),
+        options.score_breakdown,
+        false,
+    );
+    Ok(comp_printer.comp_string_with_possible_headers(&comp))
+}
+
+/// Write each [`Composition`] to its own file in `dir` (or the current directory), named after
+/// the input file and the composition's number.
+fn export(
+    comps: &[Composition],
+    format: ExportFormat,
+    toml_path: &Path,
+    dir: Option<&Path>,
+) -> anyhow::Result<()> {
+    let dir = dir.unwrap_or_else(|| Path::new("."));
+    std::fs::create_dir_all(dir)
+        .with_context(|| format!("Can't create export directory {:?}", dir))?;
+    let stem = toml_path
+        .file_stem()
+        .map_or("composition".into(), |s| s.to_string_lossy());
+    for comp in comps {
+        let (text, extension) = match format {
+            ExportFormat::MicroSiril => (comp.to_microsiril(), "siril"),
+            ExportFormat::CompLib => (comp.to_complib(), "txt"),
+        };
+        let path = dir.join(format!(
+            "{}-{}.{}",
+            stem,
+            comp.generation_number(),
+            extension
+        ));
+        std::fs::write(&path, text).with_context(|| format!("Can't write to {:?}", path))?;
+    }
+    log::info!("Exported {} compositions to {:?}", comps.len(), dir);
+    Ok(())
+}
+
 /// How this instance of Monument is being run
 #[derive(Debug, PartialEq, Eq)]
 pub enum Environment {
@@ -150,6 +223,9 @@ pub struct QueryResult {
     pub search: Arc<Search>,
     pub duration: Duration,
     pub aborted: bool,
+    /// Whether an exhaustive search proved that `comps` are the best possible.  `None` for all
+    /// other searches.
+    pub optimality: Option<Optimality>,
 
     comp_printer: self::logging::CompositionPrinter,
 }
@@ -170,6 +246,19 @@ impl QueryResult {
             },
             PrettyDuration(self.duration)
         );
+        match self.optimality {
+            Some(Optimality::Proven) => {
+                eprintln!("These are provably the best compositions.")
+            }
+            Some(Optimality::Unproven {
+                unexplored_fraction,
+            }) => eprintln!(
+                "Search aborted with roughly {:.2}% of the search tree unexplored, so better \
+                 compositions may exist.",
+                unexplored_fraction * 100.0
+            ),
+            None => {}
+        }
     }
 }
 
@@ -185,6 +274,29 @@ pub enum DebugOption {
     StopBeforeSearch,
 }
 
+/// Text formats which compositions can be exported to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    MicroSiril,
+    CompLib,
+}
+
+impl FromStr for ExportFormat {
+    type Err = String;
+
+    fn from_str(v: &str) -> Result<Self, String> {
+        Ok(match v.to_lowercase().as_str() {
+            "microsiril" | "siril" => Self::MicroSiril,
+            "complib" => Self::CompLib,
+            #[rustfmt::skip] // See https://github.com/rust-lang/rustfmt/issues/5204
+            _ => return Err(format!(
+                "Unknown value {:?}. Expected `microsiril` or `complib`.",
+                v
+            )),
+        })
+    }
+}
+
 impl FromStr for DebugOption {
     type Err = String;
 
@@ -72,7 +72,8 @@ impl SingleLineProgressLogger {
         match update {
             Update::Comp(comp) => return Some(comp),
             Update::Progress(progress) => self.last_progress = progress,
-            Update::Complete => {} // Nothing to do, we're just about to return anyway
+            Update::Optimality(_) => {} // Reported once the search is complete
+            Update::Complete => {}      // Nothing to do, we're just about to return anyway
         }
         None
     }
@@ -90,11 +91,12 @@ impl SingleLineProgressLogger {
             p.max_length
         )
         .unwrap();
-        buf.push_str(match (p.aborting, p.truncating_queue) {
-            (false, false) => "",
-            (true, false) => ".  Aborting...",
-            (false, true) => ".  Truncating queue...",
-            (true, true) => unreachable!("Must either be aborting or truncating queue"),
+        buf.push_str(match (p.aborting, p.truncating_queue, p.spilling_queue) {
+            (false, false, false) => "",
+            (true, false, false) => ".  Aborting...",
+            (false, true, false) => ".  Truncating queue...",
+            (false, false, true) => ".  Writing queue to disk...",
+            _ => unreachable!("Can only abort, truncate or write the queue at once"),
         });
     }
 
@@ -144,14 +146,24 @@ pub struct CompositionPrinter {
     /// )
     /// ```
     method_counts: Vec<(usize, String)>,
+    /// The width of the changes of method column, or `None` if it isn't displayed
+    com_width: Option<usize>,
+    /// For each call in the composition, the width of its count column and its symbol
+    call_counts: Vec<(usize, String)>,
     /// `true` if the user gave some weight to atw
     print_atw: bool,
     /// `true` if the user specified anything about duffer courses, otherwise 'false'
     print_duffers: bool,
     /// If a part head should be displayed, then what's its width
     part_head_width: Option<usize>,
+    /// If the composition is multi-extent, the width of the column showing how the extents repeat
+    extents_width: Option<usize>,
+    /// If the amount of falseness is limited, the width of the column counting false rows
+    false_rows_width: Option<usize>,
     /// The column widths of every `MusicDisplay` in the output
     music_widths: Vec<usize>,
+    /// `true` if the score of each kind of weight should be displayed
+    print_score_breakdown: bool,
 }
 
 impl CompositionPrinter {
@@ -160,6 +172,7 @@ impl CompositionPrinter {
         search: Arc<Search>,
         print_atw: bool,
         print_duffers: bool,
+        print_score_breakdown: bool,
         print_comp_widths: bool,
     ) -> Self {
         Self {
@@ -175,16 +188,35 @@ impl CompositionPrinter {
                     (max_width, shorthand)
                 })
                 .collect_vec(),
+            com_width: search.parameters().is_spliced().then(|| {
+                let max_com_width = search.parameters().max_length().to_string().len();
+                max_com_width.max(3)
+            }),
+            call_counts: search
+                .calls()
+                .map(|call| (call.symbol.len().max(3), call.symbol.clone()))
+                .collect_vec(),
             comps_printed: 0,
 
             print_atw,
             print_duffers,
             part_head_width: (search.num_parts() > 2)
                 .then(|| search.effective_part_head_stage().num_bells()),
+            extents_width: search.parameters().extent_length().map(|extent_length| {
+                // The widest structure is when every extent is different (e.g. `1+1+1+1`)
+                let max_extents =
+                    search.parameters().max_length().as_usize() / extent_length.as_usize();
+                (2 * max_extents).saturating_sub(1).max("extents".len())
+            }),
+            false_rows_width: search.parameters().is_falseness_limited().then(|| {
+                let max_false_rows = search.parameters().max_length().to_string().len();
+                max_false_rows.max("false".len())
+            }),
             music_widths: music_displays
                 .iter()
                 .map(|d| d.col_width(&search))
                 .collect_vec(),
+            print_score_breakdown,
 
             search,
             music_displays,
@@ -258,6 +290,20 @@ impl CompositionPrinter {
             }
         }
         s.push('|');
+        // Changes of method
+        if let Some(w) = self.com_width {
+            s.push(' ');
+            write_centered_text(&mut s, "COM", w);
+            s.push_str(" |");
+        }
+        // Call counts
+        if !self.call_counts.is_empty() {
+            for (width, symbol) in &self.call_counts {
+                s.push(' ');
+                write_centered_text(&mut s, symbol, *width);
+            }
+            s.push_str(" |");
+        }
         // Atw
         if self.print_atw {
             s.push_str(" atw |");
@@ -272,6 +318,16 @@ impl CompositionPrinter {
             write_centered_text(&mut s, "PH", w + 2);
             s.push('|');
         }
+        // Extents
+        if let Some(w) = self.extents_width {
+            write_centered_text(&mut s, "extents", w + 2);
+            s.push('|');
+        }
+        // False rows
+        if let Some(w) = self.false_rows_width {
+            write_centered_text(&mut s, "false", w + 2);
+            s.push('|');
+        }
         // Music
         s.push_str("  music  ");
         if !self.music_displays.is_empty() {
@@ -282,6 +338,15 @@ impl CompositionPrinter {
             write_centered_text(&mut s, &music_display.name, *col_width);
             s.push(' ');
         }
+        // Score breakdown
+        if self.print_score_breakdown {
+            s.push('|');
+            for name in SCORE_BREAKDOWN_NAMES {
+                s.push(' ');
+                write_centered_text(&mut s, name, SCORE_BREAKDOWN_WIDTH);
+            }
+            s.push(' ');
+        }
         // Everything else
         s.push_str("| avg score | calling");
         s
@@ -303,6 +368,17 @@ impl CompositionPrinter {
             }
         }
         s.push('|');
+        // Changes of method
+        if let Some(w) = self.com_width {
+            write!(s, " {:>width$} |", comp.changes_of_method(), width = w).unwrap();
+        }
+        // Call counts
+        if !self.call_counts.is_empty() {
+            for ((width, _), count) in self.call_counts.iter().zip_eq(comp.call_counts()) {
+                write!(s, " {:>width$}", count, width = *width).unwrap();
+            }
+            s.push_str(" |");
+        }
         // Atw
         if self.print_atw {
             let factor = comp.atw_factor();
@@ -328,6 +404,16 @@ impl CompositionPrinter {
         if self.part_head_width.is_some() {
             write!(s, " {} |", ShortRow(comp.part_head())).unwrap();
         }
+        // Extents, shown as the number of times each different extent is rung (e.g. `3+2+2`)
+        if let Some(w) = self.extents_width {
+            let extent_counts = comp.extent_call_strings().into_iter().counts();
+            let structure = extent_counts.values().sorted_by(|a, b| b.cmp(a)).join("+");
+            write!(s, " {:>width$} |", structure, width = w).unwrap();
+        }
+        // False rows
+        if let Some(w) = self.false_rows_width {
+            write!(s, " {:>width$} |", comp.falseness().false_rows, width = w).unwrap();
+        }
         // Music
         write!(s, " {:>7.2} ", comp.music_score()).unwrap();
         if !self.music_displays.is_empty() {
@@ -342,6 +428,25 @@ impl CompositionPrinter {
             );
             s.push(' ');
         }
+        // Score breakdown (music is already displayed)
+        if self.print_score_breakdown {
+            let breakdown = comp.score_breakdown();
+            s.push('|');
+            for score in [
+                breakdown.calls,
+                breakdown.splices,
+                breakdown.course_weights,
+                breakdown.scheduled,
+                breakdown.atw,
+                breakdown.method_balance,
+                breakdown.custom_scorer,
+            ] {
+                // Don't print rounding errors as `-0.00`
+                let score = if score.abs() < 0.005 { 0.0 } else { score };
+                write!(s, " {:>width$.2}", score, width = SCORE_BREAKDOWN_WIDTH).unwrap();
+            }
+            s.push(' ');
+        }
         // avg score, call string
         write!(
             s,
@@ -355,6 +460,13 @@ impl CompositionPrinter {
     }
 }
 
+/// The column headers of the score breakdown, in the order they're printed
+const SCORE_BREAKDOWN_NAMES: [&str; 7] = [
+    "calls", "splices", "course", "sched", "atw", "balance", "scorer",
+];
+/// The width of every column in the score breakdown
+const SCORE_BREAKDOWN_WIDTH: usize = 7;
+
 /// Write some `string` to `out`, centering it among `width` spaces (rounding to the right).
 fn write_centered_text(out: &mut String, text: &str, width: usize) {
     let w = width.saturating_sub(text.len());
@@ -1,14 +1,37 @@
 #![deny(rustdoc::broken_intra_doc_links)]
 
 use colored::Colorize;
-use monument_cli::{args::CliArgs, Environment};
-use structopt::StructOpt;
+use monument_cli::{
+    args::{CliArgs, Command},
+    Environment,
+};
+use structopt::{
+    clap::{Error as ClapError, ErrorKind},
+    StructOpt,
+};
 
 fn main() {
     // Run Monument with the CLI args
     let args = CliArgs::from_args();
     monument_cli::init_logging(args.log_level());
-    let result = monument_cli::run(&args.input_file, &args.options, Environment::Cli);
+    let result = match &args.command {
+        Some(Command::Evaluate {
+            input_file,
+            calling,
+            options,
+        }) => monument_cli::evaluate(input_file, calling, options).map(|comp_string| {
+            println!("{}", comp_string);
+            None
+        }),
+        None => match &args.input_file {
+            Some(input_file) => monument_cli::run(input_file, &args.options, Environment::Cli),
+            None => ClapError::with_description(
+                "No input file given (expected `monument <input-file>`)",
+                ErrorKind::MissingRequiredArgument,
+            )
+            .exit(),
+        },
+    };
 
     // Handle the results, either by doing a debug print or printing an error message
     match result {
@@ -17,7 +40,7 @@ fn main() {
                 query_result.print();
             }
         }
-        Ok(None) => assert!(args.options.debug_option.is_some()),
+        Ok(None) => assert!(args.options.debug_option.is_some() || args.command.is_some()),
         Err(e) => {
             // In the case of an error, print the error message nicely then terminate the program
             // with code -1 without causing a panic message.
@@ -6,12 +6,12 @@ use bellframe::{
 };
 use itertools::Itertools;
 use monument::{
-    parameters::{IdGenerator, MusicType, MusicTypeId, OptionalRangeInclusive},
+    parameters::{CountScore, IdGenerator, MusicType, MusicTypeId, OptionalRangeInclusive},
     Search,
 };
 use serde::Deserialize;
 
-use crate::utils::OptRangeInclusive;
+use crate::utils::{OptRangeInclusive, ScheduledWeight};
 
 #[derive(Debug, Clone, Copy, Deserialize)]
 #[serde(rename_all = "snake_case")]
@@ -78,12 +78,20 @@ pub enum TomlMusic {
 pub struct MusicCommon {
     #[serde(default = "crate::utils::get_one")]
     weight: f32,
+    /// Extra weights which only apply in some parts of the composition
+    #[serde(default)]
+    weight_schedule: Vec<ScheduledWeight>,
     /// Possibly unbounded range of counts which are allowed in this music type
     #[serde(rename = "count", default)]
     count_range: OptRangeInclusive,
+    /// Extra score given to the total count of this music, on top of `weight`
+    count_score: Option<TomlCountScore>,
     /// Which strokes this music can apply to
     #[serde(rename = "stroke", default)]
     strokes: StrokeSet,
+    /// If `true`, this counts wraps from one row into the next, instead of single rows
+    #[serde(default)]
+    wrap: bool,
 
     /// If `true`, the count of this will be displayed in the composition summary line.  Defaults
     /// to `true`.
@@ -94,6 +102,25 @@ pub struct MusicCommon {
     name: Option<String>,
 }
 
+/// A score given to the total count of some music
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged, deny_unknown_fields)]
+pub enum TomlCountScore {
+    /// Linearly interpolate between `[count, score]` points
+    Points(Vec<(usize, f32)>),
+    /// Give `score` to any count of at least `threshold`
+    Threshold { threshold: usize, score: f32 },
+}
+
+impl TomlCountScore {
+    fn to_count_score(&self) -> CountScore {
+        match self {
+            Self::Points(points) => CountScore::piecewise(points.clone()),
+            Self::Threshold { threshold, score } => CountScore::threshold(*threshold, *score),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy, Deserialize)]
 pub enum MusicPreset {
     #[serde(rename = "5678 combinations")]
@@ -236,8 +263,11 @@ impl Default for MusicCommon {
     fn default() -> Self {
         Self {
             weight: 1.0,
+            weight_schedule: Vec::new(),
             count_range: OptRangeInclusive::default(),
+            count_score: None,
             strokes: StrokeSet::Both,
+            wrap: false,
 
             show: true,
             name: None,
@@ -325,6 +355,27 @@ fn music_type_runs(
     // per run-length
     if common.show && common.name.is_none() {
         for &len in lengths {
+            if common.wrap {
+                // Wraps aren't at the front or back of a row, so only the total is displayed
+                music_types.push((
+                    new_music_type(
+                        id_gen,
+                        Pattern::run_wraps(stage, len),
+                        true,
+                        common.strokes,
+                        common.weight,
+                        &common.weight_schedule,
+                        None,
+                        OptionalRangeInclusive::default(),
+                    ),
+                    Some(MusicTypeDisplay {
+                        full_name: String::new(),
+                        pattern: Some((format!("{}-bell run wrap", len), PatternPosition::Total)),
+                    }),
+                ));
+                continue;
+            }
+
             let pattern_name = format!("{}-bell run", len);
             let mut all_patterns = Vec::new(); // Used to create the `MusicType` for the total
 
@@ -341,8 +392,11 @@ fn music_type_runs(
                     new_music_type(
                         id_gen,
                         patterns.clone(),
+                        common.wrap,
                         common.strokes,
                         0.0,
+                        &[],
+                        None,
                         OptionalRangeInclusive::default(),
                     ),
                     Some(MusicTypeDisplay {
@@ -365,8 +419,11 @@ fn music_type_runs(
                 new_music_type(
                     id_gen,
                     all_patterns,
+                    common.wrap,
                     common.strokes,
                     common.weight,
+                    &common.weight_schedule,
+                    None,
                     OptionalRangeInclusive::default(),
                 ),
                 Some(MusicTypeDisplay {
@@ -377,23 +434,36 @@ fn music_type_runs(
         }
     }
 
-    // If we need to enforce a count, create a single `MusicType` containing all run lengths
+    // If we need to enforce or score a count, create a single `MusicType` containing all run
+    // lengths
     let count_range = OptionalRangeInclusive::from(common.count_range);
     let need_to_add_weight = music_types.is_empty();
-    if count_range.is_set() || need_to_add_weight {
+    if count_range.is_set() || common.count_score.is_some() || need_to_add_weight {
         let patterns = lengths
             .iter()
-            .flat_map(|length| Pattern::runs(stage, *length, internal))
+            .flat_map(|length| match common.wrap {
+                true => Pattern::run_wraps(stage, *length),
+                false => Pattern::runs(stage, *length, internal),
+            })
             .collect_vec();
-        let weight = if need_to_add_weight {
-            common.weight
+        let (weight, weight_schedule) = if need_to_add_weight {
+            (common.weight, common.weight_schedule.as_slice())
         } else {
-            0.0
+            (0.0, [].as_slice())
         };
         // Runs can't take the `count_each` parameter, so can all be grouped into one
         // `MusicType`
         music_types.push((
-            new_music_type(id_gen, patterns, common.strokes, weight, count_range),
+            new_music_type(
+                id_gen,
+                patterns,
+                common.wrap,
+                common.strokes,
+                weight,
+                weight_schedule,
+                common.count_score.as_ref(),
+                count_range,
+            ),
             None,
         ));
     }
@@ -414,6 +484,12 @@ fn music_type_patterns(
     let mut patterns = Vec::new();
     for pattern_string in pattern_strings {
         let pattern = Pattern::parse(pattern_string, stage)?;
+        if common.wrap && pattern.elems().iter().any(|elem| elem.is_star()) {
+            return Err(anyhow::Error::msg(format!(
+                "Wrap pattern {:?} can't contain `*`s",
+                pattern_string
+            )));
+        }
         patterns.push(pattern);
     }
 
@@ -422,6 +498,7 @@ fn music_type_patterns(
     if individual_count.is_set() || (common.show && common.name.is_none()) {
         types.extend(patterns.iter().cloned().map(|pattern| {
             let name = match (common.show, &common.name) {
+                (true, None) if common.wrap => Some(MusicTypeDisplay::from_wrap_pattern(&pattern)),
                 (true, None) => Some(MusicTypeDisplay::from_pattern(&pattern)),
                 // If `show = true` and `name` is set, then this group *as a whole* will be named
                 (true, Some(_)) => None,
@@ -432,8 +509,11 @@ fn music_type_patterns(
                 new_music_type(
                     id_gen,
                     vec![pattern],
+                    common.wrap,
                     common.strokes,
                     common.weight,
+                    &common.weight_schedule,
+                    None,
                     individual_count,
                 ),
                 name,
@@ -441,7 +521,11 @@ fn music_type_patterns(
         }));
     }
     // Create a single music type for the whole group
-    if types.is_empty() || combined_count.is_set() || (common.show && common.name.is_some()) {
+    if types.is_empty()
+        || combined_count.is_set()
+        || common.count_score.is_some()
+        || (common.show && common.name.is_some())
+    {
         let name = match (common.show, &common.name) {
             (true, Some(name)) => Some(MusicTypeDisplay::with_custom_name(name)),
             // If `common.show = true` but `common.name` is not set, then the patterns will be named
@@ -453,10 +537,17 @@ fn music_type_patterns(
             new_music_type(
                 id_gen,
                 patterns,
+                common.wrap,
                 common.strokes,
                 // If individual `MusicType`s have already been created, then give the combined
                 // `MusicType` a weight of 0 so everything isn't counted twice
                 if types.is_empty() { common.weight } else { 0.0 },
+                if types.is_empty() {
+                    &common.weight_schedule
+                } else {
+                    &[]
+                },
+                common.count_score.as_ref(),
                 combined_count,
             ),
             name,
@@ -472,6 +563,11 @@ fn music_type_preset(
     id_gen: &mut IdGenerator<MusicTypeId>,
     stage: Stage,
 ) -> anyhow::Result<Vec<(MusicType, Option<MusicTypeDisplay>)>> {
+    if common.wrap {
+        return Err(anyhow::Error::msg(
+            "Music presets can't be counted as wraps",
+        ));
+    }
     // Determine the pattern types
     let (combined_patterns, front_back_patterns, default_name) = match preset {
         MusicPreset::Combinations5678s => match stage {
@@ -575,8 +671,11 @@ fn music_type_preset(
         new_music_type(
             id_gen,
             combined_patterns,
+            common.wrap,
             common.strokes,
             common.weight, // Add weight only to the combined `MusicType`
+            &common.weight_schedule,
+            common.count_score.as_ref(),
             OptionalRangeInclusive::from(common.count_range),
         ),
         music_type_display(PatternPosition::Total),
@@ -587,8 +686,11 @@ fn music_type_preset(
             new_music_type(
                 id_gen,
                 front_patterns,
+                common.wrap,
                 common.strokes,
                 0.0, // Weight is accounted for by the combined `MusicType`
+                &[],
+                None,
                 OptionalRangeInclusive::default(),
             ),
             music_type_display(PatternPosition::Front),
@@ -597,8 +699,11 @@ fn music_type_preset(
             new_music_type(
                 id_gen,
                 back_patterns,
+                common.wrap,
                 common.strokes,
                 0.0, // Weight is accounted for by the combined `MusicType`
+                &[],
+                None,
                 OptionalRangeInclusive::default(),
             ),
             music_type_display(PatternPosition::Back),
@@ -758,6 +863,14 @@ impl MusicTypeDisplay {
         }
     }
 
+    /// Wraps aren't at the front or back of a row, so are always displayed in full as a total
+    fn from_wrap_pattern(pattern: &Pattern) -> Self {
+        Self {
+            full_name: String::new(),
+            pattern: Some((format!("{pattern} wrap"), PatternPosition::Total)),
+        }
+    }
+
     /// Given a [`Pattern`], try to determine the underlying pattern, as well as its
     /// [`PatternPosition`] within a [`Row`].
     fn pattern(pattern: &Pattern) -> Option<(String, PatternPosition)> {
@@ -956,11 +1069,15 @@ fn compute_music_displays(
     (music_displays, music_types)
 }
 
+#[allow(clippy::too_many_arguments)]
 fn new_music_type(
     id_gen: &mut IdGenerator<MusicTypeId>,
     patterns: Vec<Pattern>,
+    wraps: bool,
     strokes: StrokeSet,
     weight: f32,
+    weight_schedule: &[ScheduledWeight],
+    count_score: Option<&TomlCountScore>,
     count_range: OptionalRangeInclusive,
 ) -> MusicType {
     MusicType {
@@ -968,8 +1085,11 @@ fn new_music_type(
         used: true,
 
         patterns,
+        wraps,
         strokes: strokes.into(),
         weight,
+        weight_schedule: crate::utils::weight_schedule(weight_schedule),
+        count_score: count_score.map(TomlCountScore::to_count_score),
         count_range,
     }
 }
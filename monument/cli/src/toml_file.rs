@@ -2,11 +2,12 @@ use std::{
     collections::HashMap,
     fmt::Write,
     path::{Path, PathBuf},
+    sync::Arc,
 };
 
 use anyhow::anyhow;
 use bellframe::{
-    method::LABEL_LEAD_END,
+    method::{LABEL_HALF_LEAD, LABEL_LEAD_END, LABEL_SIX_END},
     method_lib::QueryError,
     music::{Elem, Pattern},
     place_not::PnBlockParseError,
@@ -16,18 +17,19 @@ use colored::Colorize;
 use itertools::Itertools;
 use monument::{
     parameters::{
-        BaseCallType, CallDisplayStyle, CallId, IdGenerator, MethodId, MusicType,
+        BaseCallType, CallDisplayStyle, CallId, FalsenessLimit, IdGenerator, MethodId, MusicType,
         OptionalRangeInclusive, Parameters, DEFAULT_BOB_WEIGHT, DEFAULT_SINGLE_WEIGHT,
     },
+    scoring::DefaultScorer,
     utils::{PerPartLength, TotalLength},
-    Config, PartHeadGroup,
+    Config, PartHeadGroup, SearchStrategy,
 };
 use serde::Deserialize;
 
 use crate::{
-    calls::{BaseCalls, CustomCall},
+    calls::{BaseCalls, CallingPositions, CustomCall},
     music::{BaseMusic, MusicDisplay, TomlMusic},
-    utils::OptRangeInclusive,
+    utils::{OptRangeInclusive, ScheduledWeight},
 };
 
 use self::length::Length;
@@ -48,6 +50,10 @@ pub struct TomlFile {
     /// Monument won't stop until it generates the `num_comps` best compositions
     #[serde(default = "default_num_comps")]
     num_comps: usize,
+    /// If set, each composition must differ from every composition generated before it by at
+    /// least this many edits (inserting, removing or changing a lead, call or change of method)
+    #[serde(default)]
+    min_comp_distance: usize,
     /// Set to `false` to allow Monument to ignore falseness and generate false compositions.
     /// Compositions still won't have internal rounds.
     #[serde(default = "crate::utils::get_true")]
@@ -55,6 +61,15 @@ pub struct TomlFile {
     /// Allow Monument to ignore falseness and generate false compositions.  Compositions still
     /// won't have internal rounds.
     allow_false: Option<bool>, // Anti-alias of `require_truth`, deprecated in v0.13.0
+    /// If `true`, the composition is split into extents which each come round and are each true
+    /// within themselves (e.g. a 5040 of Minor made of seven 720s).
+    #[serde(default)]
+    multi_extent: bool,
+    /// If set, no row can appear more than this many times (requires `require_truth = false`)
+    max_row_occurrences: Option<usize>,
+    /// If set, at most this many rows can be repeats of earlier rows (requires
+    /// `require_truth = false`)
+    max_false_rows: Option<usize>,
 
     /* CONFIG OPTIONS */
     /// If set, overrides `--graph-size-limit` CLI argument
@@ -75,6 +90,13 @@ pub struct TomlFile {
     /// Bounds on how many rows of each method is allowed
     #[serde(default)]
     method_count: OptRangeInclusive,
+    /// Bounds on how many changes of method are allowed
+    #[serde(default)]
+    changes_of_method: OptRangeInclusive,
+    /// Score applied for every row by which the method counts differ from an even balance.
+    /// Defaults to `0.0`
+    #[serde(default)]
+    method_balance_weight: f32,
     /// Set to `true` to allow comps to not start at the lead head.
     #[serde(default)]
     snap_start: bool,
@@ -117,6 +139,27 @@ pub struct TomlFile {
     bob_weight: Option<f32>,
     /// The weight given to each single from `base_calls`
     single_weight: Option<f32>,
+    /// Range of how many bobs from `base_calls` are allowed
+    #[serde(default)]
+    bob_count: OptRangeInclusive,
+    /// Range of how many singles from `base_calls` are allowed
+    #[serde(default)]
+    single_count: OptRangeInclusive,
+    /// If set, bobs from `base_calls` can only be used at these calling positions
+    allowed_bob_positions: Option<CallingPositions>,
+    /// Bobs from `base_calls` can't be used at any of these calling positions
+    forbidden_bob_positions: Option<CallingPositions>,
+    /// If set, singles from `base_calls` can only be used at these calling positions
+    allowed_single_positions: Option<CallingPositions>,
+    /// Singles from `base_calls` can't be used at any of these calling positions
+    forbidden_single_positions: Option<CallingPositions>,
+    /// Range of how many calls (of any type) are allowed
+    #[serde(default)]
+    call_count: OptRangeInclusive,
+    /// Extra weights given to every call (including custom calls) in some regions of the
+    /// composition
+    #[serde(default)]
+    call_weight_schedule: Vec<ScheduledWeight>,
     /// Which calls to use in the compositions
     #[serde(default)]
     calls: Vec<CustomCall>,
@@ -145,9 +188,11 @@ pub struct TomlFile {
     /// reached.
     #[serde(default)] // The default/empty string parses to rounds on any stage
     end_row: String,
-    /// A [`Row`] which generates the part heads of this composition
+    /// One or more [`Row`]s which generate the part heads of this composition
     #[serde(default)]
-    part_head: String,
+    part_head: PartHeadGenerators,
+    /// An explicit list of every part head of this composition (overrides `part_head`)
+    part_heads: Option<Vec<String>>,
     /// If set, allows arbitrary splitting of the tenors (warning: this blows up the search size on
     /// large stages)
     #[serde(default)]
@@ -162,6 +207,9 @@ pub struct TomlFile {
     /// Weight given to every row in a course, for every handbell pair that's coursing
     #[serde(default)]
     handbell_coursing_weight: f32,
+    /// Sets of courses which must each appear in the composition
+    #[serde(default)]
+    required_courses: Vec<RequiredCourses>,
 
     /* NO-DUFFERS */
     /// Courses which should not be considered a 'duffer'.  If nothing is specified, all courses
@@ -213,7 +261,7 @@ impl TomlFile {
             })?;
 
         let (music_displays, music_types) = self.music(toml_path, stage)?;
-        let part_head = parse_row("part head", &self.part_head, stage)?;
+        let part_head_group = self.part_head_group(stage)?;
 
         let calling_bell = match self.calling_bell {
             Some(v) => Bell::from_number(v).ok_or_else(|| {
@@ -221,9 +269,35 @@ impl TomlFile {
             })?,
             None => stage.tenor(),
         };
+        // Principles like Stedman get calls at their six ends, rather than at lead ends.  If
+        // they're spliced with other methods, the base calls stay at the lead ends so that there's
+        // only one bob and one single.
+        let six_methods = parsed_methods
+            .iter()
+            .filter(|(m, _)| m.six_ends().is_some())
+            .map(|(m, _)| m.title())
+            .collect_vec();
+        let num_six_methods = six_methods.len();
+        let base_call_type = match self.base_calls.as_monument_type() {
+            Some(_) if num_six_methods == parsed_methods.len() => Some(BaseCallType::SixEnd),
+            Some(ty) => {
+                // Only warn if the user hasn't already added their own calls
+                if self.calls.is_empty() {
+                    for title in &six_methods {
+                        log::warn!(
+                            "{title} is made of sixes, but base calls are only made at lead ends.  Add custom calls with `label = \"SIX\"` to call its sixes."
+                        );
+                    }
+                }
+                Some(ty)
+            }
+            None => None,
+        };
         // TODO: Make this configurable
         // TODO: Move this into `lib/`
-        let call_display_style = if part_head.is_fixed(calling_bell) {
+        let call_display_style = if num_six_methods == parsed_methods.len() {
+            CallDisplayStyle::Sixes
+        } else if part_head_group.is_fixed(calling_bell) {
             CallDisplayStyle::CallingPositions(calling_bell)
         } else {
             CallDisplayStyle::Positional
@@ -233,22 +307,33 @@ impl TomlFile {
             length: self.length.as_total_length_range(),
             stage,
             num_comps: self.num_comps,
+            min_comp_distance: self.min_comp_distance,
             require_truth: self.require_truth,
-            maybe_unused_methods: self.build_methods(parsed_methods, &part_head, stage)?,
+            multi_extent: self.multi_extent,
+            falseness_limit: FalsenessLimit {
+                max_row_occurrences: self.max_row_occurrences,
+                max_false_rows: self.max_false_rows,
+            },
+            maybe_unused_methods: self.build_methods(parsed_methods, &part_head_group, stage)?,
             splice_style: self.splice_style.into(),
             splice_weight: self.splice_weight,
-            maybe_unused_calls: self.calls(stage)?,
+            com_range: self.changes_of_method.into(),
+            method_balance_weight: self.method_balance_weight,
+            maybe_unused_calls: self.calls(base_call_type, stage)?,
+            call_count_range: self.call_count.into(),
             call_display_style,
             atw_weight: self.atw_weight,
             require_atw: self.require_atw,
             start_row: parse_row("start row", &self.start_row, stage)?,
             end_row: parse_row("end row", &self.end_row, stage)?,
-            part_head_group: PartHeadGroup::new(&part_head),
+            part_head_group,
             course_weights: self.course_weights(stage)?,
+            required_courses: self.required_courses(stage)?,
             max_contiguous_duffer: self.max_contiguous_duffer.map(PerPartLength::new),
             max_total_duffer: self.max_total_duffer.map(TotalLength::new),
             maybe_unused_music_types: music_types,
             start_stroke: self.start_stroke,
+            scorer: Arc::new(DefaultScorer),
         };
         Ok((params, music_displays))
     }
@@ -265,6 +350,9 @@ impl TomlFile {
         let mut config = Config {
             thread_limit: opts.num_threads,
             leak_search_memory,
+            spill_dir: opts.spill_dir.clone(),
+            // Resumed searches keep updating their checkpoint, unless told to save it elsewhere
+            checkpoint_file: opts.checkpoint.clone().or_else(|| opts.resume.clone()),
             ..Default::default()
         };
         if let Some(limit) = opts.graph_size_limit.or(self.graph_size_limit) {
@@ -273,30 +361,45 @@ impl TomlFile {
         if let Some(limit) = opts.mem_limit {
             config.mem_limit = limit;
         }
+        if let Some(width) = opts.beam_width {
+            config.strategy = SearchStrategy::Beam { width };
+        } else if opts.depth_first {
+            config.strategy = SearchStrategy::DepthFirst;
+        } else if opts.exhaustive {
+            config.strategy = SearchStrategy::Exhaustive;
+        }
         config
     }
 
     /// Also check that `bobs_only` and `singles_only` aren't set at the same time.
-    fn calls(&self, stage: Stage) -> anyhow::Result<Vec<monument::parameters::Call>> {
+    fn calls(
+        &self,
+        base_call_type: Option<BaseCallType>,
+        stage: Stage,
+    ) -> anyhow::Result<Vec<monument::parameters::Call>> {
         let mut call_id_generator = IdGenerator::<CallId>::starting_at_zero();
         // Convert base calls
-        let mut calls = self.base_calls(&mut call_id_generator, stage)?;
+        let mut calls = self.base_calls(&mut call_id_generator, base_call_type, stage)?;
         // Convert custom calls
         for custom_call in &self.calls {
             calls.push(custom_call.as_monument_call(call_id_generator.next(), stage)?);
         }
+        // Add the schedule which applies to every call
+        for call in &mut calls {
+            call.weight_schedule
+                .extend(crate::utils::weight_schedule(&self.call_weight_schedule));
+        }
         Ok(calls)
     }
 
     fn base_calls(
         &self,
         id_gen: &mut IdGenerator<CallId>,
+        base_call_type: Option<BaseCallType>,
         stage: Stage,
     ) -> anyhow::Result<Vec<monument::parameters::Call>> {
-        let base_call_type = match self.base_calls {
-            BaseCalls::Near => BaseCallType::Near,
-            BaseCalls::Far => BaseCallType::Far,
-            BaseCalls::None => return Ok(vec![]), // No base calls to generate
+        let Some(base_call_type) = base_call_type else {
+            return Ok(vec![]); // No base calls to generate
         };
 
         // Suggest `{bobs,singles}_only` if the user gives calls an extreme negative weight
@@ -318,13 +421,40 @@ impl TomlFile {
             ));
         }
 
-        Ok(monument::parameters::base_calls(
+        // Bobs and singles are generated separately, so that each can be given its own count
+        // limits and calling position restrictions
+        let bob_weight = self.bob_weight.unwrap_or(DEFAULT_BOB_WEIGHT);
+        let single_weight = self.single_weight.unwrap_or(DEFAULT_SINGLE_WEIGHT);
+        let mut bobs = monument::parameters::base_calls(
             id_gen,
             base_call_type,
-            (!self.singles_only).then_some(self.bob_weight.unwrap_or(DEFAULT_BOB_WEIGHT)),
-            (!self.bobs_only).then_some(self.single_weight.unwrap_or(DEFAULT_SINGLE_WEIGHT)),
+            (!self.singles_only).then_some(bob_weight),
+            None,
             stage,
-        ))
+        );
+        for bob in &mut bobs {
+            bob.count_range = self.bob_count.into();
+            bob.allowed_calling_positions =
+                CallingPositions::opt_as_vec(&self.allowed_bob_positions);
+            bob.forbidden_calling_positions =
+                CallingPositions::opt_as_vec(&self.forbidden_bob_positions);
+        }
+        let mut singles = monument::parameters::base_calls(
+            id_gen,
+            base_call_type,
+            None,
+            (!self.bobs_only).then_some(single_weight),
+            stage,
+        );
+        for single in &mut singles {
+            single.count_range = self.single_count.into();
+            single.allowed_calling_positions =
+                CallingPositions::opt_as_vec(&self.allowed_single_positions);
+            single.forbidden_calling_positions =
+                CallingPositions::opt_as_vec(&self.forbidden_single_positions);
+        }
+        bobs.extend(singles);
+        Ok(bobs)
     }
 
     fn music(
@@ -396,23 +526,40 @@ impl TomlFile {
         Ok(weights)
     }
 
+    fn required_courses(
+        &self,
+        stage: Stage,
+    ) -> anyhow::Result<Vec<monument::parameters::RequiredCourses>> {
+        let mut required_courses = Vec::new();
+        for required in &self.required_courses {
+            required_courses.push(required.as_monument_required_courses(stage)?);
+        }
+        Ok(required_courses)
+    }
+
     fn build_methods(
         &self,
         parsed_methods: Vec<(bellframe::Method, MethodCommon)>,
-        part_head: &Row,
+        part_head_group: &PartHeadGroup,
         stage: Stage,
     ) -> anyhow::Result<Vec<monument::parameters::Method>> {
-        // Warn when using plain bob calls in Stedman or Grandsire
+        // Warn when using Plain Bob calls in methods that don't have exactly one hunt bell (e.g.
+        // Grandsire).  Principles built from sixes (e.g. Stedman) get six end calls instead.
         for (method, _) in &parsed_methods {
-            if self.base_calls != BaseCalls::None {
-                match method.name.as_str() {
-                    // TODO: More precisely, we should check for Grandsire-like methods
-                    "Grandsire" | "Stedman" => log::warn!(
-                        "It looks like you're using Plain Bob calls in {}.  Try `base_calls = \"none\"`?",
-                        method.name
-                    ),
-                    _ => {}
-                }
+            let num_hunt_bells = method
+                .stage()
+                .bells()
+                .filter(|&b| method.lead_head().is_fixed(b))
+                .count();
+            if self.base_calls != BaseCalls::None
+                && method.six_ends().is_none()
+                && num_hunt_bells != 1
+            {
+                log::warn!(
+                    "It looks like you're using Plain Bob calls in {}, which has {} hunt bells.  Try `base_calls = \"none\"`?",
+                    method.title(),
+                    num_hunt_bells
+                );
             }
         }
 
@@ -435,8 +582,10 @@ impl TomlFile {
             // or all (e.g. cyclic), but any other combinations are possible.  E.g. a composition
             // of Maximus with part head of `1765432` will still preserve 8 through 12.
             None => {
-                let tenors_unaffected_by_part_head =
-                    stage.bells().skip(6).filter(|&b| part_head.is_fixed(b));
+                let tenors_unaffected_by_part_head = stage
+                    .bells()
+                    .skip(6)
+                    .filter(|&b| part_head_group.is_fixed(b));
                 vec![Mask::with_fixed_bells(
                     stage,
                     tenors_unaffected_by_part_head,
@@ -458,6 +607,7 @@ impl TomlFile {
 
         let mut id_gen = IdGenerator::<MethodId>::starting_at_zero();
         let mut methods = Vec::new();
+        let is_spliced = parsed_methods.len() > 1;
         // TODO: Add dummy unused method, to make sure that Monument handles them correctly
         for (mut method, common) in parsed_methods {
             let lead_len_isize = method.lead_len() as isize;
@@ -477,10 +627,31 @@ impl TomlFile {
             if common.course_heads.is_some() {
                 anyhow::bail!("`methods.course_heads` has been renamed to `courses`");
             }
-            // Add lead labels
-            let labels = common.labels.unwrap_or_else(|| {
-                hmap::hmap! {
-                    LABEL_LEAD_END.to_owned() => LeadLabels::JustOne(0)
+            // Add lead labels.  Principles built from sixes are always labelled at their six ends,
+            // and only get a lead end label if the user asks for one.
+            let six_ends = method.six_ends();
+            for &(idx, six_type) in six_ends.iter().flatten() {
+                method.add_label(idx, LABEL_SIX_END.to_owned());
+                method.add_label(idx, six_type.label().to_owned());
+            }
+            let labels = common.labels.unwrap_or_else(|| match six_ends {
+                Some(_) => HashMap::new(),
+                None => {
+                    let mut labels = hmap::hmap! {
+                        LABEL_LEAD_END.to_owned() => LeadLabels::JustOne(0)
+                    };
+                    // Splice styles which use half leads also label the half leads of
+                    // symmetric methods
+                    let half_lead_idx = method
+                        .half_lead_idx()
+                        .filter(|_| self.splice_style.uses_half_leads());
+                    if let Some(idx) = half_lead_idx {
+                        labels.insert(
+                            LABEL_HALF_LEAD.to_owned(),
+                            LeadLabels::JustOne(idx as isize),
+                        );
+                    }
+                    labels
                 }
             });
             for (label, indices) in labels {
@@ -488,6 +659,15 @@ impl TomlFile {
                     method.add_label(idx, label.clone());
                 }
             }
+            if is_spliced
+                && self.splice_style == SpliceStyle::HalfLeads
+                && method.label_indices(LABEL_HALF_LEAD).next().is_none()
+            {
+                log::warn!(
+                    "{} has no half-lead label, so can only be spliced at calls.  Try adding `labels = {{ LE = 0, HL = <index> }}`?",
+                    method.title()
+                );
+            }
             // Build method
             let allowed_courses = match common.courses {
                 Some(ch_strings) => parse_masks("course mask", &ch_strings, stage)?,
@@ -520,6 +700,52 @@ impl TomlFile {
     }
 }
 
+////////////////
+// PART HEADS //
+////////////////
+
+/// The value of `part_head`, which can be either one [`Row`] or a list of them
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum PartHeadGenerators {
+    JustOne(String),
+    Many(Vec<String>),
+}
+
+impl Default for PartHeadGenerators {
+    fn default() -> Self {
+        // The empty string parses to rounds on any stage
+        Self::JustOne(String::new())
+    }
+}
+
+impl TomlFile {
+    fn part_head_group(&self, stage: Stage) -> anyhow::Result<PartHeadGroup> {
+        if let Some(part_heads) = &self.part_heads {
+            if !matches!(&self.part_head, PartHeadGenerators::JustOne(s) if s.is_empty()) {
+                anyhow::bail!("Can't set both `part_head` and `part_heads`");
+            }
+            let rows = part_heads
+                .iter()
+                .map(|s| parse_row("part head", s, stage))
+                .collect::<anyhow::Result<Vec<_>>>()?;
+            return Ok(PartHeadGroup::from_rows(stage, &rows)?);
+        }
+        Ok(match &self.part_head {
+            PartHeadGenerators::JustOne(s) => {
+                PartHeadGroup::new(&parse_row("part head", s, stage)?)
+            }
+            PartHeadGenerators::Many(strings) => {
+                let generators = strings
+                    .iter()
+                    .map(|s| parse_row("part head", s, stage))
+                    .collect::<anyhow::Result<Vec<_>>>()?;
+                PartHeadGroup::from_generators(stage, &generators)?
+            }
+        })
+    }
+}
+
 fn parse_row(name: &str, s: &str, stage: Stage) -> Result<RowBuf, anyhow::Error> {
     RowBuf::parse_with_stage(s, stage)
         .map_err(|e| anyhow::Error::msg(format!("Can't parse {} {:?}: {}", name, s, e)))
@@ -595,15 +821,36 @@ pub enum SpliceStyle {
     /// [lead ends](bellframe::method::LABEL_LEAD_END)).
     #[serde(rename = "leads")]
     LeadLabels,
+    /// Same as `LeadLabels`, but symmetric methods are also labelled at their
+    /// [half leads](bellframe::method::LABEL_HALF_LEAD) by default.
+    #[serde(rename = "every-label")]
+    EveryLabel,
+    /// Plain splices only happen at [half leads](bellframe::method::LABEL_HALF_LEAD).
+    #[serde(rename = "half-leads")]
+    HalfLeads,
+    /// Plain splices only happen at course heads.
+    #[serde(rename = "course-heads")]
+    CourseHeads,
     /// Splices only happen at calls.
     #[serde(rename = "calls")]
     Calls,
 }
 
+impl SpliceStyle {
+    /// Does this splice style need methods to be labelled at their half leads?
+    fn uses_half_leads(self) -> bool {
+        matches!(self, Self::EveryLabel | Self::HalfLeads)
+    }
+}
+
 impl From<self::SpliceStyle> for monument::parameters::SpliceStyle {
     fn from(style: self::SpliceStyle) -> Self {
         match style {
-            self::SpliceStyle::LeadLabels => monument::parameters::SpliceStyle::LeadLabels,
+            self::SpliceStyle::LeadLabels | self::SpliceStyle::EveryLabel => {
+                monument::parameters::SpliceStyle::LeadLabels
+            }
+            self::SpliceStyle::HalfLeads => monument::parameters::SpliceStyle::HalfLeads,
+            self::SpliceStyle::CourseHeads => monument::parameters::SpliceStyle::CourseHeads,
             self::SpliceStyle::Calls => monument::parameters::SpliceStyle::Calls,
         }
     }
@@ -690,7 +937,7 @@ impl CourseSet {
     ) -> anyhow::Result<monument::parameters::CourseSet> {
         Ok(match self {
             CourseSet::OneMask(mask_str) => {
-                monument::parameters::CourseSet::from(parse_mask("non-duffer", mask_str, stage)?)
+                monument::parameters::CourseSet::from(parse_mask(mask_kind, mask_str, stage)?)
             }
             CourseSet::WithOptions {
                 courses: masks,
@@ -705,6 +952,59 @@ impl CourseSet {
     }
 }
 
+//////////////////////
+// REQUIRED COURSES //
+//////////////////////
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged, deny_unknown_fields)]
+enum RequiredCourses {
+    OneMask(String),
+    WithOptions {
+        courses: Vec<String>,
+        #[serde(default)]
+        any_stroke: bool,
+        #[serde(default)]
+        any_bells: bool,
+        #[serde(default = "get_one_lead")]
+        min_leads: usize,
+    },
+}
+
+impl RequiredCourses {
+    fn as_monument_required_courses(
+        &self,
+        stage: Stage,
+    ) -> anyhow::Result<monument::parameters::RequiredCourses> {
+        let mask_kind = "required course mask";
+        Ok(match self {
+            RequiredCourses::OneMask(mask_str) => monument::parameters::RequiredCourses {
+                courses: monument::parameters::CourseSet::from(parse_mask(
+                    mask_kind, mask_str, stage,
+                )?),
+                min_leads: 1,
+            },
+            RequiredCourses::WithOptions {
+                courses: masks,
+                any_stroke,
+                any_bells,
+                min_leads,
+            } => monument::parameters::RequiredCourses {
+                courses: monument::parameters::CourseSet {
+                    masks: parse_masks(mask_kind, masks, stage)?,
+                    any_stroke: *any_stroke,
+                    any_bells: *any_bells,
+                },
+                min_leads: *min_leads,
+            },
+        })
+    }
+}
+
+fn get_one_lead() -> usize {
+    1
+}
+
 ////////////
 // LENGTH //
 ////////////
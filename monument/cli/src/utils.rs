@@ -1,7 +1,7 @@
 use std::path::Path;
 
 use bellframe::Stroke;
-use monument::parameters::OptionalRangeInclusive;
+use monument::parameters::{OptionalRangeInclusive, RowPosition};
 use serde::Deserialize;
 
 /// A version of [`OptionalRangeInclusive`] which allows for convenient deserialisation from a
@@ -35,6 +35,56 @@ impl From<OptRangeInclusive> for OptionalRangeInclusive {
     }
 }
 
+/// An extra weight which only applies to some region of each part.  Positions count rows from the
+/// start of the part, or from the end of the part if they're negative (so `start = -224` would
+/// apply the weight to the last 224 rows of each part).
+#[derive(Debug, Clone, Deserialize)]
+#[serde(try_from = "TomlScheduledWeight")]
+pub struct ScheduledWeight(pub monument::parameters::ScheduledWeight);
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TomlScheduledWeight {
+    /// The first row where the weight applies.  Defaults to the start of each part.
+    #[serde(default)]
+    start: isize,
+    /// The first row after `start` where the weight **doesn't** apply.  Defaults to the end of
+    /// each part.
+    end: Option<isize>,
+    /// The (1-indexed) parts where the weight applies.  Defaults to every part.
+    #[serde(default)]
+    parts: Vec<usize>,
+    weight: f32,
+}
+
+impl TryFrom<TomlScheduledWeight> for ScheduledWeight {
+    type Error = String;
+
+    fn try_from(w: TomlScheduledWeight) -> Result<Self, String> {
+        fn row_position(pos: isize) -> RowPosition {
+            match pos {
+                p if p < 0 => RowPosition::FromEnd(p.unsigned_abs()),
+                p => RowPosition::FromStart(p as usize),
+            }
+        }
+
+        if w.parts.contains(&0) {
+            return Err("parts in `weight_schedule` are numbered from 1".to_owned());
+        }
+        Ok(Self(monument::parameters::ScheduledWeight {
+            start: row_position(w.start),
+            end: w.end.map_or(RowPosition::FromEnd(0), row_position),
+            parts: w.parts.iter().map(|p| p - 1).collect(),
+            weight: w.weight,
+        }))
+    }
+}
+
+/// Convert a list of [`ScheduledWeight`]s into their `monument` equivalents
+pub fn weight_schedule(schedule: &[ScheduledWeight]) -> Vec<monument::parameters::ScheduledWeight> {
+    schedule.iter().map(|w| w.0.clone()).collect()
+}
+
 /// Attempt to read a file as a [`String`], returning a helpful error message on failure
 pub fn read_file_to_string(path: &Path) -> anyhow::Result<String> {
     std::fs::read_to_string(path)
@@ -109,7 +109,9 @@ impl AtwTable {
     }
 
     /// Given the [`ChunkId`] and [`PerPartLength`] of a chunk, create an [`AtwBitmap`] which
-    /// stores all of that chunk's ATW information.
+    /// stores all of that chunk's ATW information.  Chunks which don't line up with the chunks
+    /// used to build this table (e.g. when [evaluating](crate::Search::evaluate) a calling which
+    /// leaves the graph) only get the flags which they cover entirely.
     pub fn bitmap_for_chunk(
         &self,
         query: &Query,
@@ -132,12 +134,15 @@ impl AtwTable {
                     let start_idx = index_within_bit_starts(sub_lead_range.start);
                     let end_idx = index_within_bit_starts(sub_lead_range.end);
                     let bit_idxs = &bit_starts[start_idx..end_idx];
-                    assert!(!bit_idxs.is_empty());
-                    assert_eq!(bit_idxs[0].0, sub_lead_range.start);
 
                     // Add each of the bits we've found to the bitmask for this chunk
-                    for (_sub_lead_idx, bit_index) in bit_idxs {
-                        bitmap.add_bit(*bit_index);
+                    for (sub_lead_idx, bit_index) in bit_idxs {
+                        let flag = self.flag_per_bit[bit_index.0]
+                            .as_ref()
+                            .expect("Every bit index should correspond to a flag");
+                        if sub_lead_idx + flag.sub_lead_chunk_len.as_usize() <= sub_lead_range.end {
+                            bitmap.add_bit(*bit_index);
+                        }
                     }
                 }
             }
@@ -177,6 +182,12 @@ impl AtwTable {
         self.atw_weight * factor
     }
 
+    /// An upper bound on how much the atw score of a composition could increase if more chunks
+    /// were added to it.
+    pub fn max_atw_score_gain(&self, bitmap: &AtwBitmap) -> f32 {
+        (self.atw_weight - self.atw_score(bitmap)).max(0.0)
+    }
+
     /// Factor from `0.0..=1.0`, where `0.0` means no place bells are rung and `1.0` means the comp
     /// is ATW.
     pub fn atw_factor(&self, bitmap: &AtwBitmap) -> f32 {
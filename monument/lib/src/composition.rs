@@ -3,14 +3,18 @@
 use std::{collections::HashMap, hash::Hash, sync::Arc};
 
 use bellframe::{Block, Row, RowBuf};
+use itertools::Itertools;
 
 use crate::{
     atw::{AtwBitmap, AtwTable, PlaceBellRange},
     group::PartHead,
     parameters::{
-        CallDisplayStyle, CallIdx, MethodId, MethodIdx, MethodVec, MusicTypeId, Parameters,
+        Call, CallDisplayStyle, CallIdx, MethodId, MethodIdx, MethodVec, MusicTypeId,
+        ScheduledWeight, SpliceStyle,
     },
     query::Query,
+    scoring::course_weight,
+    search::graph_chunks,
     utils::{
         counts::Counts,
         lengths::{PerPartLength, TotalLength},
@@ -19,11 +23,74 @@ use crate::{
 
 #[allow(unused_imports)] // Used by doc comments
 use crate::{
-    parameters::{Method, MusicType},
+    parameters::{Method, MusicType, Parameters},
     Search,
 };
 
+/// How false a [`Composition`] is.  In a [multi-extent](Parameters::multi_extent) composition,
+/// rows only count as false if they're repeated within one extent.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Falseness {
+    /// The number of rows which are repeats of earlier rows
+    pub false_rows: usize,
+    /// The most times that any one [`Row`] appears (`1` for a true composition)
+    pub max_row_occurrences: usize,
+}
+
+impl Falseness {
+    pub fn is_true(self) -> bool {
+        self.false_rows == 0
+    }
+}
+
+/// How much each kind of weight contributes to a [`Composition`]'s
+/// [`total_score`](Composition::total_score).  These add up to the total score (give or take some
+/// floating-point error).
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ScoreBreakdown {
+    /// The score from each [`MusicType`]'s [`weight`](MusicType::weight) and
+    /// [`count_score`](MusicType::count_score) (the same as [`Composition::music_score`])
+    pub music: f32,
+    /// The score from each [`Call`]'s [`weight`](Call::weight)
+    pub calls: f32,
+    /// The score from [`splice_weight`](Parameters::splice_weight)
+    pub splices: f32,
+    /// The score from [`course_weights`](Parameters::course_weights)
+    pub course_weights: f32,
+    /// The score from the [`weight_schedule`](MusicType::weight_schedule)s of every
+    /// [`MusicType`] and [`Call`]
+    pub scheduled: f32,
+    /// The score from [`atw_weight`](Parameters::atw_weight)
+    pub atw: f32,
+    /// The score from [`method_balance_weight`](Parameters::method_balance_weight)
+    pub method_balance: f32,
+    /// The difference between the scores given by [`Parameters::scorer`] and the scores that
+    /// Monument's usual weights would give.  This is always zero for the
+    /// [`DefaultScorer`](crate::scoring::DefaultScorer).
+    pub custom_scorer: f32,
+}
+
+impl ScoreBreakdown {
+    /// The sum of every part of this `ScoreBreakdown`
+    pub fn total(&self) -> f32 {
+        self.music
+            + self.calls
+            + self.splices
+            + self.course_weights
+            + self.scheduled
+            + self.atw
+            + self.method_balance
+            + self.custom_scorer
+    }
+}
+
 /// A [`Composition`] generated by Monument.
+///
+/// Two `Composition`s are equal (and have the same hash) if they were generated by the same
+/// [`Search`] and ring the same calling with the same methods from the same start row, even if the
+/// calling is rotated (i.e. starts at a different point in each part) or they have different part
+/// heads.  [Multi-extent](Parameters::multi_extent) compositions are never rotated, because that
+/// would move the ends of their extents.
 #[derive(Debug, Clone)]
 pub struct Composition {
     /// Which index in the generation order this composition appeared, starting from 0.
@@ -31,6 +98,12 @@ pub struct Composition {
     pub(crate) generation_number: usize,
 
     pub(crate) path: Vec<PathElem>,
+    /// The index into `path` of the first [`PathElem`] of each extent.  Compositions which aren't
+    /// [multi-extent](Parameters::multi_extent) have one extent, starting at index `0`.
+    pub(crate) extent_starts: Vec<usize>,
+    /// Computed once from `path`, and used for equality and hashing.  This is boxed to keep
+    /// [`Update`](crate::search::Update)s small.
+    pub(crate) canonical_form: Box<CanonicalForm>,
 
     pub(crate) length: TotalLength,
     pub(crate) part_head: PartHead,
@@ -39,6 +112,8 @@ pub struct Composition {
     pub(crate) total_score: f32,
     /// The number of rows generated of each method
     pub(crate) method_counts: Counts,
+    /// The number of instances of each call (summed over all parts)
+    pub(crate) call_counts: Counts,
     pub(crate) atw_bitmap: AtwBitmap,
     /// The number of counts generated of each [`MusicType`]
     pub(crate) music_counts: HashMap<MusicTypeId, usize>,
@@ -60,29 +135,67 @@ impl Composition {
 
     /// Generate a human-friendly [`String`] summarising the calling of this composition.  For
     /// example, [this composition](https://complib.org/composition/87419) would have a
-    /// `call_string` of `D[B]BL[W]N[M]SE[sH]NCYW[sH]`.
+    /// `call_string` of `D[B]BL[W]N[M]SE[sH]NCYW[sH]`.  Changes of method part-way through a lead
+    /// (e.g. in half-lead spliced) are shown with a `/`, so `YC/Y[H]` is a lead of `Y` followed
+    /// by a lead which starts as `C` and finishes as `Y`.  The callings of the extents of a
+    /// [multi-extent](Parameters::multi_extent) composition are separated by ` + `.
     pub fn call_string(&self) -> String {
+        self.extent_call_strings().join(" + ")
+    }
+
+    /// The number of extents in this composition.  This is always `1` unless the composition is
+    /// [multi-extent](Parameters::multi_extent).
+    pub fn num_extents(&self) -> usize {
+        self.extent_starts.len()
+    }
+
+    /// The [`call_string`](Self::call_string) of every extent in this composition, in the order
+    /// they're rung.
+    pub fn extent_call_strings(&self) -> Vec<String> {
+        self.extents()
+            .map(|path| self.extent_call_string(path))
+            .collect_vec()
+    }
+
+    /// The [`PathElem`]s which make up each extent of this composition
+    pub(crate) fn extents(&self) -> impl Iterator<Item = &[PathElem]> {
+        extents(&self.path, &self.extent_starts)
+    }
+
+    fn extent_call_string(&self, path: &[PathElem]) -> String {
+        if self.query.call_display_style == CallDisplayStyle::Sixes && !self.query.is_spliced() {
+            return self.six_number_call_string(path);
+        }
+
         let needs_brackets = self.query.is_spliced()
-            || self.query.call_display_style == CallDisplayStyle::Positional;
-        let is_snap_start = self.path[0].start_sub_lead_idx > 0;
-        let is_snap_finish = self.path.last().unwrap().end_sub_lead_idx(&self.query) > 0;
+            || !matches!(
+                self.query.call_display_style,
+                CallDisplayStyle::CallingPositions(_)
+            );
+        let is_snap_start = path[0].start_sub_lead_idx > 0;
+        let is_snap_finish = path.last().unwrap().end_sub_lead_idx(&self.query) > 0;
         let part_head = self.part_head();
 
-        let mut path_iter = self.path.iter().peekable();
+        let mut path_iter = path.iter().peekable();
 
         let mut s = String::new();
         if self.query.call_display_style == CallDisplayStyle::Positional {
             s.push('#');
         }
         s.push_str(if is_snap_start { "<" } else { "" });
+        let mut is_first_elem = true;
         while let Some(path_elem) = path_iter.next() {
             // Method text
             if self.query.is_spliced()
                 || self.query.call_display_style == CallDisplayStyle::Positional
             {
+                // Elems which start part-way through a lead (e.g. in half-lead spliced) are
+                // separated from the previous method by a `/`, so `B/C` is a lead which starts as
+                // `B` and finishes as `C`
+                if path_elem.start_sub_lead_idx != 0 && !is_first_elem {
+                    s.push('/');
+                }
                 // Add one shorthand for every lead *covered* (not number of lead heads reached)
-                //
-                // TODO: Deal with half-lead spliced
                 let method = &self.query.methods[path_elem.method];
                 let num_leads_covered = num_leads_covered(
                     method.lead_len(),
@@ -110,16 +223,48 @@ impl Composition {
                         s.push_str(calling_position);
                     }
                     // TODO: Compute actual counts for positional calls
-                    CallDisplayStyle::Positional => s.push_str(&call.symbol),
+                    CallDisplayStyle::Positional | CallDisplayStyle::Sixes => {
+                        s.push_str(&call.symbol)
+                    }
                 }
                 s.push_str(if needs_brackets { "]" } else { "" });
             }
+            is_first_elem = false;
         }
         s.push_str(if is_snap_finish { ">" } else { "" });
 
         s
     }
 
+    /// The calling of a [`CallDisplayStyle::Sixes`] composition, where each call is given by the
+    /// number of the six it's made in (e.g. `5 s11 12 13 s17`).  Sixes are counted from the start
+    /// of the part, so the six containing the first row is six 1.
+    fn six_number_call_string(&self, path: &[PathElem]) -> String {
+        let is_snap_start = path[0].start_sub_lead_idx > 0;
+        let is_snap_finish = path.last().unwrap().end_sub_lead_idx(&self.query) > 0;
+
+        let mut calls = Vec::new();
+        let mut sixes = 0;
+        for elem in path {
+            let method = &self.query.methods[elem.method];
+            let call = elem.call_to_end.map(|call_idx| &self.query.calls[call_idx]);
+            // Count the six ends reached by this elem's plain rows
+            let num_plain_rows = elem.length.as_usize() - call.map_or(0, Call::num_covered_rows);
+            sixes += (1..=num_plain_rows)
+                .filter(|i| method.is_six_end(elem.start_sub_lead_idx + i))
+                .count();
+            if let Some(call) = call {
+                calls.push(format!("{}{}", call.short_symbol(), sixes));
+            }
+        }
+
+        let mut s = String::new();
+        s.push_str(if is_snap_start { "<" } else { "" });
+        s.push_str(&calls.join(" "));
+        s.push_str(if is_snap_finish { ">" } else { "" });
+        s
+    }
+
     /// The [`Row`] reached at the end of the first part.  If this is a 1-part, then this will be
     /// [`rounds`](Row::is_rounds).
     pub fn part_head(&self) -> &Row {
@@ -158,32 +303,40 @@ impl Composition {
         for elem in &self.path {
             assert_eq!(first_part.leftover_row(), elem.start_row.as_row());
             let plain_course = &plain_courses[elem.method];
-            // Add this elem to the first part
+            let call = elem.call_to_end.map(|call_idx| &self.query.calls[call_idx]);
+            // Add this elem to the first part (excluding any rows covered by its call)
+            let num_covered_rows = call.map_or(0, Call::num_covered_rows);
             let start_idx = elem.start_sub_lead_idx;
-            let end_idx = start_idx + elem.length.as_usize();
-            if end_idx > plain_course.len() {
-                // `elem` wraps over the course head, so copy it in two pieces
-                first_part
-                    .extend_range(plain_course, start_idx..)
-                    .expect("All path elems should have the same stage");
+            let end_idx = start_idx + elem.length.as_usize() - num_covered_rows;
+            // Copy `elem` one piece at a time, splitting it wherever it wraps over the course head.
+            // False compositions can have elems which wrap several times.
+            let mut idx = start_idx;
+            while idx < end_idx {
+                let piece_start = idx % plain_course.len();
+                let piece_len = (end_idx - idx).min(plain_course.len() - piece_start);
                 first_part
-                    .extend_range(plain_course, ..end_idx - plain_course.len())
-                    .expect("All path elems should have the same stage");
-            } else {
-                // `elem` doesn't wrap over the course head, so copy it in one piece
-                first_part
-                    .extend_range(plain_course, start_idx..end_idx)
+                    .extend_range(plain_course, piece_start..piece_start + piece_len)
                     .expect("All path elems should have the same stage");
+                idx += piece_len;
             }
-            // If this PathElem ends in a call, then change the `leftover_row` to suit
-            if let Some(call_idx) = elem.call_to_end {
-                let last_non_leftover_row = first_part.rows().next_back().unwrap();
-                let new_leftover_row = last_non_leftover_row
-                    * self.query.calls[call_idx].place_notation.transposition();
+            // If this PathElem ends in a call, then replace the `leftover_row` with the rows of
+            // that call
+            if let Some(call) = call {
+                let last_non_leftover_row = first_part.rows().next_back().unwrap().to_owned();
+                let call_rows = call.place_notation.to_rows(last_non_leftover_row).unwrap();
                 first_part
                     .leftover_row_mut()
-                    .copy_from(&new_leftover_row)
+                    .copy_from(call_rows.get(1).unwrap())
                     .unwrap();
+                // Annotate the covered rows as if they were rung in the plain lead
+                let method = &self.query.methods[elem.method];
+                let call_block = Block::with_annots_from_indices(call_rows, |i| {
+                    (method.id, (end_idx - 1 + i) % method.lead_len())
+                })
+                .unwrap();
+                first_part
+                    .extend_range(&call_block, 1..)
+                    .expect("All path elems should have the same stage");
             }
         }
 
@@ -198,6 +351,26 @@ impl Composition {
         comp
     }
 
+    /// Measure how false this composition is (i.e. how often its [`Row`]s are repeated).  This
+    /// is computed from scratch, so is fairly slow.
+    pub fn falseness(&self) -> Falseness {
+        let rows = self.rows();
+        let rows_per_block = (self.query.extent_length()).map_or(rows.len(), |l| l.as_usize());
+        let mut falseness = Falseness::default();
+        for block in &rows.rows().chunks(rows_per_block) {
+            let mut occurrences = HashMap::<&Row, usize>::new();
+            for row in block {
+                let count = occurrences.entry(row).or_insert(0);
+                *count += 1;
+                if *count > 1 {
+                    falseness.false_rows += 1;
+                }
+                falseness.max_row_occurrences = falseness.max_row_occurrences.max(*count);
+            }
+        }
+        falseness
+    }
+
     /// Returns a factor in `0.0..=1.0` where 0.0 means nothing was rung and 1.0 means everything
     /// was rung (i.e. the composition is atw)
     pub fn atw_factor(&self) -> f32 {
@@ -221,20 +394,105 @@ impl Composition {
     }
 
     /// Score generated by just the [`MusicType`]s (not including calls, changes of methods,
-    /// etc.).
+    /// etc.).  This includes each [`MusicType`]'s
+    /// [`count_score`](crate::parameters::MusicType::count_score).
     pub fn music_score(&self) -> f32 {
         self.music_counts
             .iter()
-            .map(|(id, count)| self.query.get_music_type_by_id(*id).weight * *count as f32)
+            .map(|(id, count)| {
+                let music_type = self.query.get_music_type_by_id(*id);
+                music_type.weight * *count as f32 + music_type.count_score(*count)
+            })
             .sum::<f32>()
     }
 
+    /// Splits [`total_score`](Self::total_score) into the contributions from each kind of weight.
+    /// This is computed from scratch, so is fairly slow.
+    pub fn score_breakdown(&self) -> ScoreBreakdown {
+        let calls = self
+            .query
+            .calls
+            .iter()
+            .zip_eq(self.call_counts.iter())
+            .map(|(call, &count)| call.weight * count as f32)
+            .sum::<f32>();
+        // Course weights are given to every row of every chunk, based on the lead head where that
+        // chunk starts (exactly like `DefaultScorer`)
+        let mut course_weights = 0.0;
+        if !self.query.course_weights.is_empty() {
+            let chunks = self
+                .extents()
+                .flat_map(|extent_path| graph_chunks(extent_path, &self.query));
+            for (id, length, _call) in chunks {
+                let method = &self.query.methods[id.method];
+                let plain_lead_heads = method.inner.lead_head().closure();
+                for part_head in self.query.part_head_group.rows() {
+                    let lead_head = part_head * id.lead_head.as_ref();
+                    let weight = course_weight(&lead_head, &plain_lead_heads, &self.query);
+                    course_weights += weight * length.as_usize() as f32;
+                }
+            }
+        }
+        let mut breakdown = ScoreBreakdown {
+            music: self.music_score(),
+            calls,
+            splices: self.query.splice_weight * self.changes_of_method() as f32,
+            course_weights,
+            scheduled: self.scheduled_score(true),
+            atw: self.atw_table.atw_score(&self.atw_bitmap),
+            method_balance: self.query.method_balance_score(&self.method_counts),
+            custom_scorer: 0.0,
+        };
+        // Anything left over must come from the `Scorer` not using Monument's usual weights
+        breakdown.custom_scorer = self.total_score - breakdown.total();
+        breakdown
+    }
+
+    /// The number of edits needed to turn the layout of `self` into that of `other`, where each
+    /// edit inserts, removes or changes one piece of ringing of one method (up to and including a
+    /// call or change of method).  For example, swapping the order of a
+    /// pair of calls within a course is usually 2 edits.  Compositions which are rung from
+    /// different rows but have the same calls and methods (e.g. because they have different part
+    /// heads) have a distance of `0`.
+    pub fn distance_to(&self, other: &Composition) -> usize {
+        path_distance(&self.path, &other.path)
+    }
+
+    /// The number of changes of method in this composition, summed over all the parts (including
+    /// any splices over the part head).
+    pub fn changes_of_method(&self) -> usize {
+        let num_parts = self.query.num_parts();
+        let splices_in_part = self
+            .extents()
+            .flat_map(|path| path.iter().tuple_windows())
+            .filter(|(elem, next_elem)| elem.is_splice_to(next_elem, &self.query))
+            .count();
+        let (first_elem, last_elem) = (&self.path[0], self.path.last().unwrap());
+        let splices_over_part_head = match last_elem.is_splice_to(first_elem, &self.query) {
+            true => num_parts - 1,
+            false => 0,
+        };
+        splices_in_part * num_parts + splices_over_part_head
+    }
+
     /// A slice containing the number of [`Row`]s generated for each [`Method`] used in the
     /// [`Search`].  These are stored in the same order as the [`Method`]s.
     pub fn method_counts(&self) -> &[usize] {
         self.method_counts.as_slice()
     }
 
+    /// A slice containing the number of instances of each [`Call`](crate::parameters::Call) used
+    /// in the [`Search`] (summed over all parts).  These are stored in the same order as
+    /// [`Search::calls`].
+    pub fn call_counts(&self) -> &[usize] {
+        self.call_counts.as_slice()
+    }
+
+    /// The total number of calls in this composition (summed over all parts)
+    pub fn num_calls(&self) -> usize {
+        self.call_counts.iter().sum()
+    }
+
     /// The number of *instances* of each [`MusicType`] in the [`Search`].
     pub fn music_counts(&self) -> &HashMap<MusicTypeId, usize> {
         &self.music_counts
@@ -255,8 +513,135 @@ impl Composition {
     pub fn generation_number(&self) -> usize {
         self.generation_number
     }
+
+    /// The score generated by the [`weight_schedule`](MusicType::weight_schedule)s of every
+    /// [`MusicType`] and [`Call`](crate::parameters::Call).  If `include_undeferred` is `false`,
+    /// only the [deferred](crate::parameters::ScheduledWeight::is_deferred) weights (which aren't
+    /// counted during the search) are included.
+    pub(crate) fn scheduled_score(&self, include_undeferred: bool) -> f32 {
+        let num_parts = self.query.num_parts();
+        let part_len = self.length() / num_parts;
+        let is_included = |weight: &&ScheduledWeight| include_undeferred || weight.is_deferred();
+        let mut score = 0.0;
+
+        // Music
+        let scheduled_types = self
+            .query
+            .music_types
+            .iter()
+            .filter(|ty| ty.weight_schedule.iter().any(|w| is_included(&w)))
+            .collect_vec();
+        if !scheduled_types.is_empty() {
+            let rows = self.rows();
+            // Wraps out of the last row go into the row where the composition comes round
+            let next_rows = rows
+                .rows()
+                .skip(1)
+                .chain(std::iter::once(rows.leftover_row()));
+            for (i, (row, next_row)) in rows.rows().zip_eq(next_rows).enumerate() {
+                let (part, position) = (i / part_len, i % part_len);
+                let stroke = self.query.start_stroke.offset(i);
+                for ty in &scheduled_types {
+                    let num_instances = ty.num_instances(row, Some(next_row), stroke);
+                    if num_instances == 0 {
+                        continue;
+                    }
+                    let weight = ty
+                        .weight_schedule
+                        .iter()
+                        .filter(is_included)
+                        .map(|w| w.weight_at(part, position, part_len))
+                        .sum::<f32>();
+                    score += weight * num_instances as f32;
+                }
+            }
+        }
+
+        // Calls, each of which is positioned on the last row before it takes effect
+        let mut position = 0;
+        for elem in &self.path {
+            position += elem.length.as_usize();
+            if let Some(call_idx) = elem.call_to_end {
+                let call = &self.query.calls[call_idx];
+                let call_position = position - 1 - call.num_covered_rows();
+                for part in 0..num_parts {
+                    score += call
+                        .weight_schedule
+                        .iter()
+                        .filter(is_included)
+                        .map(|w| w.weight_at(part, call_position, part_len))
+                        .sum::<f32>();
+                }
+            }
+        }
+
+        score
+    }
 }
 
+impl PartialEq for Composition {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.query, &other.query) && self.canonical_form == other.canonical_form
+    }
+}
+
+impl Eq for Composition {}
+
+impl Hash for Composition {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        Arc::as_ptr(&self.query).hash(state);
+        self.canonical_form.hash(state);
+    }
+}
+
+/// The canonical form of a [`Composition`], which is the same for every rotation and choice of
+/// part head.  This contains the start row and the layout of the composition, rotated to start at
+/// the lexicographically smallest point.  [Multi-extent](Parameters::multi_extent) compositions
+/// aren't rotated, since each of their extents must start and end at the start row.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(crate) struct CanonicalForm {
+    start_row: RowBuf,
+    elems: Vec<CanonicalElem>,
+    extent_starts: Vec<usize>,
+}
+
+impl CanonicalForm {
+    pub(crate) fn new(path: &[PathElem], extent_starts: &[usize], query: &Query) -> Self {
+        let mut elems = path.iter().map(PathElem::canonical_elem).collect_vec();
+        if extent_starts.len() == 1 {
+            // If the composition ends part-way through a block of plain leads, then (when
+            // rotated) that block joins onto the start of the composition
+            let (first_elem, last_elem) = (&path[0], path.last().unwrap());
+            if elems.len() > 1
+                && last_elem.ends_with_plain()
+                && !last_elem.is_splice_to(first_elem, query)
+            {
+                let (_, _, first_length, first_call) = elems.remove(0);
+                let last = elems.last_mut().unwrap();
+                last.2 += first_length;
+                last.3 = first_call;
+            }
+            let rotated = |i: usize| elems[i..].iter().chain(&elems[..i]);
+            let best_rotation = (0..elems.len())
+                .min_by(|&a, &b| rotated(a).cmp(rotated(b)))
+                .unwrap_or(0);
+            elems.rotate_left(best_rotation);
+        }
+        Self {
+            start_row: query.start_row.clone(),
+            elems,
+            extent_starts: extent_starts.to_vec(),
+        }
+    }
+}
+
+/// One [`PathElem`] of a [`Composition`]'s [`CanonicalForm`], which
+/// ignores the row where the [`PathElem`] starts:
+/// ```text
+/// (method, start_sub_lead_idx, length, call_to_end)
+/// ```
+pub(crate) type CanonicalElem = (MethodIdx, usize, PerPartLength, Option<CallIdx>);
+
 /// A piece of a [`Composition`]
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub(crate) struct PathElem {
@@ -275,33 +660,49 @@ impl PathElem {
     pub(crate) fn end_sub_lead_idx(&self, query: &Query) -> usize {
         query.methods[self.method].add_sub_lead_idx(self.start_sub_lead_idx, self.length)
     }
-}
 
-/// A way to display a [`Composition`] by pairing it with a [`Query`]
-#[derive(Debug, Clone, Copy)]
-struct DisplayComposition<'a>(pub &'a Composition, pub &'a Parameters);
+    /// Returns `true` if `self` and `other` contain the same ringing (except possibly starting
+    /// from different rows)
+    fn has_same_layout(&self, other: &PathElem) -> bool {
+        self.canonical_elem() == other.canonical_elem()
+    }
+
+    fn canonical_elem(&self) -> CanonicalElem {
+        (
+            self.method,
+            self.start_sub_lead_idx,
+            self.length,
+            self.call_to_end,
+        )
+    }
 
-impl std::fmt::Display for DisplayComposition<'_> {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let DisplayComposition(comp, query) = self;
+    /// Returns `true` if going from `self` to `next_elem` changes method or skips part of a lead.
+    /// This uses the same definition of 'splice' as the graph.
+    pub(crate) fn is_splice_to(&self, next_elem: &PathElem, query: &Query) -> bool {
+        self.method != next_elem.method
+            || self.end_sub_lead_idx(query) != next_elem.start_sub_lead_idx
+    }
 
-        write!(f, "len: {}, ", comp.length)?;
-        // Method counts for spliced
-        if query.is_spliced() {
-            write!(f, "ms: {:>3?}, ", comp.method_counts.as_slice())?;
-        }
-        // Part heads if multi-part with >2 parts (2-part compositions only have one possible part
-        // head)
-        if query.num_parts() > 2 {
-            write!(f, "PH: {}, ", comp.part_head())?;
+    /// Assuming that going from `self` to `next_elem` [is a splice](Self::is_splice_to), returns
+    /// `true` if that splice is allowed.  Splices must go between rows which share a label, and
+    /// plain splices must also be allowed by the [`SpliceStyle`].
+    pub(crate) fn is_valid_splice_to(&self, next_elem: &PathElem, query: &Query) -> bool {
+        let end_labels = query.methods[self.method].get_labels(self.end_sub_lead_idx(query));
+        let next_method = &query.methods[next_elem.method];
+        let start_labels = next_method.get_labels(next_elem.start_sub_lead_idx);
+        let shares_label = start_labels.iter().any(|label| {
+            end_labels.contains(label)
+                && (self.call_to_end.is_some() || query.allows_plain_splice_at(label))
+        });
+        if self.ends_with_plain() && query.splice_style == SpliceStyle::CourseHeads {
+            let lead_head = Row::solve_xa_equals_b(
+                next_method.row_in_plain_lead(next_elem.start_sub_lead_idx),
+                &next_elem.start_row,
+            )
+            .unwrap();
+            return shares_label && next_method.is_course_head(&lead_head);
         }
-        write!(
-            f,
-            "music: {:>6.2?}, avg score: {:.6}, str: {}",
-            comp.music_score(),
-            comp.average_score(),
-            comp.call_string()
-        )
+        shares_label
     }
 }
 
@@ -309,6 +710,42 @@ impl std::fmt::Display for DisplayComposition<'_> {
 // UTILS //
 ///////////
 
+/// Split a path into the slices which make up each of its extents, given the index of the first
+/// [`PathElem`] of every extent.
+pub(crate) fn extents<'p>(
+    path: &'p [PathElem],
+    extent_starts: &'p [usize],
+) -> impl Iterator<Item = &'p [PathElem]> + 'p {
+    let extent_ends = extent_starts.iter().skip(1).copied().chain([path.len()]);
+    extent_starts
+        .iter()
+        .zip(extent_ends)
+        .map(move |(&start, end)| &path[start..end])
+}
+
+/// The edit (Levenshtein) distance between two paths, ignoring the rows where each [`PathElem`]
+/// starts.  See [`Composition::distance_to`].
+pub(crate) fn path_distance(path_a: &[PathElem], path_b: &[PathElem]) -> usize {
+    // `distances[j]` is the distance between the prefixes of `path_a` processed so far and the
+    // first `j` elements of `path_b`
+    let mut distances = (0..=path_b.len()).collect_vec();
+    for (i, elem_a) in path_a.iter().enumerate() {
+        let mut distance_to_last_b = distances[0]; // Distance to `path_b[..j]` for the last `i`
+        distances[0] = i + 1;
+        for (j, elem_b) in path_b.iter().enumerate() {
+            let substitution_cost = match elem_a.has_same_layout(elem_b) {
+                true => distance_to_last_b,
+                false => distance_to_last_b + 1,
+            };
+            distance_to_last_b = distances[j + 1];
+            distances[j + 1] = substitution_cost
+                .min(distances[j] + 1) // Insert `elem_b`
+                .min(distances[j + 1] + 1); // Remove `elem_a`
+        }
+    }
+    distances[path_b.len()]
+}
+
 /// Return the number of leads covered by some [`Chunk`]
 fn num_leads_covered(lead_len: usize, start_sub_lead_idx: usize, length: PerPartLength) -> usize {
     assert_ne!(length, PerPartLength::ZERO); // 0-length chunks shouldn't exist
@@ -320,7 +757,57 @@ fn num_leads_covered(lead_len: usize, start_sub_lead_idx: usize, length: PerPart
 
 #[cfg(test)]
 mod tests {
-    use crate::utils::lengths::PerPartLength;
+    use std::{
+        collections::{hash_map::DefaultHasher, HashSet},
+        hash::{Hash, Hasher},
+        sync::{atomic::AtomicBool, Arc},
+    };
+
+    use bellframe::{Mask, RowBuf, Stage};
+
+    use crate::{
+        parameters::{CallIdx, MethodIdx, Parameters, RowPosition, ScheduledWeight},
+        scoring::{DefaultScorer, RowContext, Scorer, TransitionContext},
+        test_utils::{self, search},
+        utils::lengths::PerPartLength,
+        Update,
+    };
+
+    use super::{Composition, PathElem};
+
+    /// Touches of Plain Bob Minor, up to two courses long
+    fn plain_bob_minor(scorer: Arc<dyn Scorer>) -> Parameters {
+        Parameters {
+            scorer,
+            ..test_utils::plain_bob_minor()
+        }
+    }
+
+    /// Every composition found by a best-first search
+    fn comps(params: Parameters) -> Vec<Composition> {
+        let mut comps = Vec::new();
+        let update_fn = |update| {
+            if let Update::Comp(comp) = update {
+                comps.push(comp);
+            }
+        };
+        search(params).run(update_fn, &AtomicBool::new(false));
+        comps
+    }
+
+    /// [`DefaultScorer`], but with an extra point for every row
+    #[derive(Debug)]
+    struct RowBonusScorer;
+
+    impl Scorer for RowBonusScorer {
+        fn row_score(&self, row: &RowContext) -> f32 {
+            DefaultScorer.row_score(row) + 1.0
+        }
+
+        fn transition_score(&self, transition: &TransitionContext) -> f32 {
+            DefaultScorer.transition_score(transition)
+        }
+    }
 
     #[test]
     fn num_leads_covered() {
@@ -330,4 +817,146 @@ mod tests {
         assert_eq!(super::num_leads_covered(32, 0, PerPartLength::new(2)), 1);
         assert_eq!(super::num_leads_covered(32, 16, PerPartLength::new(24)), 2);
     }
+
+    #[test]
+    fn path_distance() {
+        // Paths are written as strings of leads of one method, each ending with a call (`-`/`s`)
+        // or a plain lead (`p`)
+        fn path(s: &str) -> Vec<PathElem> {
+            s.chars()
+                .map(|c| PathElem {
+                    start_row: RowBuf::rounds(Stage::MAJOR),
+                    method: MethodIdx::new(0),
+                    start_sub_lead_idx: 0,
+                    length: PerPartLength::new(32),
+                    call_to_end: match c {
+                        '-' => Some(CallIdx::new(0)),
+                        's' => Some(CallIdx::new(1)),
+                        _ => None,
+                    },
+                })
+                .collect()
+        }
+        let check = |a: &str, b: &str, distance: usize| {
+            assert_eq!(super::path_distance(&path(a), &path(b)), distance);
+            assert_eq!(super::path_distance(&path(b), &path(a)), distance);
+        };
+
+        check("", "", 0);
+        check("", "pp-", 3);
+        check("pp-p-", "pp-p-", 0);
+        check("pp-p-", "pp-ps", 1);
+        check("pp-p-", "p-pp-", 2);
+        check("pp-p-", "pp-pp-", 1);
+        check("---", "sss", 3);
+    }
+
+    #[test]
+    fn score_breakdown() {
+        let with_weights = |scorer: Arc<dyn Scorer>| {
+            let mut params = plain_bob_minor(scorer);
+            params.course_weights = vec![
+                (Mask::parse_with_stage("xxxx56", Stage::MINOR).unwrap(), 0.1),
+                (
+                    Mask::parse_with_stage("xxxx65", Stage::MINOR).unwrap(),
+                    -0.2,
+                ),
+            ];
+            params.atw_weight = Some(2.0);
+            params.maybe_unused_calls[0].weight_schedule = vec![ScheduledWeight {
+                start: RowPosition::FromEnd(24),
+                end: RowPosition::FromEnd(0),
+                parts: Vec::new(),
+                weight: 1.5,
+            }];
+            params
+        };
+        let params = with_weights(Arc::new(DefaultScorer));
+        let mut params_without_courses = params.clone();
+        params_without_courses.course_weights.clear();
+        let search_without_courses = search(params_without_courses);
+
+        let default_comps = comps(params);
+        assert!(!default_comps.is_empty());
+        let mut has_course_weights = false;
+        for comp in &default_comps {
+            let calling = comp.call_string();
+            let breakdown = comp.score_breakdown();
+            // The parts add up to the total, without needing anything from the scorer
+            assert!(
+                (breakdown.total() - comp.total_score).abs() < 1e-3,
+                "{calling}"
+            );
+            assert!(breakdown.custom_scorer.abs() < 1e-3, "{calling}");
+            // The course weights are exactly the score lost by removing them
+            let without_courses = search_without_courses.evaluate(&calling).unwrap();
+            let course_score = comp.total_score - without_courses.total_score;
+            assert!(
+                (breakdown.course_weights - course_score).abs() < 1e-3,
+                "{calling}"
+            );
+            has_course_weights |= breakdown.course_weights != 0.0;
+        }
+        assert!(has_course_weights);
+
+        // Scores from a custom `Scorer` are kept separate from Monument's usual weights
+        for comp in comps(with_weights(Arc::new(RowBonusScorer))) {
+            let breakdown = comp.score_breakdown();
+            let bonus = comp.length() as f32;
+            assert!((breakdown.total() - comp.total_score).abs() < 1e-3);
+            assert!((breakdown.custom_scorer - bonus).abs() < 1e-3);
+        }
+    }
+
+    #[test]
+    fn rotations_are_equal() {
+        let hash = |comp: &Composition| {
+            let mut hasher = DefaultHasher::new();
+            comp.hash(&mut hasher);
+            hasher.finish()
+        };
+        let params = plain_bob_minor(Arc::new(DefaultScorer));
+        let search = search(params.clone());
+        let comp = search.evaluate("BH").unwrap();
+
+        // `WI` is `BH`, started from a different lead
+        let rotated = search.evaluate("WI").unwrap();
+        assert_ne!(rotated.rows(), comp.rows());
+        assert_eq!(rotated, comp);
+        assert_eq!(hash(&rotated), hash(&comp));
+        // Different callings aren't equal
+        let other = search.evaluate("sHsH").unwrap();
+        assert_ne!(other, comp);
+        // The same calling rung from a different start row is a different composition
+        let mut transposed_params = params;
+        let start_row = RowBuf::parse_with_stage("213456", Stage::MINOR).unwrap();
+        transposed_params.start_row = start_row.clone();
+        transposed_params.end_row = start_row;
+        let transposed = self::search(transposed_params).evaluate("BH").unwrap();
+        assert_ne!(transposed, comp);
+        assert_ne!(hash(&transposed), hash(&comp));
+        // Compositions from different searches aren't equal, even with the same parameters
+        let repeated = self::search(plain_bob_minor(Arc::new(DefaultScorer)))
+            .evaluate("BH")
+            .unwrap();
+        assert_ne!(repeated, comp);
+    }
+
+    #[test]
+    fn multi_extent_rotations() {
+        let params = test_utils::plain_bob_doubles();
+        let comps = comps(params.clone());
+        assert!(comps.len() > 1);
+        assert_eq!(comps.iter().collect::<HashSet<_>>().len(), comps.len());
+
+        // Rotating one extent gives a different composition
+        let search = search(params);
+        let calling = |extents: [&str; 2]| search.evaluate(&extents.join(" + ")).unwrap();
+        let extent = "#PPPP[-]PPPP[-]PPPP[-]";
+        let rotated_extent = "#PPP[-]PPPP[-]PPPP[-]P";
+        let comp = calling([extent, extent]);
+        assert_ne!(calling([extent, rotated_extent]), comp);
+        assert_ne!(calling([rotated_extent, extent]), comp);
+        assert_eq!(calling([extent, extent]), comp);
+    }
 }
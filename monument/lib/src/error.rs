@@ -3,13 +3,18 @@
 use std::{
     fmt::{Display, Formatter},
     ops::RangeInclusive,
+    path::PathBuf,
 };
 
-use bellframe::{Mask, PlaceNot, RowBuf, Stage};
+use bellframe::{Mask, PnBlock, RowBuf, Stage};
+use itertools::Itertools;
 
 #[allow(unused_imports)] // Only used for doc comments
 use crate::parameters::{Call, Method, MusicType};
-use crate::{parameters::OptionalRangeInclusive, utils::TotalLength};
+use crate::{
+    parameters::{MusicTypeId, OptionalRangeInclusive},
+    utils::TotalLength,
+};
 
 /// Alias for `Result<T, monument::Error>`.
 pub type Result<T> = std::result::Result<T, Error>;
@@ -37,10 +42,27 @@ pub enum Error {
         mask_str: String,
         error: bellframe::mask::ParseError,
     },
+    /// Some list of part heads isn't closed under multiplication, so doesn't form a group
+    PartHeadsNotClosed {
+        lhs: RowBuf,
+        rhs: RowBuf,
+        product: RowBuf,
+    },
+    /// The part heads form a group with more than `limit` elements
+    TooManyPartHeads { limit: usize },
+    /// The part heads form a group which isn't cyclic, so no part head generates all the parts
+    NonCyclicPartHeads { num_parts: usize },
 
     /* QUERY VERIFICATION ERRORS */
     /// Different start/end rows were specified in a multi-part
     DifferentStartEndRowInMultipart,
+    /// Different start/end rows were specified in a multi-extent composition
+    DifferentStartEndRowInMultiExtent,
+    /// A multi-extent composition was also given several parts
+    MultipartMultiExtent,
+    /// A [`FalsenessLimit`](crate::parameters::FalsenessLimit) was given, but the composition
+    /// must also be true
+    FalsenessLimitWithTruth,
     /// Some [`Call`] refers to a label that doesn't exist
     UndefinedLabel { call_name: String, label: String },
     /// The [`SearchBuilder`] didn't define any [`Method`]s
@@ -62,19 +84,25 @@ pub enum Error {
         calling_position_len: usize,
         stage: Stage,
     },
+    /// A [`Call`] is restricted to (or from) a calling position which it doesn't have
+    UndefinedCallingPosition { call_name: String, position: String },
+    /// A [`Call`] is restricted to some calling positions, but there's no calling bell to define
+    /// those positions (because the part heads move every bell)
+    RestrictedCallingPositionsWithoutCallingBell { call_name: String },
     /// Two [`Call`]s have the same lead location and name
     DuplicateCall {
         symbol: String,
         label: String,
-        pn1: PlaceNot,
-        pn2: PlaceNot,
+        pn1: PnBlock,
+        pn2: PnBlock,
     },
 
     /* GRAPH BUILD ERRORS */
     /// The given maximum graph size limit was reached
     SizeLimit(usize),
-    /// The same chunk of ringing could start at two different strokes, and some
-    /// [`MusicType`] relies on the strokes always being the same
+    /// The same chunk of ringing could start at two different strokes (or the parts of a
+    /// multi-part could start at different strokes), and some [`MusicType`] relies on the strokes
+    /// always being the same
     InconsistentStroke,
 
     /* LENGTH PROVING ERRORS */
@@ -100,6 +128,116 @@ pub enum Error {
         max_total_method_count: usize,
         min_length: usize,
     },
+    /// Some required course can't appear in enough leads of any composition
+    UnachievableRequiredCourse {
+        course_masks: Vec<Mask>,
+        min_leads: usize,
+        max_leads: usize,
+    },
+
+    /* CHECKPOINT ERRORS */
+    /// Reading or writing a checkpoint file failed
+    CheckpointIo {
+        path: PathBuf,
+        error: std::io::Error,
+    },
+    /// The file isn't a checkpoint, or was written by an incompatible version of Monument
+    InvalidCheckpoint(PathBuf),
+    /// The checkpoint was written by a search with different parameters
+    StaleCheckpoint(PathBuf),
+
+    /* EVALUATION ERRORS */
+    /// Some part of a calling couldn't be parsed.  `unparsed` is the rest of the calling, starting
+    /// at the first character which couldn't be parsed
+    CallingParse { calling: String, unparsed: String },
+    /// The first [`Method`] of a calling can't start at the composition's start row (or can't
+    /// start with/without a snap)
+    NoCallingStart {
+        method_name: String,
+        snap_start: bool,
+    },
+    /// A call in a calling couldn't be placed (e.g. because its calling position never comes up)
+    CallNotReached { call: String, call_number: usize },
+    /// A call in a calling is at a calling position where it isn't allowed
+    CallingPositionNotAllowed { call: String, position: String },
+    /// The composition came round before the end of the calling
+    ComesRoundEarly { rows: usize },
+    /// The composition doesn't come round at the end of the calling
+    DoesntComeRound,
+    /// The calling splices between two methods which can't be spliced at that point
+    InvalidSplice {
+        from_method: String,
+        to_method: String,
+    },
+    /// The composition doesn't reach a part head which generates all the parts
+    PartHeadDoesntGenerate { part_head: RowBuf },
+    /// The composition's parts have an odd length, so they start at different strokes.  Music is
+    /// counted as though every part starts at the same stroke, so this is only an error if some
+    /// music relies on stroke
+    OddPartLength { part_len: usize },
+    /// The composition contains a lead which isn't in the [`Method`]'s allowed courses
+    CourseNotAllowed {
+        method_name: String,
+        lead_head: RowBuf,
+    },
+    /// The composition is false
+    FalseComposition { repeated_row: RowBuf },
+    /// Some extent of a multi-extent composition isn't exactly one extent long.  `extent` is
+    /// 1-indexed.
+    WrongExtentLength {
+        extent: usize,
+        length: usize,
+        extent_length: usize,
+    },
+    /// The composition's length is outside the requested range
+    WrongLength {
+        length: usize,
+        requested_range: RangeInclusive<TotalLength>,
+    },
+    /// The composition has the wrong number of rows of some [`Method`]
+    WrongMethodCount {
+        method_name: String,
+        count: usize,
+        allowed_range: RangeInclusive<TotalLength>,
+    },
+    /// The composition has the wrong number of some call
+    WrongCallCount {
+        symbol: String,
+        count: usize,
+        requested_range: OptionalRangeInclusive,
+    },
+    /// The composition has the wrong total number of calls
+    WrongTotalCallCount {
+        count: usize,
+        requested_range: OptionalRangeInclusive,
+    },
+    /// The composition has too many or too few changes of method
+    WrongComCount {
+        count: usize,
+        requested_range: OptionalRangeInclusive,
+    },
+    /// The composition has the wrong number of some [`MusicType`]
+    WrongMusicCount {
+        music_type: MusicTypeId,
+        count: usize,
+        requested_range: OptionalRangeInclusive,
+    },
+    /// The composition has too long a run of consecutive duffer rows
+    TooMuchContiguousDuffer { length: usize, limit: usize },
+    /// The composition has too many duffer rows in total
+    TooMuchTotalDuffer { length: usize, limit: usize },
+    /// The composition repeats too many rows
+    TooManyFalseRows { false_rows: usize, limit: usize },
+    /// Some row appears too many times in the composition
+    RowRepeatedTooOften { occurrences: usize, limit: usize },
+    /// The composition isn't atw, but the query requires atw compositions
+    NotAtw { atw_factor: f32 },
+    /// The composition doesn't ring enough leads of some required course
+    MissingRequiredCourse {
+        course_masks: Vec<Mask>,
+        leads: usize,
+        min_leads: usize,
+    },
 }
 
 impl Display for Error {
@@ -125,11 +263,33 @@ impl Display for Error {
                 "Error parsing course mask {} for method {:?}: {}",
                 mask_str, method_title, error
             ),
+            Error::PartHeadsNotClosed { lhs, rhs, product } => write!(
+                f,
+                "Part heads don't form a group: {lhs} * {rhs} = {product}, which isn't a part head"
+            ),
+            Error::TooManyPartHeads { limit } => {
+                write!(f, "Part heads generate more than {limit} parts")
+            }
+            Error::NonCyclicPartHeads { num_parts } => write!(
+                f,
+                "Part heads form a non-cyclic group of {num_parts} parts.  Every part has the same \
+calling, so some part head must generate all the others"
+            ),
 
             /* QUERY VERIFICATION ERRORS */
             Error::DifferentStartEndRowInMultipart => {
                 write!(f, "Start/end rows must be the same for multipart comps")
             }
+            Error::DifferentStartEndRowInMultiExtent => {
+                write!(f, "Start/end rows must be the same for multi-extent comps")
+            }
+            Error::MultipartMultiExtent => {
+                write!(f, "Multi-extent comps can't also have several parts")
+            }
+            Error::FalsenessLimitWithTruth => write!(
+                f,
+                "Limits on falseness can only be used if compositions don't have to be true"
+            ),
             Error::NoMethods => write!(f, "Can't have a composition with no methods"),
             Error::WrongCallingPositionsLength {
                 call_name,
@@ -142,6 +302,19 @@ impl Display for Error {
                 calling_position_len,
                 stage.num_bells()
             ),
+            Error::UndefinedCallingPosition {
+                call_name,
+                position,
+            } => write!(
+                f,
+                "Call {:?} is restricted by calling position {:?}, which isn't one of its calling positions",
+                call_name, position
+            ),
+            Error::RestrictedCallingPositionsWithoutCallingBell { call_name } => write!(
+                f,
+                "Call {:?} can't be restricted to calling positions, because the part heads don't keep any bell fixed to call from",
+                call_name
+            ),
             Error::DuplicateShorthand {
                 shorthand,
                 title1,
@@ -193,7 +366,7 @@ higher with `--graph-size-limit <n>`.",
             Error::InconsistentStroke => write!(
                 f,
                 "The same chunk of ringing can be at multiple strokes, probably \
-because you're using a method with odd-length leads"
+because you're using a method with odd-length leads or a multi-part with odd-length parts"
             ),
 
             /* LENGTH PROVING ERRORS */
@@ -262,10 +435,199 @@ because you're using a method with odd-length leads"
                     " but the methods can make at most {max_total_method_count}."
                 )
             }
+            Error::UnachievableRequiredCourse {
+                course_masks,
+                min_leads,
+                max_leads,
+            } => write!(
+                f,
+                "Courses {} are required in at least {min_leads} leads, but at most {max_leads} \
+are possible",
+                format_masks(course_masks)
+            ),
+
+            /* CHECKPOINT ERRORS */
+            Error::CheckpointIo { path, error } => {
+                write!(f, "Error accessing checkpoint {path:?}: {error}")
+            }
+            Error::InvalidCheckpoint(path) => write!(
+                f,
+                "{path:?} isn't a valid checkpoint (or was made by a different version of Monument)"
+            ),
+            Error::StaleCheckpoint(path) => write!(
+                f,
+                "Checkpoint {path:?} was made by a search with different parameters"
+            ),
+
+            /* EVALUATION ERRORS */
+            Error::CallingParse { calling, unparsed } => {
+                write!(f, "Can't parse calling {calling:?}: unexpected {unparsed:?}")
+            }
+            Error::NoCallingStart {
+                method_name,
+                snap_start,
+            } => write!(
+                f,
+                "{method_name:?} can't start {} from the start row",
+                match snap_start {
+                    true => "with a snap",
+                    false => "at a lead head",
+                }
+            ),
+            Error::CallNotReached { call, call_number } => write!(
+                f,
+                "Call #{call_number} ({call:?}) can't be placed in the composition"
+            ),
+            Error::CallingPositionNotAllowed { call, position } => write!(
+                f,
+                "Call {call:?} isn't allowed at calling position {position:?}"
+            ),
+            Error::ComesRoundEarly { rows } => write!(
+                f,
+                "The composition comes round after {rows} rows, before the calling finishes"
+            ),
+            Error::DoesntComeRound => {
+                write!(f, "The composition doesn't come round at the end of the calling")
+            }
+            Error::InvalidSplice {
+                from_method,
+                to_method,
+            } => write!(f, "Can't splice from {from_method:?} to {to_method:?}"),
+            Error::PartHeadDoesntGenerate { part_head } => write!(
+                f,
+                "The composition reaches part head {part_head}, which doesn't generate all the parts"
+            ),
+            Error::OddPartLength { part_len } => write!(
+                f,
+                "Each part has {part_len} rows, so the parts start at different strokes.  Music \
+which only counts at one stroke can't be scored in this composition"
+            ),
+            Error::CourseNotAllowed {
+                method_name,
+                lead_head,
+            } => write!(
+                f,
+                "Lead head {lead_head} of {method_name:?} isn't in the allowed courses"
+            ),
+            Error::FalseComposition { repeated_row } => {
+                write!(f, "The composition is false; {repeated_row} is repeated")
+            }
+            Error::WrongExtentLength {
+                extent,
+                length,
+                extent_length,
+            } => write!(
+                f,
+                "Extent {extent} has {length} rows, but each extent must have {extent_length}"
+            ),
+            Error::WrongLength {
+                length,
+                requested_range,
+            } => {
+                write!(f, "The composition has {length} rows, but the length must be ")?;
+                write_range(
+                    f,
+                    "length",
+                    Some(*requested_range.start()),
+                    Some(*requested_range.end()),
+                )
+            }
+            Error::WrongMethodCount {
+                method_name,
+                count,
+                allowed_range,
+            } => {
+                write!(
+                    f,
+                    "The composition has {count} rows of {method_name:?}, but it needs "
+                )?;
+                write_range(
+                    f,
+                    "count",
+                    Some(*allowed_range.start()),
+                    Some(*allowed_range.end()),
+                )
+            }
+            Error::WrongCallCount {
+                symbol,
+                count,
+                requested_range,
+            } => {
+                write!(
+                    f,
+                    "The composition has {count} calls with symbol {symbol:?}, but it needs "
+                )?;
+                write_range(f, "count", requested_range.min, requested_range.max)
+            }
+            Error::WrongTotalCallCount {
+                count,
+                requested_range,
+            } => {
+                write!(f, "The composition has {count} calls, but it needs ")?;
+                write_range(f, "count", requested_range.min, requested_range.max)
+            }
+            Error::WrongComCount {
+                count,
+                requested_range,
+            } => {
+                write!(
+                    f,
+                    "The composition has {count} changes of method, but it needs "
+                )?;
+                write_range(f, "count", requested_range.min, requested_range.max)
+            }
+            Error::WrongMusicCount {
+                music_type,
+                count,
+                requested_range,
+            } => {
+                write!(
+                    f,
+                    "The composition has {count} of music type #{}, but it needs ",
+                    music_type.0
+                )?;
+                write_range(f, "count", requested_range.min, requested_range.max)
+            }
+            Error::TooMuchContiguousDuffer { length, limit } => write!(
+                f,
+                "The composition has {length} contiguous rows of duffer, but at most {limit} \
+are allowed"
+            ),
+            Error::TooMuchTotalDuffer { length, limit } => write!(
+                f,
+                "The composition has {length} rows of duffer, but at most {limit} are allowed"
+            ),
+            Error::TooManyFalseRows { false_rows, limit } => write!(
+                f,
+                "The composition has {false_rows} false rows, but at most {limit} are allowed"
+            ),
+            Error::RowRepeatedTooOften { occurrences, limit } => write!(
+                f,
+                "Some row appears {occurrences} times, but rows can appear at most {limit} times"
+            ),
+            Error::NotAtw { atw_factor } => write!(
+                f,
+                "The composition is only {:.2}% atw, but atw compositions are required",
+                atw_factor * 100.0
+            ),
+            Error::MissingRequiredCourse {
+                course_masks,
+                leads,
+                min_leads,
+            } => write!(
+                f,
+                "The composition has {leads} leads of courses {}, but it needs at least {min_leads}",
+                format_masks(course_masks)
+            ),
         }
     }
 }
 
+/// Format a list of [`Mask`]s as e.g. `[*5678, *6578]`
+fn format_masks(masks: &[Mask]) -> String {
+    format!("[{}]", masks.iter().map(|m| m.to_string()).join(", "))
+}
+
 /// Prettily format a (possibly open) inclusive range as an inequality (e.g. `300 <= count <= 500`)
 fn write_range<T: Ord + Display>(
     f: &mut impl std::fmt::Write,
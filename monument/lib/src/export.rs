@@ -0,0 +1,557 @@
+//! Code for exporting [`Composition`]s into text formats understood by other ringing software.
+
+use std::fmt::Write;
+
+use bellframe::{PlaceNot, Row, RowBuf};
+use itertools::Itertools;
+
+use crate::{
+    parameters::{CallDisplayStyle, CallIdx, CallVec, MethodIdx, MethodVec},
+    query::Query,
+    Composition,
+};
+
+impl Composition {
+    /// Generate a [MicroSIRIL](https://www.ringing.org/main/pages/microsiril) program which
+    /// defines every method and call from its place notation, then proves this `Composition`.  If
+    /// the composition doesn't start from rounds, the program sets `rounds` to its start row so
+    /// that it's proved from the right row.
+    pub fn to_microsiril(&self) -> String {
+        let query = &self.query;
+        let changes = self.changes();
+        let method_names = query
+            .methods
+            .iter_enumerated()
+            .map(|(idx, m)| siril_identifier(&m.shorthand(), &format!("m{}", idx.index())))
+            .collect::<MethodVec<_>>();
+        let call_names = query
+            .calls
+            .iter_enumerated()
+            .map(|(idx, call)| match call.symbol.as_str() {
+                "-" | "–" => "bob".to_owned(),
+                "s" => "single".to_owned(),
+                s => siril_identifier(&format!("call{}", s), &format!("call{}", idx.index())),
+            })
+            .collect::<CallVec<_>>();
+
+        let mut s = String::new();
+        writeln!(s, "// {}", self.call_string()).unwrap();
+        writeln!(s, "// {} changes", self.length()).unwrap();
+        if query.end_row != query.start_row {
+            writeln!(s, "// Ends at {}", query.end_row).unwrap();
+        }
+        writeln!(s, "{} bells;", query.stage.num_bells()).unwrap();
+        if !query.start_row.is_rounds() {
+            // Prove from the composition's start row, not from rounds
+            writeln!(s, "rounds '{}';", query.start_row).unwrap();
+        }
+
+        // Methods are split into the lead without its last change, and that last change.  This
+        // way, lead-end calls are just used in place of the plain lead end.
+        writeln!(s).unwrap();
+        for (method_idx, method) in query.methods.iter_enumerated() {
+            let name = &method_names[method_idx];
+            let mut pns = lead_place_notation(method);
+            let lead_end = pns.pop().unwrap();
+            writeln!(s, "// {}", method.title()).unwrap();
+            writeln!(s, "{} = +{};", name, pn_string(&pns)).unwrap();
+            writeln!(s, "{}p = +{};", name, lead_end).unwrap();
+        }
+        for (call, name) in query.calls.iter().zip_eq(&call_names) {
+            writeln!(s, "{} = +{};", name, call.place_notation).unwrap();
+        }
+
+        // Convert the changes into a sequence of leads and calls.  Anything which isn't a whole
+        // lead (e.g. snap starts/finishes or calls within a lead) is written as place notation.
+        let mut part = Vec::<String>::new();
+        let mut explicit_pns = Vec::<PlaceNot>::new();
+        let mut change_idx = 0;
+        while let Some(change) = changes.get(change_idx) {
+            let lead_len = query.methods[change.method].lead_len();
+            let lead = changes.get(change_idx..change_idx + lead_len);
+            let is_whole_lead = lead.is_some_and(|lead| {
+                lead.iter().enumerate().all(|(i, c)| {
+                    // Only single-change calls can replace the lead end change
+                    let is_lead_end_call = |call_idx: CallIdx| {
+                        i == lead_len - 1 && query.calls[call_idx].place_notation.len() == 1
+                    };
+                    c.method == change.method
+                        && c.sub_lead_idx == i
+                        && c.call.is_none_or(is_lead_end_call)
+                })
+            });
+            if is_whole_lead {
+                if !explicit_pns.is_empty() {
+                    part.push(format!("+{}", pn_string(&explicit_pns)));
+                    explicit_pns.clear();
+                }
+                let lead_end = &changes[change_idx + lead_len - 1];
+                let name = &method_names[change.method];
+                part.push(name.clone());
+                part.push(match lead_end.call {
+                    Some(call_idx) => call_names[call_idx].clone(),
+                    None => format!("{}p", name),
+                });
+                change_idx += lead_len;
+            } else {
+                match change.call {
+                    Some(call_idx) => {
+                        if !explicit_pns.is_empty() {
+                            part.push(format!("+{}", pn_string(&explicit_pns)));
+                            explicit_pns.clear();
+                        }
+                        part.push(call_names[call_idx].clone());
+                        change_idx += query.calls[call_idx].place_notation.len();
+                    }
+                    None => {
+                        explicit_pns.push(change.place_not.clone());
+                        change_idx += 1;
+                    }
+                }
+            }
+        }
+        if !explicit_pns.is_empty() {
+            part.push(format!("+{}", pn_string(&explicit_pns)));
+        }
+
+        writeln!(s).unwrap();
+        writeln!(s, "part = {};", part.join(", ")).unwrap();
+        match query.num_parts() {
+            1 => writeln!(s, "prove part;").unwrap(),
+            n => writeln!(s, "prove {}(part);", n).unwrap(),
+        }
+        s
+    }
+
+    /// Generate a plain-text layout of this `Composition` which can be pasted into CompLib's
+    /// composition import.  This lists the methods and calls used (with their place notation),
+    /// followed by a table of the calling.
+    ///
+    /// For calling positions, the table has one line per course, with the course head in the
+    /// first column and the calls under their calling positions (plus the methods rung, if the
+    /// composition is spliced).  Positional and six-numbered callings are given lead-by-lead
+    /// instead, with each call listed under the number of the lead it ends.
+    pub fn to_complib(&self) -> String {
+        let query = &self.query;
+        let mut s = String::new();
+
+        // Methods and calls
+        for (method_idx, method) in query.methods.iter_enumerated() {
+            if self.method_counts[method_idx.index()] == 0 && query.is_spliced() {
+                continue; // Skip any methods which aren't rung
+            }
+            let pns = lead_place_notation(method);
+            writeln!(
+                s,
+                "Method: {} ({}) {}",
+                method.title(),
+                method.shorthand(),
+                symmetric_pn_string(&pns)
+            )
+            .unwrap();
+        }
+        let calls_used = self
+            .path
+            .iter()
+            .filter_map(|elem| elem.call_to_end)
+            .unique()
+            .sorted()
+            .collect_vec();
+        for call_idx in calls_used {
+            let call = &query.calls[call_idx];
+            writeln!(
+                s,
+                "Call: {} {} at {}",
+                call.symbol, call.place_notation, call.label_from
+            )
+            .unwrap();
+        }
+        if query.is_multipart() {
+            writeln!(
+                s,
+                "Part head: {} ({} parts)",
+                self.part_head(),
+                query.num_parts()
+            )
+            .unwrap();
+        }
+        writeln!(s).unwrap();
+
+        // Calling
+        match query.call_display_style {
+            CallDisplayStyle::CallingPositions(calling_bell) => {
+                s.push_str(&self.complib_coursewise(calling_bell))
+            }
+            CallDisplayStyle::Positional | CallDisplayStyle::Sixes => {
+                s.push_str(&self.complib_leadwise())
+            }
+        }
+        s
+    }
+
+    /// The calling of a composition, as a table with one line per course
+    fn complib_coursewise(&self, calling_bell: bellframe::Bell) -> String {
+        let query = &self.query;
+        let changes = self.changes();
+        let rows = self.rows();
+        let home_place = query.end_row.place_of(calling_bell).unwrap();
+
+        // Split the composition into courses
+        let mut courses = Vec::<(RowBuf, Course)>::new();
+        let mut course = Course::default();
+        for (idx, change) in changes.iter().enumerate() {
+            if change.sub_lead_idx == 0 {
+                let method = &query.methods[change.method];
+                course.methods.push_str(&method.shorthand());
+            }
+            let row_after = rows.get_row(idx + 1).unwrap();
+            if let Some(call_idx) = change.call {
+                let call = &query.calls[call_idx];
+                let row_after_call = rows.get_row(idx + call.place_notation.len()).unwrap();
+                let place = row_after_call.place_of(calling_bell).unwrap();
+                course
+                    .calls
+                    .push((call.calling_positions[place].clone(), call.symbol.clone()));
+            }
+            let is_lead_head = changes.get(idx + 1).map_or(
+                self.path.last().unwrap().end_sub_lead_idx(query) == 0,
+                |c| c.sub_lead_idx == 0,
+            );
+            let is_last_change = idx == changes.len() - 1;
+            let is_course_end =
+                is_lead_head && row_after.place_of(calling_bell) == Some(home_place);
+            if is_course_end || is_last_change {
+                courses.push((row_after.to_owned(), std::mem::take(&mut course)));
+            }
+        }
+
+        // Order the columns by where the calling positions are reached in a course
+        let method = &query.methods[MethodIdx::new(0)];
+        let columns = courses
+            .iter()
+            .flat_map(|(_, c)| c.calls.iter().map(|(pos, _)| pos))
+            .unique()
+            .sorted_by_key(|pos| position_order(pos, calling_bell, method, query))
+            .collect_vec();
+
+        let mut s = String::new();
+        write!(s, "{}", query.start_row).unwrap();
+        for pos in &columns {
+            write!(s, "\t{}", pos).unwrap();
+        }
+        if query.is_spliced() {
+            write!(s, "\tMethods").unwrap();
+        }
+        writeln!(s).unwrap();
+        for (course_head, course) in &courses {
+            write!(s, "{}", course_head).unwrap();
+            for pos in &columns {
+                let symbols = course
+                    .calls
+                    .iter()
+                    .filter(|(p, _)| p == *pos)
+                    .map(|(_, symbol)| symbol.as_str())
+                    .collect_vec();
+                let cell = match symbols.as_slice() {
+                    [] => String::new(),
+                    // Multiple bobs at the same position are written as a count
+                    syms if syms.len() > 1 && syms.iter().all(|s| *s == "-") => {
+                        syms.len().to_string()
+                    }
+                    syms => syms.concat(),
+                };
+                write!(s, "\t{}", cell).unwrap();
+            }
+            if query.is_spliced() {
+                write!(s, "\t{}", course.methods).unwrap();
+            }
+            writeln!(s).unwrap();
+        }
+        s
+    }
+
+    /// The calling of a composition, as the methods of every lead followed by the calls (each
+    /// labelled with the lead number it ends)
+    fn complib_leadwise(&self) -> String {
+        let query = &self.query;
+        let mut methods = String::new();
+        let mut num_leads = 0;
+        let mut lead_numbers = vec!["Methods".to_owned()];
+        let mut call_symbols = vec![String::new()];
+        for change in self.changes() {
+            if change.sub_lead_idx == 0 || num_leads == 0 {
+                methods.push_str(&query.methods[change.method].shorthand());
+                num_leads += 1;
+            }
+            if let Some(call_idx) = change.call {
+                lead_numbers.push(num_leads.to_string());
+                call_symbols.push(query.calls[call_idx].symbol.clone());
+            }
+        }
+        call_symbols[0] = methods;
+        format!("{}\n{}\n", lead_numbers.join("\t"), call_symbols.join("\t"))
+    }
+
+    /// Return every change made in the first part of this `Composition`
+    fn changes(&self) -> Vec<Change> {
+        let query = &self.query;
+        let rows = self.rows();
+        let mut changes = Vec::new();
+        for elem in &self.path {
+            let method = &query.methods[elem.method];
+            let call_len = elem
+                .call_to_end
+                .map_or(1, |call_idx| query.calls[call_idx].place_notation.len());
+            for i in 0..elem.length.as_usize() {
+                let idx = changes.len();
+                let is_first_change_of_call = i == elem.length.as_usize() - call_len;
+                changes.push(Change {
+                    method: elem.method,
+                    sub_lead_idx: (elem.start_sub_lead_idx + i) % method.lead_len(),
+                    place_not: PlaceNot::pn_between(
+                        rows.get_row(idx).unwrap(),
+                        rows.get_row(idx + 1).unwrap(),
+                    )
+                    .expect("Adjacent rows should be a change apart"),
+                    call: elem.call_to_end.filter(|_| is_first_change_of_call),
+                });
+            }
+        }
+        changes
+    }
+}
+
+/// A single change in a [`Composition`]
+#[derive(Debug)]
+struct Change {
+    /// The method containing the row **before** this change
+    method: MethodIdx,
+    /// The sub-lead index of the row **before** this change
+    sub_lead_idx: usize,
+    place_not: PlaceNot,
+    /// The call which starts with this change, if any
+    call: Option<CallIdx>,
+}
+
+/// The calls made in one course of a [`Composition`]
+#[derive(Debug, Default)]
+struct Course {
+    /// `(calling position, call symbol)` for every call in this course
+    calls: Vec<(String, String)>,
+    /// The shorthand of every lead rung in this course
+    methods: String,
+}
+
+/// The place notation of every change in the first lead of a [`Method`](crate::parameters::Method)
+fn lead_place_notation(method: &bellframe::Method) -> Vec<PlaceNot> {
+    let lead = method.first_lead();
+    (0..lead.len())
+        .map(|i| {
+            PlaceNot::pn_between(lead.get_row(i).unwrap(), lead.get_row(i + 1).unwrap())
+                .expect("Adjacent rows should be a change apart")
+        })
+        .collect_vec()
+}
+
+/// Format a sequence of place notations, separating non-cross changes with `.`s
+fn pn_string(pns: &[PlaceNot]) -> String {
+    let mut s = String::new();
+    let mut last_was_cross = true;
+    for pn in pns {
+        if !pn.is_cross() && !last_was_cross {
+            s.push('.');
+        }
+        write!(s, "{}", pn).unwrap();
+        last_was_cross = pn.is_cross();
+    }
+    s
+}
+
+/// Format the place notation of a lead, using the `&<half lead>,<lead end>` form if the lead is
+/// symmetric.
+fn symmetric_pn_string(pns: &[PlaceNot]) -> String {
+    let (lead_end, body) = pns.split_last().unwrap();
+    let is_symmetric = body.len() % 2 == 1 && body.iter().eq(body.iter().rev());
+    match is_symmetric {
+        true => format!("&{},{}", pn_string(&body[..=body.len() / 2]), lead_end),
+        false => pn_string(pns),
+    }
+}
+
+/// Convert a [`String`] into something which can be used as a MicroSIRIL identifier, falling back
+/// on `default` if there are no usable characters.
+fn siril_identifier(s: &str, default: &str) -> String {
+    let ident = s
+        .chars()
+        .filter(char::is_ascii_alphanumeric)
+        .collect::<String>();
+    match ident.chars().next() {
+        Some(c) if c.is_ascii_alphabetic() => ident,
+        _ => default.to_owned(),
+    }
+}
+
+/// A key used to sort calling positions into the order they're reached in a plain course of
+/// `method`.  Positions which can't be reached at any lead end are sorted last.
+fn position_order(
+    position: &str,
+    calling_bell: bellframe::Bell,
+    method: &crate::parameters::Method,
+    query: &Query,
+) -> usize {
+    let plain_course = method.plain_course();
+    let num_leads = plain_course.len() / method.lead_len();
+    (1..=num_leads)
+        .find(|&lead| {
+            query.calls.iter().any(|call| {
+                let row_before_call = plain_course
+                    .get_row(lead * method.lead_len() - call.place_notation.len())
+                    .unwrap();
+                let row_after_call: RowBuf = row_before_call * call.place_notation.transposition();
+                let place = Row::place_of(&row_after_call, calling_bell).unwrap();
+                call.calling_positions[place] == position
+            })
+        })
+        .unwrap_or(usize::MAX)
+}
+
+#[cfg(test)]
+mod tests {
+    use bellframe::{PlaceNot, PnBlock, RowBuf, Stage};
+    use itertools::Itertools;
+
+    use crate::{
+        parameters::{CallDisplayStyle, Parameters},
+        test_utils::{plain_bob_minor, search, spliced_minor},
+        Composition,
+    };
+
+    fn comp(params: Parameters, calling: &str) -> Composition {
+        search(params).evaluate(calling).unwrap()
+    }
+
+    /// Spliced Plain Bob and Cambridge, written lead by lead
+    fn positional_spliced_minor() -> Parameters {
+        Parameters {
+            call_display_style: CallDisplayStyle::Positional,
+            ..spliced_minor()
+        }
+    }
+
+    #[test]
+    fn microsiril() {
+        assert_eq!(
+            comp(plain_bob_minor(), "sHsH").to_microsiril(),
+            "// sHsH
+// 120 changes
+6 bells;
+
+// Plain Bob Minor
+P = +-16-16-16-16-16-;
+Pp = +12;
+bob = +14;
+single = +1234;
+
+part = P, Pp, P, Pp, P, Pp, P, Pp, P, single, P, Pp, P, Pp, P, Pp, P, Pp, P, single;
+prove part;
+"
+        );
+        assert_eq!(
+            comp(positional_spliced_minor(), "#P[-]C[-]P[s]C[s]").to_microsiril(),
+            "// #P[-]C[-]P[s]C[s]
+// 72 changes
+6 bells;
+
+// Plain Bob Minor
+P = +-16-16-16-16-16-;
+Pp = +12;
+// Cambridge Surprise Minor
+C = +-36-14-12-36-14-56-14-36-12-14-36-;
+Cp = +12;
+bob = +14;
+single = +1234;
+
+part = P, bob, C, bob, P, single, C, single;
+prove part;
+"
+        );
+    }
+
+    #[test]
+    fn microsiril_start_row() {
+        let start_row = RowBuf::parse("132456").unwrap();
+        let params = Parameters {
+            start_row: start_row.clone(),
+            end_row: start_row,
+            ..plain_bob_minor()
+        };
+        assert_eq!(
+            comp(params, "sHsH").to_microsiril(),
+            "// sHsH
+// 120 changes
+6 bells;
+rounds '132456';
+
+// Plain Bob Minor
+P = +-16-16-16-16-16-;
+Pp = +12;
+bob = +14;
+single = +1234;
+
+part = P, Pp, P, Pp, P, Pp, P, Pp, P, single, P, Pp, P, Pp, P, Pp, P, Pp, P, single;
+prove part;
+"
+        );
+    }
+
+    #[test]
+    fn complib() {
+        assert_eq!(
+            comp(plain_bob_minor(), "sHsH").to_complib(),
+            "Method: Plain Bob Minor (P) &-16-16-16,12
+Call: s 1234 at LE
+
+123456\tH
+124356\ts
+123456\ts
+"
+        );
+        assert_eq!(
+            comp(positional_spliced_minor(), "#P[-]C[-]P[s]C[s]").to_complib(),
+            "Method: Plain Bob Minor (P) &-16-16-16,12
+Method: Cambridge Surprise Minor (C) &-36-14-12-36-14-56,12
+Call: - 14 at LE
+Call: s 1234 at LE
+
+Methods\t1\t2\t3\t4
+PCPC\t-\t-\ts\ts
+"
+        );
+    }
+
+    fn pns(s: &str) -> Vec<PlaceNot> {
+        PnBlock::parse(s, Stage::MAJOR)
+            .unwrap()
+            .place_nots()
+            .cloned()
+            .collect_vec()
+    }
+
+    #[test]
+    fn pn_strings() {
+        assert_eq!(super::pn_string(&pns("-38-14-58")), "-38-14-58");
+        assert_eq!(super::pn_string(&pns("58.36.14-14")), "58.36.14-14");
+        assert_eq!(
+            super::symmetric_pn_string(&pns("&-38-14-58-16-12-38-14-78,12")),
+            "&-38-14-58-16-12-38-14-78,12"
+        );
+        assert_eq!(
+            super::symmetric_pn_string(&pns("&-58-14.58-58.36.14-14.58-14-18,18")),
+            "&-58-14.58-58.36.14-14.58-14-18,18"
+        );
+        assert_eq!(
+            super::symmetric_pn_string(&pns("38.18.58.18.58.18")),
+            "38.18.58.18.58.18"
+        );
+    }
+}
@@ -16,6 +16,7 @@ use itertools::Itertools;
 use super::{ChunkEquivalenceMap, ChunkIdInFirstPart};
 use crate::{
     graph::{Chunk, ChunkId, PerPartLength, RowIdx},
+    parameters::CallIdx,
     query::Query,
 };
 
@@ -94,8 +95,7 @@ impl FalsenessTable {
             for lead_head_mask in &method_data.allowed_lead_masks {
                 for (id, len) in chunks {
                     if id.method == method_idx && lead_head_mask.matches(&id.lead_head) {
-                        masks_used
-                            .insert((ChunkRange::new(id.row_idx, *len), lead_head_mask.clone()));
+                        masks_used.insert((ChunkRange::new(id, *len), lead_head_mask.clone()));
                     }
                 }
             }
@@ -116,7 +116,9 @@ impl FalsenessTable {
         reduce_masks(&mut masks_used, &mut masks_used_in_all_parts, query);
 
         // Group rows and compute self-falseness
-        let (self_false_ranges, row_groups) = group_rows(masks_used_in_all_parts, query);
+        let covered_rows = covered_rows(&masks_used_in_all_parts, query);
+        let (self_false_ranges, row_groups) =
+            group_rows(masks_used_in_all_parts, &covered_rows, query);
 
         // Compute FCHs between every `(range, le_mask)` combination
         let false_chunk_transpositions =
@@ -142,7 +144,7 @@ impl FalsenessTable {
         chunk_ids_and_lengths: &HashSet<(ChunkId, PerPartLength)>,
     ) -> Truth {
         // Get the false chunk transpositions for this chunk's range, or return on self-falseness
-        let fchs = match &self.falseness_entries[&ChunkRange::new(id.row_idx, length)] {
+        let fchs = match &self.falseness_entries[&ChunkRange::new(id, length)] {
             FalsenessEntry::FalseCourseHeads(fchs) => fchs,
             FalsenessEntry::SelfFalse => return Truth::False,
         };
@@ -157,6 +159,7 @@ impl FalsenessTable {
                     let false_id = ChunkIdInFirstPart {
                         lead_head: false_lead_head,
                         row_idx: false_range.start,
+                        covering_call: false_range.covering_call,
                     };
                     let (equiv_false_id, ph_rotation) = chunk_equiv_map.normalise(&false_id);
 
@@ -318,12 +321,13 @@ fn reduce_masks(
 ///
 /// In this loop, we also check for `ChunkRange`s which are 'self-false' (i.e. include some row
 /// multiple times).
-fn group_rows(
+fn group_rows<'a>(
     masks_used_in_all_parts: HashSet<(ChunkRange, Mask)>,
-    query: &Query,
+    covered_rows: &'a HashMap<(CallIdx, RowIdx), Vec<RowBuf>>,
+    query: &'a Query,
 ) -> (
     HashSet<ChunkRange>,
-    HashMap<(ChunkRange, Mask), HashMap<Mask, Vec<&Row>>>,
+    HashMap<(ChunkRange, Mask), HashMap<Mask, Vec<&'a Row>>>,
 ) {
     let mut self_false_ranges = HashSet::<ChunkRange>::new();
     let mut row_groups = HashMap::<(ChunkRange, Mask), RowGroups>::new();
@@ -335,11 +339,24 @@ fn group_rows(
             continue;
         }
 
+        // Any rows covered by a call come before the range's plain rows
+        let range_covered_rows: &[RowBuf] = match range.covering_call {
+            Some(call_idx) => &covered_rows[&(call_idx, range.start)],
+            None => &[],
+        };
+        let num_plain_rows = range.len.as_usize() - range_covered_rows.len();
+        let plain_rows = (0..num_plain_rows).map(|offset| {
+            let row_index = (range.start.sub_lead_idx + offset) % plain_course.len();
+            plain_course.get_row(row_index).unwrap()
+        });
+
         let mut rows_so_far = HashSet::<&Row>::new();
         let mut row_groups_for_this_range: RowGroups = HashMap::new();
-        for offset in 0..range.len.as_usize() {
-            let row_index = (range.start.sub_lead_idx + offset) % plain_course.len();
-            let row = plain_course.get_row(row_index).unwrap();
+        for row in range_covered_rows
+            .iter()
+            .map(RowBuf::as_row)
+            .chain(plain_rows)
+        {
             // Check for self-falseness.  I.e. if some row is repeated twice within a chunk,
             // then it's considered 'self-false' and should be removed from the graph
             if !rows_so_far.insert(row) {
@@ -368,6 +385,27 @@ fn group_rows(
     (self_false_ranges, row_groups)
 }
 
+/// For every [`ChunkRange`] which starts with the rows covered by a call, compute those rows
+/// (relative to the lead head of that [`ChunkRange`]).
+fn covered_rows(
+    masks_used_in_all_parts: &HashSet<(ChunkRange, Mask)>,
+    query: &Query,
+) -> HashMap<(CallIdx, RowIdx), Vec<RowBuf>> {
+    let mut covered_rows = HashMap::new();
+    for (range, _mask) in masks_used_in_all_parts {
+        if let Some(call_idx) = range.covering_call {
+            covered_rows
+                .entry((call_idx, range.start))
+                .or_insert_with(|| {
+                    let method = &query.methods[range.start.method];
+                    let row_after_call = method.row_in_plain_lead(range.start.sub_lead_idx);
+                    query.calls[call_idx].covered_rows(row_after_call)
+                });
+        }
+    }
+    covered_rows
+}
+
 /// For each (range, mask) used as an equivalence mask in the composition, compute the false chunk
 /// transpositions against every (range, mask) in **every part** of the composition.
 ///
@@ -415,11 +453,17 @@ fn generate_false_chunk_transpositions<'masks, 'groups>(
 struct ChunkRange {
     start: RowIdx,
     len: PerPartLength,
+    /// See [`ChunkId::covering_call`]
+    covering_call: Option<CallIdx>,
 }
 
 impl ChunkRange {
-    fn new(start: RowIdx, len: PerPartLength) -> Self {
-        Self { start, len }
+    fn new(id: &ChunkId, len: PerPartLength) -> Self {
+        Self {
+            start: id.row_idx,
+            len,
+            covering_call: id.covering_call,
+        }
     }
 }
 
@@ -429,6 +473,10 @@ impl Debug for ChunkRange {
             f,
             "ChunkRange({:?},{}+{})",
             self.start.method, self.start.sub_lead_idx, self.len
-        )
+        )?;
+        if let Some(call_idx) = self.covering_call {
+            write!(f, "(after {:?})", call_idx)?;
+        }
+        Ok(())
     }
 }
@@ -10,7 +10,7 @@ use itertools::Itertools;
 use crate::{
     graph::{ChunkId, Link, LinkSet, LinkSide, RowIdx},
     group::PhRotation,
-    parameters::{CallIdx, MethodIdx, MethodVec, SpliceStyle},
+    parameters::{CallDisplayStyle, CallIdx, MethodIdx, MethodVec, SpliceStyle},
     query::Query,
     utils::{
         lengths::{PerPartLength, TotalLength},
@@ -75,8 +75,8 @@ pub(super) fn chunk_lengths<'q>(
             min_distance_from_start + per_part_length.as_total(&query.part_head_group);
 
         // Stop expanding if the shortest path from rounds to the end of the chunk takes longer
-        // than the max comp length
-        if min_distance_after_chunk > query.max_length() {
+        // than the max comp (or extent) length
+        if min_distance_after_chunk > query.max_block_length() {
             continue;
         }
 
@@ -178,9 +178,10 @@ impl ChunkFactory {
         let mut links = links_at_shortest_len;
         // Replace any links which go directly to a part-head with an end (for example calling a
         // home in coursing order `54326` will cause the composition to instantly come round rather
-        // than leading to `ChunkId(12345678:#,0)` where `#` is any method)
+        // than leading to `ChunkId(12345678:#,0)` where `#` is any method).  Links after calls
+        // which cover several changes still need a chunk to contain the covered rows.
         for (id_to, _call, is_end) in &mut links {
-            *is_end |= self.end_lookup_table.is_end(id_to);
+            *is_end |= id_to.covering_call.is_none() && self.end_lookup_table.is_end(id_to);
         }
 
         (chunk_length, links)
@@ -262,10 +263,12 @@ impl EndLookupTable {
             // 0-length ends (e.g. calling a `H` at the end of a composition to make it instantly
             // come round) are checked when links are being generated.  Therefore, if we _do_
             // generate a chunk that end immediately, it must be also be a start and we should
-            // ring the entire course
-            if length == 0 {
+            // ring the entire course.  The exception is chunks which only contain the rows
+            // covered by a call, which are allowed to come round immediately after those rows.
+            if length == 0 && chunk_id.covering_call.is_none() {
                 length += course_len;
             }
+            length += query.num_rows_covered_by(chunk_id.covering_call);
             add_link(PerPartLength::new(length), end_id.to_owned(), None, true);
         }
     }
@@ -290,13 +293,6 @@ struct LinkLookupEntry {
 
 impl LinkLookupTable {
     fn new(query: &Query) -> Self {
-        // Treat non-spliced comps as having `SpliceStyle::Calls`
-        let splice_style = if query.is_spliced() {
-            query.splice_style
-        } else {
-            SpliceStyle::Calls
-        };
-
         // Maps lead labels to a list of:
         // ```
         // (
@@ -341,19 +337,26 @@ impl LinkLookupTable {
                     // ... for every call that can be placed there ...
                     for (call_idx, call) in query.calls.iter_enumerated() {
                         if &call.label_from == label {
-                            let row_before_call = method
-                                .plain_course
-                                .get_row(dist_from_lead_head - 1)
-                                .unwrap();
+                            // Calls which cover several changes start before the labelled
+                            // row, wrapping round to the end of the course if needed
+                            let mut call_pos =
+                                dist_from_lead_head as isize - call.num_covered_rows() as isize;
+                            if call_pos <= 0 {
+                                call_pos += method.plain_course.len() as isize;
+                            }
+                            let call_pos = call_pos as usize;
+                            let row_before_call =
+                                method.plain_course.get_row(call_pos - 1).unwrap();
                             let row_after_call =
                                 row_before_call * call.place_notation.transposition();
 
                             create_links(
-                                dist_from_lead_head,
+                                call_pos,
                                 Some(call_idx),
                                 &row_after_call,
                                 &call.label_to,
-                                method,
+                                RowIdx::new(method_idx, dist_from_lead_head % lead_len),
+                                false,
                                 &link_ends_by_label,
                                 query,
                                 &mut link_positions,
@@ -361,8 +364,8 @@ impl LinkLookupTable {
                         }
                     }
 
-                    // Add links for plain splices (at every possible position)
-                    if splice_style == SpliceStyle::LeadLabels {
+                    // Add links for plain splices (at every position allowed by the splice style)
+                    if query.allows_plain_splice_at(label) {
                         let row_after_plain =
                             method.plain_course.get_row(dist_from_lead_head).unwrap();
                         // Add plain links from every instance of a label to every other instance
@@ -372,7 +375,8 @@ impl LinkLookupTable {
                             None,
                             row_after_plain,
                             label,
-                            method,
+                            RowIdx::new(method_idx, dist_from_lead_head % lead_len),
+                            query.splice_style == SpliceStyle::CourseHeads,
                             &link_ends_by_label,
                             query,
                             &mut link_positions,
@@ -381,24 +385,26 @@ impl LinkLookupTable {
                 }
             }
 
-            // Add links for plain leads
-            if splice_style == SpliceStyle::Calls {
-                // Add non-splice plain links to every position where there is already a call
-                for link_positions in link_positions.values_mut() {
-                    for (dist_from_lead_head, links) in link_positions {
-                        assert!(!links.is_empty());
-                        let num_leads = dist_from_lead_head / lead_len;
-                        let sub_lead_idx = dist_from_lead_head % lead_len;
-                        links.push(LinkLookupEntry {
-                            call: None,
-                            // The LH transposition just represents the number of leads we've
-                            // rung (this always links to the same method, so the sub-lead
-                            // transpositions cancel out)
-                            lead_head_transposition: method.lead_head().pow_u(num_leads),
-                            // Only add plain links to the current method
-                            row_idx_to: RowIdx::new(method_idx, sub_lead_idx),
-                        });
+            // Add non-splice plain links to every position where there's a link but no way to
+            // carry on ringing the current method.  For `SpliceStyle::Calls`, this is every
+            // position where there is already a call.
+            for link_positions in link_positions.values_mut() {
+                for (dist_from_lead_head, links) in link_positions {
+                    assert!(!links.is_empty());
+                    if links.iter().any(|link| link.call.is_none()) {
+                        continue;
                     }
+                    let num_leads = *dist_from_lead_head / lead_len;
+                    let sub_lead_idx = *dist_from_lead_head % lead_len;
+                    links.push(LinkLookupEntry {
+                        call: None,
+                        // The LH transposition just represents the number of leads we've
+                        // rung (this always links to the same method, so the sub-lead
+                        // transpositions cancel out)
+                        lead_head_transposition: method.lead_head().pow_u(num_leads),
+                        // Only add plain links to the current method
+                        row_idx_to: RowIdx::new(method_idx, sub_lead_idx),
+                    });
                 }
             }
 
@@ -466,12 +472,24 @@ impl LinkLookupTable {
                     if len == 0 {
                         len = query.methods[chunk_id.method].plain_course.len();
                     }
+                    len += query.num_rows_covered_by(chunk_id.covering_call);
                     for link_entry in link_entries {
+                        // Calls covering several changes put their covered rows at the start of
+                        // the next chunk
+                        let covering_call = link_entry
+                            .call
+                            .filter(|&call_idx| query.calls[call_idx].num_covered_rows() > 0);
                         let next_chunk_id = ChunkIdInFirstPart {
                             lead_head: chunk_id.lead_head.as_ref()
                                 * &link_entry.lead_head_transposition,
                             row_idx: link_entry.row_idx_to,
+                            covering_call,
                         };
+                        if let Some(call_idx) = link_entry.call {
+                            if !is_call_allowed(call_idx, &next_chunk_id, query) {
+                                continue;
+                            }
+                        }
                         add_link(
                             PerPartLength::new(len),
                             next_chunk_id,
@@ -485,30 +503,75 @@ impl LinkLookupTable {
     }
 }
 
+/// Returns `true` if the given call is allowed to be placed just before `chunk_id_after_call`,
+/// i.e. if the call puts the calling bell in one of its allowed calling positions.
+fn is_call_allowed(
+    call_idx: CallIdx,
+    chunk_id_after_call: &ChunkIdInFirstPart,
+    query: &Query,
+) -> bool {
+    let call = &query.calls[call_idx];
+    if !call.has_restricted_positions() {
+        return true;
+    }
+    let calling_bell = match query.call_display_style {
+        CallDisplayStyle::CallingPositions(bell) => bell,
+        // Restricted calling positions require a calling bell (checked in `check_query`)
+        CallDisplayStyle::Positional | CallDisplayStyle::Sixes => return true,
+    };
+    let row_idx = chunk_id_after_call.row_idx;
+    let row_after_call = chunk_id_after_call.lead_head.as_row()
+        * query.methods[row_idx.method].row_in_plain_lead(row_idx.sub_lead_idx);
+    let place_of_calling_bell = row_after_call.place_of(calling_bell).unwrap();
+    call.is_allowed_at_place(place_of_calling_bell)
+}
+
 #[allow(clippy::too_many_arguments)]
 fn create_links(
     dist_from_lead_head: usize,
     call: Option<CallIdx>,
     row_after_link: &Row,
     label_to: &str,
-    method_from: &crate::query::Method,
+    // The labelled row where the link is placed
+    label_row_idx: RowIdx,
+    // If `true`, links are only added if they lead to a course head
+    only_to_course_heads: bool,
 
     link_ends_by_label: &HashMap<&str, Vec<(RowIdx, RowBuf)>>,
     query: &Query,
     link_lookup_for_method: &mut HashMap<Mask, HashMap<usize, Vec<LinkLookupEntry>>>,
 ) {
+    let method_from = &query.methods[label_row_idx.method];
+    // Links which stay in the same method also stay at the same place in the lead, if it has the
+    // right label.  Otherwise, calls at methods with repeated labels (e.g. Stedman's six ends)
+    // could jump to the wrong place in the lead.
+    let stays_in_place = method_from
+        .get_labels(label_row_idx.sub_lead_idx)
+        .iter()
+        .any(|label| label == label_to);
     let link_ends = link_ends_by_label
         .get(label_to)
         .expect("Undefined labels should be checked before graph expansion");
     // ... for every place within a lead this could go to ...
     for (row_idx_to, transposition_to_lead_head) in link_ends {
+        if stays_in_place
+            && row_idx_to.method == label_row_idx.method
+            && row_idx_to.sub_lead_idx != label_row_idx.sub_lead_idx
+        {
+            continue;
+        }
         // post-transposition
         //    from: the head of the lead the link is coming _from_
         //    to  : the head of the lead the link is coming _to_
         let lead_head_transposition = row_after_link * transposition_to_lead_head;
 
         // ... for every lead head mask of the method we're going to ...
-        for lead_head_mask_to in &query.methods[row_idx_to.method].allowed_lead_masks {
+        let method_to = &query.methods[row_idx_to.method];
+        let lead_head_masks_to = match only_to_course_heads {
+            true => &method_to.specified_course_head_masks,
+            false => &method_to.allowed_lead_masks,
+        };
+        for lead_head_mask_to in lead_head_masks_to {
             //     `lh_from * lh_transposition` satisfies `lh_mask_to`
             // iff `lh_from` satisfies `lh_mask_to * lh_transposition.inv()`
             let lead_head_mask_from = lead_head_mask_to * lead_head_transposition.inv();
@@ -553,6 +616,7 @@ fn find_locations_of_row(row: &Row, boundary: Boundary, query: &Query) -> Vec<Ch
                 locations.push(ChunkIdInFirstPart {
                     lead_head,
                     row_idx: RowIdx::new(method_idx, sub_lead_idx),
+                    covering_call: None,
                 });
             }
         }
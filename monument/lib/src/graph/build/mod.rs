@@ -4,25 +4,28 @@ mod falseness;
 mod layout;
 
 use std::{
-    collections::{HashMap, HashSet},
+    collections::{BTreeMap, HashMap, HashSet},
     ops::Deref,
     sync::Arc,
     time::Instant,
 };
 
-use bellframe::{PlaceNot, Row, RowBuf, Stroke};
+use bellframe::{PnBlock, Row, RowBuf, Stroke};
 use itertools::Itertools;
 
 use crate::{
     atw::AtwTable,
     group::{PartHeadGroup, PhRotation},
-    parameters::{Call, StrokeSet},
+    parameters::{Call, CallDisplayStyle, CallIdx, MusicTypeIdx},
     query::Query,
+    scoring::RowContext,
     search::Config,
     utils::{counts::Counts, MusicBreakdown},
 };
 
-use super::{Chunk, ChunkId, Graph, LinkSet, LinkSide, PerPartLength, RowIdx, TotalLength};
+use super::{
+    Chunk, ChunkId, Graph, LinkSet, LinkSide, PerPartLength, RowIdx, ScheduledMusic, TotalLength,
+};
 
 impl Graph {
     /// Generate a graph of all chunks which are reachable within a given length constraint.
@@ -77,17 +80,32 @@ impl Graph {
 
         // Count music
         let start = Instant::now();
-        let relies_on_stroke = query
-            .music_types
-            .iter()
-            .any(|ty| ty.strokes != StrokeSet::Both);
         let start_strokes = get_start_strokes(&chunks, &links, query);
-        if start_strokes.is_none() && relies_on_stroke {
+        if start_strokes.is_none() && query.relies_on_stroke() {
             return Err(crate::Error::InconsistentStroke);
         }
         // Now we know the starting strokes, count the music on each chunk
         for (id, chunk) in &mut chunks {
-            count_scores(id, chunk, &start_strokes, query);
+            let start_stroke = match &start_strokes {
+                Some(map) => match map.get(id) {
+                    Some(stroke) => *stroke,
+                    // If `map` exists but doesn't contain `id`, then there can't be a path from
+                    // the start to this chunk (because `map` is generated by running breadth-first
+                    // search forward through the graph).  Thus, this chunk is guaranteed to never
+                    // be used in a composition, and there's no point computing an exact score.
+                    // We still set the music to `0`s so that an optimisation pass can't see this
+                    // chunk and run `zip_eq` on the `MusicType`s, thus causing a panic.
+                    None => {
+                        chunk.music = MusicBreakdown::zero(query.music_types.len());
+                        continue;
+                    }
+                },
+                // If the nodes are inconsistent but the `MusicType`s don't rely on stroke, then
+                // `start_strokes == None` and we can just give every chunk an arbitrary start
+                // stroke
+                None => Stroke::Back,
+            };
+            count_scores(id, chunk, start_stroke, query);
         }
         log::debug!("  Music counted in {:.2?}", start.elapsed());
 
@@ -122,7 +140,7 @@ impl Graph {
 }
 
 /// Creates a blank [`Chunk`] from a [`ChunkId`] and corresponding [`PerPartLength`].
-fn expand_chunk(
+pub(crate) fn expand_chunk(
     id: &ChunkId,
     per_part_length: PerPartLength,
     query: &Query,
@@ -145,6 +163,7 @@ fn expand_chunk(
             id.method.index(),
             query.methods.len(),
         ),
+        required_course_leads: query.required_course_leads(id, per_part_length),
         atw_bitmap: atw_table.bitmap_for_chunk(query, id, per_part_length),
 
         // Filled in separate graph build passes
@@ -152,6 +171,8 @@ fn expand_chunk(
         successors: Vec::new(),
         false_chunks: Vec::new(),
         music: MusicBreakdown::zero(0),
+        scheduled_music: Vec::new(),
+        start_stroke: Stroke::Back,
 
         // Used by optimisation passes
         required: false,
@@ -165,8 +186,8 @@ fn expand_chunk(
 }
 
 /// Attempt to assign a single starting [`Stroke`] to every [`Chunk`].  If such a mapping is
-/// ambiguous (i.e. there's a cycle in the graph which doesn't preserve [`Stroke`]) then [`None`]
-/// is returned.
+/// ambiguous (i.e. there's a cycle in the graph which doesn't preserve [`Stroke`], or the parts of
+/// a multi-part could start at different strokes) then [`None`] is returned.
 // TODO: Add `Stroke` as part of `ChunkId`, so that the same chunk on two different strokes are
 // treated differently
 fn get_start_strokes(
@@ -204,8 +225,18 @@ fn get_start_strokes(
                     for succ_link_id in &chunk.successors {
                         let succ_link = &links[*succ_link_id];
                         assert_eq!(succ_link.from, LinkSide::Chunk(id.clone()));
-                        if let LinkSide::Chunk(succ_id) = &succ_link.to {
-                            frontier.push((succ_id.to_owned(), stroke_after_chunk));
+                        match &succ_link.to {
+                            LinkSide::Chunk(succ_id) => {
+                                frontier.push((succ_id.to_owned(), stroke_after_chunk))
+                            }
+                            // The end of one part is the start of the next, so every part must
+                            // end at the same stroke as the first part started
+                            LinkSide::StartOrEnd => {
+                                if query.is_multipart() && stroke_after_chunk != stroke_of_start_row
+                                {
+                                    return None;
+                                }
+                            }
                         }
                     }
                 }
@@ -216,64 +247,109 @@ fn get_start_strokes(
     Some(start_strokes)
 }
 
-/// Count the [`Score`] contributed by this [`Chunk`].  This includes both music and course head
-/// weights.
-fn count_scores(
-    id: &ChunkId,
-    chunk: &mut Chunk,
-    start_strokes: &Option<HashMap<ChunkId, Stroke>>,
-    query: &Query,
-) {
-    // Always set music to `0`s, even if the chunk is unreachable.  If we don't, then an
-    // optimisation pass could see this chunk and run `zip_eq` on the `MusicType`s, thus causing a
-    // panic.
+/// Count the [`Score`] contributed by this [`Chunk`], assuming that its first row is rung at
+/// `start_stroke`.  This includes both music and course head weights.
+pub(crate) fn count_scores(id: &ChunkId, chunk: &mut Chunk, start_stroke: Stroke, query: &Query) {
     chunk.music = MusicBreakdown::zero(query.music_types.len());
-
-    let start_stroke = match start_strokes {
-        Some(map) => match map.get(id) {
-            Some(stroke) => *stroke,
-            // If `map` exists but doesn't contain `id`, then there can't be a path from the start
-            // to this chunk (because `map` is generated by running breadth-first search forward
-            // through the graph).  Thus, this chunk is guaranteed to never be used in a
-            // composition, and there's no point computing an exact score.
-            None => return,
-        },
-        // If the nodes are inconsistent but the `MusicType`s don't rely on stroke, then
-        // `start_strokes == None` and we can just give every chunk an arbitrary
-        // start stroke
-        None => Stroke::Back,
+    chunk.scheduled_music.clear();
+    chunk.start_stroke = start_stroke;
+    // Music types whose weights depend on where they occur, which must be counted row-by-row
+    let scheduled_types = query
+        .music_types
+        .iter_enumerated()
+        .filter(|(_, ty)| ty.weight_schedule.iter().any(|w| !w.is_deferred()))
+        .map(|(idx, _)| idx)
+        .collect_vec();
+    let mut scheduled_counts = BTreeMap::<(usize, MusicTypeIdx), usize>::new();
+
+    let method = &query.methods[id.method];
+    let plain_course = &method.plain_course;
+    let lead_heads = method.inner.lead_head().closure();
+    // The rows of this chunk (relative to its lead head).  Any rows covered by a call come first
+    let covered_rows = match id.covering_call {
+        Some(call_idx) => {
+            query.calls[call_idx].covered_rows(method.row_in_plain_lead(id.sub_lead_idx))
+        }
+        None => Vec::new(),
     };
-    let plain_course = &query.methods[id.method].plain_course;
-    let lead_heads = query.methods[id.method].inner.lead_head().closure();
-
+    let num_plain_rows = chunk.per_part_length.as_usize() - covered_rows.len();
+    let plain_rows = (0..num_plain_rows).map(|offset| {
+        let index = (id.sub_lead_idx + offset) % plain_course.len();
+        plain_course.get_row(index).unwrap()
+    });
+    let rows = covered_rows
+        .iter()
+        .map(RowBuf::as_row)
+        .chain(plain_rows)
+        .collect_vec();
+
+    let has_wraps = query.has_wraps();
+    let mut transposed_row = RowBuf::rounds(query.stage);
+    let mut next_transposed_row = RowBuf::rounds(query.stage);
+    // Wraps are scored separately to the `Scorer`, so are counted separately
+    let mut music_counts = vec![0; query.music_types.len()];
+    let mut wrap_counts = vec![0; query.music_types.len()];
     for part_head in query.part_head_group.rows() {
         let lead_head_in_part = part_head * id.lead_head.as_ref();
-        let row_iter = (0..chunk.per_part_length.as_usize()).map(|offset| {
-            let index = (id.sub_lead_idx + offset) % plain_course.len();
-            plain_course.get_row(index).unwrap()
-        });
-        // Count weight from music
-        chunk.music += &MusicBreakdown::from_rows(
-            row_iter,
-            &lead_head_in_part,
-            query.music_types.as_raw_slice(),
-            start_stroke,
-        );
-        // Count weight from `course_weights`.  `course_weights` apply to every row of every course
-        // which contains a lead head matching that mask, so we have to transpose the mask by every
-        // lead head to check every lead in the course.  For example, for Plain Bob lead-head
-        // methods, `xxxxxx78` will expand into masks
-        // `[xxxxxx78, xxxxx8x7, xxx8x7xx, x8x7xxxx, x78xxxxx, xx7x8xxx, xxxx7x8x]` (every one of
-        // those leads is included in the course for `xxxxxx78`)
-        for (mask, weight) in &query.course_weights {
-            for lead_head in &lead_heads {
-                if (mask * lead_head).matches(&lead_head_in_part) {
-                    // Weight applies to each row
-                    chunk.music.score += *weight * chunk.per_part_length.as_usize() as f32;
+        for (offset, row) in rows.iter().enumerate() {
+            lead_head_in_part
+                .mul_into(row, &mut transposed_row)
+                .unwrap();
+            // Wraps out of the last row depend on the next chunk, so are counted on the links
+            let next_row = match rows.get(offset + 1) {
+                Some(next_row) if has_wraps => {
+                    lead_head_in_part
+                        .mul_into(next_row, &mut next_transposed_row)
+                        .unwrap();
+                    Some(next_transposed_row.as_row())
+                }
+                _ => None,
+            };
+            let stroke = start_stroke.offset(offset);
+            // Count the instances of each music type in this row
+            for ((count, wrap_count), ty) in music_counts
+                .iter_mut()
+                .zip_eq(&mut wrap_counts)
+                .zip_eq(&query.music_types)
+            {
+                let num_instances = ty.num_instances(&transposed_row, next_row, stroke);
+                (*count, *wrap_count) = match ty.wraps {
+                    true => (0, num_instances),
+                    false => (num_instances, 0),
+                };
+            }
+            for (idx, (&count, &wrap_count)) in music_counts.iter().zip(&wrap_counts).enumerate() {
+                chunk.music.counts[idx] += count + wrap_count;
+            }
+            chunk.music.score += query.wrap_score(&wrap_counts);
+            // Score the row
+            chunk.music.score += query.scorer.row_score(&RowContext {
+                row: &transposed_row,
+                stroke,
+                method: &method.inner,
+                lead_head: &lead_head_in_part,
+                plain_lead_heads: &lead_heads,
+                music_types: query.music_types.as_raw_slice(),
+                music_counts: &music_counts,
+                parameters: &query.parameters,
+            });
+            // Record the positions of music with scheduled weights
+            for ty_idx in &scheduled_types {
+                let num_instances = music_counts[ty_idx.index()] + wrap_counts[ty_idx.index()];
+                if num_instances > 0 {
+                    *scheduled_counts.entry((offset, *ty_idx)).or_default() += num_instances;
                 }
             }
         }
     }
+    chunk.scheduled_music = scheduled_counts
+        .into_iter()
+        .map(|((offset, music_type), count)| ScheduledMusic {
+            offset,
+            music_type,
+            count,
+        })
+        .collect();
 }
 
 ////////////////////
@@ -286,6 +362,18 @@ fn check_query(query: &Query) -> crate::Result<()> {
     if query.is_multipart() && query.start_row != query.end_row {
         return Err(crate::Error::DifferentStartEndRowInMultipart);
     }
+    // Each extent of a multi-extent composition starts where the last one came round
+    if query.multi_extent {
+        if query.is_multipart() {
+            return Err(crate::Error::MultipartMultiExtent);
+        }
+        if query.start_row != query.end_row {
+            return Err(crate::Error::DifferentStartEndRowInMultiExtent);
+        }
+    }
+    if query.require_truth && query.falseness_limit.is_set() {
+        return Err(crate::Error::FalsenessLimitWithTruth);
+    }
 
     // Two methods using the same shorthand
     for (i1, m1) in query.methods.iter_enumerated() {
@@ -311,6 +399,32 @@ fn check_query(query: &Query) -> crate::Result<()> {
         }
     }
 
+    // Calls restricted to calling positions which don't exist, or without a calling bell
+    for call in &query.calls {
+        let restricted_positions = call
+            .allowed_calling_positions
+            .iter()
+            .chain(&call.forbidden_calling_positions);
+        for position in restricted_positions {
+            if !call.calling_positions.contains(position) {
+                return Err(crate::Error::UndefinedCallingPosition {
+                    call_name: call.symbol.clone(),
+                    position: position.clone(),
+                });
+            }
+        }
+        if call.has_restricted_positions()
+            && !matches!(
+                query.call_display_style,
+                CallDisplayStyle::CallingPositions(_)
+            )
+        {
+            return Err(crate::Error::RestrictedCallingPositionsWithoutCallingBell {
+                call_name: call.symbol.clone(),
+            });
+        }
+    }
+
     // Calls referring to non-existent labels
     let mut defined_labels = HashSet::<&String>::new();
     for m in &query.methods {
@@ -366,7 +480,7 @@ fn check_for_duplicate_call_names(query: &Query) -> crate::Result<()> {
     let sorted_calls = query
         .calls
         .iter()
-        .map(|call: &Call| -> (&str, &str, &PlaceNot) {
+        .map(|call: &Call| -> (&str, &str, &PnBlock) {
             (&call.symbol, &call.label_from, &call.place_notation)
         })
         .sorted_by_key(|&(sym, lead_loc, _pn)| (sym, lead_loc));
@@ -394,6 +508,8 @@ fn check_for_duplicate_call_names(query: &Query) -> crate::Result<()> {
 struct ChunkIdInFirstPart {
     lead_head: RowBuf,
     row_idx: RowIdx,
+    /// See [`ChunkId::covering_call`]
+    covering_call: Option<CallIdx>,
 }
 
 impl Deref for ChunkIdInFirstPart {
@@ -430,8 +546,9 @@ impl<'query> ChunkEquivalenceMap<'query> {
                     .insert(part_head * &id.lead_head, (arc_lead_head.clone(), element));
             }
         }
-        // Now normalise the ChunkId (by normalising its `lead_head` and preserving the row index)
+        // Now normalise the ChunkId (by normalising its `lead_head` and preserving everything else)
         let (normalised_lead_head, rotation) = self.normalisation[&id.lead_head].clone();
-        (ChunkId::new(normalised_lead_head, id.row_idx), rotation)
+        let chunk_id = ChunkId::new(normalised_lead_head, id.row_idx, id.covering_call);
+        (chunk_id, rotation)
     }
 }
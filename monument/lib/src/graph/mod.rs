@@ -6,6 +6,8 @@
 mod build;
 mod optimise;
 
+pub(crate) use build::{count_scores, expand_chunk};
+
 use std::{
     collections::HashMap,
     fmt::{Debug, Display, Formatter},
@@ -13,13 +15,13 @@ use std::{
     sync::Arc,
 };
 
-use bellframe::Row;
+use bellframe::{Row, Stroke};
 use datasize::DataSize;
 
 use crate::{
     atw::AtwBitmap,
     group::PhRotation,
-    parameters::{CallIdx, MethodIdx},
+    parameters::{CallIdx, MethodIdx, MusicTypeIdx},
     utils::{
         counts::Counts,
         lengths::{PerPartLength, TotalLength},
@@ -73,10 +75,19 @@ pub(crate) struct Chunk {
     pub(crate) total_length: TotalLength,
     /// The number of rows of each method generated by this chunk
     pub(crate) method_counts: Counts,
+    /// The number of leads of each of [`Parameters::required_courses`] started by this chunk
+    ///
+    /// [`Parameters::required_courses`]: crate::parameters::Parameters::required_courses
+    pub(crate) required_course_leads: Counts,
     /// The music generated by this chunk in the composition.  Optimisation passes can't change this
     pub(crate) music: MusicBreakdown,
+    /// Instances of any [`MusicType`](crate::parameters::MusicType)s whose weight depends on where
+    /// this chunk is rung.  These aren't included in `music.score`.
+    pub(crate) scheduled_music: Vec<ScheduledMusic>,
     /// An [`AtwBitmap`] storing which sections of methods have been rung in this chunk
     pub(crate) atw_bitmap: AtwBitmap,
+    /// The [`Stroke`] of the first row of this chunk.  This is set at the same time as `music`
+    pub(crate) start_stroke: Stroke,
 
     /// Does this chunk need to be included in every composition in this search?
     pub(crate) required: bool,
@@ -98,6 +109,17 @@ pub(crate) struct Chunk {
     pub(crate) lb_distance_to_non_duffer: PerPartLength,
 }
 
+/// Instances of a [`MusicType`](crate::parameters::MusicType) with a position-dependent weight,
+/// all on the same row of a [`Chunk`]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(crate) struct ScheduledMusic {
+    /// The index of the row within the [`Chunk`]
+    pub offset: usize,
+    pub music_type: MusicTypeIdx,
+    /// The number of instances on this row, summed over all the parts
+    pub count: usize,
+}
+
 /// A link between two [`Chunk`]s in a [`Graph`]
 #[derive(Debug, Clone, Eq, PartialEq, Hash)]
 pub(crate) struct Link {
@@ -174,11 +196,19 @@ impl Chunk {
 pub(crate) struct ChunkId {
     pub lead_head: Arc<Row>, // `Arc` is used to make cloning cheaper
     pub row_idx: RowIdx,
+    /// If this chunk directly follows a call which covers more than one change, then the rows
+    /// rung in the middle of that call are included at the start of this chunk (before the row
+    /// at `row_idx`).  For all other chunks, this is `None`.
+    pub covering_call: Option<CallIdx>,
 }
 
 impl ChunkId {
-    pub fn new(lead_head: Arc<Row>, row_idx: RowIdx) -> Self {
-        Self { lead_head, row_idx }
+    pub fn new(lead_head: Arc<Row>, row_idx: RowIdx, covering_call: Option<CallIdx>) -> Self {
+        Self {
+            lead_head,
+            row_idx,
+            covering_call,
+        }
     }
 }
 
@@ -203,6 +233,9 @@ impl Display for ChunkId {
             "{},{:?}:{}",
             self.lead_head, self.method, self.sub_lead_idx,
         )?;
+        if let Some(call_idx) = self.covering_call {
+            write!(f, "(after {:?})", call_idx)?;
+        }
         Ok(())
     }
 }
@@ -345,6 +345,7 @@ mod passes {
             Pass::Single(Box::new(super::music::remove_chunks_exceeding_max_count)),
             // Required chunk optimisation
             mark_single_start_or_end_as_required(),
+            mark_only_chunks_in_required_courses_as_required(),
             remove_chunks_false_against_required(),
             // Misc optimisations
             remove_links_between_false_chunks(),
@@ -407,7 +408,7 @@ mod passes {
                 let expanded_chunk_distances = super::compute_distances(
                     view.starts().iter().map(|(_, chunk_id)| chunk_id),
                     &view,
-                    Some(query.max_length()),
+                    Some(query.max_block_length()),
                 );
                 // Set the chunk distances and strip out unreachable chunks
                 view.retain_chunks(
@@ -432,7 +433,7 @@ mod passes {
                     let min_comp_length_with_chunk = chunk.lb_distance_from_rounds
                         + chunk.total_length
                         + chunk.lb_distance_to_rounds;
-                    min_comp_length_with_chunk <= query.max_length()
+                    min_comp_length_with_chunk <= query.max_block_length()
                 });
             },
         ))
@@ -454,6 +455,34 @@ mod passes {
         }))
     }
 
+    /// A [`Pass`] which checks each of [`Parameters::required_courses`] and, if only one chunk
+    /// contains any leads of those courses, marks that chunk as required.
+    ///
+    /// [`Parameters::required_courses`]: crate::parameters::Parameters::required_courses
+    fn mark_only_chunks_in_required_courses_as_required() -> Pass {
+        Pass::Single(Box::new(|graph: &mut Graph, query: &Query, _| {
+            // A required course only has to appear in one extent of a multi-extent composition,
+            // so the other extents can still be false against it
+            if query.multi_extent {
+                return;
+            }
+            for idx in 0..query.required_courses.len() {
+                let single_chunk_id = match graph
+                    .chunks
+                    .iter()
+                    .filter(|(_id, chunk)| chunk.required_course_leads[idx] > 0)
+                    .exactly_one()
+                {
+                    Ok((id, _chunk)) => id.clone(),
+                    Err(_) => continue,
+                };
+                if let Some(chunk) = graph.chunks.get_mut(&single_chunk_id) {
+                    chunk.required = true;
+                }
+            }
+        }))
+    }
+
     /// A [`Pass`] which removes any chunks which are false against a chunk marked as required
     fn remove_chunks_false_against_required() -> Pass {
         Pass::Single(Box::new(|graph: &mut Graph, _, _| {
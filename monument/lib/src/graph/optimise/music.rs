@@ -15,17 +15,22 @@ const ITERATION_LIMIT: usize = 10_000;
 pub(super) fn required_music_min(graph: &mut Graph, query: &Query, _ranges: &RefinedRanges) {
     // log::debug!("\n\n\n");
 
-    // For each `MusicType`, maps its index to minimum count
+    // For each `MusicType`, maps its index to minimum count.  Wraps can also happen between chunks,
+    // so chunks aren't the only source of wraps and can't be required to produce them
     let min_music_counts = query
         .music_types
         .iter()
         .enumerate()
+        .filter(|(_, ty)| !ty.wraps)
         .filter_map(|(i, ty)| ty.count_range.min.map(|min| (i, min)))
         .collect::<HashMap<_, _>>();
 
     if min_music_counts.is_empty() {
         return; // If there are no music bounds, then there's nothing to do
     }
+    if query.multi_extent {
+        return; // Chunks can be repeated in different extents, so can't be combined like this
+    }
 
     // The `(ChunkId, Chunk)` pairs of chunks which contribute to the types of music that we care
     // about.
@@ -1,11 +1,22 @@
-use std::ops::{Deref, Mul, Not};
+use std::{
+    collections::HashSet,
+    ops::{Deref, Mul, Not},
+};
 
 use bellframe::{Bell, Row, RowBuf, Stage};
 use datasize::DataSize;
 use gcd::Gcd;
 
-/// A group of [`Row`]s, used to represent part heads.  Currently limited to cyclic groups (in the
-/// mathematical sense of 'cyclic').
+use crate::Error;
+
+/// The largest number of parts that a [`PartHeadGroup`] can have
+const MAX_PARTS: usize = 64;
+
+/// A group of [`Row`]s, used to represent part heads.
+///
+/// Every part of a composition uses the same calling, so its part heads are always the powers of
+/// the row which ends the first part.  Therefore, a `PartHeadGroup` must be cyclic (in the
+/// mathematical sense of 'cyclic'), even if it's specified by several generators.
 #[derive(Debug, Clone)]
 pub struct PartHeadGroup {
     /// The [`Row`]s which make up the `PartHeadGroup`.  `part_heads[0]` is always rounds.
@@ -19,14 +30,14 @@ pub struct PartHeadGroup {
 }
 
 /// A compact representation of a single `PartHead` within a [`PartHeadGroup`]
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, DataSize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, DataSize)]
 pub struct PartHead {
     /// The index into the owning [`PartHeadGroup`]'s `part_heads` list.
     index: u8,
 }
 
 /// A compact representation of a transformation between two `PartHead`s
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct PhRotation {
     /// The index into the owning [`PartHeadGroup`]'s `part_heads` list.
     rotation: u8,
@@ -44,6 +55,62 @@ impl PartHeadGroup {
         }
     }
 
+    /// Create a new `PartHeadGroup` containing every [`Row`] generated by any combination of
+    /// `generators`.  For example, `134256` and `123465` generate a 6-part group.
+    ///
+    /// This returns an error if the resulting group has too many parts, or if it isn't cyclic
+    /// (because then no part head would generate all the others).
+    pub fn from_generators(stage: Stage, generators: &[RowBuf]) -> crate::Result<Self> {
+        let mut rows = vec![RowBuf::rounds(stage)];
+        let mut rows_seen = rows.iter().cloned().collect::<HashSet<_>>();
+        let mut next_idx = 0;
+        while let Some(row) = rows.get(next_idx) {
+            let products = generators.iter().map(|gen| row * gen).collect::<Vec<_>>();
+            for product in products {
+                if rows_seen.insert(product.clone()) {
+                    rows.push(product);
+                }
+            }
+            if rows.len() > MAX_PARTS {
+                return Err(Error::TooManyPartHeads { limit: MAX_PARTS });
+            }
+            next_idx += 1;
+        }
+        // The group is cyclic iff one of its rows generates every other row
+        match rows.iter().find(|r| r.order() == rows.len()) {
+            Some(generator) => Ok(Self::new(generator)),
+            None => Err(Error::NonCyclicPartHeads {
+                num_parts: rows.len(),
+            }),
+        }
+    }
+
+    /// Create a new `PartHeadGroup` from an explicit list of part heads, checking that they
+    /// really do form a group.  Rounds is always added, and the order of the `rows` doesn't
+    /// matter.
+    pub fn from_rows(stage: Stage, rows: &[RowBuf]) -> crate::Result<Self> {
+        let mut all_rows = vec![RowBuf::rounds(stage)];
+        for r in rows {
+            if !all_rows.contains(r) {
+                all_rows.push(r.clone());
+            }
+        }
+        // A finite set of rows is a group iff it's closed under multiplication
+        for lhs in &all_rows {
+            for rhs in &all_rows {
+                let product = lhs * rhs;
+                if !all_rows.contains(&product) {
+                    return Err(Error::PartHeadsNotClosed {
+                        lhs: lhs.clone(),
+                        rhs: rhs.clone(),
+                        product,
+                    });
+                }
+            }
+        }
+        Self::from_generators(stage, &all_rows)
+    }
+
     /// Create a new `PartHeadGroup` containing only one part.
     pub fn one_part(stage: Stage) -> Self {
         Self::new(&RowBuf::rounds(stage))
@@ -91,9 +158,12 @@ impl PartHeadGroup {
 
     /// Return a set of groups of [`Bell`]s which share paths through the compositions.
     ///
-    /// For example, a composition with `parthead = "134265"` will return
-    /// `[[1], [2, 3, 4], [5, 6], [7], [8]]`.  The ordering of the [`Bell`]s and groups is
-    /// non-deterministic.
+    /// For example, a composition with `parthead = "134265"` (or with the part heads generated by
+    /// `134256` and `123465`) will return `[[1], [2, 3, 4], [5, 6], [7], [8]]`.  The ordering of
+    /// the [`Bell`]s and groups is non-deterministic.
+    ///
+    /// These are the orbits of the [`Bell`]s under the whole group but, because the group is
+    /// cyclic, they're also the cycles of any part head which generates it.
     pub fn bell_cycles(&self) -> Vec<Vec<Bell>> {
         let base_part_head = self.part_heads.last().unwrap();
 
@@ -118,11 +188,23 @@ impl PartHeadGroup {
         groups
     }
 
+    /// Returns `true` if the given [`Bell`] is fixed by every part head
+    pub fn is_fixed(&self, bell: Bell) -> bool {
+        self.rows().all(|r| r.is_fixed(bell))
+    }
+
     /// Given an abstract [`PartHead`], return the corresponding [`Row`]
     pub fn get_row(&self, element: PartHead) -> &Row {
         &self.part_heads[element.index as usize]
     }
 
+    /// Given a [`Row`], return the corresponding [`PartHead`] (or `None` if that [`Row`] isn't in
+    /// this group)
+    pub(crate) fn get_part_head(&self, row: &Row) -> Option<PartHead> {
+        let index = self.part_heads.iter().position(|ph| ph.as_row() == row)?;
+        Some(PartHead { index: index as u8 })
+    }
+
     /// Returns `true` iff every [`PartHead`] in this group is a power of the given `part_head`
     pub fn is_generator(&self, part_head: PartHead) -> bool {
         self.is_generator_bitmap & (1 << part_head.index) != 0
@@ -181,3 +263,34 @@ fn coprime_bitmap(n: usize) -> u64 {
     }
     mask
 }
+
+#[cfg(test)]
+mod tests {
+    use bellframe::{RowBuf, Stage};
+
+    use super::PartHeadGroup;
+    use crate::Error;
+
+    fn rows(strs: &[&str]) -> Vec<RowBuf> {
+        strs.iter().map(|s| RowBuf::parse(s).unwrap()).collect()
+    }
+
+    #[test]
+    fn from_generators() {
+        let group = PartHeadGroup::from_generators(Stage::MINOR, &rows(&["134256", "123465"]));
+        assert_eq!(group.unwrap().size(), 6);
+        let group = PartHeadGroup::from_generators(Stage::MINOR, &rows(&["134256", "132456"]));
+        assert!(matches!(
+            group,
+            Err(Error::NonCyclicPartHeads { num_parts: 6 })
+        ));
+    }
+
+    #[test]
+    fn from_rows() {
+        let group = PartHeadGroup::from_rows(Stage::MINOR, &rows(&["134256", "142356"]));
+        assert_eq!(group.unwrap().size(), 3);
+        let group = PartHeadGroup::from_rows(Stage::MINOR, &rows(&["134256"]));
+        assert!(matches!(group, Err(Error::PartHeadsNotClosed { .. })));
+    }
+}
@@ -44,15 +44,19 @@
 mod atw;
 mod composition;
 mod error;
+mod export;
 mod graph;
 mod group;
 pub mod parameters;
 mod prove_length;
 mod query;
+pub mod scoring;
 mod search;
+#[cfg(test)]
+mod test_utils;
 pub mod utils;
 
-pub use composition::Composition;
+pub use composition::{Composition, Falseness, ScoreBreakdown};
 pub use error::{Error, Result};
 pub use group::PartHeadGroup;
-pub use search::{Config, Progress, Search, Update};
+pub use search::{Config, Optimality, Progress, Search, SearchHandle, SearchStrategy, Update};
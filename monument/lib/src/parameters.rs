@@ -4,16 +4,20 @@ use std::{
     collections::HashSet,
     marker::PhantomData,
     ops::{Range, RangeInclusive},
-    sync::atomic::AtomicBool,
+    sync::{atomic::AtomicBool, Arc},
 };
 
 use bellframe::{
-    method::LABEL_LEAD_END, music::Pattern, Bell, Mask, PlaceNot, RowBuf, Stage, Stroke,
+    method::{LABEL_LEAD_END, LABEL_SIX_END},
+    music::Pattern,
+    Bell, Mask, PlaceNot, PnBlock, Row, RowBuf, Stage, Stroke,
 };
 use itertools::Itertools;
 
 use crate::{
+    composition::Falseness,
     group::PartHeadGroup,
+    scoring::Scorer,
     utils::lengths::{PerPartLength, TotalLength},
     Composition, Config, Search, Update,
 };
@@ -29,13 +33,32 @@ pub struct Parameters {
     pub length: RangeInclusive<TotalLength>,
     pub stage: Stage,
     pub num_comps: usize,
+    /// If non-zero, a composition is only generated if its [distance](Composition::distance_to)
+    /// to every composition generated before it is at least this large.  Rejected compositions
+    /// don't count towards `num_comps`.
+    pub min_comp_distance: usize,
     pub require_truth: bool,
+    /// If `true`, the composition is made of several extents, each of which must come round and
+    /// (if `require_truth` is set) be true within itself.  Rows can be repeated between different
+    /// extents.
+    pub multi_extent: bool,
+    /// Limits on how false the composition can be when `require_truth` is `false`.  In a
+    /// multi-extent composition, rows only count as false if they're repeated within one extent.
+    pub falseness_limit: FalsenessLimit,
 
     // METHODS & CALLING
     pub maybe_unused_methods: Vec<Method>,
     pub splice_style: SpliceStyle,
     pub splice_weight: f32, // TODO: Do we need so many instances of 'Score'
+    /// Range of the number of changes of method (summed over all parts) allowed in each
+    /// composition
+    pub com_range: OptionalRangeInclusive,
+    /// Score applied for every row by which the method counts differ from an even balance.  A
+    /// negative weight encourages even method balance.
+    pub method_balance_weight: f32,
     pub maybe_unused_calls: Vec<Call>,
+    /// Range of the total number of calls (summed over all parts) allowed in each composition
+    pub call_count_range: OptionalRangeInclusive,
     pub call_display_style: CallDisplayStyle, // TODO: Make this defined per-method?
     pub atw_weight: Option<f32>,
     pub require_atw: bool, // `true` to make Monument only output atw comps
@@ -50,6 +73,8 @@ pub struct Parameters {
     /// [`Score`]s applied to every row in every course containing a lead head matching the
     /// corresponding [`Mask`].
     pub course_weights: Vec<(Mask, f32)>,
+    /// Sets of courses which must each appear in every composition
+    pub required_courses: Vec<RequiredCourses>,
 
     // NON-DUFFERS
     pub max_contiguous_duffer: Option<PerPartLength>,
@@ -61,6 +86,12 @@ pub struct Parameters {
     /// `self.start_row`
     // TODO: Compute this automatically from sub-lead index
     pub start_stroke: Stroke,
+
+    // SCORING
+    /// Gives the score of every [`Row`](bellframe::Row) and transition in the composition.  Use
+    /// [`DefaultScorer`](crate::scoring::DefaultScorer) to score with the weights given in these
+    /// `Parameters`.
+    pub scorer: Arc<dyn Scorer>,
 }
 
 impl Parameters {
@@ -87,6 +118,29 @@ impl Parameters {
         *self.length.end()
     }
 
+    /// The number of [`Row`](bellframe::Row)s in each extent of a
+    /// [`multi_extent`](Self::multi_extent) composition, or `None` if the composition isn't
+    /// multi-extent.
+    pub fn extent_length(&self) -> Option<TotalLength> {
+        let extent_length = self.stage.extent_length().unwrap_or(usize::MAX);
+        self.multi_extent.then_some(TotalLength::new(extent_length))
+    }
+
+    /// The longest piece of ringing which can be rung between leaving and coming back to the
+    /// start (i.e. the whole composition, or a single extent of a multi-extent composition).
+    pub(crate) fn max_block_length(&self) -> TotalLength {
+        match self.extent_length() {
+            Some(extent_length) => extent_length.min(self.max_length()),
+            None => self.max_length(),
+        }
+    }
+
+    /// Returns `true` if the composition can be false, but only up to the
+    /// [`falseness_limit`](Self::falseness_limit)
+    pub fn is_falseness_limited(&self) -> bool {
+        !self.require_truth && self.falseness_limit.is_set()
+    }
+
     pub fn is_spliced(&self) -> bool {
         self.methods_used() > 1
     }
@@ -160,6 +214,13 @@ pub enum SpliceStyle {
     /// Splices could happen at any lead label (usually just
     /// [lead ends](bellframe::method::LABEL_LEAD_END)).
     LeadLabels,
+    /// Plain splices only happen at [half leads](bellframe::method::LABEL_HALF_LEAD).  Splices
+    /// can still happen at any call.
+    HalfLeads,
+    /// Plain splices only happen at [lead ends](bellframe::method::LABEL_LEAD_END) where the new
+    /// lead head is a course head (i.e. it satisfies one of the [`Method::allowed_courses`]).
+    /// Splices can still happen at any call.
+    CourseHeads,
     /// Splices only happen at calls.
     Calls,
 }
@@ -182,6 +243,14 @@ impl Method {
     pub fn add_sub_lead_idx(&self, sub_lead_idx: usize, len: PerPartLength) -> usize {
         (sub_lead_idx + len.as_usize()) % self.lead_len()
     }
+
+    /// Returns `true` if the row at `sub_lead_idx` (modulo the lead length) is the first row after
+    /// a [six end](LABEL_SIX_END)
+    pub(crate) fn is_six_end(&self, sub_lead_idx: usize) -> bool {
+        self.get_labels(sub_lead_idx % self.lead_len())
+            .iter()
+            .any(|label| label == LABEL_SIX_END)
+    }
 }
 
 impl std::ops::Deref for Method {
@@ -213,16 +282,29 @@ pub struct Call {
 
     pub symbol: String,
     pub calling_positions: Vec<String>,
+    /// The calling positions (from `calling_positions`) where this call can be used.  If empty,
+    /// the call can be used at any calling position.
+    pub allowed_calling_positions: Vec<String>,
+    /// The calling positions (from `calling_positions`) where this call can't be used
+    pub forbidden_calling_positions: Vec<String>,
 
     pub label_from: String,
     pub label_to: String,
-    // TODO: Allow calls to cover multiple PNs (e.g. singles in Grandsire)
-    pub place_notation: PlaceNot,
+    /// The changes which this call replaces.  The last of these is rung immediately before the
+    /// `label_to` row, and the rest replace the changes leading up to it (for example, `3.123` for
+    /// Grandsire singles).
+    pub place_notation: PnBlock,
 
     pub weight: f32,
+    /// Extra weights applied to this call in specific regions of the composition.  A call's
+    /// position is that of the last [`Row`](bellframe::Row) before the call takes effect.
+    pub weight_schedule: Vec<ScheduledWeight>,
+    /// Range of the number of instances of this call (summed over all parts) allowed in each
+    /// composition
+    pub count_range: OptionalRangeInclusive,
 }
 
-#[derive(Debug, Clone, Copy, Hash)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct CallId(pub u16);
 
 impl From<u16> for CallId {
@@ -244,6 +326,10 @@ pub enum CallDisplayStyle {
     Positional,
     /// Calls should be displayed based on the position of the provided 'observation' [`Bell`].
     CallingPositions(Bell),
+    /// Calls should be displayed as the number of the six they're made in, counting from the start
+    /// of each part (e.g. `5 s11 12 13 s17`).  This is the usual style for principles like Stedman,
+    /// and requires the methods to have [six end labels](bellframe::method::LABEL_SIX_END).
+    Sixes,
 }
 
 impl Call {
@@ -257,18 +343,70 @@ impl Call {
         }
     }
 
+    /// Returns `true` if this call can only be used at some of its calling positions
+    pub(crate) fn has_restricted_positions(&self) -> bool {
+        !self.allowed_calling_positions.is_empty() || !self.forbidden_calling_positions.is_empty()
+    }
+
+    /// Returns `true` if this call can be used when it puts the calling bell into the given
+    /// `place` (i.e. at calling position `self.calling_positions[place]`)
+    pub(crate) fn is_allowed_at_place(&self, place: usize) -> bool {
+        let position = &self.calling_positions[place];
+        let is_allowed = self.allowed_calling_positions.is_empty()
+            || self.allowed_calling_positions.contains(position);
+        is_allowed && !self.forbidden_calling_positions.contains(position)
+    }
+
+    /// The number of [`Row`]s rung in the middle of this call, which therefore don't belong to
+    /// any lead of any method.  This is one less than the number of changes the call covers.
+    pub(crate) fn num_covered_rows(&self) -> usize {
+        self.place_notation.len() - 1
+    }
+
+    /// Given the first [`Row`] after this call, return the [`Row`]s rung in the middle of it (see
+    /// [`Self::num_covered_rows`]).
+    pub(crate) fn covered_rows(&self, row_after_call: &Row) -> Vec<RowBuf> {
+        let row_before_call = row_after_call * &self.place_notation.transposition().inv();
+        let rows = self.place_notation.to_rows(row_before_call).unwrap();
+        rows.iter()
+            .skip(1)
+            .take(self.num_covered_rows())
+            .map(Row::to_owned)
+            .collect()
+    }
+
     /// Create a [`parameters::Call`] which replaces the lead end with a given [`PlaceNot`]
     pub fn lead_end_call(id: CallId, place_not: PlaceNot, symbol: &str, weight: f32) -> Self {
+        Self::single_change_call(id, place_not, symbol, weight, LABEL_LEAD_END)
+    }
+
+    /// Create a [`Call`] which replaces the change before every [`LABEL_SIX_END`]
+    /// with a given [`PlaceNot`]
+    pub fn six_end_call(id: CallId, place_not: PlaceNot, symbol: &str, weight: f32) -> Self {
+        Self::single_change_call(id, place_not, symbol, weight, LABEL_SIX_END)
+    }
+
+    fn single_change_call(
+        id: CallId,
+        place_not: PlaceNot,
+        symbol: &str,
+        weight: f32,
+        label: &str,
+    ) -> Self {
         Self {
             id,
             used: true,
 
             symbol: symbol.to_owned(),
             calling_positions: default_calling_positions(&place_not),
-            label_from: LABEL_LEAD_END.to_owned(),
-            label_to: LABEL_LEAD_END.to_owned(),
-            place_notation: place_not,
+            allowed_calling_positions: Vec::new(),
+            forbidden_calling_positions: Vec::new(),
+            label_from: label.to_owned(),
+            label_to: label.to_owned(),
+            place_notation: PnBlock::from_vec(vec![place_not]).unwrap(),
             weight,
+            weight_schedule: Vec::new(),
+            count_range: OptionalRangeInclusive::OPEN,
         }
     }
 }
@@ -280,6 +418,9 @@ pub enum BaseCallType {
     Near,
     /// `1<n-2>` bobs and `1<n-2><n-1><n>` singles
     Far,
+    /// `<n-2>` bobs and `<n-2><n-1><n>` singles, made at every
+    /// [six end](bellframe::method::LABEL_SIX_END) (as in Stedman)
+    SixEnd,
 }
 
 /// Default weight given to bobs.
@@ -298,19 +439,20 @@ pub fn base_calls(
 ) -> Vec<Call> {
     let n = stage.num_bells_u8();
 
+    let new_call = match type_ {
+        BaseCallType::Near | BaseCallType::Far => Call::lead_end_call,
+        BaseCallType::SixEnd => Call::six_end_call,
+    };
+
     let mut calls = Vec::new();
     // Add bob
     if let Some(bob_weight) = bob_weight {
         let bob_pn = match type_ {
             BaseCallType::Near => PlaceNot::parse("14", stage).unwrap(),
             BaseCallType::Far => PlaceNot::from_slice(&mut [0, n - 3], stage).unwrap(),
+            BaseCallType::SixEnd => PlaceNot::from_slice(&mut [n - 3], stage).unwrap(),
         };
-        calls.push(Call::lead_end_call(
-            id_generator.next(),
-            bob_pn,
-            "-",
-            bob_weight,
-        ));
+        calls.push(new_call(id_generator.next(), bob_pn, "-", bob_weight));
     }
     // Add single
     if let Some(single_weight) = single_weight {
@@ -319,13 +461,11 @@ pub fn base_calls(
             BaseCallType::Far => {
                 PlaceNot::from_slice(&mut [0, n - 3, n - 2, n - 1], stage).unwrap()
             }
+            BaseCallType::SixEnd => {
+                PlaceNot::from_slice(&mut [n - 3, n - 2, n - 1], stage).unwrap()
+            }
         };
-        calls.push(Call::lead_end_call(
-            id_generator.next(),
-            single_pn,
-            "s",
-            single_weight,
-        ));
+        calls.push(new_call(id_generator.next(), single_pn, "s", single_weight));
     }
 
     calls
@@ -408,21 +548,128 @@ pub struct MusicType {
     pub used: bool,
 
     pub patterns: Vec<Pattern>,
+    /// If `true`, `patterns` match [wraps](Pattern::num_wraps) from one row into the next, rather
+    /// than single rows.  Wraps are counted on the row where they start, so `strokes` refers to
+    /// the stroke of that row.  Wraps are always scored using `weight`, on top of the
+    /// [`Scorer`]'s scores.
+    pub wraps: bool,
     pub strokes: StrokeSet,
     pub weight: f32,
+    /// Extra weights applied to instances of this music in specific regions of the composition
+    pub weight_schedule: Vec<ScheduledWeight>,
+    /// Extra score given to the total number of instances of this music, on top of `weight`
+    pub count_score: Option<CountScore>,
     pub count_range: OptionalRangeInclusive,
 }
 
 impl MusicType {
     /// Return the total number of possible instances of this music type, or `None` if the
-    /// computation caused `usize` to overflow.
+    /// computation caused `usize` to overflow or this music type counts [wraps](Self::wraps)
+    /// (which aren't limited by the number of rows).
     pub(crate) fn max_count(&self) -> Option<usize> {
+        if self.wraps {
+            return None;
+        }
         let mut sum = 0;
         for r in &self.patterns {
             sum += r.num_matching_rows()?;
         }
         Some(sum)
     }
+
+    /// The number of instances of this music type in `row`, which is rung at `stroke`.  For
+    /// [wraps](Self::wraps), these are the wraps from `row` into `next_row` (or none if `row` is
+    /// the last row).
+    pub(crate) fn num_instances(&self, row: &Row, next_row: Option<&Row>, stroke: Stroke) -> usize {
+        if !self.strokes.contains(stroke) {
+            return 0;
+        }
+        // Unwraps are safe because `patterns` must have the same `Stage` as the rows
+        match (self.wraps, next_row) {
+            (false, _) => (self.patterns.iter())
+                .filter(|pattern| pattern.matches(row).unwrap())
+                .count(),
+            (true, Some(next_row)) => (self.patterns.iter())
+                .map(|pattern| pattern.num_wraps(row, next_row).unwrap())
+                .sum(),
+            (true, None) => 0,
+        }
+    }
+
+    /// The score given by [`count_score`](Self::count_score) to a total of `count` instances
+    pub fn count_score(&self, count: usize) -> f32 {
+        self.count_score.as_ref().map_or(0.0, |f| f.score(count))
+    }
+}
+
+/// A score given to the total number of instances of a [`MusicType`] in a composition, allowing
+/// music to be scored non-linearly (e.g. a bonus for getting all 24 5678 combinations, or
+/// diminishing returns on 4-bell runs).
+///
+/// This is a piecewise-linear function through a set of `(count, score)` points.  Counts below the
+/// first point or above the last point get the score of that point, so the function is flat
+/// outside the points.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CountScore {
+    /// The `(count, score)` points, sorted by count
+    points: Vec<(usize, f32)>,
+}
+
+impl CountScore {
+    /// Create a `CountScore` which linearly interpolates between some `(count, score)` points.  If
+    /// `points` is empty, every count scores `0.0`.
+    pub fn piecewise(mut points: Vec<(usize, f32)>) -> Self {
+        points.sort_by_key(|&(count, _)| count);
+        Self { points }
+    }
+
+    /// Create a `CountScore` which gives `score` to any count of at least `count`, and `0.0` to
+    /// anything less.
+    pub fn threshold(count: usize, score: f32) -> Self {
+        match count.checked_sub(1) {
+            Some(count_below) => Self::piecewise(vec![(count_below, 0.0), (count, score)]),
+            None => Self::piecewise(vec![(0, score)]),
+        }
+    }
+
+    pub fn points(&self) -> &[(usize, f32)] {
+        &self.points
+    }
+
+    /// The score given to a total of `count` instances
+    pub fn score(&self, count: usize) -> f32 {
+        let (first, last) = match (self.points.first(), self.points.last()) {
+            (Some(first), Some(last)) => (first, last),
+            _ => return 0.0,
+        };
+        if count <= first.0 {
+            return first.1;
+        }
+        if count >= last.0 {
+            return last.1;
+        }
+        // Find the segment containing `count`, and interpolate along it
+        let (&(c1, s1), &(c2, s2)) = self
+            .points
+            .iter()
+            .tuple_windows()
+            .find(|(_, &(c2, _))| count < c2)
+            .unwrap();
+        let fraction = (count - c1) as f32 / (c2 - c1) as f32;
+        s1 + (s2 - s1) * fraction
+    }
+
+    /// An upper bound on the extra score which can be gained if the count increases from
+    /// `count`.  This is never negative.
+    pub(crate) fn max_gain_from(&self, count: usize) -> f32 {
+        let current_score = self.score(count);
+        // The function is piecewise-linear, so its maximum is at one of the points
+        let max_later_score = (self.points.iter())
+            .filter(|&&(c, _)| c > count)
+            .map(|&(_, s)| s)
+            .fold(current_score, f32::max);
+        max_later_score - current_score
+    }
 }
 
 /// A set of at least one [`Stroke`]
@@ -476,6 +723,83 @@ pub struct CourseSet {
     pub any_bells: bool,
 }
 
+/// An extra weight applied to music or calls within some region of each part of a composition.
+/// For example, music in the last two courses of Major could be boosted by setting `start` to
+/// `FromEnd(224)` and `end` to `FromEnd(0)`.
+#[derive(Debug, Clone)]
+pub struct ScheduledWeight {
+    /// The first position in each part where this weight applies
+    pub start: RowPosition,
+    /// The first position in each part (after `start`) where this weight **doesn't** apply
+    pub end: RowPosition,
+    /// The (0-indexed) parts in which this weight applies.  If empty, it applies in every part.
+    pub parts: Vec<usize>,
+    /// Weight applied to each instance in this region, on top of the usual weight
+    pub weight: f32,
+}
+
+/// The position of a [`Row`](bellframe::Row) within one part of a composition
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RowPosition {
+    /// The given number of rows after the start of the part (so `FromStart(0)` is the part's first
+    /// row)
+    FromStart(usize),
+    /// The given number of rows before the end of the part (so `FromEnd(0)` is just after the
+    /// part's last row)
+    FromEnd(usize),
+}
+
+impl ScheduledWeight {
+    /// Returns `true` if this weight can only be applied once the composition is complete (i.e. if
+    /// it depends on the length of the part, or on the order that the parts are rung).
+    pub(crate) fn is_deferred(&self) -> bool {
+        !self.parts.is_empty()
+            || matches!(self.start, RowPosition::FromEnd(_))
+            || matches!(self.end, RowPosition::FromEnd(_))
+    }
+
+    /// The weight applied at `position` in the `part`th part, where each part is `part_len` rows
+    /// long
+    pub(crate) fn weight_at(&self, part: usize, position: usize, part_len: usize) -> f32 {
+        let in_part = self.parts.is_empty() || self.parts.contains(&part);
+        let in_region = (self.start.index(part_len)..self.end.index(part_len)).contains(&position);
+        match in_part && in_region {
+            true => self.weight,
+            false => 0.0,
+        }
+    }
+
+    /// An upper bound on the number of rows covered by this weight in each part
+    pub(crate) fn max_rows_per_part(&self, max_part_len: usize) -> usize {
+        use RowPosition::*;
+        match (self.start, self.end) {
+            (FromStart(s), FromStart(e)) => e.saturating_sub(s),
+            (FromEnd(s), FromEnd(e)) => s.saturating_sub(e),
+            _ => max_part_len,
+        }
+    }
+}
+
+impl RowPosition {
+    /// The index of this position within a part of length `part_len`
+    fn index(self, part_len: usize) -> usize {
+        match self {
+            Self::FromStart(n) => n,
+            Self::FromEnd(n) => part_len.saturating_sub(n),
+        }
+    }
+}
+
+/// A [`CourseSet`] which must appear in every composition, as set by
+/// [`Parameters::required_courses`].
+#[derive(Debug, Clone)]
+pub struct RequiredCourses {
+    pub courses: CourseSet,
+    /// The minimum number of leads (summed over all parts) which must be rung in `courses`.  A
+    /// lead is only counted if its lead head is part of the composition.
+    pub min_leads: usize,
+}
+
 impl CourseSet {
     /// Convert many `CourseSet`s into the corresponding lead head [`Mask`]s.
     pub(crate) fn to_lead_masks(
@@ -597,6 +921,32 @@ fn try_fixing_bells(mut mask: Mask, fixed_bells: &[(Bell, usize)]) -> Option<Mas
     Some(mask)
 }
 
+/// Limits on how many times [`Row`]s can be repeated in a composition which doesn't have to be
+/// true.  The [`Default`] value allows any amount of falseness.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct FalsenessLimit {
+    /// The most times that any one [`Row`] can appear in the composition
+    pub max_row_occurrences: Option<usize>,
+    /// The most rows which can be repeats of earlier rows
+    pub max_false_rows: Option<usize>,
+}
+
+impl FalsenessLimit {
+    /// Returns `true` if at least one of the limits is set
+    pub fn is_set(self) -> bool {
+        self.max_row_occurrences.is_some() || self.max_false_rows.is_some()
+    }
+
+    /// Returns `true` if a composition with the given [`Falseness`] fits within these limits
+    pub fn allows(self, falseness: Falseness) -> bool {
+        self.max_row_occurrences
+            .is_none_or(|max| falseness.max_row_occurrences <= max)
+            && self
+                .max_false_rows
+                .is_none_or(|max| falseness.false_rows <= max)
+    }
+}
+
 /// An inclusive range where each side is optionally bounded.
 ///
 /// This is essentially a combination of [`RangeInclusive`](std::ops::RangeInclusive)
@@ -621,6 +971,11 @@ impl OptionalRangeInclusive {
         self.min.is_some() || self.max.is_some()
     }
 
+    /// Returns `true` if `value` is within this range
+    pub fn contains(self, value: usize) -> bool {
+        self.min.is_none_or(|min| value >= min) && self.max.is_none_or(|max| value <= max)
+    }
+
     /// Applies [`Option::or`] to both `min` and `max`
     pub fn or(self, other: Self) -> Self {
         Self {
@@ -716,4 +1071,19 @@ mod tests {
             assert_eq!(positions, *exp_positions);
         }
     }
+
+    #[test]
+    fn count_score() {
+        let diminishing = super::CountScore::piecewise(vec![(20, 20.0), (0, 0.0), (60, 30.0)]);
+        assert_eq!(diminishing.score(10), 10.0);
+        assert_eq!(diminishing.score(40), 25.0);
+        assert_eq!(diminishing.score(100), 30.0);
+        assert_eq!(diminishing.max_gain_from(40), 5.0);
+
+        let bonus = super::CountScore::threshold(24, 50.0);
+        assert_eq!(bonus.score(23), 0.0);
+        assert_eq!(bonus.score(24), 50.0);
+        assert_eq!(bonus.max_gain_from(0), 50.0);
+        assert_eq!(bonus.max_gain_from(24), 0.0);
+    }
 }
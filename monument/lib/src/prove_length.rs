@@ -5,7 +5,7 @@ use std::{
     time::Instant,
 };
 
-use bellframe::Mask;
+use bellframe::{Mask, RowBuf};
 use itertools::Itertools;
 
 use crate::{
@@ -29,6 +29,8 @@ pub(crate) struct RefinedRanges {
 pub(crate) fn prove_lengths(graph: &Graph, query: &Query) -> crate::Result<RefinedRanges> {
     log::debug!("Proving lengths");
 
+    check_required_courses(graph, query)?;
+
     /* TOTAL LENGTH */
 
     // Work out which lengths are possible
@@ -97,6 +99,45 @@ pub(crate) fn prove_lengths(graph: &Graph, query: &Query) -> crate::Result<Refin
     })
 }
 
+//////////////////////
+// REQUIRED COURSES //
+//////////////////////
+
+/// Check that every one of [`Parameters::required_courses`] could appear in enough leads.  If the
+/// composition must be true then each lead head can be rung at most once, so the number of leads
+/// is bounded by the number of distinct lead heads in the graph.
+///
+/// [`Parameters::required_courses`]: crate::parameters::Parameters::required_courses
+fn check_required_courses(graph: &Graph, query: &Query) -> crate::Result<()> {
+    // Chunks can overlap (and so share leads), so count each lead head once
+    let mut lead_heads = vec![HashSet::<RowBuf>::new(); query.required_courses.len()];
+    for (id, chunk) in &graph.chunks {
+        for (idx, lead_head) in query.required_course_lead_heads(id, chunk.per_part_length) {
+            lead_heads[idx].insert(lead_head);
+        }
+    }
+
+    for (required, lead_heads) in query.required_courses.iter().zip_eq(&lead_heads) {
+        // Chunks can be repeated in different extents of a multi-extent composition
+        let max_leads = match query.require_truth && !query.multi_extent {
+            true => lead_heads.len(),
+            // False compositions can repeat chunks, so any lead can be rung arbitrarily often
+            false => match lead_heads.is_empty() {
+                false => usize::MAX,
+                true => 0,
+            },
+        };
+        if max_leads < required.min_leads {
+            return Err(crate::Error::UnachievableRequiredCourse {
+                course_masks: required.courses.masks.clone(),
+                min_leads: required.min_leads,
+                max_leads,
+            });
+        }
+    }
+    Ok(())
+}
+
 /////////////////////////
 // REFINE TOTAL LENGTH //
 /////////////////////////
@@ -155,7 +196,7 @@ fn possible_lengths(graph: &Graph, query: &Query) -> Vec<TotalLength> {
                 }
                 // If this is the first end-length to be too long, then there's no point
                 // continuing the search (which otherwise would never finish)
-                if length > query.max_length() {
+                if length > query.max_block_length() {
                     break;
                 }
             }
@@ -171,6 +212,18 @@ fn possible_lengths(graph: &Graph, query: &Query) -> Vec<TotalLength> {
 
     log::debug!("  Lengths computed in {:.2?}", start.elapsed());
 
+    // In a multi-extent composition, every block must be exactly one extent long.  So the
+    // composition can be any (non-zero) number of extents, but only if an extent is possible.
+    if let Some(extent_length) = query.extent_length() {
+        let num_extents = match total_lengths.contains(&extent_length) {
+            true => query.max_length().as_usize() / extent_length.as_usize() + 1,
+            false => 0,
+        };
+        total_lengths = (1..=num_extents)
+            .map(|n| TotalLength::new(extent_length.as_usize() * n))
+            .collect_vec();
+    }
+
     total_lengths
 }
 
@@ -383,7 +436,7 @@ fn possible_method_counts(
             continue; // Don't bother adding to a length we've seen before
         }
         counts.push(count);
-        if count > query.max_length() {
+        if count > query.max_block_length() {
             break; // Stop searching as soon as we find one length that's above our limit
         }
         // Add every possible interior length to this
@@ -403,11 +456,36 @@ fn possible_method_counts(
         counts.push(TotalLength::ZERO);
     }
 
+    // In a multi-extent composition, the method's count is the sum of its counts in each extent
+    if query.multi_extent {
+        counts = sums_of_counts(&counts, query.max_length());
+    }
+
     log::trace!("  Final counts: {:?}", counts);
 
     counts
 }
 
+/// Compute every possible sum of any number of `counts` (including repeats), up to *and
+/// including* the first sum which is longer than `max_length`.
+fn sums_of_counts(counts: &[TotalLength], max_length: TotalLength) -> Vec<TotalLength> {
+    let mut sums = Vec::new();
+    let mut frontier = BinaryHeap::from([Reverse(TotalLength::ZERO)]);
+    while let Some(Reverse(sum)) = frontier.pop() {
+        if sums.last() == Some(&sum) {
+            continue;
+        }
+        sums.push(sum);
+        if sum > max_length {
+            break;
+        }
+        for c in counts.iter().filter(|c| **c > TotalLength::ZERO) {
+            frontier.push(Reverse(sum + *c));
+        }
+    }
+    sums
+}
+
 fn method_bounds(
     query: &Query,
     total_len_range: &RangeInclusive<TotalLength>,
@@ -705,3 +783,60 @@ fn matching_lengths(lengths: &[TotalLength], range: &RangeInclusive<TotalLength>
         next_larger,
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        graph::Graph, query::Query, test_utils, utils::lengths::TotalLength, Config, Error,
+    };
+
+    use super::RefinedRanges;
+
+    fn lengths(lengths: &[usize]) -> Vec<TotalLength> {
+        lengths.iter().copied().map(TotalLength::new).collect()
+    }
+
+    /// Prove the lengths of two extents of Plain Bob Doubles, but with the length set to `min..=max`
+    fn plain_bob_doubles(min: usize, max: usize) -> crate::Result<RefinedRanges> {
+        let mut params = test_utils::plain_bob_doubles();
+        params.length = TotalLength::new(min)..=TotalLength::new(max);
+        let query = Query::new(params);
+        let (graph, _atw_table) = Graph::unoptimised(&query, &Config::default())?;
+        super::prove_lengths(&graph, &query)
+    }
+
+    #[test]
+    fn sums_of_counts() {
+        let sums = |counts: &[usize], max_length: usize| {
+            super::sums_of_counts(&lengths(counts), TotalLength::new(max_length))
+        };
+        assert_eq!(sums(&[0], 100), lengths(&[0]));
+        assert_eq!(
+            sums(&[0, 24, 36], 80),
+            lengths(&[0, 24, 36, 48, 60, 72, 84])
+        );
+        assert_eq!(sums(&[30], 60), lengths(&[0, 30, 60, 90]));
+    }
+
+    #[test]
+    fn multi_extent_lengths() {
+        let ranges = plain_bob_doubles(100, 300).unwrap();
+        let extents = TotalLength::new(120)..=TotalLength::new(240);
+        assert_eq!(ranges.length, extents);
+        // A method's count is the sum of its counts in each extent, so can be longer than one
+        // extent
+        let method_counts = &ranges.method_counts.as_raw_slice()[0];
+        assert!(*method_counts.start() <= TotalLength::new(120));
+        assert!(*method_counts.end() >= TotalLength::new(240));
+
+        // Multi-extent compositions can't contain part of an extent
+        assert!(matches!(
+            plain_bob_doubles(130, 230),
+            Err(Error::UnachievableLength {
+                next_shorter_len: Some(120),
+                next_longer_len: Some(240),
+                ..
+            })
+        ));
+    }
+}
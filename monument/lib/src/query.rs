@@ -3,16 +3,20 @@ use std::{
     ops::{Deref, Range},
 };
 
-use bellframe::{Bell, Block, Mask, Row, RowBuf, Stage};
+use bellframe::{
+    method::{LABEL_HALF_LEAD, LABEL_LEAD_END},
+    Bell, Block, Mask, Row, RowBuf, Stage, Stroke,
+};
 use itertools::Itertools;
 
 use crate::{
     graph::ChunkId,
     parameters::{
-        CallVec, CourseSet, MethodId, MethodIdx, MethodVec, MusicType, MusicTypeId, MusicTypeVec,
-        Parameters,
+        CallIdx, CallVec, CourseSet, MethodId, MethodIdx, MethodVec, MusicType, MusicTypeId,
+        MusicTypeIdx, MusicTypeVec, Parameters, ScheduledWeight, SpliceStyle, StrokeSet,
     },
-    utils::{Boundary, PerPartLength},
+    scoring::TransitionContext,
+    utils::{counts::Counts, Boundary, PerPartLength},
     PartHeadGroup,
 };
 
@@ -39,6 +43,12 @@ pub(crate) struct Query {
     pub music_types: MusicTypeVec<MusicType>,
 
     pub fixed_bells: Vec<(Bell, usize)>,
+    /// For each of [`Parameters::required_courses`], the lead head [`Mask`]s of each method which
+    /// are part of those courses
+    pub required_lead_masks: Vec<MethodVec<Vec<Mask>>>,
+    /// Upper bound on the score of one splice over the part head, as given by the
+    /// [`Scorer`](crate::scoring::Scorer).  This is never negative.
+    pub max_part_head_splice_score: f32,
     // TODO: Compute lengths
 }
 
@@ -78,6 +88,33 @@ impl Query {
         self.music_types.iter().find(|mt| mt.id == id).unwrap()
     }
 
+    /// Returns `true` if the composition can change method (or jump to another place in the lead)
+    /// without a call, at a row with the given `label`.  Non-spliced compositions only ever do
+    /// this at calls.
+    pub(crate) fn allows_plain_splice_at(&self, label: &str) -> bool {
+        if !self.is_spliced() {
+            return false;
+        }
+        match self.splice_style {
+            SpliceStyle::LeadLabels => true,
+            SpliceStyle::HalfLeads => label == LABEL_HALF_LEAD,
+            SpliceStyle::CourseHeads => label == LABEL_LEAD_END,
+            SpliceStyle::Calls => false,
+        }
+    }
+
+    /// Does any [`MusicType`] only count rows at one stroke?
+    pub(crate) fn relies_on_stroke(&self) -> bool {
+        self.music_types
+            .iter()
+            .any(|ty| ty.strokes != StrokeSet::Both)
+    }
+
+    /// Does any [`MusicType`] count [wraps](MusicType::wraps)?
+    pub(crate) fn has_wraps(&self) -> bool {
+        self.music_types.iter().any(|ty| ty.wraps)
+    }
+
     /// For a given chunk, split that chunk's range into segments where each one falls within a
     /// unique lead.  For example, a chunk with ID `ChunkId { <Little Bob>, 12345678, sub_lead_idx: 2 }`
     /// and length 18 would return the following regions:
@@ -107,6 +144,8 @@ impl Query {
     ///    Output ranges --/       1
     ///                           1
     /// ```
+    ///
+    /// Rows covered by a [`ChunkId::covering_call`] don't belong to any lead, so aren't included.
     pub(crate) fn chunk_lead_regions(
         &self,
         id: &ChunkId,
@@ -115,7 +154,7 @@ impl Query {
         let method = &self.methods[id.method];
 
         let mut lead_head: RowBuf = id.lead_head.deref().to_owned();
-        let mut length_left = length.as_usize();
+        let mut length_left = length.as_usize() - self.num_rows_covered_by(id.covering_call);
         let mut sub_lead_idx = id.sub_lead_idx;
 
         let mut lead_regions = Vec::new();
@@ -138,6 +177,271 @@ impl Query {
         }
         lead_regions
     }
+
+    /// Every [`Row`] rung by the chunk with a given [`ChunkId`] and `length`, in every part.  Any
+    /// rows covered by the [`ChunkId::covering_call`] come first in each part.
+    pub(crate) fn chunk_rows(&self, id: &ChunkId, length: PerPartLength) -> Vec<RowBuf> {
+        let method = &self.methods[id.method];
+        let plain_course = &method.plain_course;
+        let covered_rows = match id.covering_call {
+            Some(call_idx) => {
+                self.calls[call_idx].covered_rows(method.row_in_plain_lead(id.sub_lead_idx))
+            }
+            None => Vec::new(),
+        };
+        let num_plain_rows = length.as_usize() - covered_rows.len();
+        let plain_rows = (0..num_plain_rows).map(|offset| {
+            let index = (id.sub_lead_idx + offset) % plain_course.len();
+            plain_course.get_row(index).unwrap()
+        });
+        let rows_in_plain_course = covered_rows
+            .iter()
+            .map(RowBuf::as_row)
+            .chain(plain_rows)
+            .collect_vec();
+
+        let mut rows = Vec::with_capacity(rows_in_plain_course.len() * self.part_head_group.size());
+        for part_head in self.part_head_group.rows() {
+            let lead_head_in_part = part_head * id.lead_head.as_ref();
+            rows.extend(rows_in_plain_course.iter().map(|r| &lead_head_in_part * *r));
+        }
+        rows
+    }
+
+    /// The last [`Row`] of a chunk, along with the [`Row`] which is rung after it if the chunk is
+    /// followed by `call` (or a plain lead if `call` is `None`).  These are both in the first part.
+    /// Rows after a splice are the same as for a plain lead, because the method we're splicing
+    /// into starts with the row produced by the previous method.
+    pub(crate) fn last_and_next_row(
+        &self,
+        id: &ChunkId,
+        length: PerPartLength,
+        call: Option<CallIdx>,
+    ) -> (RowBuf, RowBuf) {
+        let last_row = self
+            .chunk_rows(id, length)
+            .into_iter()
+            .nth(length.as_usize() - 1)
+            .expect("Chunks can't be empty");
+        let next_row = match call {
+            Some(call_idx) => {
+                let call_rows = self.calls[call_idx]
+                    .place_notation
+                    .to_rows(last_row.clone());
+                call_rows.unwrap().get(1).unwrap().to_owned()
+            }
+            None => {
+                let method = &self.methods[id.method];
+                let num_covered_rows = self.num_rows_covered_by(id.covering_call);
+                let sub_lead_idx = (id.sub_lead_idx + length.as_usize() - num_covered_rows)
+                    % method.plain_course.len();
+                id.lead_head.as_ref() * method.plain_course.get_row(sub_lead_idx).unwrap()
+            }
+        };
+        (last_row, next_row)
+    }
+
+    /// The number of [wraps](MusicType::wraps) of each [`MusicType`] from `last_row` into
+    /// `next_row` (both given in the first part), summed over all the parts.  `last_row` is rung
+    /// at `stroke`.
+    pub(crate) fn wrap_counts(&self, last_row: &Row, next_row: &Row, stroke: Stroke) -> Counts {
+        let mut counts = Counts::zeros(self.music_types.len());
+        for part_head in self.part_head_group.rows() {
+            let last_row_in_part = part_head * last_row;
+            let next_row_in_part = part_head * next_row;
+            for (idx, ty) in self.music_types.iter_enumerated() {
+                if ty.wraps {
+                    counts[idx.index()] +=
+                        ty.num_instances(&last_row_in_part, Some(&next_row_in_part), stroke);
+                }
+            }
+        }
+        counts
+    }
+
+    /// The score generated by some [wraps](MusicType::wraps), which isn't given by the
+    /// [`Scorer`](crate::scoring::Scorer)
+    pub(crate) fn wrap_score(&self, wrap_counts: &[usize]) -> f32 {
+        (self.music_types.iter().zip_eq(wrap_counts))
+            .filter(|(ty, _)| ty.wraps)
+            .map(|(ty, &count)| ty.weight * count as f32)
+            .sum()
+    }
+
+    /// The score given by the [`Scorer`](crate::scoring::Scorer) to one transition between chunks
+    /// of ringing, in one part.  `to_method` is `None` if the composition comes round.
+    pub(crate) fn transition_score(
+        &self,
+        call: Option<CallIdx>,
+        is_splice: bool,
+        from_method: MethodIdx,
+        to_method: Option<MethodIdx>,
+    ) -> f32 {
+        self.scorer.transition_score(&TransitionContext {
+            call: call.map(|idx| &self.calls[idx]),
+            is_splice,
+            from_method: &self.methods[from_method].inner,
+            to_method: to_method.map(|idx| &self.methods[idx].inner),
+            parameters: &self.parameters,
+        })
+    }
+
+    /// The number of [`Row`]s rung in the middle of a given call (or `0` for no call).  See
+    /// [`Call::num_covered_rows`](crate::parameters::Call::num_covered_rows).
+    pub(crate) fn num_rows_covered_by(&self, call: Option<CallIdx>) -> usize {
+        call.map_or(0, |idx| self.calls[idx].num_covered_rows())
+    }
+
+    /// The extra weight given to an instance of some [`MusicType`] at a `position` within every
+    /// part, ignoring any [deferred](ScheduledWeight::is_deferred) weights
+    pub(crate) fn scheduled_music_weight(&self, music_type: MusicTypeIdx, position: usize) -> f32 {
+        undeferred_weight(&self.music_types[music_type].weight_schedule, position)
+    }
+
+    /// The extra weight given to a call at a `position` within every part, ignoring any
+    /// [deferred](ScheduledWeight::is_deferred) weights
+    pub(crate) fn scheduled_call_weight(&self, call: CallIdx, position: usize) -> f32 {
+        undeferred_weight(&self.calls[call].weight_schedule, position)
+    }
+
+    /// An upper bound on [`Self::scheduled_music_weight`] at any position
+    pub(crate) fn max_scheduled_music_weight(&self, music_type: MusicTypeIdx) -> f32 {
+        max_undeferred_weight(&self.music_types[music_type].weight_schedule)
+    }
+
+    /// An upper bound on [`Self::scheduled_call_weight`] at any position
+    pub(crate) fn max_scheduled_call_weight(&self, call: CallIdx) -> f32 {
+        max_undeferred_weight(&self.calls[call].weight_schedule)
+    }
+
+    /// Does any [`MusicType`] have a [`count_score`](MusicType::count_score)?
+    pub(crate) fn has_count_scores(&self) -> bool {
+        self.music_types.iter().any(|ty| ty.count_score.is_some())
+    }
+
+    /// The total [`count_score`](MusicType::count_score) of a composition with the given
+    /// `music_counts`
+    pub(crate) fn count_score(&self, music_counts: &Counts) -> f32 {
+        (self.music_types.iter().zip_eq(music_counts.iter()))
+            .map(|(ty, &count)| ty.count_score(count))
+            .sum()
+    }
+
+    /// The total [`count_score`](MusicType::count_score) of a composition with no music.  This
+    /// isn't included in the scores of composition prefixes during the search.
+    pub(crate) fn base_count_score(&self) -> f32 {
+        self.music_types.iter().map(|ty| ty.count_score(0)).sum()
+    }
+
+    /// An upper bound on how much the total [`count_score`](MusicType::count_score) could
+    /// increase from a prefix with the given `music_counts`
+    pub(crate) fn max_count_score_gain(&self, music_counts: &Counts) -> f32 {
+        (self.music_types.iter().zip_eq(music_counts.iter()))
+            .filter_map(|(ty, &count)| Some(ty.count_score.as_ref()?.max_gain_from(count)))
+            .sum()
+    }
+
+    /// An upper bound on the total score which any composition could gain from
+    /// [deferred](ScheduledWeight::is_deferred) weights.  These are only added once a composition
+    /// is complete, so this is needed to keep the search's score bounds admissible.
+    pub(crate) fn max_deferred_score(&self) -> f32 {
+        let max_part_len = self.max_length().as_usize() / self.num_parts();
+        // The maximum score gain from one `ScheduledWeight`, if at most `instances_per_row`
+        // instances can happen on each row
+        let max_gain = |weight: &ScheduledWeight, instances_per_row: usize| -> f32 {
+            if !weight.is_deferred() || weight.weight <= 0.0 {
+                return 0.0;
+            }
+            let num_parts = match weight.parts.len() {
+                0 => self.num_parts(),
+                n => n,
+            };
+            let max_instances = weight.max_rows_per_part(max_part_len) * instances_per_row;
+            weight.weight * (max_instances * num_parts) as f32
+        };
+
+        let music_gain = self
+            .music_types
+            .iter()
+            .flat_map(|ty| {
+                // Each pattern can wrap from a row into the next at several places
+                let instances_per_row = match ty.wraps {
+                    true => ty.patterns.len() * (self.stage.num_bells() - 1),
+                    false => ty.patterns.len(),
+                };
+                ty.weight_schedule
+                    .iter()
+                    .map(move |w| max_gain(w, instances_per_row))
+            })
+            .sum::<f32>();
+        let call_gain = self
+            .calls
+            .iter()
+            .flat_map(|call| call.weight_schedule.iter().map(|w| max_gain(w, 1)))
+            .sum::<f32>();
+        music_gain + call_gain
+    }
+
+    /// The score generated by how evenly balanced the given `method_counts` are (see
+    /// [`Parameters::method_balance_weight`])
+    pub(crate) fn method_balance_score(&self, method_counts: &Counts) -> f32 {
+        if self.method_balance_weight == 0.0 {
+            return 0.0;
+        }
+        let total_rows = method_counts.iter().sum::<usize>() as f32;
+        let mean_rows = total_rows / method_counts.len() as f32;
+        let deviation = method_counts
+            .iter()
+            .map(|&count| (count as f32 - mean_rows).abs())
+            .sum::<f32>();
+        self.method_balance_weight * deviation
+    }
+
+    /// An upper bound on [`Self::method_balance_score`] for any composition
+    pub(crate) fn max_method_balance_score(&self) -> f32 {
+        // The deviation from an even balance can be at most twice the composition's length (which
+        // happens when all the rows are in one method)
+        let max_deviation = 2 * self.max_length().as_usize();
+        self.method_balance_weight.max(0.0) * max_deviation as f32
+    }
+
+    /// For each of [`Parameters::required_courses`], count how many leads of those courses are
+    /// started by a given chunk (summed over all the parts).
+    pub(crate) fn required_course_leads(&self, id: &ChunkId, length: PerPartLength) -> Counts {
+        let mut counts = Counts::zeros(self.required_lead_masks.len());
+        for (idx, _lead_head) in self.required_course_lead_heads(id, length) {
+            counts[idx] += 1;
+        }
+        counts
+    }
+
+    /// The lead heads (in every part) of the leads started by a given chunk which belong to
+    /// [`Parameters::required_courses`], each paired with the index of the required courses it
+    /// belongs to.
+    pub(crate) fn required_course_lead_heads(
+        &self,
+        id: &ChunkId,
+        length: PerPartLength,
+    ) -> Vec<(usize, RowBuf)> {
+        let mut lead_heads = Vec::new();
+        for (lead_head, range) in self.chunk_lead_regions(id, length) {
+            if range.start != 0 {
+                continue; // Leads only count if their lead head is rung
+            }
+            for part_head in self.part_head_group.rows() {
+                let lead_head_in_part = part_head * lead_head.as_row();
+                for (idx, masks) in self.required_lead_masks.iter().enumerate() {
+                    if masks[id.method]
+                        .iter()
+                        .any(|m| m.matches(&lead_head_in_part))
+                    {
+                        lead_heads.push((idx, lead_head_in_part.clone()));
+                    }
+                }
+            }
+        }
+        lead_heads
+    }
 }
 
 impl Method {
@@ -146,6 +450,14 @@ impl Method {
         self.allowed_lead_masks.iter().any(|m| m.matches(lead_head))
     }
 
+    /// Checks if `lead_head` is the head of a course in this method (according to the CH masks
+    /// provided).
+    pub(crate) fn is_course_head(&self, lead_head: &Row) -> bool {
+        self.specified_course_head_masks
+            .iter()
+            .any(|m| m.matches(lead_head))
+    }
+
     pub(crate) fn is_lead_head_duffer(&self, lead_head: &Row) -> bool {
         !self
             .non_duffer_lead_masks
@@ -210,6 +522,37 @@ impl Query {
             parameters.stage,
         );
 
+        let required_lead_masks = parameters
+            .required_courses
+            .iter()
+            .map(|required| {
+                used_methods
+                    .iter()
+                    .map(|m| {
+                        CourseSet::to_lead_masks(
+                            std::slice::from_ref(&required.courses),
+                            m,
+                            &fixed_bells,
+                        )
+                    })
+                    .collect()
+            })
+            .collect_vec();
+
+        let max_part_head_splice_score = used_methods
+            .iter()
+            .cartesian_product(&used_methods)
+            .map(|(from_method, to_method)| {
+                parameters.scorer.transition_score(&TransitionContext {
+                    call: None,
+                    is_splice: true,
+                    from_method,
+                    to_method: Some(to_method),
+                    parameters: &parameters,
+                })
+            })
+            .fold(0.0, f32::max);
+
         Self {
             methods: used_methods
                 .into_iter()
@@ -219,6 +562,8 @@ impl Query {
             music_types: used_music_types,
 
             fixed_bells,
+            required_lead_masks,
+            max_part_head_splice_score,
             parameters,
         }
     }
@@ -274,6 +619,25 @@ impl Method {
     }
 }
 
+/// Sum of the non-[deferred](ScheduledWeight::is_deferred) weights which apply at `position`
+fn undeferred_weight(schedule: &[ScheduledWeight], position: usize) -> f32 {
+    schedule
+        .iter()
+        .filter(|w| !w.is_deferred())
+        // Non-deferred weights apply in every part and don't depend on the part length
+        .map(|w| w.weight_at(0, position, 0))
+        .sum()
+}
+
+/// Upper bound on [`undeferred_weight`] at any position
+fn max_undeferred_weight(schedule: &[ScheduledWeight]) -> f32 {
+    schedule
+        .iter()
+        .filter(|w| !w.is_deferred())
+        .map(|w| w.weight.max(0.0))
+        .sum()
+}
+
 fn wrap_sub_lead_indices(indices: &[isize], method: &bellframe::Method) -> Vec<usize> {
     indices
         .iter()
@@ -331,28 +695,44 @@ fn filter_bells_fixed_by_call(
     call: &crate::parameters::Call,
     set: &mut HashSet<Bell>,
 ) {
-    // Note that all calls are required to only substitute one piece of place notation.
+    let lead_len = method.lead_len();
+    let lead_head = method.first_lead().get_row(lead_len).unwrap();
+    // The plain row `idx` rows after the start of the first lead, where `idx` can be negative
+    // (i.e. refer to the lead before the first lead) for calls which cover multiple changes
+    let plain_row = |idx: isize| -> RowBuf {
+        let first_lead = method.first_lead();
+        match idx {
+            i if i >= 0 => first_lead.get_row(i as usize).unwrap().to_owned(),
+            i => {
+                &lead_head.inv()
+                    * first_lead
+                        .get_row((i + lead_len as isize) as usize)
+                        .unwrap()
+            }
+        }
+    };
+
     for sub_lead_idx_after_call in method.label_indices(&call.label_from) {
         // TODO: Handle different from/to locations
-        let idx_before_call = (sub_lead_idx_after_call + method.lead_len() - 1) % method.lead_len();
-        let idx_after_call = idx_before_call + 1; // in range `1..=method.lead_len()`
-
-        // The row before a call in this location in the _first lead_
-        let row_before_call = method.first_lead().get_row(idx_before_call).unwrap();
-        // The row after a plain call in this location in the _first lead_
-        let row_after_no_call = method.first_lead().get_row(idx_after_call).unwrap();
-        // The row after a call in this location in the _first lead_
-        let mut row_after_call = row_before_call.to_owned();
-        call.place_notation.permute(&mut row_after_call).unwrap();
-
-        // A bell is _affected_ by the call iff it's in a different place in `row_after_call` than
-        // `row_after_no_call`.  These should be removed from the set, because they are no longer
-        // fixed.
-        for (bell_after_no_call, bell_after_call) in
-            row_after_no_call.bell_iter().zip(&row_after_call)
-        {
-            if bell_after_call != bell_after_no_call {
-                set.remove(&bell_after_call);
+        let idx_before_label = (sub_lead_idx_after_call + lead_len - 1) % lead_len;
+        // The index of the row just before the call starts
+        let idx_before_call = idx_before_label as isize + 1 - call.place_notation.len() as isize;
+
+        // A bell is _affected_ by the call iff it's in a different place in some row of the call
+        // than in the corresponding plain row.  These should be removed from the set, because they
+        // are no longer fixed.
+        let call_rows = call
+            .place_notation
+            .to_rows(plain_row(idx_before_call))
+            .unwrap();
+        for (i, row_of_call) in call_rows.iter().enumerate().skip(1) {
+            let plain_row = plain_row(idx_before_call + i as isize);
+            for (bell_after_no_call, bell_after_call) in
+                plain_row.bell_iter().zip(row_of_call.bell_iter())
+            {
+                if bell_after_call != bell_after_no_call {
+                    set.remove(&bell_after_call);
+                }
             }
         }
     }
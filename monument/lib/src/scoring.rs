@@ -0,0 +1,272 @@
+//! Pluggable scoring of [`Composition`](crate::Composition)s.
+//!
+//! Every [`Row`] and every transition between chunks of ringing (i.e. a call or plain lead,
+//! possibly with a change of method) is given a score by a [`Scorer`], which is carried by the
+//! [`Parameters`].  These scores are computed once while building the graph, so the search itself
+//! isn't slowed down by complex [`Scorer`]s.  Monument's own weights (music, calls, splices and
+//! `course_weights`) are implemented by [`DefaultScorer`].
+//!
+//! Some weights depend on where in the composition they happen, or on the composition as a whole
+//! (e.g. [`weight_schedule`](crate::parameters::MusicType::weight_schedule)s,
+//! [`atw_weight`](Parameters::atw_weight) and
+//! [`method_balance_weight`](Parameters::method_balance_weight)).  These aren't part of
+//! [`Scorer`], and are always applied on top of its scores.  [Wraps](MusicType::wraps) cross from
+//! one row into the next (and possibly into the next chunk), so they are also scored outside
+//! [`Scorer`].
+
+use std::fmt::Debug;
+
+use bellframe::{Row, RowBuf, Stroke};
+use itertools::Itertools;
+
+use crate::parameters::{Call, Method, MusicType, Parameters};
+
+/// A way of giving scores to the [`Row`]s and transitions which make up a composition.  Monument
+/// will try to find the compositions with the highest average score per row.
+///
+/// Scores are computed for every part of the composition separately, so in a multi-part a
+/// [`Row`] in the first part and the corresponding [`Row`]s in the other parts are scored
+/// separately.
+pub trait Scorer: Debug + Send + Sync {
+    /// The score generated by ringing a single [`Row`]
+    fn row_score(&self, row: &RowContext) -> f32;
+
+    /// The score generated by one chunk of ringing leading into the next, either through a
+    /// [`Call`] or a plain lead (and possibly changing method).
+    fn transition_score(&self, transition: &TransitionContext) -> f32;
+
+    /// An upper bound on the total [`row_score`](Self::row_score) of every row in a chunk of
+    /// ringing, in one part.  Exhaustive searches use this when pruning, so this can be used to
+    /// leave room for scores which aren't known when the graph is built.  If this returns
+    /// `None` (the default), Monument uses the chunk's actual score.
+    fn max_chunk_score(&self, _chunk: &ChunkContext) -> Option<f32> {
+        None
+    }
+
+    /// A string identifying this `Scorer` and its settings.  Checkpoints store this, and can
+    /// only be resumed by searches whose `Scorer` has the same `id`.  The default uses the
+    /// [`Debug`] output, so this should be overridden if that doesn't capture everything which
+    /// affects the scores.
+    fn id(&self) -> String {
+        format!("{self:?}")
+    }
+}
+
+/// Everything known about a [`Row`] when it's given to [`Scorer::row_score`]
+#[derive(Debug, Clone, Copy)]
+pub struct RowContext<'a> {
+    /// The [`Row`] being scored
+    pub row: &'a Row,
+    /// The [`Stroke`] at which `row` is rung
+    pub stroke: Stroke,
+    /// The [`Method`] being rung
+    pub method: &'a Method,
+    /// The lead head of the lead where the chunk containing this [`Row`] starts.  Every row in a
+    /// chunk is in the same course, so this identifies the course being rung.
+    pub lead_head: &'a Row,
+    /// The lead heads of every lead in `method`'s plain course.  Transposing these by `lead_head`
+    /// gives the lead heads of the course being rung.
+    pub plain_lead_heads: &'a [RowBuf],
+    /// The [`MusicType`]s used by the composition
+    pub music_types: &'a [MusicType],
+    /// For each of `music_types`, the number of instances found in `row` (taking
+    /// [`MusicType::strokes`] into account).  [Wraps](MusicType::wraps) aren't included.
+    pub music_counts: &'a [usize],
+    pub parameters: &'a Parameters,
+}
+
+/// Everything known about a transition between chunks when it's given to
+/// [`Scorer::transition_score`]
+#[derive(Debug, Clone, Copy)]
+pub struct TransitionContext<'a> {
+    /// The [`Call`] made at this transition, or `None` for a plain lead
+    pub call: Option<&'a Call>,
+    /// `true` if this transition changes method (or jumps to another place in the lead)
+    pub is_splice: bool,
+    /// The [`Method`] being rung before the transition
+    pub from_method: &'a Method,
+    /// The [`Method`] being rung after the transition, or `None` if the composition comes round
+    pub to_method: Option<&'a Method>,
+    pub parameters: &'a Parameters,
+}
+
+/// Everything known about a chunk of ringing when it's given to [`Scorer::max_chunk_score`]
+#[derive(Debug, Clone, Copy)]
+pub struct ChunkContext<'a> {
+    /// The [`Method`] being rung
+    pub method: &'a Method,
+    /// The lead head of the lead where this chunk starts, in the first part
+    pub lead_head: &'a Row,
+    /// The number of [`Row`]s in this chunk, in one part
+    pub length: usize,
+    pub parameters: &'a Parameters,
+}
+
+/// The [`Scorer`] built into Monument, which uses the weights given in the [`Parameters`]:
+///
+/// - Every instance of a [`MusicType`] scores its [`weight`](MusicType::weight).
+/// - Every [`Row`] in a course matching one of the
+///   [`course_weights`](Parameters::course_weights) scores the corresponding weight.
+/// - Every [`Call`] scores its [`weight`](Call::weight), and every change of method scores
+///   [`splice_weight`](Parameters::splice_weight).
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DefaultScorer;
+
+impl Scorer for DefaultScorer {
+    fn row_score(&self, row: &RowContext) -> f32 {
+        let music_score = row
+            .music_types
+            .iter()
+            .zip_eq(row.music_counts)
+            .map(|(ty, &count)| ty.weight * count as f32)
+            .sum::<f32>();
+        let course_score = course_weight(row.lead_head, row.plain_lead_heads, row.parameters);
+        music_score + course_score
+    }
+
+    fn transition_score(&self, transition: &TransitionContext) -> f32 {
+        let call_weight = transition.call.map_or(0.0, |call| call.weight);
+        let splice_weight = match transition.is_splice {
+            true => transition.parameters.splice_weight,
+            false => 0.0,
+        };
+        call_weight + splice_weight
+    }
+}
+
+/// The score which [`Parameters::course_weights`] gives to each row of a chunk starting in the
+/// lead with lead head `lead_head`.
+pub(crate) fn course_weight(
+    lead_head: &Row,
+    plain_lead_heads: &[RowBuf],
+    params: &Parameters,
+) -> f32 {
+    // `course_weights` apply to every row of every course which contains a lead head matching
+    // that mask, so we have to transpose the mask by every lead head to check every lead in
+    // the course.  For example, for Plain Bob lead-head methods, `xxxxxx78` will expand into
+    // masks `[xxxxxx78, xxxxx8x7, xxx8x7xx, x8x7xxxx, x78xxxxx, xx7x8xxx, xxxx7x8x]` (every
+    // one of those leads is included in the course for `xxxxxx78`)
+    let mut score = 0.0;
+    for (mask, weight) in &params.course_weights {
+        for plain_lead_head in plain_lead_heads {
+            if (mask * plain_lead_head).matches(lead_head) {
+                score += *weight;
+            }
+        }
+    }
+    score
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{atomic::AtomicBool, Arc};
+
+    use bellframe::Bell;
+
+    use crate::{
+        parameters::Parameters, test_utils, Composition, Config, Search, SearchStrategy, Update,
+    };
+
+    use super::{ChunkContext, RowContext, Scorer, TransitionContext};
+
+    /// Scores every row led by the treble, and penalises every call
+    #[derive(Debug)]
+    struct TrebleLeadScorer;
+
+    impl Scorer for TrebleLeadScorer {
+        fn row_score(&self, row: &RowContext) -> f32 {
+            match row.row.place_of(Bell::TREBLE) {
+                Some(0) => 1.0,
+                _ => 0.0,
+            }
+        }
+
+        fn transition_score(&self, transition: &TransitionContext) -> f32 {
+            match transition.call {
+                Some(_) => -2.0,
+                None => 0.0,
+            }
+        }
+    }
+
+    /// [`TrebleLeadScorer`], but with a very loose bound on each chunk's score
+    #[derive(Debug)]
+    struct LooseBoundScorer;
+
+    impl Scorer for LooseBoundScorer {
+        fn row_score(&self, row: &RowContext) -> f32 {
+            TrebleLeadScorer.row_score(row)
+        }
+
+        fn transition_score(&self, transition: &TransitionContext) -> f32 {
+            TrebleLeadScorer.transition_score(transition)
+        }
+
+        fn max_chunk_score(&self, chunk: &ChunkContext) -> Option<f32> {
+            Some(chunk.length as f32 * 10.0)
+        }
+    }
+
+    /// Touches of Plain Bob Minor, up to two courses long
+    fn plain_bob_minor(scorer: Arc<dyn Scorer>) -> Parameters {
+        Parameters {
+            scorer,
+            ..test_utils::plain_bob_minor()
+        }
+    }
+
+    /// Every composition found by a search, along with the [`Search`] which found them
+    fn comps(params: Parameters, strategy: SearchStrategy) -> (Search, Vec<Composition>) {
+        let config = Config {
+            thread_limit: Some(1),
+            strategy,
+            ..Default::default()
+        };
+        let search = Search::new(params, config).unwrap();
+        let mut comps = Vec::new();
+        let update_fn = |update| {
+            if let Update::Comp(comp) = update {
+                comps.push(comp);
+            }
+        };
+        search.run(update_fn, &AtomicBool::new(false));
+        (search, comps)
+    }
+
+    #[test]
+    fn custom_scorer() {
+        let (search, comps) = comps(
+            plain_bob_minor(Arc::new(TrebleLeadScorer)),
+            SearchStrategy::BestFirst,
+        );
+        assert!(!comps.is_empty());
+        for comp in &comps {
+            let rows = comp.rows();
+            let treble_leads = (rows.rows())
+                .filter(|row| row.place_of(Bell::TREBLE) == Some(0))
+                .count();
+            let expected_score = treble_leads as f32 - 2.0 * comp.num_calls() as f32;
+            assert_eq!(comp.total_score, expected_score, "{}", comp.call_string());
+            // The scores used by the search are also used when evaluating callings
+            let evaluated = search.evaluate(&comp.call_string()).unwrap();
+            assert_eq!(evaluated.total_score, expected_score);
+        }
+    }
+
+    #[test]
+    fn max_chunk_score() {
+        // Loosening the bounds on chunk scores can only slow down an exhaustive search, not change
+        // which compositions it finds
+        let best_scores = |scorer: Arc<dyn Scorer>| {
+            let mut params = plain_bob_minor(scorer);
+            params.num_comps = 5;
+            let (_, comps) = comps(params, SearchStrategy::Exhaustive);
+            let mut scores = comps.iter().map(|c| c.average_score()).collect::<Vec<_>>();
+            scores.sort_by(|a, b| b.total_cmp(a));
+            scores
+        };
+        let scores = best_scores(Arc::new(TrebleLeadScorer));
+        assert_eq!(scores.len(), 5);
+        assert_eq!(scores, best_scores(Arc::new(LooseBoundScorer)));
+    }
+}
@@ -0,0 +1,113 @@
+use std::collections::{BTreeMap, BinaryHeap};
+
+use crate::utils::lengths::TotalLength;
+
+use super::{
+    comp_filter::CompFilter, handle::Controls, path::Paths, prefix::CompPrefix, Progress, Search,
+    Update, ITERS_BETWEEN_ABORT_CHECKS, ITERS_BETWEEN_PROGRESS_UPDATES, MIN_PATHS_BEFORE_GC,
+};
+
+/// Searches a [`Graph`](m_gr::Graph) for compositions using beam search.
+///
+/// Prefixes are grouped into 'buckets' by length, and the buckets are expanded in order of
+/// increasing length.  Only the best `width` prefixes in each bucket are ever expanded, so the
+/// number of prefixes stored at once is roughly `width` times the number of distinct lengths.
+/// Because all the prefixes in a bucket have the same length, comparing their average scores is
+/// the same as comparing their total scores.
+pub(super) fn search(
+    search: &Search,
+    width: usize,
+    mut update_fn: impl FnMut(Update),
+    controls: &Controls,
+) {
+    let width = width.max(1);
+    log::debug!("Running beam search with width {width}");
+
+    let mut paths = Paths::new();
+    let mut buckets = BTreeMap::<TotalLength, Vec<CompPrefix>>::new();
+    for prefix in CompPrefix::starts(search, &mut paths) {
+        add_to_bucket(&mut buckets, prefix, width);
+    }
+
+    let mut iter_count = 0;
+    let mut num_comps = 0;
+    let mut nodes_before_gc = MIN_PATHS_BEFORE_GC;
+    let mut successors = BinaryHeap::new();
+    let mut comp_filter = CompFilter::new(search.query.min_comp_distance);
+
+    let progress = |buckets: &BTreeMap<_, Vec<_>>, iter_count, num_comps| {
+        Progress::from_prefixes(
+            buckets.values().flatten(),
+            iter_count,
+            num_comps,
+            controls.abort_flag,
+        )
+    };
+    // Send 'empty' update before search starts
+    update_fn(Update::Progress(progress(&buckets, iter_count, num_comps)));
+
+    'search: while let Some((_length, mut bucket)) = buckets.pop_first() {
+        truncate_bucket(&mut bucket, width);
+        for prefix in bucket {
+            let comp = prefix.expand(search, &mut paths, &mut successors, num_comps);
+            if let Some(comp) = comp.filter(|comp| comp_filter.accept(comp)) {
+                update_fn(Update::Comp(comp));
+                num_comps += 1;
+                if num_comps >= controls.num_comps() {
+                    break 'search; // Stop the search once we've got enough comps
+                }
+            }
+            for succ in successors.drain() {
+                add_to_bucket(&mut buckets, succ, width);
+            }
+
+            iter_count += 1;
+
+            // Check for abort every so often
+            if iter_count.is_multiple_of(ITERS_BETWEEN_ABORT_CHECKS) && controls.should_abort() {
+                break 'search;
+            }
+            // Send stats every so often
+            if iter_count.is_multiple_of(ITERS_BETWEEN_PROGRESS_UPDATES) {
+                update_fn(Update::Progress(progress(&buckets, iter_count, num_comps)));
+            }
+        }
+
+        // Most paths are discarded when the buckets are truncated, so garbage-collect them
+        // whenever the number of path nodes doubles
+        if paths.num_nodes() >= nodes_before_gc {
+            paths.gc(buckets.values().flatten().map(CompPrefix::path_head));
+            nodes_before_gc = (paths.num_nodes() * 2).max(MIN_PATHS_BEFORE_GC);
+        }
+    }
+
+    // Always send a final update before finishing
+    update_fn(Update::Progress(progress(&buckets, iter_count, num_comps)));
+    // See `best_first::Worker::run` for why we do this
+    if search.config.leak_search_memory {
+        std::mem::forget(buckets);
+    }
+    update_fn(Update::Complete);
+}
+
+/// Adds a [`CompPrefix`] to the bucket for its length.  Buckets which get much bigger than `width`
+/// are truncated straight away, so that the number of stored prefixes stays bounded.
+fn add_to_bucket(
+    buckets: &mut BTreeMap<TotalLength, Vec<CompPrefix>>,
+    prefix: CompPrefix,
+    width: usize,
+) {
+    let bucket = buckets.entry(prefix.length()).or_default();
+    bucket.push(prefix);
+    if bucket.len() >= width * 2 {
+        truncate_bucket(bucket, width);
+    }
+}
+
+/// Removes all but the `width` best prefixes from a bucket
+fn truncate_bucket(bucket: &mut Vec<CompPrefix>, width: usize) {
+    if bucket.len() > width {
+        bucket.select_nth_unstable_by(width, |a, b| b.cmp(a)); // Sort highest score first
+        bucket.truncate(width);
+    }
+}
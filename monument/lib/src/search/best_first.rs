@@ -1,141 +1,808 @@
 use std::{
     collections::BinaryHeap,
-    sync::atomic::{AtomicBool, Ordering},
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        mpsc::{self, RecvTimeoutError, Sender},
+        Condvar, Mutex,
+    },
+    time::{Duration, Instant},
 };
 
 use datasize::DataSize;
 use ringing_utils::BigNumInt;
 
-use crate::utils::lengths::TotalLength;
+use crate::{utils::lengths::TotalLength, Composition};
 
-use super::{path::Paths, prefix::CompPrefix, Progress, Search, Update};
+use super::{
+    checkpoint::{Checkpoint, FlatPath, PathTree, RestoredState},
+    comp_filter::CompFilter,
+    graph::{StartIdx, SuccIdx},
+    handle::Controls,
+    path::Paths,
+    prefix::CompPrefix,
+    spill::SpilledFrontier,
+    Progress, Search, Update, ITERS_BETWEEN_ABORT_CHECKS, ITERS_BETWEEN_PATH_GCS,
+    ITERS_BETWEEN_PROGRESS_UPDATES,
+};
+
+const ITERS_BETWEEN_WORK_SHARING_CHECKS: usize = 1_000;
 
-const ITERS_BETWEEN_ABORT_CHECKS: usize = 10_000;
-const ITERS_BETWEEN_PROGRESS_UPDATES: usize = 100_000;
-const ITERS_BETWEEN_PATH_GCS: usize = 100_000_000;
+/// The most prefixes which a thread will give away in one go when another thread runs out of work
+const MAX_PREFIXES_PER_DONATION: usize = 1_000;
+/// The most prefixes which a thread will load from disk in one go (see
+/// [`Config::spill_dir`](super::Config::spill_dir))
+const MAX_PREFIXES_PER_RELOAD: usize = 100_000;
+/// When checkpoints are enabled, snapshotting a thread's frontier temporarily needs about 2x the
+/// memory used by its [`Paths`] (12 bytes per [`PathTree`] node and 4 bytes per path for
+/// de-duplication, compared to 8 bytes per path in [`Paths`]).  That memory is counted against
+/// the memory limit by multiplying the size of the [`Paths`] by this factor.
+const PATHS_SIZE_FACTOR_WITH_CHECKPOINTS: usize = 3;
+/// How long the calling thread waits for messages before checking whether a checkpoint is due
+const CHECKPOINT_POLL_INTERVAL: Duration = Duration::from_secs(1);
 
-/// Searches a [`Graph`](m_gr::Graph) for compositions
-pub(crate) fn search(search: &Search, mut update_fn: impl FnMut(Update), abort_flag: &AtomicBool) {
+/// Searches a [`Graph`](m_gr::Graph) for compositions, optionally continuing from a
+/// [`RestoredState`] loaded from a checkpoint.
+///
+/// The search is split between several worker threads, each of which runs best-first search over
+/// its own shard of the frontier (with its own [`Paths`]).  The start prefixes are dealt out
+/// between the threads, and any thread which runs out of prefixes is given some of the best
+/// prefixes from the other threads.  Compositions and progress updates are sent back to the
+/// calling thread, which is the only thread to call `update_fn`.
+pub(crate) fn search(
+    search: &Search,
+    mut update_fn: impl FnMut(Update),
+    controls: &Controls,
+    restored_state: Option<RestoredState>,
+) {
+    let num_threads = search.num_threads();
     log::info!(
         "Limiting memory usage to {}B",
         BigNumInt(search.config.mem_limit)
     );
+    log::debug!("Searching with {num_threads} thread(s)");
 
-    // Initialise the frontier to just the start chunks
-    let mut paths = Paths::new();
-    let mut frontier: BinaryHeap<CompPrefix> = CompPrefix::starts(search, &mut paths);
-
-    if frontier.is_empty() {
+    if search.graph.starts.is_empty() {
         return; // Don't run a search if no compositions are possible
     }
 
-    // Number of bytes occupied by each `CompPrefix` in the frontier.
-    let prefix_size = frontier.peek().unwrap().size();
-
-    let mut iter_count = 0;
+    let shared = Shared::new(num_threads);
+    let (msg_tx, msg_rx) = mpsc::channel::<WorkerMsg>();
+    let mut worker_stats = vec![WorkerStats::default(); num_threads];
     let mut num_comps = 0;
+    // The paths of every comp passed to `update_fn`, which are needed to write checkpoints
+    let mut comp_paths = Vec::<FlatPath>::new();
+    // The paths of comps which arrived after we had enough comps.  These are saved in checkpoints
+    // (as part of the frontier), so that a resumed search which asks for more comps finds them.
+    let mut ignored_comp_paths = Vec::<FlatPath>::new();
+    let mut comp_filter = CompFilter::new(search.query.min_comp_distance);
 
-    macro_rules! send_progress_update {
-        (truncating_queue = $truncating_queue: expr) => {
-            send_progress_update(
-                &frontier,
-                &mut update_fn,
-                iter_count,
-                num_comps,
-                $truncating_queue,
-                abort_flag.load(Ordering::SeqCst),
-            );
-        };
+    // Re-emit the comps found before the checkpoint, and hand out its frontier to the threads
+    let (base_iter_count, mut frontiers) = match restored_state {
+        Some(RestoredState {
+            iter_count,
+            comps,
+            frontiers,
+        }) => {
+            for (comp, path) in comps {
+                // Later comps still have to be different enough from the comps found before the
+                // checkpoint
+                comp_filter.accept(&comp);
+                update_fn(Update::Comp(comp));
+                comp_paths.push(path);
+                num_comps += 1;
+            }
+            (iter_count, frontiers.into_iter().map(Some).collect())
+        }
+        None => (0, (0..num_threads).map(|_| None).collect::<Vec<_>>()),
+    };
+    shared.num_comps.store(num_comps, Ordering::Relaxed);
+    if num_comps >= controls.num_comps() {
+        shared.finish(); // The checkpoint already contains all the comps we need
     }
 
     // Send 'empty' update before search starts
-    send_progress_update!(truncating_queue = false);
+    send_progress_update(
+        &worker_stats,
+        base_iter_count,
+        &mut update_fn,
+        num_comps,
+        controls.abort_flag,
+    );
+
+    let mut checkpointer = search
+        .config
+        .checkpoint_file
+        .as_deref()
+        .map(|path| Checkpointer::new(path, num_threads));
+
+    std::thread::scope(|scope| {
+        for (worker_idx, frontier) in frontiers.iter_mut().enumerate() {
+            let worker = Worker::new(search, &shared, worker_idx, msg_tx.clone(), frontier.take());
+            scope.spawn(move || worker.run(controls));
+        }
+        // Drop our copy of the sender, so that the loop below terminates once every worker has
+        // finished
+        drop(msg_tx);
+
+        loop {
+            let msg = match msg_rx.recv_timeout(CHECKPOINT_POLL_INTERVAL) {
+                Ok(msg) => Some(msg),
+                Err(RecvTimeoutError::Timeout) => None,
+                Err(RecvTimeoutError::Disconnected) => break, // Every worker has finished
+            };
+            match msg {
+                Some(WorkerMsg::Comp(mut comp, path)) => {
+                    // Other threads may have found comps after we've got enough, but before they
+                    // notice that the search is finished.  Those extra comps are ignored.
+                    if num_comps >= controls.num_comps() {
+                        ignored_comp_paths.push(path);
+                        continue;
+                    }
+                    // Comps which repeat (or are too similar to) earlier comps don't count
+                    if !comp_filter.accept(&comp) {
+                        continue;
+                    }
+                    // Generation numbers are assigned here so that they're unique and in the
+                    // order that comps are given to `update_fn`
+                    comp.generation_number = num_comps;
+                    update_fn(Update::Comp(*comp));
+                    comp_paths.push(path);
+                    num_comps += 1;
+                    shared.num_comps.store(num_comps, Ordering::Relaxed);
+
+                    if num_comps >= controls.num_comps() {
+                        shared.finish(); // Stop the search once we've got enough comps
+                    }
+                }
+                Some(WorkerMsg::Stats { worker_idx, stats }) => {
+                    worker_stats[worker_idx] = stats;
+                    send_progress_update(
+                        &worker_stats,
+                        base_iter_count,
+                        &mut update_fn,
+                        num_comps,
+                        controls.abort_flag,
+                    );
+                }
+                Some(WorkerMsg::Snapshot {
+                    worker_idx,
+                    iter_count,
+                    tree,
+                    is_final,
+                }) => {
+                    worker_stats[worker_idx].iter_count = iter_count;
+                    if let Some(checkpointer) = &mut checkpointer {
+                        checkpointer.add_snapshot(worker_idx, tree, is_final);
+                    }
+                }
+                None => {}
+            }
+
+            // Save a checkpoint every so often.  Saving happens in two stages: first every thread
+            // is asked to send a snapshot of its frontier and pause, then (once all the snapshots
+            // have arrived) the checkpoint is written and the threads are allowed to continue.
+            if let Some(checkpointer) = &mut checkpointer {
+                let is_due = checkpointer.last_save.elapsed() >= search.config.checkpoint_interval;
+                if is_due && !checkpointer.in_progress && !shared.is_finished() {
+                    checkpointer.start(&shared);
+                }
+                if checkpointer.in_progress && checkpointer.has_all_snapshots() {
+                    let iter_count = total_iter_count(&worker_stats, base_iter_count);
+                    checkpointer.save(
+                        search,
+                        &shared,
+                        iter_count,
+                        &comp_paths,
+                        &ignored_comp_paths,
+                    );
+                    shared.end_checkpoint();
+                }
+            }
+        }
+    });
+
+    // Always save the final state of the search, so that it can be continued later (e.g. after an
+    // abort, or to generate more comps)
+    if let Some(checkpointer) = &mut checkpointer {
+        let iter_count = total_iter_count(&worker_stats, base_iter_count);
+        checkpointer.save(
+            search,
+            &shared,
+            iter_count,
+            &comp_paths,
+            &ignored_comp_paths,
+        );
+    }
+
+    // Always send a final update before finishing
+    for stats in &mut worker_stats {
+        stats.queue_state = QueueState::Normal;
+    }
+    send_progress_update(
+        &worker_stats,
+        base_iter_count,
+        &mut update_fn,
+        num_comps,
+        controls.abort_flag,
+    );
+
+    // Signal that the search is complete
+    update_fn(Update::Complete);
+}
+
+/// A single thread of the search, which owns one shard of the frontier
+struct Worker<'s> {
+    search: &'s Search,
+    shared: &'s Shared,
+    idx: usize,
+    msg_tx: Sender<WorkerMsg>,
+
+    paths: Paths,
+    frontier: BinaryHeap<CompPrefix>,
+    /// The lowest-scoring prefixes of this thread's frontier, which have been written to disk to
+    /// save memory.  `None` if [`Config::spill_dir`](super::Config::spill_dir) isn't set.
+    spilled: Option<SpilledFrontier>,
+    /// Number of bytes occupied by each `CompPrefix` in the frontier.
+    prefix_size: usize,
+    /// Factor by which the heap size of `paths` is multiplied when estimating memory usage
+    paths_size_factor: usize,
+    /// The number of bytes which this thread is allowed to use.  Each thread gets an equal share
+    /// of [`Config::mem_limit`](super::Config::mem_limit).
+    mem_limit: usize,
+    iter_count: usize,
+}
+
+impl<'s> Worker<'s> {
+    fn new(
+        search: &'s Search,
+        shared: &'s Shared,
+        idx: usize,
+        msg_tx: Sender<WorkerMsg>,
+        restored_frontier: Option<(Paths, BinaryHeap<CompPrefix>)>,
+    ) -> Self {
+        // Every thread adds *all* the starts to its `Paths` (so that prefixes can be moved between
+        // threads), but only keeps every `num_workers`th start prefix in its frontier
+        let mut paths = Paths::new();
+        let starts = CompPrefix::starts(search, &mut paths).into_vec();
+        let prefix_size = starts.first().map_or(0, CompPrefix::size);
+        let (paths, frontier) = match restored_frontier {
+            Some(restored_frontier) => restored_frontier,
+            None => {
+                let frontier = starts
+                    .into_iter()
+                    .enumerate()
+                    .filter(|(start_idx, _)| start_idx % shared.num_workers == idx)
+                    .map(|(_, prefix)| prefix)
+                    .collect::<BinaryHeap<_>>();
+                (paths, frontier)
+            }
+        };
 
-    // Repeatedly choose the best prefix and expand it (i.e. add each way of extending it to the
-    // frontier).  This is best-first search (and can be A* depending on the cost function used).
-    while let Some(prefix) = frontier.pop() {
-        let maybe_comp = prefix.expand(search, &mut paths, &mut frontier, num_comps);
+        Self {
+            search,
+            shared,
+            idx,
+            msg_tx,
 
-        // Submit new compositions when they're generated
-        if let Some(comp) = maybe_comp {
-            update_fn(Update::Comp(comp));
-            num_comps += 1;
+            paths,
+            frontier,
+            spilled: search.config.spill_dir.as_deref().map(SpilledFrontier::new),
+            prefix_size,
+            paths_size_factor: match search.config.checkpoint_file {
+                Some(_) => PATHS_SIZE_FACTOR_WITH_CHECKPOINTS,
+                None => 1,
+            },
+            mem_limit: search.config.mem_limit / shared.num_workers,
+            iter_count: 0,
+        }
+    }
+
+    fn run(mut self, controls: &Controls) {
+        // Repeatedly choose the best prefix and expand it (i.e. add each way of extending it to the
+        // frontier).  This is best-first search (and can be A* depending on the cost function
+        // used).
+        while let Some(prefix) = self.next_prefix() {
+            let path = prefix.path_head();
+            let maybe_comp = prefix.expand(
+                self.search,
+                &mut self.paths,
+                &mut self.frontier,
+                self.shared.num_comps.load(Ordering::Relaxed),
+            );
+
+            // Submit new compositions when they're generated
+            if let Some(comp) = maybe_comp {
+                self.send(WorkerMsg::Comp(Box::new(comp), self.paths.flatten(path)));
+            }
+
+            // If we end up using too much memory, half the size of the queue (either by writing
+            // the rest to disk or by throwing it away) and garbage-collect the paths.
+            if self.mem_usage() >= self.mem_limit {
+                self.shrink_frontier();
+            }
 
-            if num_comps == search.query.num_comps {
-                break; // Stop the search once we've got enough comps
+            self.iter_count += 1;
+
+            // Check for abort every so often
+            if self.iter_count.is_multiple_of(ITERS_BETWEEN_ABORT_CHECKS) && controls.should_abort()
+            {
+                self.shared.finish();
+                break;
+            }
+            // Give some work to any threads which have run out
+            if self
+                .iter_count
+                .is_multiple_of(ITERS_BETWEEN_WORK_SHARING_CHECKS)
+            {
+                self.share_work();
+            }
+            // Send stats every so often
+            if self
+                .iter_count
+                .is_multiple_of(ITERS_BETWEEN_PROGRESS_UPDATES)
+            {
+                self.send_stats(QueueState::Normal);
+            }
+            // Garbage-collect the paths every so often, even if we don't run out of memory
+            // (because otherwise the collection structure will keep expanding even if it contains
+            // a load of dead paths)
+            if self.iter_count.is_multiple_of(ITERS_BETWEEN_PATH_GCS) {
+                self.gc_paths();
             }
         }
 
-        // If we end up using too much memory, half the size of the queue and garbage-collect the
-        // paths.
-        let mem_usage = frontier.len() * prefix_size + paths.estimate_heap_size();
-        if mem_usage >= search.config.mem_limit {
-            send_progress_update!(truncating_queue = true);
-            truncate_queue(frontier.len() / 2, &mut frontier);
-            paths.gc(frontier.iter().map(|prefix| prefix.path_head()));
-            send_progress_update!(truncating_queue = false);
+        // Always send final stats (and the final state of the frontier) before finishing
+        self.send_stats(QueueState::Normal);
+        if self.search.config.checkpoint_file.is_some() {
+            self.send_snapshot(true);
         }
 
-        iter_count += 1;
+        // If we're running the CLI, then `mem::forget` the frontier to avoid tons of drop calls.
+        // We don't care about leaking because the Monument process is about to terminate and the
+        // OS will clean up the memory anyway.
+        if self.search.config.leak_search_memory {
+            std::mem::forget(self.frontier);
+        }
+    }
 
-        // Check for abort every so often
-        if iter_count % ITERS_BETWEEN_ABORT_CHECKS == 0 && abort_flag.load(Ordering::Relaxed) {
-            send_progress_update!(truncating_queue = false);
-            break;
+    /// Gets the next [`CompPrefix`] to expand, waiting for other threads to share their work if
+    /// this thread's frontier is empty.  Returns `None` once the search is finished.
+    fn next_prefix(&mut self) -> Option<CompPrefix> {
+        loop {
+            if self.shared.is_finished() {
+                return None;
+            }
+            // Pause while a checkpoint is being saved
+            if self.shared.checkpoint_requested.load(Ordering::Relaxed) {
+                self.send_snapshot(false);
+                self.shared.wait_for_checkpoint();
+                continue;
+            }
+            // Load prefixes from disk once they're better than the ones in memory
+            if let Some(spilled) = &mut self.spilled {
+                let best_spilled_score = spilled.best_score();
+                let best_score = self.frontier.peek().map(CompPrefix::avg_score);
+                if best_spilled_score.is_some() && best_score < best_spilled_score {
+                    let max_prefixes = (self.mem_limit / self.prefix_size.max(1) / 8)
+                        .clamp(1, MAX_PREFIXES_PER_RELOAD);
+                    spilled.reload(
+                        self.search,
+                        &mut self.paths,
+                        &mut self.frontier,
+                        max_prefixes,
+                    );
+                }
+            }
+            if let Some(prefix) = self.frontier.pop() {
+                return Some(prefix);
+            }
+            // Out of work, so wait for other threads to donate some prefixes
+            self.send_stats(QueueState::Normal);
+            match self.shared.wait_for_work() {
+                WaitResult::Work(donated_prefixes) => {
+                    for DonatedPrefix {
+                        mut prefix,
+                        start_idx,
+                        succs,
+                    } in donated_prefixes
+                    {
+                        prefix.set_path_head(self.paths.add_flattened(start_idx, &succs));
+                        self.frontier.push(prefix);
+                    }
+                }
+                WaitResult::Checkpoint => {} // Handled at the top of the loop
+                WaitResult::Finished => return None,
+            }
         }
-        // Send stats every so often
-        if iter_count % ITERS_BETWEEN_PROGRESS_UPDATES == 0 {
-            send_progress_update!(truncating_queue = false);
+    }
+
+    fn mem_usage(&self) -> usize {
+        self.frontier.len() * self.prefix_size
+            + self.paths.estimate_heap_size() * self.paths_size_factor
+    }
+
+    /// Remove the worst half of the frontier, either by writing it to disk or (if
+    /// [`Config::spill_dir`](super::Config::spill_dir) isn't set) by throwing it away.
+    fn shrink_frontier(&mut self) {
+        self.send_stats(match self.spilled {
+            Some(_) => QueueState::Spilling,
+            None => QueueState::Truncating,
+        });
+        match &mut self.spilled {
+            Some(spilled) => {
+                let mut prefixes = std::mem::take(&mut self.frontier).into_vec();
+                prefixes.sort_by(|a, b| b.cmp(a)); // Sort highest score first
+                let worst_prefixes = prefixes.split_off(prefixes.len() / 2);
+                if let Err(e) = spilled.spill(&worst_prefixes, &self.paths) {
+                    log::warn!(
+                        "Couldn't write prefixes to {:?}, so some have been dropped: {e}",
+                        spilled.dir()
+                    );
+                }
+                self.frontier = BinaryHeap::from(prefixes);
+            }
+            None => truncate_queue(self.frontier.len() / 2, &mut self.frontier),
+        }
+        self.gc_paths();
+        self.send_stats(QueueState::Normal);
+    }
+
+    /// If some other threads are waiting for work, then donate some of our best prefixes to them.
+    fn share_work(&mut self) {
+        if self.shared.num_idle_workers.load(Ordering::Relaxed) == 0 {
+            return;
         }
-        // Garbage-collect the paths every so often, even if we don't run out of memory (because
-        // otherwise the collection structure will keep expanding even if it contains a load of
-        // dead paths)
-        if iter_count % ITERS_BETWEEN_PATH_GCS == 0 {
-            paths.gc(frontier.iter().map(|prefix| prefix.path_head()));
+        let num_to_donate = (self.frontier.len() / 2).min(MAX_PREFIXES_PER_DONATION);
+        if num_to_donate == 0 {
+            return;
         }
+        self.shared.donate(|| {
+            // Give away every other one of our best prefixes, so that every thread keeps working
+            // on high-scoring prefixes
+            let mut kept_prefixes = Vec::with_capacity(num_to_donate);
+            let mut donated_prefixes = Vec::with_capacity(num_to_donate);
+            for _ in 0..num_to_donate {
+                kept_prefixes.extend(self.frontier.pop());
+                let prefix = self.frontier.pop().unwrap();
+                let (start_idx, succs) = self.paths.flatten(prefix.path_head());
+                donated_prefixes.push(DonatedPrefix {
+                    prefix,
+                    start_idx,
+                    succs,
+                });
+            }
+            self.frontier.extend(kept_prefixes);
+            donated_prefixes
+        });
     }
 
-    // Always send a final update before finishing
-    send_progress_update!(truncating_queue = false);
+    fn gc_paths(&mut self) {
+        self.paths
+            .gc(self.frontier.iter().map(|prefix| prefix.path_head()));
+    }
 
-    // If we're running the CLI, then `mem::forget` the frontier to avoid tons of drop calls.  We
-    // don't care about leaking because the Monument process is about to terminate and the OS will
-    // clean up the memory anyway.
-    if search.config.leak_search_memory {
-        std::mem::forget(frontier);
+    fn send_stats(&self, queue_state: QueueState) {
+        let mut total_len = 0u64; // NOTE: We have use `u64` here to avoid overflow
+        let mut max_length = TotalLength::ZERO;
+        self.frontier.iter().for_each(|n| {
+            total_len += n.length().as_usize() as u64;
+            max_length = max_length.max(n.length());
+        });
+        let mut queue_len = self.frontier.len();
+        let mut max_length = max_length.as_usize();
+        if let Some(spilled) = &self.spilled {
+            queue_len += spilled.len();
+            total_len += spilled.total_len();
+            max_length = max_length.max(spilled.max_length());
+        }
+        self.send(WorkerMsg::Stats {
+            worker_idx: self.idx,
+            stats: WorkerStats {
+                iter_count: self.iter_count,
+                queue_len,
+                total_len,
+                max_length,
+                queue_state,
+            },
+        });
     }
 
-    // Signal that the search is complete
-    update_fn(Update::Complete);
+    fn send_snapshot(&self, is_final: bool) {
+        let mut tree = self
+            .paths
+            .to_tree(self.frontier.iter().map(|prefix| prefix.path_head()));
+        if let Some(spilled) = &self.spilled {
+            spilled.add_to_tree(&mut tree);
+        }
+        self.send(WorkerMsg::Snapshot {
+            worker_idx: self.idx,
+            iter_count: self.iter_count,
+            tree,
+            is_final,
+        });
+    }
+
+    fn send(&self, msg: WorkerMsg) {
+        // The receiver lives until every worker has finished, so this can't fail
+        self.msg_tx
+            .send(msg)
+            .expect("Search thread's receiver was dropped");
+    }
+}
+
+/// State shared between all the search threads
+struct Shared {
+    num_workers: usize,
+    /// The number of compositions which have been passed to `update_fn`.  This is only written by
+    /// the calling thread
+    num_comps: AtomicUsize,
+    /// Set once all threads should stop (either because of an abort, because enough comps have
+    /// been found, or because every thread has run out of prefixes)
+    is_finished: AtomicBool,
+    /// Set while a checkpoint is being saved.  While this is set, every thread sends a snapshot
+    /// of its frontier and then pauses.  Only modified with `donated_prefixes` locked
+    checkpoint_requested: AtomicBool,
+    /// The number of threads which are waiting for work.  Only modified with `donated_prefixes`
+    /// locked
+    num_idle_workers: AtomicUsize,
+    /// Prefixes which have been given away by a busy thread, waiting to be picked up by an idle
+    /// one
+    donated_prefixes: Mutex<Vec<DonatedPrefix>>,
+    /// Notified when `donated_prefixes` is filled, a checkpoint is requested or the search
+    /// finishes
+    work_available: Condvar,
+    /// Notified when a checkpoint has finished saving
+    checkpoint_saved: Condvar,
+}
+
+/// The ways that [`Shared::wait_for_work`] can return
+enum WaitResult {
+    Work(Vec<DonatedPrefix>),
+    Checkpoint,
+    Finished,
+}
+
+impl Shared {
+    fn new(num_workers: usize) -> Self {
+        Self {
+            num_workers,
+            num_comps: AtomicUsize::new(0),
+            is_finished: AtomicBool::new(false),
+            checkpoint_requested: AtomicBool::new(false),
+            num_idle_workers: AtomicUsize::new(0),
+            donated_prefixes: Mutex::new(Vec::new()),
+            work_available: Condvar::new(),
+            checkpoint_saved: Condvar::new(),
+        }
+    }
+
+    fn is_finished(&self) -> bool {
+        self.is_finished.load(Ordering::Relaxed)
+    }
+
+    /// Tell all threads to stop searching
+    fn finish(&self) {
+        let _donated_prefixes = self.donated_prefixes.lock().unwrap();
+        self.is_finished.store(true, Ordering::Relaxed);
+        self.work_available.notify_all();
+    }
+
+    /// Add some prefixes to the donation pool, but only if there aren't already prefixes waiting
+    /// to be picked up.
+    fn donate(&self, get_prefixes: impl FnOnce() -> Vec<DonatedPrefix>) {
+        let mut donated_prefixes = self.donated_prefixes.lock().unwrap();
+        if donated_prefixes.is_empty() {
+            *donated_prefixes = get_prefixes();
+            self.work_available.notify_all();
+        }
+    }
+
+    /// Block until some prefixes are donated to this thread, a checkpoint is requested or the
+    /// search finishes.
+    fn wait_for_work(&self) -> WaitResult {
+        let mut donated_prefixes = self.donated_prefixes.lock().unwrap();
+        self.num_idle_workers.fetch_add(1, Ordering::Relaxed);
+        loop {
+            if self.is_finished() {
+                return WaitResult::Finished;
+            }
+            if self.checkpoint_requested.load(Ordering::Relaxed) {
+                self.num_idle_workers.fetch_sub(1, Ordering::Relaxed);
+                return WaitResult::Checkpoint;
+            }
+            if !donated_prefixes.is_empty() {
+                self.num_idle_workers.fetch_sub(1, Ordering::Relaxed);
+                return WaitResult::Work(std::mem::take(&mut *donated_prefixes));
+            }
+            // If every thread is waiting for work, then the whole frontier is empty and the search
+            // is exhausted
+            if self.num_idle_workers.load(Ordering::Relaxed) == self.num_workers {
+                self.is_finished.store(true, Ordering::Relaxed);
+                self.work_available.notify_all();
+                return WaitResult::Finished;
+            }
+            donated_prefixes = self.work_available.wait(donated_prefixes).unwrap();
+        }
+    }
+
+    /// Ask every thread to send a snapshot of its frontier, then pause until
+    /// [`Shared::end_checkpoint`] is called
+    fn request_checkpoint(&self) {
+        let _donated_prefixes = self.donated_prefixes.lock().unwrap();
+        self.checkpoint_requested.store(true, Ordering::Relaxed);
+        self.work_available.notify_all();
+    }
+
+    /// Block until the current checkpoint has been saved
+    fn wait_for_checkpoint(&self) {
+        let mut donated_prefixes = self.donated_prefixes.lock().unwrap();
+        while self.checkpoint_requested.load(Ordering::Relaxed) {
+            donated_prefixes = self.checkpoint_saved.wait(donated_prefixes).unwrap();
+        }
+    }
+
+    /// Let paused threads continue searching after a checkpoint has been saved
+    fn end_checkpoint(&self) {
+        let _donated_prefixes = self.donated_prefixes.lock().unwrap();
+        self.checkpoint_requested.store(false, Ordering::Relaxed);
+        self.checkpoint_saved.notify_all();
+    }
+}
+
+/// A [`CompPrefix`] which is being moved between threads.  Each thread has its own [`Paths`], so
+/// the prefix's path is stored in flattened form.
+struct DonatedPrefix {
+    prefix: CompPrefix,
+    start_idx: StartIdx,
+    succs: Vec<SuccIdx>,
+}
+
+/// Messages sent from the worker threads to the calling thread
+enum WorkerMsg {
+    /// A new composition.  This is boxed because `Composition`s are much larger than the other
+    /// messages.
+    Comp(Box<Composition>, FlatPath),
+    Stats {
+        worker_idx: usize,
+        stats: WorkerStats,
+    },
+    /// The paths of every prefix in a thread's frontier.  Sent when a checkpoint is requested,
+    /// and when the thread finishes (with `is_final` set).
+    Snapshot {
+        worker_idx: usize,
+        iter_count: usize,
+        tree: PathTree,
+        is_final: bool,
+    },
+}
+
+/// The most recent statistics about one worker thread's frontier
+#[derive(Debug, Clone, Default)]
+struct WorkerStats {
+    iter_count: usize,
+    queue_len: usize,
+    total_len: u64,
+    max_length: usize,
+    queue_state: QueueState,
+}
+
+/// What a worker thread is doing to reduce its memory usage
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+enum QueueState {
+    #[default]
+    Normal,
+    /// Throwing away the worst half of its frontier
+    Truncating,
+    /// Writing the worst half of its frontier to disk
+    Spilling,
+}
+
+/// Collects the frontier snapshots sent by the worker threads, and writes them to the checkpoint
+/// file
+struct Checkpointer<'p> {
+    path: &'p Path,
+    /// The most recent snapshot from each thread
+    snapshots: Vec<Option<PathTree>>,
+    /// For each thread, `true` if that thread has finished (so its snapshot won't change)
+    finished: Vec<bool>,
+    in_progress: bool,
+    last_save: Instant,
+}
+
+impl<'p> Checkpointer<'p> {
+    fn new(path: &'p Path, num_workers: usize) -> Self {
+        Self {
+            path,
+            snapshots: (0..num_workers).map(|_| None).collect(),
+            finished: vec![false; num_workers],
+            in_progress: false,
+            last_save: Instant::now(),
+        }
+    }
+
+    fn start(&mut self, shared: &Shared) {
+        // Threads which have finished won't send any more snapshots, so keep their final ones
+        for (snapshot, is_finished) in self.snapshots.iter_mut().zip(&self.finished) {
+            if !is_finished {
+                *snapshot = None;
+            }
+        }
+        self.in_progress = true;
+        shared.request_checkpoint();
+    }
+
+    fn add_snapshot(&mut self, worker_idx: usize, tree: PathTree, is_final: bool) {
+        self.snapshots[worker_idx] = Some(tree);
+        self.finished[worker_idx] |= is_final;
+    }
+
+    fn has_all_snapshots(&self) -> bool {
+        self.snapshots.iter().all(Option::is_some)
+    }
+
+    /// Write the snapshots to the checkpoint file.  This must only be called when every thread
+    /// has either paused or finished, so that no prefixes are in the middle of moving between
+    /// threads.  The prefixes which generated `ignored_comps` are added back to the frontier.
+    fn save(
+        &mut self,
+        search: &Search,
+        shared: &Shared,
+        iter_count: usize,
+        comps: &[FlatPath],
+        ignored_comps: &[FlatPath],
+    ) {
+        let donated_prefixes = shared.donated_prefixes.lock().unwrap();
+        let extra_tree = PathTree::from_flat_paths(
+            (donated_prefixes.iter())
+                .map(|p| (p.start_idx, p.succs.as_slice()))
+                .chain(
+                    ignored_comps
+                        .iter()
+                        .map(|(s, succs)| (*s, succs.as_slice())),
+                ),
+        );
+        let frontier = self.snapshots.iter().flatten().chain([&extra_tree]);
+        match Checkpoint::save(search, self.path, iter_count as u64, comps, frontier) {
+            Ok(()) => log::debug!("Saved checkpoint to {:?}", self.path),
+            Err(e) => log::warn!("Couldn't save checkpoint: {e}"),
+        }
+        self.in_progress = false;
+        self.last_save = Instant::now();
+    }
+}
+
+fn total_iter_count(worker_stats: &[WorkerStats], base_iter_count: usize) -> usize {
+    base_iter_count + worker_stats.iter().map(|s| s.iter_count).sum::<usize>()
 }
 
 fn send_progress_update(
-    frontier: &BinaryHeap<CompPrefix>,
+    worker_stats: &[WorkerStats],
+    base_iter_count: usize,
     update_fn: &mut impl FnMut(Update),
-    iter_count: usize,
     num_comps: usize,
-    truncating_queue: bool,
-    aborting: bool,
+    abort_flag: &AtomicBool,
 ) {
-    let mut total_len = 0u64; // NOTE: We have use `u64` here to avoid overflow
-    let mut max_length = TotalLength::ZERO;
-    frontier.iter().for_each(|n| {
-        total_len += n.length().as_usize() as u64;
-        max_length = max_length.max(n.length());
-    });
+    let queue_len = worker_stats.iter().map(|s| s.queue_len).sum::<usize>();
+    let total_len = worker_stats.iter().map(|s| s.total_len).sum::<u64>();
+    let aborting = abort_flag.load(Ordering::SeqCst);
+    let is_in_state = |state| worker_stats.iter().any(|s| s.queue_state == state);
     update_fn(Update::Progress(Progress {
-        iter_count,
+        iter_count: total_iter_count(worker_stats, base_iter_count),
         num_comps,
 
-        queue_len: frontier.len(),
-        avg_length: if frontier.is_empty() {
+        queue_len,
+        avg_length: if queue_len == 0 {
             0.0 // Avoid returning `NaN` if the frontier is empty
         } else {
-            total_len as f32 / frontier.len() as f32
+            total_len as f32 / queue_len as f32
         },
-        max_length: max_length.as_usize(),
+        max_length: worker_stats.iter().map(|s| s.max_length).max().unwrap_or(0),
 
-        truncating_queue,
+        // Other threads can still be truncating their queues after an abort is signalled, but
+        // `Progress` only reports one of these at once
+        truncating_queue: !aborting && is_in_state(QueueState::Truncating),
+        spilling_queue: !aborting && is_in_state(QueueState::Spilling),
         aborting,
     }));
 }
@@ -149,3 +816,103 @@ fn truncate_queue<T: Ord>(len: usize, queue: &mut BinaryHeap<T>) {
     }
     *queue = BinaryHeap::from(chunks);
 }
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use crate::{
+        composition::CanonicalForm, parameters::Parameters, test_utils, Composition, Config,
+    };
+
+    /// Touches of Plain Bob Minor, up to two courses long
+    fn plain_bob_minor(num_comps: usize) -> Parameters {
+        Parameters {
+            num_comps,
+            ..test_utils::plain_bob_minor()
+        }
+    }
+
+    fn run(num_comps: usize, num_threads: usize) -> Vec<Composition> {
+        let config = Config {
+            thread_limit: Some(num_threads),
+            ..Default::default()
+        };
+        plain_bob_minor(num_comps).run_with_config(config).unwrap()
+    }
+
+    /// The canonical forms of `comps`.  Unlike the `Composition`s themselves, these can be compared
+    /// between different searches.
+    fn canonical_forms(comps: &[Composition]) -> HashSet<CanonicalForm> {
+        comps
+            .iter()
+            .map(|comp| comp.canonical_form.as_ref().clone())
+            .collect()
+    }
+
+    #[test]
+    fn spilling_keeps_every_prefix() {
+        let spill_dir = std::env::temp_dir().join(format!(
+            "monument-test-{}-best-first-spill",
+            std::process::id()
+        ));
+        let all_comps = run(10_000, 1);
+        // With a tiny memory limit, the search will spend most of its time writing prefixes to
+        // disk (often enough that the runs get merged) and reading them back.  It should still find
+        // every comp, in the same order.
+        let config = Config {
+            thread_limit: Some(1),
+            mem_limit: 2_000,
+            spill_dir: Some(spill_dir.clone()),
+            ..Default::default()
+        };
+        let spilled_comps = plain_bob_minor(10_000).run_with_config(config).unwrap();
+        let scores = |comps: &[Composition]| {
+            comps
+                .iter()
+                .map(Composition::average_score)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(scores(&spilled_comps), scores(&all_comps));
+        assert_eq!(canonical_forms(&spilled_comps), canonical_forms(&all_comps));
+        // Every run should have been deleted
+        assert_eq!(std::fs::read_dir(&spill_dir).unwrap().count(), 0);
+        std::fs::remove_dir(&spill_dir).unwrap();
+    }
+
+    #[test]
+    fn stops_when_frontier_is_empty() {
+        // There are far fewer than 10,000 possible touches, so the search can only finish by
+        // running out of prefixes
+        let comps = run(10_000, 1);
+        assert!(!comps.is_empty());
+        assert!(comps.len() < 10_000);
+        // Every touch should be generated exactly once
+        let num_comps = comps.len();
+        assert_eq!(comps.into_iter().collect::<HashSet<_>>().len(), num_comps);
+    }
+
+    #[test]
+    fn same_comps_on_several_threads() {
+        let single_threaded = run(10_000, 1);
+        let multi_threaded = run(10_000, 4);
+
+        // Both searches should agree on the best compositions
+        let best_scores = |comps: &[Composition]| {
+            let mut scores = comps
+                .iter()
+                .map(Composition::average_score)
+                .collect::<Vec<_>>();
+            scores.sort_by(|a, b| b.total_cmp(a));
+            scores.truncate(10);
+            scores
+        };
+        assert_eq!(best_scores(&single_threaded), best_scores(&multi_threaded));
+        // Rotations of a comp have the same canonical form, so this doesn't depend on which thread
+        // found which rotation first
+        assert_eq!(
+            canonical_forms(&single_threaded),
+            canonical_forms(&multi_threaded)
+        );
+    }
+}
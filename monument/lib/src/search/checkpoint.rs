@@ -0,0 +1,689 @@
+//! Code for saving the state of a [`Search`] to disk, and resuming it later.
+//!
+//! A checkpoint doesn't store [`CompPrefix`]es directly (they contain large bitmaps which can be
+//! regenerated).  Instead, it stores the paths taken through the [`Graph`](super::graph::Graph)
+//! by every prefix, compressed into [`PathTree`]s.  When resuming, the prefixes are rebuilt by
+//! walking through these trees.  Because [`StartIdx`]s and [`SuccIdx`]s are only meaningful
+//! within one graph, every checkpoint contains a fingerprint of the graph and the parts of the
+//! [`Query`](crate::query::Query) used during the search.  Checkpoints with different fingerprints
+//! are rejected.
+
+use std::{
+    collections::BinaryHeap,
+    hash::{Hash, Hasher},
+    io::{BufReader, BufWriter, Read, Write},
+    path::Path,
+};
+
+use crate::Composition;
+
+use super::{
+    graph::{StartIdx, SuccIdx},
+    path::{PathId, Paths},
+    prefix::CompPrefix,
+    Search,
+};
+
+/// Bytes at the start of every checkpoint file
+const MAGIC: &[u8; 8] = b"MNMTCKPT";
+/// Version of the checkpoint format.  Incremented whenever the format changes.
+const VERSION: u32 = 1;
+
+/// The state of a [`Search`], as stored in a checkpoint file
+#[derive(Debug)]
+pub(super) struct Checkpoint {
+    /// The total number of prefixes expanded so far
+    pub iter_count: u64,
+    /// The paths of all the [`Composition`]s emitted so far, in the order they were emitted
+    pub comps: Vec<FlatPath>,
+    /// The paths of all the prefixes in the frontier
+    pub frontier: Vec<PathTree>,
+}
+
+/// A single path through the graph, as returned by [`Paths::flatten`]
+pub(super) type FlatPath = (StartIdx, Vec<SuccIdx>);
+
+/// A set of paths, stored as a tree such that common prefixes are only stored once.  Every node's
+/// parent comes before it in `nodes`.
+#[derive(Debug, Default)]
+pub(super) struct PathTree {
+    pub nodes: Vec<TreeNode>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(super) struct TreeNode {
+    /// The node which this extends, or `None` if this node is a start
+    pub parent: Option<u32>,
+    /// The [`StartIdx`] (if `parent` is `None`) or [`SuccIdx`] (otherwise) of this node
+    pub value: u32,
+    /// The number of prefixes in the frontier which finish at this node
+    pub num_prefixes: u32,
+}
+
+impl PathTree {
+    /// Creates a `PathTree` containing some flattened paths
+    pub fn from_flat_paths<'p>(paths: impl IntoIterator<Item = (StartIdx, &'p [SuccIdx])>) -> Self {
+        let mut tree = Self::default();
+        for (start_idx, succs) in paths {
+            tree.add_flat_path(start_idx, succs);
+        }
+        tree
+    }
+
+    /// Adds a single flattened path to this tree.  The path doesn't share any nodes with the
+    /// paths which are already in the tree.
+    pub fn add_flat_path(&mut self, start_idx: StartIdx, succs: &[SuccIdx]) {
+        self.nodes.push(TreeNode {
+            parent: None,
+            value: start_idx.raw(),
+            num_prefixes: 0,
+        });
+        for succ in succs {
+            self.nodes.push(TreeNode {
+                parent: Some(self.nodes.len() as u32 - 1),
+                value: succ.raw(),
+                num_prefixes: 0,
+            });
+        }
+        self.nodes.last_mut().unwrap().num_prefixes += 1;
+    }
+}
+
+impl Checkpoint {
+    /// Write a checkpoint to a file.  The checkpoint is first written to a temporary file which
+    /// then replaces `path`, so an existing checkpoint is never left half-written.
+    ///
+    /// This takes the parts of a [`Checkpoint`] by reference, so that the search's state doesn't
+    /// have to be copied whenever a checkpoint is saved.
+    pub fn save<'t>(
+        search: &Search,
+        path: &Path,
+        iter_count: u64,
+        comps: &[FlatPath],
+        frontier: impl IntoIterator<Item = &'t PathTree>,
+    ) -> crate::Result<()> {
+        let io_error = |error| crate::Error::CheckpointIo {
+            path: path.to_owned(),
+            error,
+        };
+
+        let mut temp_path = path.as_os_str().to_owned();
+        temp_path.push(".tmp");
+        let file = std::fs::File::create(&temp_path).map_err(io_error)?;
+        let mut w = BufWriter::new(file);
+        let frontier = frontier.into_iter().collect::<Vec<_>>();
+        Self::write(search, iter_count, comps, &frontier, &mut w).map_err(io_error)?;
+        w.into_inner()
+            .map_err(|e| io_error(e.into_error()))?
+            .sync_all()
+            .map_err(io_error)?;
+        std::fs::rename(&temp_path, path).map_err(io_error)
+    }
+
+    /// Load a `Checkpoint` which was previously written by [`Checkpoint::save`] by a [`Search`]
+    /// with the same fingerprint as `search`.
+    pub fn load(search: &Search, path: &Path) -> crate::Result<Self> {
+        let file = std::fs::File::open(path).map_err(|error| crate::Error::CheckpointIo {
+            path: path.to_owned(),
+            error,
+        })?;
+        let mut r = BufReader::new(file);
+
+        let mut magic = [0u8; 8];
+        let header = (|| -> std::io::Result<_> {
+            r.read_exact(&mut magic)?;
+            Ok((read_u32(&mut r)?, read_u64(&mut r)?))
+        })();
+        match header {
+            Ok((VERSION, fingerprint)) if &magic == MAGIC => {
+                if fingerprint != search_fingerprint(search) {
+                    return Err(crate::Error::StaleCheckpoint(path.to_owned()));
+                }
+            }
+            _ => return Err(crate::Error::InvalidCheckpoint(path.to_owned())),
+        }
+
+        Self::read_body(&mut r, search)
+            .ok_or_else(|| crate::Error::InvalidCheckpoint(path.to_owned()))
+    }
+
+    fn write(
+        search: &Search,
+        iter_count: u64,
+        comps: &[FlatPath],
+        frontier: &[&PathTree],
+        w: &mut impl Write,
+    ) -> std::io::Result<()> {
+        w.write_all(MAGIC)?;
+        w.write_all(&VERSION.to_le_bytes())?;
+        w.write_all(&search_fingerprint(search).to_le_bytes())?;
+
+        w.write_all(&iter_count.to_le_bytes())?;
+        w.write_all(&(comps.len() as u32).to_le_bytes())?;
+        for (start_idx, succs) in comps {
+            w.write_all(&start_idx.raw().to_le_bytes())?;
+            w.write_all(&(succs.len() as u32).to_le_bytes())?;
+            for succ in succs {
+                w.write_all(&succ.raw().to_le_bytes())?;
+            }
+        }
+        w.write_all(&(frontier.len() as u32).to_le_bytes())?;
+        for tree in frontier {
+            w.write_all(&(tree.nodes.len() as u32).to_le_bytes())?;
+            for node in &tree.nodes {
+                w.write_all(&node.parent.unwrap_or(u32::MAX).to_le_bytes())?;
+                w.write_all(&node.value.to_le_bytes())?;
+                w.write_all(&node.num_prefixes.to_le_bytes())?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Read everything after the header, returning `None` if the data is malformed
+    fn read_body(r: &mut impl Read, search: &Search) -> Option<Self> {
+        let num_starts = search.graph.starts.len() as u32;
+
+        let iter_count = read_u64(r).ok()?;
+        let num_comps = read_u32(r).ok()?;
+        let mut comps = Vec::new();
+        for _ in 0..num_comps {
+            let start_idx = read_u32(r).ok()?;
+            if start_idx >= num_starts {
+                return None;
+            }
+            let num_succs = read_u32(r).ok()?;
+            let mut succs = Vec::new();
+            for _ in 0..num_succs {
+                succs.push(SuccIdx::from_raw(read_u32(r).ok()?));
+            }
+            comps.push((StartIdx::from_raw(start_idx), succs));
+        }
+
+        let num_trees = read_u32(r).ok()?;
+        let mut frontier = Vec::new();
+        for _ in 0..num_trees {
+            let num_nodes = read_u32(r).ok()?;
+            let mut nodes = Vec::new();
+            for node_idx in 0..num_nodes {
+                let parent = match read_u32(r).ok()? {
+                    u32::MAX => None,
+                    p if p < node_idx => Some(p),
+                    _ => return None, // Parents must come before their children
+                };
+                let value = read_u32(r).ok()?;
+                if parent.is_none() && value >= num_starts {
+                    return None;
+                }
+                let num_prefixes = read_u32(r).ok()?;
+                nodes.push(TreeNode {
+                    parent,
+                    value,
+                    num_prefixes,
+                });
+            }
+            frontier.push(PathTree { nodes });
+        }
+
+        // Check that we've reached the end of the file
+        if r.read(&mut [0u8]).ok()? != 0 {
+            return None;
+        }
+
+        Some(Self {
+            iter_count,
+            comps,
+            frontier,
+        })
+    }
+}
+
+pub(super) fn read_u32(r: &mut impl Read) -> std::io::Result<u32> {
+    let mut bytes = [0u8; 4];
+    r.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn read_u64(r: &mut impl Read) -> std::io::Result<u64> {
+    let mut bytes = [0u8; 8];
+    r.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+///////////////
+// RESTORING //
+///////////////
+
+/// A [`Checkpoint`] which has been converted back into the data structures used by the search
+pub(super) struct RestoredState {
+    pub iter_count: usize,
+    /// Every [`Composition`] emitted before the checkpoint, along with its path
+    pub comps: Vec<(Composition, FlatPath)>,
+    /// The frontier, split between the search threads
+    pub frontiers: Vec<(Paths, BinaryHeap<CompPrefix>)>,
+}
+
+impl Checkpoint {
+    /// Regenerate the compositions and prefixes stored in this `Checkpoint`, splitting the
+    /// prefixes into `num_shards` frontiers.  Returns `None` if the checkpoint doesn't describe a
+    /// valid state of `search`.
+    pub fn restore(self, search: &Search, num_shards: usize) -> Option<RestoredState> {
+        let mut comps = Vec::new();
+        for (generation_number, path) in self.comps.into_iter().enumerate() {
+            comps.push((restore_comp(search, &path, generation_number)?, path));
+        }
+        Some(RestoredState {
+            iter_count: self.iter_count as usize,
+            comps,
+            frontiers: restore_frontiers(search, &self.frontier, num_shards)?,
+        })
+    }
+}
+
+/// Regenerate the [`Composition`] at the end of a flattened path.  Returns `None` if the path
+/// doesn't generate a valid [`Composition`] in this [`Search`].
+fn restore_comp(
+    search: &Search,
+    (start_idx, succs): &FlatPath,
+    generation_number: usize,
+) -> Option<Composition> {
+    let mut paths = paths_with_starts(search);
+    let mut prefix = CompPrefix::start(search, *start_idx, paths.start_path(*start_idx));
+    for &succ_idx in succs {
+        let last_path = prefix.path_head();
+        prefix = prefix.follow(search, succ_idx)?;
+        prefix.set_path_head(paths.add(last_path, succ_idx));
+    }
+    if !prefix.is_finished() {
+        return None;
+    }
+    prefix.expand(
+        search,
+        &mut paths,
+        &mut BinaryHeap::new(),
+        generation_number,
+    )
+}
+
+/// Rebuild the prefixes stored in some [`PathTree`]s, dealing them out between `num_shards`
+/// frontiers (each with its own [`Paths`]).  Returns `None` if the trees don't describe valid
+/// prefixes for this [`Search`].
+fn restore_frontiers(
+    search: &Search,
+    trees: &[PathTree],
+    num_shards: usize,
+) -> Option<Vec<(Paths, BinaryHeap<CompPrefix>)>> {
+    let mut restorer = Restorer {
+        search,
+        shards: (0..num_shards)
+            .map(|_| (paths_with_starts(search), BinaryHeap::new()))
+            .collect(),
+        next_shard: 0,
+        stack: Vec::new(),
+    };
+    for tree in trees {
+        let mut children = vec![Vec::new(); tree.nodes.len()];
+        for (node_idx, node) in tree.nodes.iter().enumerate() {
+            if let Some(parent) = node.parent {
+                children[parent as usize].push(node_idx);
+            }
+        }
+        for (node_idx, node) in tree.nodes.iter().enumerate() {
+            if node.parent.is_none() {
+                let start_idx = StartIdx::from_raw(node.value);
+                let prefix = CompPrefix::start(search, start_idx, PathId::from_raw(0));
+                restorer.visit(tree, &children, node_idx, prefix)?;
+            }
+        }
+    }
+    Some(restorer.shards)
+}
+
+/// Walks over [`PathTree`]s, regenerating the [`CompPrefix`]es at each node
+struct Restorer<'s> {
+    search: &'s Search,
+    shards: Vec<(Paths, BinaryHeap<CompPrefix>)>,
+    /// The shard which will be given the next prefix
+    next_shard: usize,
+    /// For every node on the path from the root to the current node, the node's value and its
+    /// [`PathId`] within each shard (if it has been added to that shard).
+    stack: Vec<(u32, Vec<Option<PathId>>)>,
+}
+
+impl Restorer<'_> {
+    fn visit(
+        &mut self,
+        tree: &PathTree,
+        children: &[Vec<usize>],
+        node_idx: usize,
+        prefix: CompPrefix,
+    ) -> Option<()> {
+        let node = tree.nodes[node_idx];
+        self.stack.push((node.value, vec![None; self.shards.len()]));
+
+        // Add the prefixes which finish at this node
+        for _ in 0..node.num_prefixes {
+            let shard_idx = self.next_shard;
+            self.next_shard = (self.next_shard + 1) % self.shards.len();
+
+            let mut shard_prefix = prefix.clone();
+            shard_prefix.set_path_head(self.path_in_shard(shard_idx));
+            self.shards[shard_idx].1.push(shard_prefix);
+        }
+        // Visit this node's children
+        for &child_idx in &children[node_idx] {
+            let succ_idx = SuccIdx::from_raw(tree.nodes[child_idx].value);
+            let child_prefix = prefix.clone().follow(self.search, succ_idx)?;
+            self.visit(tree, children, child_idx, child_prefix)?;
+        }
+
+        self.stack.pop();
+        Some(())
+    }
+
+    /// Gets the [`PathId`] of the current node within a given shard's [`Paths`], adding the
+    /// current node (and any of its ancestors) if needed.
+    fn path_in_shard(&mut self, shard_idx: usize) -> PathId {
+        let paths = &mut self.shards[shard_idx].0;
+        let first_missing = self
+            .stack
+            .iter()
+            .rposition(|(_, path_ids)| path_ids[shard_idx].is_some())
+            .map_or(0, |idx| idx + 1);
+        for idx in first_missing..self.stack.len() {
+            let value = self.stack[idx].0;
+            let path_id = match idx {
+                0 => paths.start_path(StartIdx::from_raw(value)),
+                _ => {
+                    let last = self.stack[idx - 1].1[shard_idx].unwrap();
+                    paths.add(last, SuccIdx::from_raw(value))
+                }
+            };
+            self.stack[idx].1[shard_idx] = Some(path_id);
+        }
+        self.stack.last().unwrap().1[shard_idx].unwrap()
+    }
+}
+
+/// Creates a set of [`Paths`] containing a start node for every start in the [`Search`]'s graph
+pub(super) fn paths_with_starts(search: &Search) -> Paths {
+    let mut paths = Paths::new();
+    for start_idx in search.graph.starts.indices() {
+        paths.add_start(start_idx);
+    }
+    paths
+}
+
+/////////////////
+// FINGERPRINT //
+/////////////////
+
+/// Computes a hash of everything that determines how a [`Search`] expands its prefixes.  If two
+/// [`Search`]es have the same fingerprint, then prefixes from one can be used in the other.
+fn search_fingerprint(search: &Search) -> u64 {
+    let mut hasher = FnvHasher::default();
+
+    for chunk in &search.graph.chunks {
+        chunk.id.hash(&mut hasher);
+        chunk.score.to_bits().hash(&mut hasher);
+        chunk.per_part_length.hash(&mut hasher);
+        chunk.total_length.hash(&mut hasher);
+        chunk.method_counts.hash(&mut hasher);
+        chunk.required_course_leads.hash(&mut hasher);
+        chunk.scheduled_music.hash(&mut hasher);
+        chunk.music_counts.hash(&mut hasher);
+        chunk.min_len_to_rounds.hash(&mut hasher);
+        chunk.duffer.hash(&mut hasher);
+        chunk.min_dist_to_non_duffer.hash(&mut hasher);
+        chunk.falseness.hash(&mut hasher);
+        for link in &chunk.succs {
+            link.call.hash(&mut hasher);
+            link.next.hash(&mut hasher);
+            link.score.to_bits().hash(&mut hasher);
+            link.is_splice.hash(&mut hasher);
+            link.ph_rotation.hash(&mut hasher);
+            link.wrap_counts.hash(&mut hasher);
+        }
+    }
+    for (chunk_idx, _link_id, part_head) in &search.graph.starts {
+        chunk_idx.hash(&mut hasher);
+        part_head.hash(&mut hasher);
+    }
+
+    let query = &search.query;
+    search.refined_ranges.length.hash(&mut hasher);
+    for range in &search.refined_ranges.method_counts {
+        range.hash(&mut hasher);
+    }
+    for required in &query.required_courses {
+        required.min_leads.hash(&mut hasher);
+    }
+    let schedules = (query.music_types.iter().map(|ty| &ty.weight_schedule))
+        .chain(query.calls.iter().map(|call| &call.weight_schedule));
+    for schedule in schedules {
+        schedule.len().hash(&mut hasher);
+        for weight in schedule {
+            weight.start.hash(&mut hasher);
+            weight.end.hash(&mut hasher);
+            weight.parts.hash(&mut hasher);
+            weight.weight.to_bits().hash(&mut hasher);
+        }
+    }
+    for music_type in &query.music_types {
+        let points = music_type.count_score.as_ref().map(|f| f.points());
+        for &(count, score) in points.unwrap_or_default() {
+            count.hash(&mut hasher);
+            score.to_bits().hash(&mut hasher);
+        }
+        points.is_some().hash(&mut hasher);
+    }
+    query.max_contiguous_duffer.hash(&mut hasher);
+    query.max_total_duffer.hash(&mut hasher);
+    query.atw_weight.map(f32::to_bits).hash(&mut hasher);
+    query.require_atw.hash(&mut hasher);
+    query.require_truth.hash(&mut hasher);
+    query.multi_extent.hash(&mut hasher);
+    query.falseness_limit.hash(&mut hasher);
+    query.splice_style.hash(&mut hasher);
+    query.splice_weight.to_bits().hash(&mut hasher);
+    (query.com_range.min, query.com_range.max).hash(&mut hasher);
+    for call in &query.calls {
+        (call.count_range.min, call.count_range.max).hash(&mut hasher);
+    }
+    let call_count_range = query.call_count_range;
+    (call_count_range.min, call_count_range.max).hash(&mut hasher);
+    query.method_balance_weight.to_bits().hash(&mut hasher);
+    query.num_parts().hash(&mut hasher);
+    query.scorer.id().hash(&mut hasher);
+
+    hasher.finish()
+}
+
+/// A 64-bit [FNV-1a](https://en.wikipedia.org/wiki/Fowler%E2%80%93Noll%E2%80%93Vo_hash_function)
+/// hasher.  Unlike [`std::collections::hash_map::DefaultHasher`], this is guaranteed to give the
+/// same hashes in every version of Monument.
+struct FnvHasher(u64);
+
+impl Default for FnvHasher {
+    fn default() -> Self {
+        Self(0xcbf2_9ce4_8422_2325)
+    }
+}
+
+impl Hasher for FnvHasher {
+    fn write(&mut self, bytes: &[u8]) {
+        for &b in bytes {
+            self.0 ^= b as u64;
+            self.0 = self.0.wrapping_mul(0x0000_0100_0000_01b3);
+        }
+    }
+
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashSet,
+        path::PathBuf,
+        sync::{atomic::AtomicBool, Arc},
+    };
+
+    use crate::{
+        parameters::Parameters,
+        scoring::{DefaultScorer, RowContext, Scorer, TransitionContext},
+        test_utils, Composition, Config, Search, Update,
+    };
+
+    /// Touches of Plain Bob Minor, up to two courses long
+    fn plain_bob_minor(num_comps: usize, scorer: Arc<dyn Scorer>) -> Parameters {
+        Parameters {
+            num_comps,
+            scorer,
+            ..test_utils::plain_bob_minor()
+        }
+    }
+
+    /// Gives the same scores as [`DefaultScorer`], but under a different name
+    #[derive(Debug)]
+    struct OtherScorer;
+
+    impl Scorer for OtherScorer {
+        fn row_score(&self, row: &RowContext) -> f32 {
+            DefaultScorer.row_score(row)
+        }
+
+        fn transition_score(&self, transition: &TransitionContext) -> f32 {
+            DefaultScorer.transition_score(transition)
+        }
+    }
+
+    fn checkpoint_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!(
+            "monument-test-{}-{name}.checkpoint",
+            std::process::id()
+        ))
+    }
+
+    /// Run a search of Plain Bob Minor, optionally resuming from a checkpoint
+    fn run(num_comps: usize, checkpoint_file: Option<PathBuf>, resume: bool) -> Vec<Composition> {
+        let config = Config {
+            thread_limit: Some(1),
+            checkpoint_file: checkpoint_file.clone(),
+            ..Default::default()
+        };
+        let params = plain_bob_minor(num_comps, Arc::new(DefaultScorer));
+        let search = Search::new(params, config).unwrap();
+        let mut comps = Vec::new();
+        let update_fn = |update| {
+            if let Update::Comp(comp) = update {
+                comps.push(comp);
+            }
+        };
+        let abort_flag = AtomicBool::new(false);
+        match (checkpoint_file, resume) {
+            (Some(path), true) => search.resume(&path, update_fn, &abort_flag).unwrap(),
+            _ => search.run(update_fn, &abort_flag),
+        }
+        comps
+    }
+
+    #[test]
+    fn resume() {
+        let path = checkpoint_path("resume");
+        let all_comps = run(10_000, None, false);
+        let first_comps = run(5, Some(path.clone()), false);
+        assert_eq!(first_comps.len(), 5);
+
+        // The resumed search should start by re-emitting the comps from before the checkpoint,
+        // then carry on from the saved frontier to find all the others
+        let resumed_comps = run(10_000, Some(path.clone()), true);
+        let call_strings = |comps: &[Composition]| {
+            comps
+                .iter()
+                .map(Composition::call_string)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            call_strings(&resumed_comps[..5]),
+            call_strings(&first_comps)
+        );
+        assert_eq!(resumed_comps.len(), all_comps.len());
+        let canonical_forms = |comps: &[Composition]| {
+            comps
+                .iter()
+                .map(|comp| comp.canonical_form.as_ref().clone())
+                .collect::<HashSet<_>>()
+        };
+        assert_eq!(canonical_forms(&resumed_comps), canonical_forms(&all_comps));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn stale_checkpoint() {
+        let path = checkpoint_path("stale");
+        run(5, Some(path.clone()), false);
+
+        let resume_with = |params: Parameters| {
+            let search = Search::new(params, Config::default()).unwrap();
+            search.resume(&path, |_| {}, &AtomicBool::new(false))
+        };
+        // Changing a weight changes the scores in the graph
+        let mut params = plain_bob_minor(5, Arc::new(DefaultScorer));
+        params.maybe_unused_calls[0].weight = -1.0;
+        assert!(matches!(
+            resume_with(params),
+            Err(crate::Error::StaleCheckpoint(_))
+        ));
+        // Scorers with the same scores but different `id`s can't share checkpoints
+        let params = plain_bob_minor(5, Arc::new(OtherScorer));
+        assert!(matches!(
+            resume_with(params),
+            Err(crate::Error::StaleCheckpoint(_))
+        ));
+        // ... but the same parameters are fine
+        assert!(resume_with(plain_bob_minor(5, Arc::new(DefaultScorer))).is_ok());
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn invalid_checkpoint() {
+        let path = checkpoint_path("invalid");
+        run(5, Some(path.clone()), false);
+        let bytes = std::fs::read(&path).unwrap();
+
+        let resume_from = |bytes: &[u8]| {
+            std::fs::write(&path, bytes).unwrap();
+            let params = plain_bob_minor(5, Arc::new(DefaultScorer));
+            let search = Search::new(params, Config::default()).unwrap();
+            search.resume(&path, |_| {}, &AtomicBool::new(false))
+        };
+        assert!(resume_from(&bytes).is_ok());
+        // Truncated files
+        for len in [0, 4, 16, bytes.len() - 1] {
+            assert!(matches!(
+                resume_from(&bytes[..len]),
+                Err(crate::Error::InvalidCheckpoint(_))
+            ));
+        }
+        // Bad magic number
+        let mut bad_magic = bytes.clone();
+        bad_magic[0] = b'X';
+        assert!(matches!(
+            resume_from(&bad_magic),
+            Err(crate::Error::InvalidCheckpoint(_))
+        ));
+        // Extra data on the end
+        let mut extended = bytes.clone();
+        extended.push(0);
+        assert!(matches!(
+            resume_from(&extended),
+            Err(crate::Error::InvalidCheckpoint(_))
+        ));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
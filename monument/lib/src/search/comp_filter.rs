@@ -0,0 +1,106 @@
+use std::collections::HashSet;
+
+use crate::{
+    composition::{CanonicalForm, PathElem},
+    Composition,
+};
+
+/// Decides which [`Composition`]s found by the search are actually generated.  A [`Composition`]
+/// is rejected if it's [equal](Composition::eq) to one which has already been generated (e.g. it's
+/// a rotation of an earlier composition), or if it's too similar to one (see
+/// [`Parameters::min_comp_distance`](crate::parameters::Parameters::min_comp_distance)).
+#[derive(Debug)]
+pub(super) struct CompFilter {
+    /// The [`CanonicalForm`] of every [`Composition`] accepted so far
+    canonical_forms: HashSet<CanonicalForm>,
+
+    min_distance: usize,
+    /// The paths of every [`Composition`] accepted so far.  This is left empty if
+    /// `min_distance` is `0`.
+    accepted_paths: Vec<Vec<PathElem>>,
+}
+
+impl CompFilter {
+    pub fn new(min_distance: usize) -> Self {
+        Self {
+            canonical_forms: HashSet::new(),
+            min_distance,
+            accepted_paths: Vec::new(),
+        }
+    }
+
+    /// Returns `true` if `comp` should be generated, in which case it is also recorded as
+    /// accepted.
+    pub fn accept(&mut self, comp: &Composition) -> bool {
+        if self.canonical_forms.contains(comp.canonical_form.as_ref()) || !self.is_diverse(comp) {
+            return false;
+        }
+        self.canonical_forms
+            .insert(comp.canonical_form.as_ref().clone());
+        if self.min_distance > 0 {
+            self.accepted_paths.push(comp.path.clone());
+        }
+        true
+    }
+
+    /// Returns `true` if `comp` is far enough away from every [`Composition`] accepted so far
+    fn is_diverse(&self, comp: &Composition) -> bool {
+        self.accepted_paths
+            .iter()
+            .all(|path| crate::composition::path_distance(&comp.path, path) >= self.min_distance)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::{parameters::Parameters, test_utils, Search};
+
+    use super::CompFilter;
+
+    /// Touches of Plain Bob Minor, up to two courses long
+    fn plain_bob_minor() -> Parameters {
+        Parameters {
+            num_comps: 10,
+            ..test_utils::plain_bob_minor()
+        }
+    }
+
+    fn search() -> Search {
+        test_utils::search(plain_bob_minor())
+    }
+
+    #[test]
+    fn repeats() {
+        let search = search();
+        let comp = |calling: &str| search.evaluate(calling).unwrap();
+        let mut filter = CompFilter::new(0);
+        assert!(filter.accept(&comp("BH")));
+        // The same comp, or any rotation of it, is a repeat
+        assert!(!filter.accept(&comp("BH")));
+        assert!(!filter.accept(&comp("WI")));
+        // Other comps aren't
+        assert!(filter.accept(&comp("sHsH")));
+        assert!(!filter.accept(&comp("sHsH")));
+    }
+
+    #[test]
+    fn min_distance() {
+        let search = search();
+        let comp = |calling: &str| search.evaluate(calling).unwrap();
+        let distance =
+            |a: &str, b: &str| crate::composition::path_distance(&comp(a).path, &comp(b).path);
+        assert_eq!(distance("BH", "sHsH"), 2);
+        assert!(distance("BH", "WBBIIH") >= 3);
+        assert!(distance("sHsH", "WBBIIH") >= 3);
+
+        let mut filter = CompFilter::new(3);
+        assert!(filter.accept(&comp("BH")));
+        assert!(!filter.accept(&comp("sHsH"))); // Too close to `BH`
+        assert!(filter.accept(&comp("WBBIIH")));
+        // Comps which are exactly `min_distance` apart are allowed
+        let mut filter = CompFilter::new(2);
+        assert!(filter.accept(&comp("BH")));
+        assert!(filter.accept(&comp("sHsH")));
+    }
+}
@@ -0,0 +1,77 @@
+use std::collections::BinaryHeap;
+
+use super::{
+    comp_filter::CompFilter, handle::Controls, path::Paths, prefix::CompPrefix, Progress, Search,
+    Update, ITERS_BETWEEN_ABORT_CHECKS, ITERS_BETWEEN_PROGRESS_UPDATES, MIN_PATHS_BEFORE_GC,
+};
+
+/// Searches a [`Graph`](m_gr::Graph) for compositions using depth-first search.
+///
+/// The search keeps a stack of 'levels', where each level contains the unexplored extensions of
+/// the prefix below it (sorted so that the highest-scoring extension is explored first).  The
+/// number of prefixes stored is therefore bounded by the length of the longest composition
+/// multiplied by the number of successors of each chunk.
+pub(super) fn search(search: &Search, mut update_fn: impl FnMut(Update), controls: &Controls) {
+    log::debug!("Running depth-first search");
+
+    let mut paths = Paths::new();
+    let mut stack = vec![CompPrefix::starts(search, &mut paths).into_sorted_vec()];
+
+    let mut iter_count = 0;
+    let mut num_comps = 0;
+    let mut nodes_before_gc = MIN_PATHS_BEFORE_GC;
+    let mut successors = BinaryHeap::new();
+    let mut comp_filter = CompFilter::new(search.query.min_comp_distance);
+
+    let progress = |stack: &Vec<Vec<_>>, iter_count, num_comps| {
+        Progress::from_prefixes(
+            stack.iter().flatten(),
+            iter_count,
+            num_comps,
+            controls.abort_flag,
+        )
+    };
+    // Send 'empty' update before search starts
+    update_fn(Update::Progress(progress(&stack, iter_count, num_comps)));
+
+    while let Some(level) = stack.last_mut() {
+        // Take the best unexplored prefix from the deepest level, or backtrack if there isn't one
+        let Some(prefix) = level.pop() else {
+            stack.pop();
+            continue;
+        };
+        let comp = prefix.expand(search, &mut paths, &mut successors, num_comps);
+        if let Some(comp) = comp.filter(|comp| comp_filter.accept(comp)) {
+            update_fn(Update::Comp(comp));
+            num_comps += 1;
+            if num_comps >= controls.num_comps() {
+                break; // Stop the search once we've got enough comps
+            }
+        }
+        if !successors.is_empty() {
+            // `into_sorted_vec` sorts in ascending order, so `pop` returns the best successor
+            stack.push(std::mem::take(&mut successors).into_sorted_vec());
+        }
+
+        iter_count += 1;
+
+        // Check for abort every so often
+        if iter_count.is_multiple_of(ITERS_BETWEEN_ABORT_CHECKS) && controls.should_abort() {
+            break;
+        }
+        // Send stats every so often
+        if iter_count.is_multiple_of(ITERS_BETWEEN_PROGRESS_UPDATES) {
+            update_fn(Update::Progress(progress(&stack, iter_count, num_comps)));
+        }
+        // Every path that we backtrack over becomes garbage, so garbage-collect the paths whenever
+        // the number of path nodes doubles
+        if paths.num_nodes() >= nodes_before_gc {
+            paths.gc(stack.iter().flatten().map(CompPrefix::path_head));
+            nodes_before_gc = (paths.num_nodes() * 2).max(MIN_PATHS_BEFORE_GC);
+        }
+    }
+
+    // Always send a final update before finishing
+    update_fn(Update::Progress(progress(&stack, iter_count, num_comps)));
+    update_fn(Update::Complete);
+}
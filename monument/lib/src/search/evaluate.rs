@@ -0,0 +1,1187 @@
+//! Code for evaluating a user-supplied calling, rather than searching for compositions.
+
+use std::collections::HashSet;
+
+use bellframe::{Bell, Row, RowBuf};
+use itertools::Itertools;
+
+use crate::{
+    composition::{extents, CanonicalForm, Composition, PathElem},
+    graph::{count_scores, expand_chunk, ChunkId, RowIdx},
+    group::PartHead,
+    parameters::{CallDisplayStyle, CallIdx, MethodIdx, SpliceStyle},
+    query::Query,
+    utils::{
+        counts::Counts,
+        lengths::{PerPartLength, TotalLength},
+    },
+    Error,
+};
+
+use super::Search;
+
+impl Search {
+    /// Parse a calling (in the format generated by [`Composition::call_string`]), ring it using
+    /// the methods and calls of this `Search` and return the resulting [`Composition`].  If the
+    /// calling can't be rung, or the [`Composition`] breaks one of the
+    /// [`Parameters`](crate::parameters::Parameters)' constraints, then an [`Error`] describing
+    /// the problem is returned.
+    ///
+    /// Callings can be given in either [`CallDisplayStyle`], regardless of which style this
+    /// `Search` uses to display its [`Composition`]s:
+    /// - Positional callings start with `#` and give the shorthand of every lead (e.g.
+    ///   `#PPP[-]PP[s]`).
+    /// - Callings with calling positions either give every lead in the same way (e.g.
+    ///   `D[B]BL[W]N[M]`, required for spliced) or just list the calls (e.g. `WsWWH`).  In the
+    ///   latter case, each call is made at the first place where it puts the calling bell into the
+    ///   requested position.
+    /// - In lead-by-lead callings, a `/` after a method (or call) shows that the method changes
+    ///   part-way through the lead, at the next place where it could be spliced (e.g. `B/C` for a
+    ///   lead which is half Bristol and half Cambridge).
+    /// - Six-numbered callings (e.g. `5 s11 12 13 s17`) give the number of the six containing each
+    ///   call, counting from the start of the composition.  Bobs can be written with or without
+    ///   their `-`.
+    ///
+    /// For [multi-extent](crate::parameters::Parameters::multi_extent) compositions, the callings
+    /// of the extents are separated by `+`s (e.g. `HHsH + sHsHH`).
+    pub fn evaluate(&self, calling: &str) -> crate::Result<Composition> {
+        let extent_callings = match self.query.multi_extent {
+            true => calling.split('+').collect_vec(),
+            false => vec![calling],
+        };
+        let mut path = Vec::new();
+        let mut extent_starts = Vec::new();
+        let mut part_head = PartHead::rounds();
+        for extent_calling in extent_callings {
+            let calling = Calling::parse(extent_calling, &self.query)?;
+            let (extent_path, extent_part_head) = if !calling.six_numbers.is_empty() {
+                ring_six_numbers(&calling, &self.query)?
+            } else if calling.lead_by_lead {
+                ring_lead_by_lead(&calling, &self.query)?
+            } else {
+                ring_calling_positions(&calling, &self.query)?
+            };
+            extent_starts.push(path.len());
+            path.extend(extent_path);
+            part_head = extent_part_head;
+        }
+        self.composition_from_path(path, extent_starts, part_head)
+    }
+
+    /// Total up the music, score, atw, etc. of a `path` which has already been rung, and check
+    /// that the resulting [`Composition`] satisfies the query.  `extent_starts` gives the index
+    /// of the first [`PathElem`] of every extent.
+    fn composition_from_path(
+        &self,
+        path: Vec<PathElem>,
+        extent_starts: Vec<usize>,
+        part_head: PartHead,
+    ) -> crate::Result<Composition> {
+        let query = &self.query;
+        let num_parts = query.num_parts() as f32;
+
+        let mut length = TotalLength::ZERO;
+        let mut score = 0.0;
+        let mut method_counts = Counts::zeros(query.methods.len());
+        let mut required_course_leads = Counts::zeros(query.required_courses.len());
+        let mut call_counts = Counts::zeros(query.calls.len());
+        let mut music_counts = Counts::zeros(query.music_types.len());
+        let mut atw_bitmap = self.atw_table.empty_bitmap();
+
+        let mut contiguous_duffer_lengths = Vec::<PerPartLength>::new();
+        let mut total_duffer = TotalLength::ZERO;
+        let mut consecutive_duffer = PerPartLength::ZERO;
+        let mut max_consecutive_duffer = PerPartLength::ZERO;
+        let mut was_last_chunk_duffer = false; // No last chunk, but the start is non-duffer
+
+        let mut start_stroke = !query.start_stroke; // Stroke of `query.start_row`
+        let extent_chunks = extents(&path, &extent_starts)
+            .flat_map(|extent_path| graph_chunks(extent_path, query))
+            .collect_vec();
+        for (id, chunk_length, call_to_end) in extent_chunks {
+            let method = &query.methods[id.method];
+            // Check that every lead is in the allowed courses
+            for (lead_head, _) in query.chunk_lead_regions(&id, chunk_length) {
+                if !method.is_lead_head_allowed(&lead_head) {
+                    return Err(Error::CourseNotAllowed {
+                        method_name: method.title(),
+                        lead_head,
+                    });
+                }
+            }
+
+            // Build this chunk as though it were in the graph
+            let mut chunk = expand_chunk(&id, chunk_length, query, &self.atw_table);
+            count_scores(&id, &mut chunk, start_stroke, query);
+            // Wraps out of this chunk's last row depend on what comes after it
+            if query.has_wraps() {
+                let (last_row, next_row) = query.last_and_next_row(&id, chunk_length, call_to_end);
+                let last_stroke = start_stroke.offset(chunk_length.as_usize() - 1);
+                let wrap_counts = query.wrap_counts(&last_row, &next_row, last_stroke);
+                score += query.wrap_score(wrap_counts.as_slice());
+                music_counts += &wrap_counts;
+            }
+            start_stroke = start_stroke.offset(chunk_length.as_usize());
+
+            length += chunk.total_length;
+            score += chunk.music.score;
+            method_counts += &chunk.method_counts;
+            required_course_leads += &chunk.required_course_leads;
+            music_counts += &chunk.music.counts;
+            atw_bitmap.union_with(&chunk.atw_bitmap);
+            // Duffers
+            if chunk.duffer {
+                total_duffer += chunk.total_length;
+            }
+            match (was_last_chunk_duffer, chunk.duffer) {
+                (false, true) => consecutive_duffer = chunk.per_part_length, // Starting duffers
+                (true, true) => consecutive_duffer += chunk.per_part_length, // Continuing duffers
+                (true, false) => contiguous_duffer_lengths.push(consecutive_duffer), // Finishing
+                (false, false) => {
+                    // Calls which join two different non-duffer courses is a transition of zero
+                    if call_to_end.is_some() {
+                        contiguous_duffer_lengths.push(PerPartLength::ZERO);
+                    }
+                }
+            }
+            if chunk.duffer {
+                max_consecutive_duffer = max_consecutive_duffer.max(consecutive_duffer);
+            }
+            was_last_chunk_duffer = chunk.duffer;
+        }
+        for (elem_idx, elem) in path.iter().enumerate() {
+            if let Some(call_idx) = elem.call_to_end {
+                call_counts[call_idx.index()] += query.num_parts();
+            }
+            // Score from the link out of this elem.  Different extents are rung separately, so
+            // can't be spliced together
+            let next_elem = match extent_starts.contains(&(elem_idx + 1)) {
+                true => None,
+                false => path.get(elem_idx + 1),
+            };
+            let is_splice = next_elem.is_some_and(|next| elem.is_splice_to(next, query));
+            let to_method = next_elem.map(|next| next.method);
+            score += query.transition_score(elem.call_to_end, is_splice, elem.method, to_method)
+                * num_parts;
+        }
+        score += self.atw_table.atw_score(&atw_bitmap);
+        score += query.count_score(&music_counts);
+        // Music is counted as though every part starts at the same stroke
+        let part_len = length.as_usize() / query.num_parts();
+        if query.is_multipart() && query.relies_on_stroke() && part_len % 2 == 1 {
+            return Err(Error::OddPartLength { part_len });
+        }
+
+        // Handle splices over the part head
+        let first_elem = path.first().expect("Must have at least one chunk");
+        let last_elem = path.last().expect("Must have at least one chunk");
+        if query.is_multipart() && last_elem.is_splice_to(first_elem, query) {
+            if !last_elem.is_valid_splice_to(first_elem, query) {
+                return Err(Error::InvalidSplice {
+                    from_method: query.methods[last_elem.method].title(),
+                    to_method: query.methods[first_elem.method].title(),
+                });
+            }
+            let splice_score =
+                query.transition_score(None, true, last_elem.method, Some(first_elem.method));
+            score += splice_score * (query.num_parts() - 1) as f32;
+        }
+
+        let canonical_form = Box::new(CanonicalForm::new(&path, &extent_starts, query));
+        let mut comp = Composition {
+            generation_number: 0,
+            path,
+            extent_starts,
+            canonical_form,
+
+            part_head,
+            length,
+            method_counts,
+            call_counts,
+            atw_bitmap,
+            music_counts: query
+                .music_types
+                .iter()
+                .zip_eq(music_counts.iter())
+                .map(|(music_type, count)| (music_type.id, *count))
+                .collect(),
+            total_score: score,
+
+            contiguous_duffer_lengths,
+            total_duffer,
+
+            query: self.query.clone(),
+            atw_table: self.atw_table.clone(),
+        };
+
+        // Add the weights which depend on where things happen in the composition
+        comp.total_score += comp.scheduled_score(true);
+        comp.total_score += query.method_balance_score(&comp.method_counts);
+
+        /* Check the composition against the query */
+
+        if let Some(extent_length) = query.extent_length() {
+            for (idx, extent_path) in comp.extents().enumerate() {
+                let length = extent_path
+                    .iter()
+                    .map(|e| e.length.as_usize())
+                    .sum::<usize>();
+                if length != extent_length.as_usize() {
+                    return Err(Error::WrongExtentLength {
+                        extent: idx + 1,
+                        length,
+                        extent_length: extent_length.as_usize(),
+                    });
+                }
+            }
+        }
+        if query.require_truth {
+            // Multi-extent compositions only have to be true within each extent
+            let rows = comp.rows();
+            let rows_per_block = query.extent_length().map_or(rows.len(), |l| l.as_usize());
+            for block in &rows.rows().chunks(rows_per_block) {
+                let mut rows_so_far = HashSet::<&Row>::with_capacity(rows_per_block);
+                for row in block {
+                    if !rows_so_far.insert(row) {
+                        return Err(Error::FalseComposition {
+                            repeated_row: row.to_owned(),
+                        });
+                    }
+                }
+            }
+        }
+        if query.is_falseness_limited() {
+            let falseness = comp.falseness();
+            let limit = query.falseness_limit;
+            if let Some(limit) = limit.max_row_occurrences {
+                if falseness.max_row_occurrences > limit {
+                    return Err(Error::RowRepeatedTooOften {
+                        occurrences: falseness.max_row_occurrences,
+                        limit,
+                    });
+                }
+            }
+            if let Some(limit) = limit.max_false_rows {
+                if falseness.false_rows > limit {
+                    return Err(Error::TooManyFalseRows {
+                        false_rows: falseness.false_rows,
+                        limit,
+                    });
+                }
+            }
+        }
+        if !query.length.contains(&length) {
+            return Err(Error::WrongLength {
+                length: length.as_usize(),
+                requested_range: query.length.clone(),
+            });
+        }
+        if !query.part_head_group.is_generator(part_head) {
+            return Err(Error::PartHeadDoesntGenerate {
+                part_head: comp.part_head().to_owned(),
+            });
+        }
+        for ((method, &count), range) in query
+            .methods
+            .iter()
+            .zip_eq(comp.method_counts.iter())
+            .zip_eq(&self.refined_ranges.method_counts)
+        {
+            if !(range.start().as_usize()..=range.end().as_usize()).contains(&count) {
+                return Err(Error::WrongMethodCount {
+                    method_name: method.title(),
+                    count,
+                    allowed_range: range.clone(),
+                });
+            }
+        }
+        for (required, &leads) in query
+            .required_courses
+            .iter()
+            .zip_eq(required_course_leads.iter())
+        {
+            if leads < required.min_leads {
+                return Err(Error::MissingRequiredCourse {
+                    course_masks: required.courses.masks.clone(),
+                    leads,
+                    min_leads: required.min_leads,
+                });
+            }
+        }
+        for (call, &count) in query.calls.iter().zip_eq(comp.call_counts.iter()) {
+            if !call.count_range.contains(count) {
+                return Err(Error::WrongCallCount {
+                    symbol: call.symbol.clone(),
+                    count,
+                    requested_range: call.count_range,
+                });
+            }
+        }
+        if !query.call_count_range.contains(comp.num_calls()) {
+            return Err(Error::WrongTotalCallCount {
+                count: comp.num_calls(),
+                requested_range: query.call_count_range,
+            });
+        }
+        let changes_of_method = comp.changes_of_method();
+        if !query.com_range.contains(changes_of_method) {
+            return Err(Error::WrongComCount {
+                count: changes_of_method,
+                requested_range: query.com_range,
+            });
+        }
+        for music_type in &query.music_types {
+            let count = comp.music_counts[&music_type.id];
+            let range = music_type.count_range;
+            if range.min.is_some_and(|min| count < min) || range.max.is_some_and(|max| count > max)
+            {
+                return Err(Error::WrongMusicCount {
+                    music_type: music_type.id,
+                    count,
+                    requested_range: range,
+                });
+            }
+        }
+        if let Some(limit) = query.max_contiguous_duffer {
+            if max_consecutive_duffer > limit {
+                return Err(Error::TooMuchContiguousDuffer {
+                    length: max_consecutive_duffer.as_usize(),
+                    limit: limit.as_usize(),
+                });
+            }
+        }
+        if let Some(limit) = query.max_total_duffer {
+            if total_duffer > limit {
+                return Err(Error::TooMuchTotalDuffer {
+                    length: total_duffer.as_usize(),
+                    limit: limit.as_usize(),
+                });
+            }
+        }
+        if query.require_atw && comp.atw_factor() < 0.99999 {
+            return Err(Error::NotAtw {
+                atw_factor: comp.atw_factor(),
+            });
+        }
+
+        Ok(comp)
+    }
+}
+
+/// Split a path into the [`ChunkId`]s (and lengths) which the [`Graph`](crate::graph::Graph)
+/// would use to represent it, along with the call at the end of each chunk.  These only differ
+/// from the [`PathElem`]s when calls cover several changes: the graph gives the covered rows to
+/// the chunk after the call, adding an extra chunk if the composition ends with such a call.
+pub(crate) fn graph_chunks(
+    path: &[PathElem],
+    query: &Query,
+) -> Vec<(ChunkId, PerPartLength, Option<CallIdx>)> {
+    let covering_call = |call: Option<CallIdx>| {
+        call.filter(|&call_idx| query.calls[call_idx].num_covered_rows() > 0)
+    };
+
+    let mut chunks = Vec::with_capacity(path.len() + 1);
+    let mut last_call = None;
+    for elem in path {
+        let method = &query.methods[elem.method];
+        let lead_head = Row::solve_xa_equals_b(
+            method.row_in_plain_lead(elem.start_sub_lead_idx),
+            &elem.start_row,
+        )
+        .unwrap();
+        let id = ChunkId::new(
+            lead_head.to_arc(),
+            RowIdx::new(elem.method, elem.start_sub_lead_idx),
+            covering_call(last_call),
+        );
+        let length = elem.length.as_usize() - query.num_rows_covered_by(elem.call_to_end)
+            + query.num_rows_covered_by(last_call);
+        chunks.push((id, PerPartLength::new(length), elem.call_to_end));
+        last_call = elem.call_to_end;
+    }
+    // If the composition ends with a call covering several changes, then its covered rows need
+    // their own chunk
+    if let Some(call_idx) = covering_call(last_call) {
+        let call = &query.calls[call_idx];
+        let last_elem = path.last().unwrap();
+        let (last_id, _, _) = chunks.last().unwrap();
+        let method = &query.methods[last_elem.method];
+        let num_plain_rows = last_elem.length.as_usize() - call.num_covered_rows();
+        let row_before_call = last_id.lead_head.as_ref()
+            * &method.row_in_plain_course(last_elem.start_sub_lead_idx + num_plain_rows - 1);
+        let row_after_call = &row_before_call * &call.place_notation.transposition();
+        let end_sub_lead_idx = last_elem.end_sub_lead_idx(query);
+        let lead_head =
+            Row::solve_xa_equals_b(method.row_in_plain_lead(end_sub_lead_idx), &row_after_call)
+                .unwrap();
+        let id = ChunkId::new(
+            lead_head.to_arc(),
+            RowIdx::new(last_elem.method, end_sub_lead_idx),
+            Some(call_idx),
+        );
+        chunks.push((id, PerPartLength::new(call.num_covered_rows()), None));
+    }
+    chunks
+}
+
+/////////////
+// PARSING //
+/////////////
+
+/// A calling, parsed from a [`String`]
+#[derive(Debug)]
+struct Calling {
+    /// The string from which this `Calling` was parsed
+    string: String,
+    snap_start: bool,
+    /// If `true`, the calling gives the method of every lead (along with the calls in brackets).
+    /// Otherwise, the calling is a list of calls and calling positions (e.g. `WsWWH`).
+    lead_by_lead: bool,
+    tokens: Vec<Token>,
+    /// For callings given as six numbers (e.g. `5 s11 12`), the number of the six containing each
+    /// call in `tokens`.  Empty for all other callings.
+    six_numbers: Vec<usize>,
+}
+
+#[derive(Debug)]
+enum Token {
+    Method(MethodIdx),
+    /// A `/` after a method, showing that the method is left part-way through its lead (e.g. at a
+    /// half lead)
+    MidLeadSplice,
+    Call {
+        /// The text used to specify this call (used for error messages)
+        text: String,
+        /// Every call which this text could refer to, paired with the calling position that
+        /// call must be made at (or `None` for positional callings)
+        options: Vec<(CallIdx, Option<String>)>,
+    },
+}
+
+/// A call in a lead-by-lead [`Calling`], along with its 1-indexed position in the calling
+type CallToken<'c> = (usize, &'c str, &'c [(CallIdx, Option<String>)]);
+
+impl Calling {
+    fn parse(calling: &str, query: &Query) -> crate::Result<Self> {
+        let parse_error = |unparsed: &str| Error::CallingParse {
+            calling: calling.to_owned(),
+            unparsed: unparsed.to_owned(),
+        };
+
+        let mut s = calling.trim();
+        let is_positional = s.starts_with('#');
+        s = s.strip_prefix('#').unwrap_or(s);
+        let snap_start = s.starts_with('<');
+        s = s.strip_prefix('<').unwrap_or(s);
+        // Snap finishes are determined by where the composition comes round, so `>` is ignored
+        s = s.strip_suffix('>').unwrap_or(s);
+        let lead_by_lead = is_positional || query.is_spliced() || s.contains('[');
+
+        // Callings like `5 s11 12` give each call by the number of the six it's made in
+        let is_six_numbers = !lead_by_lead
+            && !s.trim().is_empty()
+            && s.split_whitespace()
+                .all(|word| word.ends_with(|c: char| c.is_ascii_digit()));
+        if is_six_numbers {
+            let mut tokens = Vec::new();
+            let mut six_numbers = Vec::new();
+            for word in s.split_whitespace() {
+                let symbol = word.trim_end_matches(|c: char| c.is_ascii_digit());
+                let six_number = word[symbol.len()..]
+                    .parse::<usize>()
+                    .map_err(|_| parse_error(word))?;
+                // Bobs can be written with or without their `-`
+                let options = query
+                    .calls
+                    .iter_enumerated()
+                    .filter(|(_, call)| call.symbol == symbol || call.short_symbol() == symbol)
+                    .map(|(call_idx, _)| (call_idx, None))
+                    .collect_vec();
+                if options.is_empty() {
+                    return Err(parse_error(word));
+                }
+                tokens.push(Token::Call {
+                    text: word.to_owned(),
+                    options,
+                });
+                six_numbers.push(six_number);
+            }
+            return Ok(Self {
+                string: calling.to_owned(),
+                snap_start,
+                lead_by_lead,
+                tokens,
+                six_numbers,
+            });
+        }
+
+        // Every string which could be used for a call, along with what it represents
+        let call_strings = query
+            .calls
+            .iter_enumerated()
+            .flat_map(
+                |(call_idx, call)| -> Vec<(String, CallIdx, Option<String>)> {
+                    match is_positional {
+                        true => vec![(call.symbol.clone(), call_idx, None)],
+                        false => call
+                            .calling_positions
+                            .iter()
+                            .map(|pos| {
+                                let string = format!("{}{}", call.short_symbol(), pos);
+                                (string, call_idx, Some(pos.clone()))
+                            })
+                            .collect_vec(),
+                    }
+                },
+            )
+            .collect_vec();
+        let call_token = |text: &str| -> Option<Token> {
+            let options = call_strings
+                .iter()
+                .filter(|(string, _, _)| string == text)
+                .map(|(_, call_idx, pos)| (*call_idx, pos.clone()))
+                .collect_vec();
+            (!options.is_empty()).then(|| Token::Call {
+                text: text.to_owned(),
+                options,
+            })
+        };
+
+        let mut tokens = Vec::new();
+        loop {
+            s = s.trim_start();
+            if s.is_empty() {
+                break;
+            }
+            let (token, len) = if lead_by_lead {
+                if s.starts_with('/') {
+                    (Token::MidLeadSplice, 1)
+                } else if let Some(rest) = s.strip_prefix('[') {
+                    // Calls are given in brackets
+                    let (text, _) = rest.split_once(']').ok_or_else(|| parse_error(s))?;
+                    let token = call_token(text).ok_or_else(|| parse_error(s))?;
+                    (token, text.len() + 2)
+                } else {
+                    // Anything else must be a method shorthand
+                    let (method_idx, shorthand) = query
+                        .methods
+                        .iter_enumerated()
+                        .map(|(idx, m)| (idx, m.shorthand()))
+                        .filter(|(_, shorthand)| s.starts_with(shorthand.as_str()))
+                        .max_by_key(|(_, shorthand)| shorthand.len())
+                        .ok_or_else(|| parse_error(s))?;
+                    (Token::Method(method_idx), shorthand.len())
+                }
+            } else {
+                // Take the longest call string which matches
+                let text = call_strings
+                    .iter()
+                    .map(|(string, _, _)| string.as_str())
+                    .filter(|string| !string.is_empty() && s.starts_with(string))
+                    .max_by_key(|string| string.len())
+                    .ok_or_else(|| parse_error(s))?;
+                (call_token(text).unwrap(), text.len())
+            };
+            tokens.push(token);
+            s = &s[len..];
+        }
+
+        Ok(Self {
+            string: calling.to_owned(),
+            snap_start,
+            lead_by_lead,
+            tokens,
+            six_numbers: Vec::new(),
+        })
+    }
+}
+
+/////////////
+// RINGING //
+/////////////
+
+/// Ring a [`Calling`] where every lead's method is given (e.g. `D[B]BL[W]N[M]`)
+fn ring_lead_by_lead(calling: &Calling, query: &Query) -> crate::Result<(Vec<PathElem>, PartHead)> {
+    // Group the tokens into `(method, optional call at the end of the lead, ends mid-lead)`
+    // triples.  These 'leads' are really pieces of leads if the composition splices part-way
+    // through a lead.
+    let mut leads = Vec::<(MethodIdx, Option<CallToken>, bool)>::new();
+    let mut call_number = 0;
+    for token in &calling.tokens {
+        match token {
+            Token::Method(method_idx) => leads.push((*method_idx, None, false)),
+            Token::MidLeadSplice => match leads.last_mut() {
+                Some((_, _, ends_mid_lead @ false)) => *ends_mid_lead = true,
+                // `/`s must directly follow a method or call
+                _ => {
+                    return Err(Error::CallingParse {
+                        calling: calling.string.clone(),
+                        unparsed: "/".to_owned(),
+                    })
+                }
+            },
+            Token::Call { text, options } => {
+                call_number += 1;
+                match leads.last_mut() {
+                    Some((_, call @ None, false)) => *call = Some((call_number, text, options)),
+                    // Calls must directly follow a method
+                    _ => {
+                        return Err(Error::CallingParse {
+                            calling: calling.string.clone(),
+                            unparsed: format!("[{}]", text),
+                        })
+                    }
+                }
+            }
+        }
+    }
+    let first_method = match leads.first() {
+        Some((method_idx, _, _)) => *method_idx,
+        None => return Err(Error::DoesntComeRound),
+    };
+
+    let mut ringer = Ringer::start(query, first_method, calling.snap_start)?;
+    let mut prev_lead_had_call = false;
+    for (lead_idx, (method_idx, call, ends_mid_lead)) in leads.iter().enumerate() {
+        let is_last_lead = lead_idx == leads.len() - 1;
+        // Splice into this lead's method (calls will have already moved into the new method)
+        if !prev_lead_had_call && *method_idx != ringer.method {
+            ringer.splice(*method_idx)?;
+        }
+        prev_lead_had_call = call.is_some();
+
+        let mut rows_in_lead = 0;
+        loop {
+            ringer.step();
+            rows_in_lead += 1;
+            // Calls take priority over coming round, since the call changes the next row
+            if let Some((call_number, text, options)) = call {
+                if let Some((call_idx, row_after_call)) = ringer.find_call(options) {
+                    let next_method = leads.get(lead_idx + 1).map_or(*method_idx, |l| l.0);
+                    ringer.call(call_idx, row_after_call, next_method)?;
+                    if let Some(part_head) = ringer.end_part_head() {
+                        return match is_last_lead {
+                            true => Ok((ringer.finish(), part_head)),
+                            false => Err(Error::ComesRoundEarly {
+                                rows: ringer.rows_rung,
+                            }),
+                        };
+                    }
+                    break;
+                }
+                if rows_in_lead >= ringer.lead_len() {
+                    return Err(Error::CallNotReached {
+                        call: text.to_string(),
+                        call_number: *call_number,
+                    });
+                }
+            }
+            if let Some(part_head) = ringer.end_part_head() {
+                return match is_last_lead && call.is_none() {
+                    true => Ok((ringer.finish(), part_head)),
+                    false => Err(Error::ComesRoundEarly {
+                        rows: ringer.rows_rung,
+                    }),
+                };
+            }
+            // Plain leads continue to the next lead end, or the next place where they could be
+            // spliced if they end part-way through the lead
+            if call.is_none() && ringer.sub_lead_idx == 0 {
+                break;
+            }
+            if call.is_none() && *ends_mid_lead && ringer.can_plain_splice() {
+                break;
+            }
+        }
+    }
+    Err(Error::DoesntComeRound)
+}
+
+/// Ring a [`Calling`] which only consists of calls (e.g. `WsWWH`), placing each call at the first
+/// location where the calling bell ends up in the right calling position.
+fn ring_calling_positions(
+    calling: &Calling,
+    query: &Query,
+) -> crate::Result<(Vec<PathElem>, PartHead)> {
+    // Non-spliced callings only use one method
+    let method_idx = MethodIdx::new(0);
+    let course_len = query.methods[method_idx].plain_course.len();
+
+    let mut ringer = Ringer::start(query, method_idx, calling.snap_start)?;
+    for (call_idx_in_calling, token) in calling.tokens.iter().enumerate() {
+        let (text, options) = match token {
+            Token::Call { text, options } => (text, options),
+            Token::Method(_) | Token::MidLeadSplice => {
+                unreachable!("Only calls are parsed without brackets")
+            }
+        };
+        let mut rows_since_last_call = 0;
+        loop {
+            ringer.step();
+            rows_since_last_call += 1;
+            // Calls take priority over coming round, since the call changes the next row
+            if let Some((call_idx, row_after_call)) = ringer.find_call(options) {
+                ringer.call(call_idx, row_after_call, method_idx)?;
+                if let Some(part_head) = ringer.end_part_head() {
+                    return match call_idx_in_calling == calling.tokens.len() - 1 {
+                        true => Ok((ringer.finish(), part_head)),
+                        false => Err(Error::ComesRoundEarly {
+                            rows: ringer.rows_rung,
+                        }),
+                    };
+                }
+                break;
+            }
+            if ringer.end_part_head().is_some() {
+                return Err(Error::ComesRoundEarly {
+                    rows: ringer.rows_rung,
+                });
+            }
+            // If we've rung a whole course without finding the calling position, we never will
+            if rows_since_last_call > course_len {
+                return Err(Error::CallNotReached {
+                    call: text.clone(),
+                    call_number: call_idx_in_calling + 1,
+                });
+            }
+        }
+    }
+    // Ring plain leads until the composition comes round
+    for _ in 0..course_len {
+        ringer.step();
+        if let Some(part_head) = ringer.end_part_head() {
+            return Ok((ringer.finish(), part_head));
+        }
+    }
+    Err(Error::DoesntComeRound)
+}
+
+/// Ring a [`Calling`] given as six numbers (e.g. `5 s11 12`), placing each call at the first
+/// location where it can be made within the six with that number.
+fn ring_six_numbers(calling: &Calling, query: &Query) -> crate::Result<(Vec<PathElem>, PartHead)> {
+    // Non-spliced callings only use one method
+    let method_idx = MethodIdx::new(0);
+    let method = &query.methods[method_idx];
+    let course_len = method.plain_course.len();
+
+    let mut ringer = Ringer::start(query, method_idx, calling.snap_start)?;
+    let mut sixes = 0;
+    let calls = calling.tokens.iter().zip_eq(&calling.six_numbers);
+    for (call_idx_in_calling, (token, &six_number)) in calls.enumerate() {
+        let (text, options) = match token {
+            Token::Call { text, options } => (text, options),
+            Token::Method(_) | Token::MidLeadSplice => {
+                unreachable!("Only calls are parsed in six-numbered callings")
+            }
+        };
+        let call_not_reached = || Error::CallNotReached {
+            call: text.clone(),
+            call_number: call_idx_in_calling + 1,
+        };
+        // Without any six ends, we'd never reach the right six
+        if !(0..method.lead_len()).any(|idx| method.is_six_end(idx)) {
+            return Err(call_not_reached());
+        }
+        loop {
+            ringer.step();
+            if method.is_six_end(ringer.sub_lead_idx) {
+                sixes += 1;
+            }
+            if sixes > six_number {
+                return Err(call_not_reached());
+            }
+            // Calls take priority over coming round, since the call changes the next row
+            if sixes == six_number {
+                if let Some((call_idx, row_after_call)) = ringer.find_call(options) {
+                    ringer.call(call_idx, row_after_call, method_idx)?;
+                    if let Some(part_head) = ringer.end_part_head() {
+                        return match call_idx_in_calling == calling.tokens.len() - 1 {
+                            true => Ok((ringer.finish(), part_head)),
+                            false => Err(Error::ComesRoundEarly {
+                                rows: ringer.rows_rung,
+                            }),
+                        };
+                    }
+                    break;
+                }
+            }
+            if ringer.end_part_head().is_some() {
+                return Err(Error::ComesRoundEarly {
+                    rows: ringer.rows_rung,
+                });
+            }
+        }
+    }
+    // Ring plain sixes until the composition comes round
+    for _ in 0..course_len {
+        ringer.step();
+        if let Some(part_head) = ringer.end_part_head() {
+            return Ok((ringer.finish(), part_head));
+        }
+    }
+    Err(Error::DoesntComeRound)
+}
+
+/// State used whilst ringing a calling one row at a time, building up a list of [`PathElem`]s.
+#[derive(Debug)]
+struct Ringer<'q> {
+    query: &'q Query,
+    path: Vec<PathElem>,
+
+    /// The method being rung
+    method: MethodIdx,
+    /// The head of the lead containing the next row to be rung
+    lead_head: RowBuf,
+    /// The sub-lead index of the next row to be rung
+    sub_lead_idx: usize,
+    /// The last row to be rung (or the start row if nothing has been rung yet)
+    last_row: RowBuf,
+    /// The number of rows rung so far (in one part)
+    rows_rung: usize,
+
+    /* The `PathElem` currently being rung */
+    elem_start_row: RowBuf,
+    elem_start_sub_lead_idx: usize,
+    elem_len: usize,
+}
+
+impl<'q> Ringer<'q> {
+    /// Create a `Ringer` at the start of the composition
+    fn start(query: &'q Query, method_idx: MethodIdx, snap_start: bool) -> crate::Result<Self> {
+        let method = &query.methods[method_idx];
+        let start_sub_lead_idx = method
+            .start_indices
+            .iter()
+            .copied()
+            .filter(|&idx| (idx != 0) == snap_start)
+            .min()
+            .ok_or_else(|| Error::NoCallingStart {
+                method_name: method.title(),
+                snap_start,
+            })?;
+        let start_row = query.start_row.clone();
+        let lead_head =
+            Row::solve_xa_equals_b(method.row_in_plain_lead(start_sub_lead_idx), &start_row)
+                .unwrap();
+        Ok(Self {
+            query,
+            path: Vec::new(),
+
+            method: method_idx,
+            lead_head,
+            sub_lead_idx: start_sub_lead_idx,
+            last_row: start_row.clone(),
+            rows_rung: 0,
+
+            elem_start_row: start_row,
+            elem_start_sub_lead_idx: start_sub_lead_idx,
+            elem_len: 0,
+        })
+    }
+
+    fn lead_len(&self) -> usize {
+        self.query.methods[self.method].lead_len()
+    }
+
+    /// The next [`Row`] to be rung (i.e. the row after the last one rung)
+    fn next_row(&self) -> RowBuf {
+        let method = &self.query.methods[self.method];
+        self.lead_head.as_row() * method.row_in_plain_lead(self.sub_lead_idx)
+    }
+
+    /// Ring one row of the current method
+    fn step(&mut self) {
+        self.last_row = self.next_row();
+        self.rows_rung += 1;
+        self.elem_len += 1;
+        self.sub_lead_idx += 1;
+        if self.sub_lead_idx == self.lead_len() {
+            // Move into the next lead
+            self.sub_lead_idx = 0;
+            self.lead_head = self.lead_head.as_row() * self.query.methods[self.method].lead_head();
+        }
+    }
+
+    /// If the composition can come round at the current location, return the [`PartHead`]
+    /// reached.  Like in the graph, compositions always finish the first time they can.
+    fn end_part_head(&self) -> Option<PartHead> {
+        if self.rows_rung == 0 {
+            return None; // Compositions can't come round before they start
+        }
+        let method = &self.query.methods[self.method];
+        if !method.end_indices.contains(&self.sub_lead_idx) {
+            return None;
+        }
+        let part_head_row = self.next_row().as_row() * self.query.end_row.inv().as_row();
+        self.query.part_head_group.get_part_head(&part_head_row)
+    }
+
+    /// Find the first of the `options` which can be called at the current location, returning
+    /// that call along with the row it would produce.  Calls which cover several changes start
+    /// before their labelled row, so are found that many rows earlier.
+    fn find_call(&self, options: &[(CallIdx, Option<String>)]) -> Option<(CallIdx, RowBuf)> {
+        if self.elem_len == 0 {
+            return None; // Don't place calls without ringing any rows
+        }
+        let method = &self.query.methods[self.method];
+        options.iter().find_map(|(call_idx, position)| {
+            let call = &self.query.calls[*call_idx];
+            let label_idx = (self.sub_lead_idx + call.num_covered_rows()) % self.lead_len();
+            if !method.get_labels(label_idx).contains(&call.label_from) {
+                return None;
+            }
+            let row_after_call = self.last_row.as_row() * call.place_notation.transposition();
+            if let Some(position) = position {
+                let place_of_calling_bell = row_after_call.place_of(self.calling_bell()).unwrap();
+                if &call.calling_positions[place_of_calling_bell] != position {
+                    return None;
+                }
+            }
+            Some((*call_idx, row_after_call))
+        })
+    }
+
+    /// Place a call at the current location, moving into `next_method`
+    fn call(
+        &mut self,
+        call_idx: CallIdx,
+        row_after_call: RowBuf,
+        next_method: MethodIdx,
+    ) -> crate::Result<()> {
+        let call = &self.query.calls[call_idx];
+        if call.has_restricted_positions() {
+            let place_of_calling_bell = row_after_call.place_of(self.calling_bell()).unwrap();
+            if !call.is_allowed_at_place(place_of_calling_bell) {
+                return Err(Error::CallingPositionNotAllowed {
+                    call: call.symbol.clone(),
+                    position: call.calling_positions[place_of_calling_bell].clone(),
+                });
+            }
+        }
+        let label_to = &call.label_to;
+        let method_to = &self.query.methods[next_method];
+        // Stay at the same sub-lead index if possible
+        let label_idx = (self.sub_lead_idx + call.num_covered_rows()) % self.lead_len();
+        let next_sub_lead_idx =
+            if next_method == self.method && method_to.get_labels(label_idx).contains(label_to) {
+                Some(label_idx)
+            } else {
+                method_to.label_indices(label_to).next()
+            };
+        let next_sub_lead_idx = next_sub_lead_idx.ok_or_else(|| self.splice_error(next_method))?;
+        // The rows in the middle of the call belong to the `PathElem` ending with it
+        self.rows_rung += call.num_covered_rows();
+        self.elem_len += call.num_covered_rows();
+        self.finish_elem(Some(call_idx));
+        self.jump(next_method, next_sub_lead_idx, row_after_call);
+        Ok(())
+    }
+
+    /// Returns `true` if a plain splice could be made at the current location
+    fn can_plain_splice(&self) -> bool {
+        let method = &self.query.methods[self.method];
+        method
+            .get_labels(self.sub_lead_idx)
+            .iter()
+            .any(|label| self.query.allows_plain_splice_at(label))
+    }
+
+    /// Splice into `next_method` at the current location, without a call
+    fn splice(&mut self, next_method: MethodIdx) -> crate::Result<()> {
+        let query = self.query;
+        let labels = query.methods[self.method].get_labels(self.sub_lead_idx);
+        let method_to = &query.methods[next_method];
+        let next_sub_lead_idx = method_to
+            .first_lead()
+            .annots()
+            .position(|labels_to| {
+                labels_to
+                    .iter()
+                    .any(|l| labels.contains(l) && query.allows_plain_splice_at(l))
+            })
+            .ok_or_else(|| self.splice_error(next_method))?;
+        let next_row = self.next_row();
+        if query.splice_style == SpliceStyle::CourseHeads {
+            let lead_head =
+                Row::solve_xa_equals_b(method_to.row_in_plain_lead(next_sub_lead_idx), &next_row)
+                    .unwrap();
+            if !method_to.is_course_head(&lead_head) {
+                return Err(self.splice_error(next_method));
+            }
+        }
+        self.finish_elem(None);
+        self.jump(next_method, next_sub_lead_idx, next_row);
+        Ok(())
+    }
+
+    fn splice_error(&self, next_method: MethodIdx) -> Error {
+        Error::InvalidSplice {
+            from_method: self.query.methods[self.method].title(),
+            to_method: self.query.methods[next_method].title(),
+        }
+    }
+
+    /// The [`Bell`] used to determine calling positions
+    fn calling_bell(&self) -> Bell {
+        match self.query.call_display_style {
+            CallDisplayStyle::CallingPositions(bell) => bell,
+            CallDisplayStyle::Positional | CallDisplayStyle::Sixes => self.query.stage.tenor(),
+        }
+    }
+
+    /// Start a new [`PathElem`] at some location
+    fn jump(&mut self, method_idx: MethodIdx, sub_lead_idx: usize, row: RowBuf) {
+        let method = &self.query.methods[method_idx];
+        self.lead_head =
+            Row::solve_xa_equals_b(method.row_in_plain_lead(sub_lead_idx), &row).unwrap();
+        self.method = method_idx;
+        self.sub_lead_idx = sub_lead_idx;
+        self.elem_start_row = row;
+        self.elem_start_sub_lead_idx = sub_lead_idx;
+        self.elem_len = 0;
+    }
+
+    /// Add the [`PathElem`] currently being rung to the path
+    fn finish_elem(&mut self, call_to_end: Option<CallIdx>) {
+        self.path.push(PathElem {
+            start_row: self.elem_start_row.clone(),
+            method: self.method,
+            start_sub_lead_idx: self.elem_start_sub_lead_idx,
+            length: PerPartLength::new(self.elem_len),
+            call_to_end,
+        });
+    }
+
+    /// Finish the composition, returning the path which was rung
+    fn finish(mut self) -> Vec<PathElem> {
+        if self.elem_len > 0 {
+            self.finish_elem(None);
+        }
+        self.path
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicBool;
+
+    use bellframe::Stage;
+
+    use crate::{
+        parameters::{CallDisplayStyle, Parameters},
+        test_utils::{self, search, stedman_triples},
+        Error, Update,
+    };
+
+    /// Touches of Plain Bob Minor, up to two courses long
+    fn plain_bob_minor(call_display_style: CallDisplayStyle) -> Parameters {
+        Parameters {
+            call_display_style,
+            ..test_utils::plain_bob_minor()
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let styles = [
+            CallDisplayStyle::CallingPositions(Stage::MINOR.tenor()),
+            CallDisplayStyle::Positional,
+        ];
+        for style in styles {
+            let search = search(plain_bob_minor(style));
+            let mut comps = Vec::new();
+            search.run(
+                |update| {
+                    if let Update::Comp(comp) = update {
+                        comps.push(comp);
+                    }
+                },
+                &AtomicBool::new(false),
+            );
+            assert!(comps.len() > 10);
+
+            // Evaluating the call string of a composition should give the same composition
+            for comp in comps {
+                let call_string = comp.call_string();
+                let evaluated = search.evaluate(&call_string).unwrap();
+                assert_eq!(evaluated.call_string(), call_string);
+                assert_eq!(evaluated.length(), comp.length());
+                assert_eq!(evaluated.music_counts(), comp.music_counts());
+                assert!((evaluated.total_score() - comp.total_score()).abs() < 1e-4);
+                assert_eq!(evaluated, comp);
+            }
+        }
+    }
+
+    #[test]
+    fn errors() {
+        let mut params = plain_bob_minor(CallDisplayStyle::CallingPositions(Stage::MINOR.tenor()));
+        let search = search(params.clone());
+        let error = |calling: &str| search.evaluate(calling).unwrap_err();
+
+        // Bad calls and calling positions
+        assert!(matches!(error("Q"), Error::CallingParse { unparsed, .. } if unparsed == "Q"));
+        assert!(matches!(error("HsI"), Error::CallingParse { unparsed, .. } if unparsed == "sI"));
+        assert!(matches!(
+            error("BL"),
+            Error::CallNotReached { call, call_number: 2 } if call == "L"
+        ));
+        // Callings which don't come round at the end
+        assert!(matches!(error("W"), Error::DoesntComeRound));
+        assert!(matches!(error("L"), Error::ComesRoundEarly { rows: 60 }));
+        // False callings
+        assert!(matches!(error("HB"), Error::FalseComposition { .. }));
+
+        // Calls at calling positions which aren't allowed
+        params.maybe_unused_calls[0].allowed_calling_positions = vec!["W".to_owned()];
+        let search = self::search(params);
+        assert!(search.evaluate("WsFWsF").is_ok());
+        assert!(matches!(
+            search.evaluate("HHH"),
+            Err(Error::CallingPositionNotAllowed { call, position }) if call == "-" && position == "H"
+        ));
+    }
+
+    #[test]
+    fn stedman() {
+        let search = search(stedman_triples());
+        let mut comps = Vec::new();
+        search.run(
+            |update| {
+                if let Update::Comp(comp) = update {
+                    comps.push(comp);
+                }
+            },
+            &AtomicBool::new(false),
+        );
+        assert!(comps.len() > 10);
+        assert!(comps.iter().any(|comp| comp.call_string().contains('s')));
+
+        for comp in comps {
+            // Calls are given by the numbers of their sixes, which can't decrease
+            let call_string = comp.call_string();
+            let six_numbers = call_string
+                .split_whitespace()
+                .map(|call| call.trim_start_matches('s').parse::<usize>().unwrap())
+                .collect::<Vec<_>>();
+            assert!(six_numbers.windows(2).all(|w| w[0] <= w[1]));
+            assert!(six_numbers.iter().all(|&n| n <= comp.length() / 6));
+
+            // Evaluating the call string of a composition should give the same composition, and
+            // bobs can also be written with their `-`
+            let evaluated = search.evaluate(&call_string).unwrap();
+            assert_eq!(evaluated.call_string(), call_string);
+            assert_eq!(evaluated.length(), comp.length());
+            assert_eq!(evaluated.music_counts(), comp.music_counts());
+            assert_eq!(evaluated, comp);
+            let with_bob_symbols = (call_string.split_whitespace())
+                .map(|call| match call.starts_with('s') {
+                    true => call.to_owned(),
+                    false => format!("-{call}"),
+                })
+                .collect::<Vec<_>>()
+                .join(" ");
+            assert_eq!(search.evaluate(&with_bob_symbols).unwrap(), comp);
+        }
+    }
+}
@@ -0,0 +1,268 @@
+use std::collections::BinaryHeap;
+
+use index_vec::IndexVec;
+
+use crate::{graph::LinkSide, parameters::MusicTypeIdx, Composition};
+
+use super::{
+    graph::ChunkIdx, handle::Controls, path::Paths, prefix::CompPrefix, Optimality, Progress,
+    Search, Update, ITERS_BETWEEN_ABORT_CHECKS, ITERS_BETWEEN_PROGRESS_UPDATES,
+    MIN_PATHS_BEFORE_GC,
+};
+
+/// Prefixes are only pruned if their bound is at least this much lower than the worst of the best
+/// compositions found so far.  This stops floating-point rounding errors from pruning prefixes
+/// which could produce compositions that are equal to (or very slightly better than) those
+/// already found.
+const BOUND_TOLERANCE: f32 = 1e-4;
+
+/// Searches a [`Graph`](m_gr::Graph) for the best [`Composition`]s (by
+/// [`average_score`](Composition::average_score)) using branch-and-bound.
+///
+/// This is a depth-first search which keeps track of the best `num_comps` compositions found so
+/// far.  Any prefix whose [upper bound](CompPrefix::avg_score_bound) can't beat the worst of those
+/// is pruned.  If the search isn't aborted, every prefix is either expanded or pruned, so the
+/// compositions generated are provably the best possible.  Because better compositions can be
+/// found at any point, compositions are only passed to `update_fn` once the search finishes.
+pub(super) fn search(search: &Search, mut update_fn: impl FnMut(Update), controls: &Controls) {
+    log::debug!("Running exhaustive branch-and-bound search");
+
+    let max_rate_from = max_rate_from(search);
+    let mut paths = Paths::new();
+    let starts = CompPrefix::starts(search, &mut paths).into_sorted_vec();
+    let mut stack = vec![Level::new(starts, 1.0)];
+    let mut best_comps = BestComps::new(controls.num_comps(), search.query.min_comp_distance);
+
+    let mut iter_count = 0;
+    let mut num_comps_found = 0;
+    let mut aborted = false;
+    let mut nodes_before_gc = MIN_PATHS_BEFORE_GC;
+    let mut successors = BinaryHeap::new();
+
+    let progress = |stack: &Vec<Level>, iter_count, num_comps| {
+        let prefixes = stack.iter().flat_map(|level| &level.prefixes);
+        Progress::from_prefixes(prefixes, iter_count, num_comps, controls.abort_flag)
+    };
+    // Send 'empty' update before search starts
+    update_fn(Update::Progress(progress(&stack, iter_count, 0)));
+
+    while let Some(level) = stack.last_mut() {
+        // Take the best unexplored prefix from the deepest level, or backtrack if there isn't one
+        let Some(prefix) = level.prefixes.pop() else {
+            stack.pop();
+            continue;
+        };
+        let weight = level.weight_per_prefix;
+
+        // Prune this prefix if it can't beat the compositions we've already found
+        if !best_comps.could_be_improved_by(prefix.avg_score_bound(search, &max_rate_from)) {
+            continue;
+        }
+        if let Some(comp) = prefix.expand(search, &mut paths, &mut successors, num_comps_found) {
+            num_comps_found += 1;
+            best_comps.insert(comp);
+        }
+        if !successors.is_empty() {
+            // `into_sorted_vec` sorts in ascending order, so `pop` returns the best successor
+            let prefixes = std::mem::take(&mut successors).into_sorted_vec();
+            stack.push(Level::new(prefixes, weight));
+        }
+
+        iter_count += 1;
+
+        // Check for abort every so often
+        if iter_count.is_multiple_of(ITERS_BETWEEN_ABORT_CHECKS) && controls.should_abort() {
+            aborted = true;
+            break;
+        }
+        // Send stats every so often
+        if iter_count.is_multiple_of(ITERS_BETWEEN_PROGRESS_UPDATES) {
+            update_fn(Update::Progress(progress(
+                &stack,
+                iter_count,
+                best_comps.comps.len(),
+            )));
+        }
+        // Garbage-collect the paths whenever the number of path nodes doubles
+        if paths.num_nodes() >= nodes_before_gc {
+            let prefixes = stack.iter().flat_map(|level| &level.prefixes);
+            paths.gc(prefixes.map(CompPrefix::path_head));
+            nodes_before_gc = (paths.num_nodes() * 2).max(MIN_PATHS_BEFORE_GC);
+        }
+    }
+
+    let optimality = match aborted {
+        false => Optimality::Proven,
+        true => Optimality::Unproven {
+            unexplored_fraction: stack
+                .iter()
+                .map(|level| level.weight_per_prefix * level.prefixes.len() as f64)
+                .sum(),
+        },
+    };
+
+    // Always send a final update before finishing
+    let comps = best_comps.into_comps();
+    update_fn(Update::Progress(progress(&stack, iter_count, comps.len())));
+    for comp in comps {
+        update_fn(Update::Comp(comp));
+    }
+    update_fn(Update::Optimality(optimality));
+    update_fn(Update::Complete);
+}
+
+/// The unexplored prefixes which extend one prefix (or the start prefixes)
+struct Level {
+    /// Sorted so that the best prefix is last
+    prefixes: Vec<CompPrefix>,
+    /// The proportion of the whole search tree covered by each of `prefixes`.  This assumes that
+    /// every prefix in a level has an equally sized subtree, which isn't true but gives a
+    /// reasonable estimate of how much of the tree is left when the search is aborted.
+    weight_per_prefix: f64,
+}
+
+impl Level {
+    fn new(prefixes: Vec<CompPrefix>, total_weight: f64) -> Self {
+        Self {
+            weight_per_prefix: total_weight / prefixes.len() as f64,
+            prefixes,
+        }
+    }
+}
+
+/// The best [`Composition`]s found so far.  No two of these are [equal](Composition::eq) and, if
+/// `min_distance` is non-zero, no two are closer than `min_distance` (the better of any two close
+/// compositions is kept).
+struct BestComps {
+    max_len: usize,
+    min_distance: usize,
+    comps: Vec<Composition>,
+    /// Once `comps` is full, this holds the lowest average score out of `comps`.  Any new
+    /// composition has to beat this to be included.
+    threshold: Option<f32>,
+}
+
+impl BestComps {
+    fn new(max_len: usize, min_distance: usize) -> Self {
+        Self {
+            max_len,
+            min_distance,
+            comps: Vec::new(),
+            threshold: None,
+        }
+    }
+
+    /// Returns `false` if a [`Composition`] with an average score of `avg_score_bound` would
+    /// definitely not be added to `self`.
+    fn could_be_improved_by(&self, avg_score_bound: f32) -> bool {
+        match self.threshold {
+            Some(threshold) => avg_score_bound + BOUND_TOLERANCE >= threshold,
+            None => true, // We don't have enough comps yet, so any comp is an improvement
+        }
+    }
+
+    fn insert(&mut self, comp: Composition) {
+        if self.max_len == 0 {
+            return;
+        }
+        // Compositions which repeat (or are too similar to) a better composition are rejected,
+        // and any worse compositions which are too similar to `comp` are replaced by it
+        let is_close = |other: &Composition| {
+            *other == comp || (self.min_distance > 0 && comp.distance_to(other) < self.min_distance)
+        };
+        let avg_score = comp.average_score();
+        if (self.comps.iter()).any(|other| is_close(other) && other.average_score() >= avg_score) {
+            return;
+        }
+        let num_comps_before = self.comps.len();
+        self.comps.retain(|other| !is_close(other));
+        if self.comps.len() < num_comps_before {
+            self.threshold = None;
+        }
+        if self.comps.len() < self.max_len {
+            self.comps.push(comp);
+        } else {
+            let worst_idx = self.worst_idx();
+            if comp.average_score() > self.comps[worst_idx].average_score() {
+                self.comps[worst_idx] = comp;
+            }
+        }
+        if self.comps.len() >= self.max_len {
+            self.threshold = Some(self.comps[self.worst_idx()].average_score());
+        }
+    }
+
+    fn worst_idx(&self) -> usize {
+        (0..self.comps.len())
+            .min_by(|&a, &b| {
+                let score_a = self.comps[a].average_score();
+                score_a.total_cmp(&self.comps[b].average_score())
+            })
+            .expect("Can't find the worst of no comps")
+    }
+
+    /// Returns the [`Composition`]s in the order they were found, renumbering their generation
+    /// numbers so that they're contiguous.
+    fn into_comps(mut self) -> Vec<Composition> {
+        self.comps.sort_by_key(|comp| comp.generation_number);
+        for (generation_number, comp) in self.comps.iter_mut().enumerate() {
+            comp.generation_number = generation_number;
+        }
+        self.comps
+    }
+}
+
+/// For each chunk `c`, compute an upper bound on the score per row of any chunk reachable from `c`
+/// (including `c` itself).  The score of a chunk includes the best score of the links out of it.
+/// In a multi-extent composition, any chunk can be reached by starting a new extent, so every
+/// chunk gets the same bound.
+pub(super) fn max_rate_from(search: &Search) -> IndexVec<ChunkIdx, f32> {
+    let graph = &search.graph;
+    let num_parts = search.query.num_parts() as f32;
+    let mut max_rates = graph
+        .chunks
+        .iter()
+        .map(|chunk| {
+            let max_link_score = chunk
+                .succs
+                .iter()
+                .map(|link| {
+                    // Calls and wraps could also gain score from their weight schedules
+                    let max_scheduled_call_score = link.call.map_or(0.0, |call| {
+                        search.query.max_scheduled_call_weight(call) * num_parts
+                    });
+                    let max_scheduled_wrap_score = (link.wrap_counts.iter().enumerate())
+                        .map(|(idx, &count)| {
+                            let music_type = MusicTypeIdx::new(idx);
+                            search.query.max_scheduled_music_weight(music_type) * count as f32
+                        })
+                        .sum::<f32>();
+                    link.score + max_scheduled_call_score + max_scheduled_wrap_score
+                })
+                .max_by(f32::total_cmp)
+                .unwrap_or(0.0);
+            let max_chunk_score = chunk.max_score + chunk.max_scheduled_score;
+            (max_chunk_score + max_link_score) / chunk.total_length.as_usize() as f32
+        })
+        .collect::<IndexVec<ChunkIdx, f32>>();
+    // Propagate the rates backwards along the links until nothing changes
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for (chunk_idx, chunk) in graph.chunks.iter_enumerated() {
+            for link in &chunk.succs {
+                if let LinkSide::Chunk(succ_idx) = link.next {
+                    if max_rates[succ_idx] > max_rates[chunk_idx] {
+                        max_rates[chunk_idx] = max_rates[succ_idx];
+                        changed = true;
+                    }
+                }
+            }
+        }
+    }
+    if search.query.multi_extent {
+        let max_rate = max_rates.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+        max_rates.iter_mut().for_each(|rate| *rate = max_rate);
+    }
+    max_rates
+}
@@ -1,16 +1,20 @@
 use std::collections::HashMap;
 
+use itertools::Itertools;
+
 use crate::{
     atw::AtwBitmap,
-    graph::LinkSide,
+    graph::{LinkSide, ScheduledMusic},
     group::{PartHead, PhRotation},
     parameters::CallIdx,
     query::Query,
+    scoring::ChunkContext,
     utils::{
         counts::Counts,
         lengths::{PerPartLength, TotalLength},
     },
 };
+use bellframe::RowBuf;
 use bit_vec::BitVec;
 
 /// An immutable version of [`monument_graph::Graph`] which can be traversed without hash table
@@ -19,6 +23,9 @@ use bit_vec::BitVec;
 pub(super) struct Graph {
     pub starts: StartVec<(ChunkIdx, crate::graph::LinkId, PartHead)>,
     pub chunks: ChunkVec<Chunk>,
+    /// The number of distinct [`Row`](bellframe::Row)s rung by any chunk, if the number of
+    /// repeated rows is being limited (otherwise `0`).  These are indexed by [`Chunk::row_ids`].
+    pub num_rows: usize,
 }
 
 #[derive(Debug, Clone)]
@@ -26,23 +33,34 @@ pub(super) struct Chunk {
     pub id: crate::graph::ChunkId,
 
     pub score: f32,
-    pub music_counts: Counts, // PERF: Not used in search
+    pub music_counts: Counts,
+    /// Music with position-dependent weights, which isn't included in `score`
+    pub scheduled_music: Vec<ScheduledMusic>,
+    /// Upper bound on the score which `scheduled_music` could add, wherever this chunk is rung
+    pub max_scheduled_score: f32,
+    /// Upper bound on `score`, which can be larger than `score` if the
+    /// [`Scorer`](crate::scoring::Scorer) gives one
+    pub max_score: f32,
 
     pub per_part_length: PerPartLength, // PERF: Not used in search
     pub total_length: TotalLength,
     pub method_counts: Counts,
+    pub required_course_leads: Counts,
     /// Minimum number of rows required to go from the end of `self` to rounds
     pub min_len_to_rounds: TotalLength,
     pub duffer: bool,
     pub min_dist_to_non_duffer: PerPartLength,
 
-    // Indices must be aligned with those from the source graph
+    // Sorted so that indices don't depend on `HashMap` ordering (checkpoints rely on this)
     pub succs: SuccVec<SuccLink>,
     // If this chunk is added to a composition, these bits denote the set of chunks will be marked
     // as unreachable.  This includes `Self`, because every chunk is guaranteed to be false against
     // itself.
     pub falseness: BitVec,
     pub atw_bitmap: AtwBitmap,
+    /// If the number of repeated rows is being limited, this is an index for every
+    /// [`Row`](bellframe::Row) rung by this chunk (in every part).  Otherwise, this is empty.
+    pub row_ids: Vec<u32>,
 }
 
 /// A link between a chunk and its successor
@@ -51,7 +69,14 @@ pub(super) struct SuccLink {
     pub call: Option<CallIdx>,
     pub next: LinkSide<ChunkIdx>,
     pub score: f32,
+    /// `true` if this link changes method (or jumps to a different place in the lead)
+    pub is_splice: bool,
     pub ph_rotation: PhRotation,
+    /// The number of [wraps](crate::parameters::MusicType::wraps) of each music type from the
+    /// last row of the chunk into the next row, summed over all the parts.  Their weights are
+    /// included in `score`, except for any position-dependent weights.  If no music type counts
+    /// wraps, this is empty.
+    pub wrap_counts: Counts,
 }
 
 ///////////////////////////////////////////
@@ -65,15 +90,18 @@ impl Graph {
         // Assign each chunk ID to a unique `ChunkIdx`, and vice versa.  This way, we can now label
         // the set of chunks with numbers that can be used to index into a BitVec for falseness
         // computation.
+        // The chunks are sorted so that the indices don't depend on the order of the source
+        // graph's `HashMap`, meaning that identical graphs always get identical indices (which is
+        // required for checkpoints to be resumed by a different process).
         let mut index_to_id = ChunkVec::<(crate::graph::ChunkId, &crate::graph::Chunk)>::new();
         let mut id_to_index = HashMap::<crate::graph::ChunkId, ChunkIdx>::new();
-        for (id, chunk) in &source_graph.chunks {
+        for (id, chunk) in source_graph.chunks.iter().sorted_by_key(|(id, _)| *id) {
             let index = index_to_id.push((id.to_owned(), chunk));
             id_to_index.insert(id.to_owned(), index);
         }
 
         // Now convert chunks from `monument_graph::Chunk` to `self::Chunk`
-        let chunks: ChunkVec<_> = (0..num_chunks)
+        let mut chunks: ChunkVec<_> = (0..num_chunks)
             .map(|index| {
                 // Get the source chunk and its ChunkId
                 let index = ChunkIdx::new(index);
@@ -86,7 +114,7 @@ impl Graph {
                     falseness.set(false_chunk_idx.index(), true);
                 }
 
-                let succs = source_chunk
+                let mut succs = source_chunk
                     .successors
                     .iter()
                     .filter_map(|link_id| {
@@ -95,24 +123,55 @@ impl Graph {
                             LinkSide::Chunk(ch_id) => LinkSide::Chunk(*id_to_index.get(ch_id)?),
                             LinkSide::StartOrEnd => LinkSide::StartOrEnd,
                         };
+                        let is_splice = is_splice(source_chunk, link, query);
+                        let wrap_counts = link_wrap_counts(&from_id, source_chunk, link, query);
+                        let mut score = link_score(&from_id, link, is_splice, query);
+                        if !wrap_counts.is_empty() {
+                            score += query.wrap_score(wrap_counts.as_slice());
+                        }
                         Some(SuccLink {
                             call: link.call,
-                            score: link_score(source_chunk, link, query),
+                            score,
+                            is_splice,
                             next,
                             ph_rotation: link.ph_rotation,
+                            wrap_counts,
                         })
                     })
-                    .collect();
+                    .collect::<SuccVec<_>>();
+                succs.sort_by_key(|link| (link.next, link.call, link.ph_rotation));
+
+                // The scorer can only loosen the bound on this chunk's score
+                let max_score = query
+                    .scorer
+                    .max_chunk_score(&ChunkContext {
+                        method: &query.methods[from_id.method].inner,
+                        lead_head: &from_id.lead_head,
+                        length: source_chunk.per_part_length.as_usize(),
+                        parameters: &query.parameters,
+                    })
+                    .map_or(source_chunk.music.score, |max_score_in_part| {
+                        let max_score = max_score_in_part * query.num_parts() as f32;
+                        max_score.max(source_chunk.music.score)
+                    });
 
                 Chunk {
                     id: from_id,
 
                     score: source_chunk.music.score,
+                    max_score,
                     music_counts: source_chunk.music.counts.clone(),
+                    scheduled_music: source_chunk.scheduled_music.clone(),
+                    max_scheduled_score: source_chunk
+                        .scheduled_music
+                        .iter()
+                        .map(|m| m.count as f32 * query.max_scheduled_music_weight(m.music_type))
+                        .sum(),
 
                     per_part_length: source_chunk.per_part_length,
                     total_length: source_chunk.total_length,
                     method_counts: source_chunk.method_counts.clone(),
+                    required_course_leads: source_chunk.required_course_leads.clone(),
                     min_len_to_rounds: source_chunk.lb_distance_to_rounds,
 
                     duffer: source_chunk.duffer,
@@ -121,10 +180,27 @@ impl Graph {
                     succs,
                     falseness,
                     atw_bitmap: source_chunk.atw_bitmap.clone(),
+                    row_ids: Vec::new(),
                 }
             })
             .collect();
 
+        // If the search allows a limited amount of falseness, then give every row an index so that
+        // prefixes can count how many times each row has been rung
+        let mut row_indices = HashMap::<RowBuf, u32>::new();
+        if query.is_falseness_limited() {
+            for chunk in chunks.iter_mut() {
+                chunk.row_ids = query
+                    .chunk_rows(&chunk.id, chunk.per_part_length)
+                    .into_iter()
+                    .map(|row| {
+                        let next_id = row_indices.len() as u32;
+                        *row_indices.entry(row).or_insert(next_id)
+                    })
+                    .collect_vec();
+            }
+        }
+
         // Compute the list of start chunks and their labels
         let mut starts = StartVec::new();
         for (start_link_id, start_chunk_id) in &source_graph.starts {
@@ -141,14 +217,20 @@ impl Graph {
             }
         }
 
-        Graph { starts, chunks }
+        starts.sort_by_key(|&(chunk_idx, _link_id, part_head)| (chunk_idx, part_head));
+
+        Graph {
+            starts,
+            chunks,
+            num_rows: row_indices.len(),
+        }
     }
 }
 
-/// Gets the total [`Score`] generated by a given [`Link`].  For end links, this **doesn't**
-/// include the [`Score`] from splices over the part end.
-fn link_score(source_chunk: &crate::graph::Chunk, link: &crate::graph::Link, query: &Query) -> f32 {
-    let is_splice = match (&link.from, &link.to) {
+/// Returns `true` if a given [`Link`] changes method.  For end links, this **doesn't** include
+/// splices over the part end.
+fn is_splice(source_chunk: &crate::graph::Chunk, link: &crate::graph::Link, query: &Query) -> bool {
+    match (&link.from, &link.to) {
         // A link between chunks is a splice iff c2's RowIdx directly
         // follows from c1's (i.e. it's the same method and is one row
         // later).  For example:
@@ -157,21 +239,53 @@ fn link_score(source_chunk: &crate::graph::Chunk, link: &crate::graph::Link, que
         // - (Bristol, 31) -> (Cambridge, 0)  **is** a splice (method changes)
         // - (Bristol, 17) -> (Bristol, 0)    **is** a splice (it skips half a lead)
         (LinkSide::Chunk(c1), LinkSide::Chunk(c2)) => {
+            // Rows covered by calls belong to the chunk after the call, but still take up
+            // positions in the lead
+            let rows_until_c2 = source_chunk.per_part_length.as_usize()
+                - query.num_rows_covered_by(c1.covering_call)
+                + query.num_rows_covered_by(c2.covering_call);
             let sub_lead_idx_after_prev_chunk = query.methods[c1.method]
-                .add_sub_lead_idx(c1.sub_lead_idx, source_chunk.per_part_length);
+                .add_sub_lead_idx(c1.sub_lead_idx, PerPartLength::new(rows_until_c2));
             let is_continuation =
                 c1.method == c2.method && sub_lead_idx_after_prev_chunk == c2.sub_lead_idx;
             !is_continuation
         }
         // If either side is a start/end, then no splice occurs
         _ => false,
+    }
+}
+
+/// Gets the total [`Score`] generated by a given [`Link`].  For end links, this **doesn't**
+/// include the [`Score`] from splices over the part end.
+fn link_score(
+    source_chunk_id: &crate::graph::ChunkId,
+    link: &crate::graph::Link,
+    is_splice: bool,
+    query: &Query,
+) -> f32 {
+    let to_method = match &link.to {
+        LinkSide::Chunk(id) => Some(id.method),
+        LinkSide::StartOrEnd => None,
     };
-    let call_weight = match link.call {
-        Some(idx) => query.calls[idx].weight,
-        None => 0.0, // Plain leads have no weight
-    };
-    let splice_weight = if is_splice { query.splice_weight } else { 0.0 };
-    (call_weight + splice_weight) * query.num_parts() as f32
+    let score = query.transition_score(link.call, is_splice, source_chunk_id.method, to_method);
+    score * query.num_parts() as f32
+}
+
+/// Counts the [wraps](crate::parameters::MusicType::wraps) from the last row of a chunk into the
+/// row after a given [`Link`].  If no music type counts wraps, this returns an empty [`Counts`].
+fn link_wrap_counts(
+    source_chunk_id: &crate::graph::ChunkId,
+    source_chunk: &crate::graph::Chunk,
+    link: &crate::graph::Link,
+    query: &Query,
+) -> Counts {
+    if !query.has_wraps() {
+        return Counts::zeros(0);
+    }
+    let length = source_chunk.per_part_length;
+    let (last_row, next_row) = query.last_and_next_row(source_chunk_id, length, link.call);
+    let last_stroke = source_chunk.start_stroke.offset(length.as_usize() - 1);
+    query.wrap_counts(&last_row, &next_row, last_stroke)
 }
 
 index_vec::define_index_type! { pub struct ChunkIdx = usize; }
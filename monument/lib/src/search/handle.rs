@@ -0,0 +1,399 @@
+//! A handle to a [`Search`] running on a background thread, which yields [`Update`]s as an
+//! [`Iterator`].
+
+use std::{
+    cell::RefCell,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        mpsc::{self, Receiver, TryRecvError},
+        Arc, Condvar, Mutex,
+    },
+    thread::JoinHandle,
+};
+
+use super::{Search, Update};
+
+impl Search {
+    /// Start running this `Search` on a new thread, returning a [`SearchHandle`] which yields the
+    /// search's [`Update`]s and can be used to pause, resume or abort the search.
+    ///
+    /// Unlike [`Search::run`], this returns immediately.  The search will stop on its own once
+    /// it's finished (or once the [`SearchHandle`] is dropped).
+    pub fn spawn(self: Arc<Self>) -> SearchHandle {
+        let state = Arc::new(HandleState {
+            abort_flag: AtomicBool::new(false),
+            paused: Mutex::new(false),
+            unpaused: Condvar::new(),
+            num_comps: AtomicUsize::new(self.query.num_comps),
+            #[cfg(feature = "stream")]
+            waker: Mutex::new(None),
+        });
+        let (update_tx, update_rx) = mpsc::channel();
+
+        let thread_state = state.clone();
+        let thread = std::thread::spawn(move || {
+            let update_fn = |update| {
+                // If the handle has been dropped, then nothing is listening for updates
+                let _ = update_tx.send(update);
+                thread_state.wake();
+            };
+            let controls = Controls {
+                abort_flag: &thread_state.abort_flag,
+                handle_state: Some(&thread_state),
+                num_comps: self.query.num_comps,
+            };
+            self.run_with_controls(update_fn, &controls);
+            // Disconnect the channel, then wake the handle so that it sees that there are no more
+            // updates
+            drop(update_tx);
+            thread_state.wake();
+        });
+
+        SearchHandle {
+            state,
+            update_rx,
+            peeked_update: RefCell::new(None),
+            thread: Some(thread),
+        }
+    }
+}
+
+/// Handle to a [`Search`] running on a background thread, created by [`Search::spawn`].
+///
+/// `SearchHandle` is an [`Iterator`] over the [`Update`]s sent by the search.  Calling
+/// [`next`](Iterator::next) blocks until the next [`Update`] arrives, and the iterator finishes
+/// once the search has stopped (after sending [`Update::Complete`]).  Dropping a `SearchHandle`
+/// aborts its search.
+///
+/// With the `stream` feature enabled, `SearchHandle` also implements
+/// [`futures_core::Stream`](https://docs.rs/futures-core/0.3/futures_core/stream/trait.Stream.html).
+#[derive(Debug)]
+pub struct SearchHandle {
+    state: Arc<HandleState>,
+    update_rx: Receiver<Update>,
+    /// An [`Update`] which was received by [`is_finished`](Self::is_finished), and should be
+    /// returned before any others
+    peeked_update: RefCell<Option<Update>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl SearchHandle {
+    /// Pause the search.  The search's threads will stop shortly after this is called, and won't
+    /// continue until [`resume`](Self::resume) or [`abort`](Self::abort) is called.
+    pub fn pause(&self) {
+        *self.state.paused.lock().unwrap() = true;
+    }
+
+    /// Continue a search which has been [paused](Self::pause)
+    pub fn resume(&self) {
+        *self.state.paused.lock().unwrap() = false;
+        self.state.unpaused.notify_all();
+    }
+
+    pub fn is_paused(&self) -> bool {
+        *self.state.paused.lock().unwrap()
+    }
+
+    /// Signal that the search should stop.  The search will still send its final [`Update`]s
+    /// (including [`Update::Complete`]) before the iterator finishes.
+    pub fn abort(&self) {
+        self.state.abort_flag.store(true, Ordering::SeqCst);
+        // Aborting a paused search should still stop it
+        self.resume();
+    }
+
+    /// Change the number of [`Composition`](crate::Composition)s which the search will generate
+    /// before stopping.  This only has an effect if the search is still running, and
+    /// [`SearchStrategy::Exhaustive`](super::SearchStrategy::Exhaustive) searches always generate
+    /// the number of compositions requested when they started.
+    pub fn set_num_comps(&self, num_comps: usize) {
+        self.state.num_comps.store(num_comps, Ordering::SeqCst);
+    }
+
+    /// The number of [`Composition`](crate::Composition)s which the search will generate before
+    /// stopping.
+    pub fn num_comps(&self) -> usize {
+        self.state.num_comps.load(Ordering::SeqCst)
+    }
+
+    /// Returns the next [`Update`] if one is available, without blocking.  Returns `None` if no
+    /// [`Update`] has arrived yet, or if the search has finished.
+    pub fn try_next(&mut self) -> Option<Update> {
+        self.try_recv().ok()
+    }
+
+    /// Returns `true` once the search has stopped and every [`Update`] has been received.
+    pub fn is_finished(&self) -> bool {
+        let mut peeked_update = self.peeked_update.borrow_mut();
+        if peeked_update.is_some() {
+            return false;
+        }
+        match self.update_rx.try_recv() {
+            Ok(update) => {
+                // Keep the update so that it's still returned by `next`
+                *peeked_update = Some(update);
+                false
+            }
+            Err(TryRecvError::Empty) => false,
+            Err(TryRecvError::Disconnected) => true,
+        }
+    }
+
+    /// Receive the next [`Update`] without blocking, starting with any which was peeked by
+    /// [`is_finished`](Self::is_finished)
+    fn try_recv(&self) -> Result<Update, TryRecvError> {
+        match self.peeked_update.borrow_mut().take() {
+            Some(update) => Ok(update),
+            None => self.update_rx.try_recv(),
+        }
+    }
+}
+
+impl Iterator for SearchHandle {
+    type Item = Update;
+
+    fn next(&mut self) -> Option<Update> {
+        match self.peeked_update.get_mut().take() {
+            Some(update) => Some(update),
+            None => self.update_rx.recv().ok(),
+        }
+    }
+}
+
+#[cfg(feature = "stream")]
+impl futures_core::Stream for SearchHandle {
+    type Item = Update;
+
+    fn poll_next(
+        self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Option<Update>> {
+        use std::task::Poll;
+
+        let try_recv = |handle: &Self| match handle.try_recv() {
+            Ok(update) => Some(Poll::Ready(Some(update))),
+            Err(TryRecvError::Disconnected) => Some(Poll::Ready(None)),
+            Err(TryRecvError::Empty) => None,
+        };
+        if let Some(poll) = try_recv(&self) {
+            return poll;
+        }
+        *self.state.waker.lock().unwrap() = Some(cx.waker().clone());
+        // Check again, in case an update was sent (or the search finished) before the waker was
+        // stored
+        try_recv(&self).unwrap_or(Poll::Pending)
+    }
+}
+
+impl Drop for SearchHandle {
+    fn drop(&mut self) {
+        self.abort();
+        // Don't wait for the search thread, since it could take a while to stop
+        drop(self.thread.take());
+    }
+}
+
+/// State shared between a [`SearchHandle`] and its search thread
+#[derive(Debug)]
+struct HandleState {
+    abort_flag: AtomicBool,
+    paused: Mutex<bool>,
+    unpaused: Condvar,
+    num_comps: AtomicUsize,
+    /// The [`Waker`](std::task::Waker) of the task waiting for the next [`Update`], if any
+    #[cfg(feature = "stream")]
+    waker: Mutex<Option<std::task::Waker>>,
+}
+
+impl HandleState {
+    /// Wake the task waiting for the next [`Update`] (if there is one)
+    fn wake(&self) {
+        #[cfg(feature = "stream")]
+        if let Some(waker) = self.waker.lock().unwrap().take() {
+            waker.wake();
+        }
+    }
+}
+
+/// The ways that a running search can be controlled.  Searches started with [`Search::run`] can
+/// only be aborted, whereas those started with [`Search::spawn`] can also be paused or have their
+/// number of compositions changed.
+#[derive(Debug)]
+pub(super) struct Controls<'a> {
+    pub(super) abort_flag: &'a AtomicBool,
+    handle_state: Option<&'a HandleState>,
+    /// The number of compositions to generate, if there's no [`HandleState`]
+    num_comps: usize,
+}
+
+impl<'a> Controls<'a> {
+    pub(super) fn new(abort_flag: &'a AtomicBool, num_comps: usize) -> Self {
+        Self {
+            abort_flag,
+            handle_state: None,
+            num_comps,
+        }
+    }
+
+    /// Returns `true` if the search should stop.  If the search is paused, this blocks until the
+    /// search is either resumed or aborted.
+    pub(super) fn should_abort(&self) -> bool {
+        if let Some(state) = self.handle_state {
+            let paused = state.paused.lock().unwrap();
+            drop(state.unpaused.wait_while(paused, |paused| *paused).unwrap());
+        }
+        self.abort_flag.load(Ordering::Relaxed)
+    }
+
+    /// The number of compositions which the search should generate before stopping
+    pub(super) fn num_comps(&self) -> usize {
+        match self.handle_state {
+            Some(state) => state.num_comps.load(Ordering::Relaxed),
+            None => self.num_comps,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, time::Duration};
+
+    use crate::{
+        parameters::Parameters, test_utils, utils::lengths::TotalLength, Config, Search,
+        SearchHandle, Update,
+    };
+
+    /// Compositions of Plain Bob Minor between `min_length` and `max_length` rows long
+    fn plain_bob_minor(min_length: usize, max_length: usize, num_comps: usize) -> Parameters {
+        Parameters {
+            length: TotalLength::new(min_length)..=TotalLength::new(max_length),
+            num_comps,
+            ..test_utils::plain_bob_minor()
+        }
+    }
+
+    fn spawn(params: Parameters) -> SearchHandle {
+        let config = Config {
+            thread_limit: Some(1),
+            mem_limit: 100_000_000,
+            ..Default::default()
+        };
+        Arc::new(Search::new(params, config).unwrap()).spawn()
+    }
+
+    /// A search for every touch up to two courses long, which finishes quickly
+    fn touches() -> SearchHandle {
+        spawn(plain_bob_minor(60, 120, 10_000))
+    }
+
+    /// A search for extents, which won't finish until it's aborted
+    fn extents() -> SearchHandle {
+        spawn(plain_bob_minor(720, 720, usize::MAX))
+    }
+
+    fn num_comps(updates: &[Update]) -> usize {
+        let is_comp = |update: &&Update| matches!(update, Update::Comp(_));
+        updates.iter().filter(is_comp).count()
+    }
+
+    #[test]
+    fn iterator() {
+        let mut handle = touches();
+        let updates = handle.by_ref().collect::<Vec<_>>();
+        assert!(matches!(updates.last(), Some(Update::Complete)));
+        assert_eq!(num_comps(&updates), 28);
+        assert!(handle.is_finished());
+        assert!(handle.next().is_none());
+    }
+
+    #[test]
+    fn is_finished_keeps_updates() {
+        let mut handle = touches();
+        let mut updates = Vec::new();
+        while !handle.is_finished() {
+            updates.extend(handle.try_next());
+        }
+        assert!(matches!(updates.last(), Some(Update::Complete)));
+        assert_eq!(num_comps(&updates), 28);
+    }
+
+    #[test]
+    fn pause_resume_abort() {
+        let mut handle = extents();
+        assert!(!handle.is_paused());
+        handle.pause();
+        assert!(handle.is_paused());
+        // Once the search has stopped, it shouldn't send any more updates
+        std::thread::sleep(Duration::from_millis(200));
+        while handle.try_next().is_some() {}
+        std::thread::sleep(Duration::from_millis(200));
+        assert!(handle.try_next().is_none());
+        assert!(!handle.is_finished());
+
+        handle.resume();
+        assert!(!handle.is_paused());
+        handle.abort();
+        // Aborted searches still finish normally
+        let updates = handle.by_ref().collect::<Vec<_>>();
+        assert!(matches!(updates.last(), Some(Update::Complete)));
+        assert!(handle.is_finished());
+    }
+
+    #[test]
+    fn abort_while_paused() {
+        let handle = extents();
+        handle.pause();
+        handle.abort();
+        assert!(!handle.is_paused());
+        assert!(matches!(handle.last(), Some(Update::Complete)));
+    }
+
+    #[test]
+    fn set_num_comps() {
+        // Longer touches take a little while to find, so the first comp won't have been found
+        // before the number of comps is changed
+        let mut handle = spawn(plain_bob_minor(240, 360, usize::MAX));
+        handle.set_num_comps(3);
+        assert_eq!(handle.num_comps(), 3);
+        let updates = handle.by_ref().collect::<Vec<_>>();
+        assert_eq!(num_comps(&updates), 3);
+        assert!(matches!(updates.last(), Some(Update::Complete)));
+    }
+
+    #[cfg(feature = "stream")]
+    #[test]
+    fn stream() {
+        use std::{
+            pin::Pin,
+            task::{Context, Poll, Wake, Waker},
+            thread::Thread,
+        };
+
+        use futures_core::Stream;
+
+        /// Wakes a thread which is blocked in [`std::thread::park`]
+        struct ThreadWaker(Thread);
+
+        impl Wake for ThreadWaker {
+            fn wake(self: Arc<Self>) {
+                self.0.unpark();
+            }
+        }
+
+        // Poll the handle like an executor would, parking the thread until it's woken.  If the
+        // handle wasn't woken at the end of the search, this would hang.
+        let mut handle = touches();
+        let waker = Waker::from(Arc::new(ThreadWaker(std::thread::current())));
+        let mut cx = Context::from_waker(&waker);
+        let mut updates = Vec::new();
+        loop {
+            match Pin::new(&mut handle).poll_next(&mut cx) {
+                Poll::Ready(Some(update)) => updates.push(update),
+                Poll::Ready(None) => break,
+                Poll::Pending => std::thread::park(),
+            }
+        }
+        assert!(matches!(updates.last(), Some(Update::Complete)));
+        assert_eq!(num_comps(&updates), 28);
+    }
+}
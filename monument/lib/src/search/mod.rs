@@ -1,17 +1,27 @@
 //! Monument's search routines, along with the code for interacting with in-progress [`Search`]es.
 
+mod beam;
 mod best_first;
+mod checkpoint;
+mod comp_filter;
+mod depth_first;
+mod evaluate;
+mod exhaustive;
 mod graph;
+mod handle;
 mod path;
 mod prefix;
+mod spill;
 
 use std::{
     convert::TryInto,
     ops::RangeInclusive,
+    path::{Path, PathBuf},
     sync::{
         atomic::{AtomicBool, Ordering},
         Arc,
     },
+    time::Duration,
 };
 
 use bellframe::Stage;
@@ -26,6 +36,18 @@ use crate::{
     Composition,
 };
 
+use self::{handle::Controls, prefix::CompPrefix};
+
+pub(crate) use self::evaluate::graph_chunks;
+pub use self::handle::SearchHandle;
+
+const ITERS_BETWEEN_ABORT_CHECKS: usize = 10_000;
+const ITERS_BETWEEN_PROGRESS_UPDATES: usize = 100_000;
+const ITERS_BETWEEN_PATH_GCS: usize = 100_000_000;
+/// Searches which garbage-collect their [`Paths`](path::Paths) whenever they double in size
+/// won't bother collecting until they contain at least this many nodes
+const MIN_PATHS_BEFORE_GC: usize = 1_000_000;
+
 /// Handle to a search being run by Monument.
 ///
 /// This is used if you want to keep control over searches as they are running, for example
@@ -81,7 +103,53 @@ impl Search {
         // We want this to be sequentially consistent to make sure that the worker threads don't
         // see the previous value (which could be 'true').
         abort_flag.store(false, Ordering::SeqCst);
-        best_first::search(self, update_fn, abort_flag);
+        self.run_with_controls(update_fn, &Controls::new(abort_flag, self.query.num_comps));
+    }
+
+    fn run_with_controls(&self, update_fn: impl FnMut(Update), controls: &Controls) {
+        match self.config.strategy {
+            SearchStrategy::BestFirst => best_first::search(self, update_fn, controls, None),
+            SearchStrategy::Beam { width } => beam::search(self, width, update_fn, controls),
+            SearchStrategy::DepthFirst => depth_first::search(self, update_fn, controls),
+            SearchStrategy::Exhaustive => exhaustive::search(self, update_fn, controls),
+        }
+    }
+
+    /// Continues a search from a checkpoint file (saved by a previous search with
+    /// [`Config::checkpoint_file`] set), **blocking the current thread** until either the search is
+    /// completed or `abort_flag` is set.  The [`Composition`]s found before the checkpoint are
+    /// passed to `update_fn` before the search continues.
+    ///
+    /// The checkpoint must have been made by a search with the same [`Parameters`] (though the
+    /// [`Config`], including the number of threads, can be different).  Checkpoints are only made
+    /// by [`SearchStrategy::BestFirst`], so the search is always resumed using best-first search.
+    pub fn resume(
+        &self,
+        checkpoint_file: &Path,
+        update_fn: impl FnMut(Update),
+        abort_flag: &AtomicBool,
+    ) -> crate::Result<()> {
+        // Reset the abort flag *before* loading the checkpoint, so that an abort signalled while
+        // the checkpoint is being restored isn't lost
+        abort_flag.store(false, Ordering::SeqCst);
+        if self.config.strategy != SearchStrategy::BestFirst {
+            log::warn!("Checkpoints can only be resumed with best-first search");
+        }
+        let checkpoint = checkpoint::Checkpoint::load(self, checkpoint_file)?;
+        let restored_state = checkpoint
+            .restore(self, self.num_threads())
+            .ok_or_else(|| crate::Error::InvalidCheckpoint(checkpoint_file.to_owned()))?;
+        let controls = Controls::new(abort_flag, self.query.num_comps);
+        best_first::search(self, update_fn, &controls, Some(restored_state));
+        Ok(())
+    }
+
+    /// The number of threads which this `Search` will use
+    fn num_threads(&self) -> usize {
+        self.config
+            .thread_limit
+            .unwrap_or_else(num_cpus::get_physical)
+            .max(1)
     }
 }
 
@@ -97,6 +165,12 @@ impl Search {
         self.query.methods.iter().map(|m| (&m.inner, m.shorthand()))
     }
 
+    /// The [`Call`](crate::parameters::Call)s used in this `Search`, in the same order as
+    /// [`Composition::call_counts`](crate::Composition::call_counts)
+    pub fn calls(&self) -> impl Iterator<Item = &crate::parameters::Call> {
+        self.query.calls.iter()
+    }
+
     pub fn music_type_ids(&self) -> impl Iterator<Item = MusicTypeId> + '_ {
         self.query.music_types.iter().map(|ty| ty.id)
     }
@@ -145,10 +219,29 @@ pub enum Update {
     Comp(Composition),
     /// A thread is sending a status update
     Progress(Progress),
+    /// An [exhaustive](SearchStrategy::Exhaustive) search has finished, and is reporting whether
+    /// or not its compositions are the best possible.  Sent just before [`Update::Complete`].
+    Optimality(Optimality),
     /// The search has completed
     Complete,
 }
 
+/// Whether an [exhaustive](SearchStrategy::Exhaustive) search has proven that the
+/// [`Composition`]s it generated are the best possible.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Optimality {
+    /// Every possible composition was either generated or proven to be worse than the generated
+    /// compositions.  No composition has a higher [`average_score`](Composition::average_score)
+    /// than the compositions generated.
+    Proven,
+    /// The search was aborted, so better compositions may exist.
+    Unproven {
+        /// An estimate of the proportion of the search tree which wasn't explored (from `0.0` to
+        /// `1.0`).
+        unexplored_fraction: f64,
+    },
+}
+
 /// How much of a [`Search`] has been completed so far.
 #[derive(Debug, Clone, Copy)]
 pub struct Progress {
@@ -157,7 +250,8 @@ pub struct Progress {
     /// How many [`Composition`]s have been generated so far.
     pub num_comps: usize,
 
-    /// The current length of the queue of [`Composition`] prefixes waiting to be expanded.
+    /// The current length of the queue of [`Composition`] prefixes waiting to be expanded,
+    /// including any prefixes which have been written to [`Config::spill_dir`].
     pub queue_len: usize,
     /// The average length of [`Composition`] prefixes in the queue.
     pub avg_length: f32,
@@ -166,6 +260,9 @@ pub struct Progress {
 
     /// `true` if the search routine is currently shortening the prefix queue to save memory.
     pub truncating_queue: bool,
+    /// `true` if the search routine is currently writing part of the prefix queue to
+    /// [`Config::spill_dir`] to save memory.
+    pub spilling_queue: bool,
     /// `true` if the search routine is in the process of aborting
     pub aborting: bool,
 }
@@ -181,19 +278,60 @@ impl Progress {
         max_length: 0,
 
         truncating_queue: false,
+        spilling_queue: false,
         aborting: false,
     };
+
+    /// Summarise the [`Progress`] of a single-threaded search, given every [`CompPrefix`] which
+    /// it's currently storing.
+    fn from_prefixes<'p>(
+        prefixes: impl IntoIterator<Item = &'p CompPrefix>,
+        iter_count: usize,
+        num_comps: usize,
+        abort_flag: &AtomicBool,
+    ) -> Self {
+        let mut queue_len = 0;
+        let mut total_len = 0u64; // NOTE: We have use `u64` here to avoid overflow
+        let mut max_length = TotalLength::ZERO;
+        for prefix in prefixes {
+            queue_len += 1;
+            total_len += prefix.length().as_usize() as u64;
+            max_length = max_length.max(prefix.length());
+        }
+        Self {
+            iter_count,
+            num_comps,
+
+            queue_len,
+            avg_length: if queue_len == 0 {
+                0.0 // Avoid returning `NaN` if there are no prefixes
+            } else {
+                total_len as f32 / queue_len as f32
+            },
+            max_length: max_length.as_usize(),
+
+            truncating_queue: false,
+            spilling_queue: false,
+            aborting: abort_flag.load(Ordering::SeqCst),
+        }
+    }
 }
 
 /// Configuration options for a [`Search`].
 ///
-/// `Config` *won't* change which compositions are generated, unlike the parameters set by
-/// [`Search`]'s builder API.
+/// `Config` *won't* change which compositions are valid, unlike the parameters set by
+/// [`Search`]'s builder API.  However, some [`SearchStrategy`]s won't find every valid
+/// composition.
 #[derive(Debug, Clone)]
 pub struct Config {
     /* General */
     /// Number of threads used to generate compositions.  If `None`, this uses the number of
     /// **physical** CPU cores (i.e. ignoring hyper-threading).
+    ///
+    /// Searches on more than one thread aren't deterministic: the threads race each other, so a
+    /// search which stops early (e.g. after finding [`Parameters::num_comps`] compositions or
+    /// reaching [`Config::mem_limit`]) can find a different set of compositions each time it's
+    /// run.  Set this to `Some(1)` to make searches repeatable.
     pub thread_limit: Option<usize>,
 
     /* Graph Generation */
@@ -203,6 +341,9 @@ pub struct Config {
     pub graph_size_limit: usize,
 
     /* Search */
+    /// The algorithm used to search for compositions.  Defaults to
+    /// [`SearchStrategy::BestFirst`].
+    pub strategy: SearchStrategy,
     /// The maximum number of bytes of heap memory which the search routine is allowed to use.
     /// Defaults to 80% of available memory.
     pub mem_limit: usize,
@@ -212,6 +353,17 @@ pub struct Config {
     /// where the process will do exactly one search run before terminating (thus returning the memory
     /// to the OS anyway).
     pub leak_search_memory: bool,
+    /// If set, best-first searches which reach [`Config::mem_limit`] will write their
+    /// lowest-scoring prefixes to files in this directory (instead of throwing them away), and
+    /// read them back once the prefixes in memory are worse.  The files are deleted when the
+    /// search finishes.  Defaults to `None`.
+    pub spill_dir: Option<PathBuf>,
+    /// If set, the state of the search will periodically be saved to this file.  The search can
+    /// then be continued (e.g. after a crash or an abort) using [`Search::resume`].
+    pub checkpoint_file: Option<PathBuf>,
+    /// How often to save checkpoints to [`Config::checkpoint_file`].  A checkpoint is always
+    /// saved when the search finishes.  Defaults to 5 minutes.
+    pub checkpoint_interval: Duration,
 }
 
 impl Default for Config {
@@ -237,8 +389,200 @@ impl Default for Config {
 
             graph_size_limit: 100_000,
 
+            strategy: SearchStrategy::BestFirst,
             mem_limit,
             leak_search_memory: false,
+            spill_dir: None,
+            checkpoint_file: None,
+            checkpoint_interval: Duration::from_secs(5 * 60),
         }
     }
 }
+
+/// The algorithm which a [`Search`] uses to find [`Composition`]s.  Every strategy prunes
+/// composition prefixes in the same way, so they all generate valid [`Composition`]s.  They differ
+/// in which order prefixes are expanded, and in how many prefixes are kept in memory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SearchStrategy {
+    /// Repeatedly expand the highest-scoring prefix out of every prefix seen so far, spread over
+    /// [`Config::thread_limit`] threads.  This produces the best compositions, but stores a huge
+    /// number of prefixes (up to [`Config::mem_limit`]).  This is the only strategy which saves
+    /// checkpoints.
+    BestFirst,
+    /// Expand prefixes in order of increasing length, only keeping the `width` highest-scoring
+    /// prefixes of each length.  Memory usage is roughly proportional to `width`, but a narrow
+    /// beam can miss good compositions (or find none at all).
+    Beam { width: usize },
+    /// Expand prefixes depth-first, exploring the highest-scoring extension of each prefix first.
+    /// This only stores the prefixes along the current path (and their siblings), so uses very
+    /// little memory, but can take a long time to find good compositions.
+    DepthFirst,
+    /// Explore every possible composition (depth-first), using branch-and-bound to skip any
+    /// prefixes which can't beat the best [`Composition`]s found so far.  If the search isn't
+    /// aborted, the [`Composition`]s generated are guaranteed to be those with the highest
+    /// [`average_score`](Composition::average_score)s.  Compositions are only generated once the
+    /// search finishes, followed by an [`Update::Optimality`].  This is only practical for small
+    /// or very constrained searches.
+    Exhaustive,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashSet, sync::atomic::AtomicBool};
+
+    use bellframe::{method::LABEL_LEAD_END, RowBuf, Stage};
+
+    use crate::{
+        group::PartHeadGroup,
+        parameters::{Parameters, StrokeSet},
+        test_utils,
+        utils::lengths::TotalLength,
+        Composition, Error,
+    };
+
+    use super::{Config, Optimality, Search, SearchStrategy, Update};
+
+    /// Touches of Plain Bob Minor, up to two courses long
+    fn plain_bob_minor(num_comps: usize) -> Parameters {
+        Parameters {
+            num_comps,
+            ..test_utils::plain_bob_minor()
+        }
+    }
+
+    /// Run a search, returning every [`Update`] it sends (except [`Update::Progress`])
+    fn run(params: Parameters, strategy: SearchStrategy) -> Vec<Update> {
+        let config = Config {
+            thread_limit: Some(1),
+            strategy,
+            ..Default::default()
+        };
+        let search = Search::new(params, config).unwrap();
+        let mut updates = Vec::new();
+        let update_fn = |update| {
+            if !matches!(update, Update::Progress(_)) {
+                updates.push(update);
+            }
+        };
+        search.run(update_fn, &AtomicBool::new(false));
+        updates
+    }
+
+    /// Every touch allowed by `params`, found by draining a best-first search
+    fn all_comps(mut params: Parameters) -> HashSet<Composition> {
+        params.num_comps = 10_000;
+        let updates = run(params, SearchStrategy::BestFirst);
+        updates
+            .into_iter()
+            .filter_map(|update| match update {
+                Update::Comp(comp) => Some(comp),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn beam_and_depth_first() {
+        let all_comps = all_comps(plain_bob_minor(10));
+        for strategy in [
+            SearchStrategy::Beam { width: 5 },
+            SearchStrategy::DepthFirst,
+        ] {
+            let mut updates = run(plain_bob_minor(10), strategy);
+            assert!(matches!(updates.pop(), Some(Update::Complete)));
+
+            let mut comps = HashSet::new();
+            for update in updates {
+                let Update::Comp(comp) = update else {
+                    panic!("{strategy:?} sent unexpected update {update:?}");
+                };
+                // Every comp should be one that best-first search can find, and none should be
+                // repeated
+                assert!(
+                    all_comps
+                        .iter()
+                        .any(|c| c.canonical_form == comp.canonical_form),
+                    "{strategy:?} generated an invalid comp"
+                );
+                assert!(comps.insert(comp), "{strategy:?} repeated a comp");
+            }
+            assert!(!comps.is_empty() && comps.len() <= 10);
+        }
+    }
+
+    #[test]
+    fn exhaustive_is_optimal() {
+        // Only score calls, so that every rotation of a comp has the same score.  Otherwise,
+        // best-first search could generate a worse rotation of a comp before the best one (which
+        // would then be skipped as a repeat).
+        let mut params = plain_bob_minor(5);
+        params.maybe_unused_music_types.clear();
+
+        let mut all_comps = all_comps(params.clone()).into_iter().collect::<Vec<_>>();
+        all_comps.sort_by(|a, b| b.average_score().total_cmp(&a.average_score()));
+
+        let mut updates = run(params, SearchStrategy::Exhaustive);
+        assert!(matches!(updates.pop(), Some(Update::Complete)));
+        assert!(matches!(
+            updates.pop(),
+            Some(Update::Optimality(Optimality::Proven))
+        ));
+        let comps = updates
+            .into_iter()
+            .map(|update| match update {
+                Update::Comp(comp) => comp,
+                _ => panic!("Unexpected update {update:?}"),
+            })
+            .collect::<Vec<_>>();
+
+        // The exhaustive search should find the same best comps as best-first search (though
+        // comps with equal scores could be in any order)
+        let mut scores = comps
+            .iter()
+            .map(Composition::average_score)
+            .collect::<Vec<_>>();
+        scores.sort_by(|a, b| b.total_cmp(a));
+        let best_scores = all_comps[..5].iter().map(Composition::average_score);
+        assert_eq!(scores, best_scores.collect::<Vec<_>>());
+        for comp in &comps {
+            assert!(all_comps
+                .iter()
+                .any(|c| c.canonical_form == comp.canonical_form));
+        }
+    }
+
+    #[test]
+    fn odd_length_parts() {
+        let search = |params: Parameters| Search::new(params, Config::default());
+
+        // Every part of a multi-part of Stedman has a whole number of leads, so music which only
+        // counts at backstroke is fine
+        let mut params = test_utils::stedman_triples();
+        params.length = TotalLength::new(36)..=TotalLength::new(252);
+        params.part_head_group = PartHeadGroup::new(&RowBuf::parse("1342567").unwrap());
+        params.maybe_unused_music_types[0].strokes = StrokeSet::Back;
+        params.require_truth = false; // Only strokes matter here, not truth
+        assert!(search(params).is_ok());
+
+        // But a 2-part where each part is a single 7-row lead would start its parts at different
+        // strokes
+        let mut params = test_utils::plain_bob_minor();
+        let mut method =
+            bellframe::Method::from_place_not_string("Odd".to_owned(), Stage::MINOR, "x16x16x16x")
+                .unwrap();
+        method.add_label(0, LABEL_LEAD_END.to_owned());
+        params.maybe_unused_methods[0].inner = method;
+        params.maybe_unused_methods[0].end_indices = vec![0];
+        params.length = TotalLength::new(14)..=TotalLength::new(14);
+        params.part_head_group = PartHeadGroup::new(&RowBuf::parse("563412").unwrap());
+        params.maybe_unused_music_types[0].strokes = StrokeSet::Back;
+        params.require_truth = false; // Only strokes matter here, not truth
+        assert!(matches!(
+            search(params.clone()),
+            Err(Error::InconsistentStroke)
+        ));
+        // Which is fine if music doesn't depend on stroke
+        params.maybe_unused_music_types[0].strokes = StrokeSet::Both;
+        assert!(search(params).is_ok());
+    }
+}
@@ -1,6 +1,9 @@
 use datasize::DataSize;
 
-use super::graph::{StartIdx, SuccIdx};
+use super::{
+    checkpoint::{PathTree, TreeNode},
+    graph::{StartIdx, SuccIdx},
+};
 
 /// A container of prefix paths, stored as a linked-list style tree such that common prefixes are
 /// only stored once.
@@ -89,6 +92,12 @@ impl Paths {
         }
     }
 
+    /// The number of nodes which haven't been garbage collected.  This includes nodes which are
+    /// unreachable but haven't been collected yet.
+    pub(super) fn num_nodes(&self) -> usize {
+        self.size
+    }
+
     /// Run a garbage collection pass to mark any unreachable nodes as 'empty'
     pub(super) fn gc(&mut self, heads: impl IntoIterator<Item = PathId>) {
         // Mark which nodes are reachable, by marking all the non-start nodes as 'empty' then
@@ -129,6 +138,71 @@ impl Paths {
         }
     }
 
+    /// Re-creates a path which was [`flatten`](Self::flatten)ed out of another set of [`Paths`],
+    /// returning the [`PathId`] of its last node.  This is used to move prefixes between the
+    /// frontiers of different search threads.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the start nodes of `self` weren't added in order of their [`StartIdx`]s.
+    pub(super) fn add_flattened(&mut self, start_idx: StartIdx, succs: &[SuccIdx]) -> PathId {
+        let mut node_idx = self.start_path(start_idx);
+        for &succ in succs {
+            node_idx = self.add(node_idx, succ);
+        }
+        node_idx
+    }
+
+    /// Gets the [`PathId`] of the [`PathNode::Start`] for a given [`StartIdx`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if the start nodes of `self` weren't added in order of their [`StartIdx`]s.
+    pub(super) fn start_path(&self, start_idx: StartIdx) -> PathId {
+        let node_idx = PathId::from_usize(start_idx.index());
+        assert!(matches!(self.get(node_idx), PathNode::Start(idx) if idx == start_idx));
+        node_idx
+    }
+
+    /// Converts the paths ending at each of the `heads` into a compact [`PathTree`], which can be
+    /// written to a checkpoint.
+    pub(super) fn to_tree(&self, heads: impl IntoIterator<Item = PathId>) -> PathTree {
+        let mut tree = PathTree::default();
+        // For each node in `self`, its index in `tree` (or `u32::MAX` if it hasn't been added).
+        // This is a `Vec` rather than a `HashMap` to keep the memory usage of checkpoints down
+        let mut tree_idxs = vec![u32::MAX; self.nodes.len()];
+        for head in heads {
+            let tree_idx = self.add_to_tree(head, &mut tree, &mut tree_idxs);
+            tree.nodes[tree_idx as usize].num_prefixes += 1;
+        }
+        tree
+    }
+
+    /// Adds the node at `node_idx` (and every node it extends) to `tree`, returning its index
+    /// within `tree`.
+    fn add_to_tree(&self, node_idx: PathId, tree: &mut PathTree, tree_idxs: &mut [u32]) -> u32 {
+        if tree_idxs[node_idx.index()] != u32::MAX {
+            return tree_idxs[node_idx.index()];
+        }
+        let tree_node = match self.get(node_idx) {
+            PathNode::Start(start_idx) => TreeNode {
+                parent: None,
+                value: start_idx.raw(),
+                num_prefixes: 0,
+            },
+            PathNode::Cons { last, succ } => TreeNode {
+                parent: Some(self.add_to_tree(last, tree, tree_idxs)),
+                value: succ.raw(),
+                num_prefixes: 0,
+            },
+            PathNode::Empty { .. } => panic!("GCed nodes shouldn't be in a path"),
+        };
+        let tree_idx = tree.nodes.len() as u32;
+        tree.nodes.push(tree_node);
+        tree_idxs[node_idx.index()] = tree_idx;
+        tree_idx
+    }
+
     /// Return the path which finishes at a given [`PathId`].
     pub(super) fn flatten(&self, node_idx: PathId) -> (StartIdx, Vec<SuccIdx>) {
         let mut succs = Vec::new();
@@ -12,10 +12,10 @@ use ordered_float::OrderedFloat;
 
 use crate::{
     atw::AtwBitmap,
-    composition::{Composition, PathElem},
+    composition::{CanonicalForm, Composition, Falseness, PathElem},
     graph::LinkSide,
     group::PartHead,
-    parameters::SpliceStyle,
+    parameters::MusicTypeIdx,
     utils::{
         counts::Counts,
         div_rounding_up,
@@ -24,7 +24,7 @@ use crate::{
 };
 
 use super::{
-    graph::ChunkIdx,
+    graph::{ChunkIdx, StartIdx, SuccIdx, SuccLink},
     path::{PathId, Paths},
     Search,
 };
@@ -61,6 +61,9 @@ pub(super) struct PrefixInner {
     /// head we reached.
     // TODO: Compute this after search
     part_head: PartHead,
+    /// The length of the composition when the current extent started.  This is always zero
+    /// unless the composition is [multi-extent](crate::parameters::Parameters::multi_extent).
+    extent_start: TotalLength,
 
     /// Length of contiguous run of duffers up to the end of the prefix
     contiguous_duffer: PerPartLength,
@@ -69,48 +72,87 @@ pub(super) struct PrefixInner {
 
     /// Method counts refers to the **end** of the current chunk
     method_counts: Counts,
+    /// The number of leads rung in each of the required courses
+    required_course_leads: Counts,
+    /// The number of changes of method so far, summed over all the parts
+    changes_of_method: usize,
+    /// The number of instances of each call so far, summed over all the parts
+    call_counts: Counts,
     /// Bitmap storing the parts of methods rung by each bell so far in the composition
     atw_bitmap: AtwBitmap,
+
+    /// If the amount of falseness is [limited](crate::Parameters::falseness_limit), this
+    /// contains the number of times that each row (indexed by
+    /// [`Chunk::row_ids`](super::graph::Chunk::row_ids)) has been rung in the current extent.
+    /// Otherwise, this is empty.  These are `u32`s, like [`TotalLength`], so that they can't
+    /// overflow however long the composition is.
+    row_counts: Vec<u32>,
+    /// The number of rows so far which are repeats of earlier rows in the same extent
+    false_rows: usize,
+    /// The most times that any row has been rung in one extent
+    max_row_occurrences: usize,
+    /// If any [`MusicType`](crate::parameters::MusicType) has a
+    /// [`count_score`](crate::parameters::MusicType::count_score), this contains the number of
+    /// instances of each music type so far.  Otherwise, this is empty.
+    music_counts: Counts,
 }
 
 impl CompPrefix {
     /// Given a index-based [`Graph`], return [`CompPrefix`]es representing each of the possible
     /// start links.
     pub fn starts(search: &Search, paths: &mut Paths) -> BinaryHeap<Self> {
-        // `BitVec` that marks every `Chunk` as ringable
-        let all_chunks_ringable = BitVec::from_elem(search.graph.chunks.len(), false);
-
         search
             .graph
             .starts
-            .iter_enumerated()
-            .map(|(start_idx, &(chunk_idx, _link_id, part_head))| {
-                let chunk = &search.graph.chunks[chunk_idx];
-                Self {
-                    score: 0.0, // Start links can't have any score
-                    length: TotalLength::ZERO,
-                    inner: Box::new(PrefixInner {
-                        path: paths.add_start(start_idx),
-                        next_link_side: LinkSide::Chunk(chunk_idx),
-                        unringable_chunks: all_chunks_ringable.clone(),
-                        part_head,
-                        contiguous_duffer: PerPartLength::ZERO, // Start is considered a non-duffer
-                        total_duffer: TotalLength::ZERO,
-                        method_counts: Counts::zeros(chunk.method_counts.len()),
-                        atw_bitmap: search.atw_table.empty_bitmap(),
-                    }),
-                }
-            })
+            .indices()
+            .map(|start_idx| Self::start(search, start_idx, paths.add_start(start_idx)))
             .collect()
     }
 
+    /// Creates the [`CompPrefix`] for a single start link, which has already been added to some
+    /// [`Paths`] at `path`.
+    pub fn start(search: &Search, start_idx: StartIdx, path: PathId) -> Self {
+        let (chunk_idx, _link_id, part_head) = search.graph.starts[start_idx];
+        let chunk = &search.graph.chunks[chunk_idx];
+        Self {
+            score: 0.0, // Start links can't have any score
+            length: TotalLength::ZERO,
+            inner: Box::new(PrefixInner {
+                path,
+                next_link_side: LinkSide::Chunk(chunk_idx),
+                // `BitVec` that marks every `Chunk` as ringable
+                unringable_chunks: BitVec::from_elem(search.graph.chunks.len(), false),
+                part_head,
+                extent_start: TotalLength::ZERO,
+                contiguous_duffer: PerPartLength::ZERO, // Start is considered a non-duffer
+                total_duffer: TotalLength::ZERO,
+                method_counts: Counts::zeros(chunk.method_counts.len()),
+                required_course_leads: Counts::zeros(chunk.required_course_leads.len()),
+                changes_of_method: 0,
+                call_counts: Counts::zeros(search.query.calls.len()),
+                atw_bitmap: search.atw_table.empty_bitmap(),
+                row_counts: vec![0; search.graph.num_rows],
+                false_rows: 0,
+                max_row_occurrences: 0,
+                music_counts: match search.query.has_count_scores() {
+                    true => Counts::zeros(search.query.music_types.len()),
+                    false => Counts::zeros(0),
+                },
+            }),
+        }
+    }
+
     /// Returns the number of bytes of memory occupied by `self`
     pub fn size(&self) -> usize {
         std::mem::size_of::<Self>()
             + std::mem::size_of::<PrefixInner>()
             + div_rounding_up(self.inner.unringable_chunks.len(), 8)
             + self.inner.method_counts.estimate_heap_size()
+            + self.inner.required_course_leads.estimate_heap_size()
+            + self.inner.call_counts.estimate_heap_size()
             + self.inner.atw_bitmap.estimate_heap_size()
+            + self.inner.row_counts.len() * std::mem::size_of::<u32>()
+            + self.inner.music_counts.estimate_heap_size()
     }
 
     pub fn avg_score(&self) -> OrderedFloat<f32> {
@@ -121,6 +163,41 @@ impl CompPrefix {
         self.path
     }
 
+    /// Moves this prefix to a different [`PathId`].  Used when prefixes are moved between
+    /// threads, each of which has its own [`Paths`].
+    pub fn set_path_head(&mut self, path: PathId) {
+        self.inner.path = path;
+    }
+
+    /// Returns `true` if this prefix has come round to the end of the composition
+    pub fn is_finished(&self) -> bool {
+        self.next_link_side.is_start_or_end()
+    }
+
+    /// Returns `true` if this prefix has just come round at the end of an extent, and there's
+    /// enough length left to start another one.
+    fn can_start_new_extent(&self, search: &Search) -> bool {
+        self.is_finished()
+            && search.query.extent_length().is_some_and(|extent_length| {
+                self.length + extent_length <= *search.refined_ranges.length.end()
+            })
+    }
+
+    /// Start a new extent of a multi-extent composition, from the start link at `start_idx`.  The
+    /// path of the returned prefix isn't updated.
+    fn start_new_extent(mut self, search: &Search, start_idx: StartIdx) -> Self {
+        let (chunk_idx, _link_id, part_head) = search.graph.starts[start_idx];
+        let inner = &mut *self.inner;
+        inner.next_link_side = LinkSide::Chunk(chunk_idx);
+        // Falseness only applies within each extent
+        inner.unringable_chunks.clear();
+        inner.row_counts.fill(0);
+        inner.part_head = part_head;
+        inner.extent_start = self.length;
+        inner.contiguous_duffer = PerPartLength::ZERO; // Start is considered a non-duffer
+        self
+    }
+
     pub fn length(&self) -> TotalLength {
         self.length
     }
@@ -160,7 +237,6 @@ impl Deref for CompPrefix {
 
 impl CompPrefix {
     /// Expand this [`CompPrefix`], adding every 1-chunk-longer prefix to the `frontier`
-    #[allow(clippy::let_unit_value)]
     pub(super) fn expand(
         self,
         search: &Search,
@@ -171,119 +247,374 @@ impl CompPrefix {
         // Determine the chunk being expanded (or if it's an end, complete the composition)
         let chunk_idx = match self.next_link_side {
             LinkSide::Chunk(chunk_idx) => chunk_idx,
-            LinkSide::StartOrEnd => return self.check_comp(search, paths, num_comps_so_far),
+            LinkSide::StartOrEnd => {
+                // In a multi-extent composition, coming round can also start the next extent.  In
+                // the path, these are stored as the 'successors' of the end.
+                if self.can_start_new_extent(search) {
+                    for start_idx in search.graph.starts.indices() {
+                        let mut next_extent = self.clone().start_new_extent(search, start_idx);
+                        next_extent.inner.path =
+                            paths.add(self.path, SuccIdx::from_raw(start_idx.raw()));
+                        frontier.push(next_extent);
+                    }
+                }
+                return self.check_comp(search, paths, num_comps_so_far);
+            }
         };
-        let chunk = &search.graph.chunks[chunk_idx];
 
         /* From now on, we know we're expanding a chunk, not finishing a comp */
 
+        let path = self.path;
+        let prefix_after_chunk = self.add_chunk(search, chunk_idx);
+        for (succ_idx, link) in search.graph.chunks[chunk_idx].succs.iter_enumerated() {
+            if let Some(mut succ_prefix) = prefix_after_chunk.follow_link(search, link) {
+                succ_prefix.inner.path = paths.add(path, succ_idx);
+                frontier.push(succ_prefix);
+            }
+        }
+
+        None
+    }
+
+    /// Extend this [`CompPrefix`] by exactly one chunk, taking the `succ_idx`th successor of the
+    /// chunk being expanded.  The path of the returned prefix isn't updated.  Returns `None` if
+    /// that successor doesn't exist (e.g. because this prefix has already finished the
+    /// composition) or would be pruned by [`CompPrefix::expand`].  If this prefix has finished an
+    /// extent of a multi-extent composition, `succ_idx` is the [`StartIdx`] of the next extent.
+    pub(super) fn follow(self, search: &Search, succ_idx: SuccIdx) -> Option<Self> {
+        let chunk_idx = match self.next_link_side {
+            LinkSide::Chunk(chunk_idx) => chunk_idx,
+            LinkSide::StartOrEnd if self.can_start_new_extent(search) => {
+                let start_idx = StartIdx::from_raw(succ_idx.raw());
+                search.graph.starts.get(start_idx)?;
+                return Some(self.start_new_extent(search, start_idx));
+            }
+            LinkSide::StartOrEnd => return None,
+        };
+        let link = search.graph.chunks[chunk_idx].succs.get(succ_idx)?;
+        self.add_chunk(search, chunk_idx).follow_link(search, link)
+    }
+
+    /// Add the [`Chunk`](super::graph::Chunk) at `chunk_idx` to the end of `self`.  The
+    /// `next_link_side` of the returned prefix is left unchanged.
+    fn add_chunk(self, search: &Search, chunk_idx: ChunkIdx) -> Self {
+        let chunk = &search.graph.chunks[chunk_idx];
         let CompPrefix {
-            inner,
+            mut inner,
             mut length,
             mut score,
         } = self;
-        let PrefixInner {
-            path,
-            next_link_side: _,
-            mut unringable_chunks,
-            mut method_counts,
-            part_head, // Don't make this `mut` because it would get updated in every loop iteration
-            mut contiguous_duffer,
-            mut total_duffer,
-            mut atw_bitmap,
-        } = *inner;
+
+        // Add the weights of any music whose weight depends on where `chunk` is rung
+        let start_position = length.as_usize() / search.query.num_parts();
+        for music in &chunk.scheduled_music {
+            let weight = search
+                .query
+                .scheduled_music_weight(music.music_type, start_position + music.offset);
+            score += weight * music.count as f32;
+        }
 
         // Compute the values for after `chunk`
         length += chunk.total_length;
         if chunk.duffer {
-            contiguous_duffer += chunk.per_part_length;
-            total_duffer += chunk.total_length;
+            inner.contiguous_duffer += chunk.per_part_length;
+            inner.total_duffer += chunk.total_length;
         } else {
-            contiguous_duffer = PerPartLength::ZERO;
+            inner.contiguous_duffer = PerPartLength::ZERO;
         }
         score += chunk.score;
-        method_counts += &chunk.method_counts;
-        unringable_chunks.or(&chunk.falseness);
+        inner.method_counts += &chunk.method_counts;
+        inner.required_course_leads += &chunk.required_course_leads;
+        inner.unringable_chunks.or(&chunk.falseness);
         // Factor in the change in atw score by subtracting and adding the scores on either side
         // of the change
-        score -= search.atw_table.atw_score(&atw_bitmap);
-        atw_bitmap.union_with(&chunk.atw_bitmap);
-        score += search.atw_table.atw_score(&atw_bitmap);
+        score -= search.atw_table.atw_score(&inner.atw_bitmap);
+        inner.atw_bitmap.union_with(&chunk.atw_bitmap);
+        score += search.atw_table.atw_score(&inner.atw_bitmap);
+        // Factor in the change in count scores in the same way.  The prefix's score only includes
+        // how much the count scores have changed since the start; the scores of zero counts are
+        // added once the composition is complete.
+        if !inner.music_counts.is_empty() {
+            score -= search.query.count_score(&inner.music_counts);
+            inner.music_counts += &chunk.music_counts;
+            score += search.query.count_score(&inner.music_counts);
+        }
+        // Count how many times each row has been rung (only if falseness is limited, otherwise
+        // `row_ids` is empty)
+        for &row_id in &chunk.row_ids {
+            let count = &mut inner.row_counts[row_id as usize];
+            *count += 1;
+            if *count > 1 {
+                inner.false_rows += 1;
+            }
+            inner.max_row_occurrences = inner.max_row_occurrences.max(*count as usize);
+        }
 
-        let succ_iter = chunk.succs.iter_enumerated();
-        #[allow(unused_variables)]
-        let chunk = (); // Prevent the loop from accessing `chunk` by accident
+        CompPrefix {
+            inner,
+            length,
+            score,
+        }
+    }
 
+    /// Given a prefix which has just had a chunk added (by [`Self::add_chunk`]), create the
+    /// prefix which follows `link` out of that chunk.  Returns `None` if `link` makes the
+    /// composition obviously impossible to complete.
+    fn follow_link(&self, search: &Search, link: &SuccLink) -> Option<Self> {
+        let PrefixInner {
+            path,
+            next_link_side: _,
+            unringable_chunks,
+            part_head,
+            extent_start,
+            contiguous_duffer,
+            total_duffer,
+            method_counts,
+            required_course_leads,
+            changes_of_method,
+            call_counts,
+            atw_bitmap,
+            row_counts,
+            false_rows,
+            max_row_occurrences,
+            music_counts,
+        } = &*self.inner;
+        let length = self.length;
         let max_length = *search.refined_ranges.length.end();
-        for (succ_idx, link) in succ_iter {
-            let part_head = part_head * link.ph_rotation;
-            let score = score + link.score;
-
-            // If this `link` would add a new `Chunk`, check if that `Chunk` would make the comps
-            // obviously impossible to complete
-            if let LinkSide::Chunk(succ_idx) = link.next {
-                let succ_chunk = &search.graph.chunks[succ_idx];
-                let length_after_succ = length + succ_chunk.total_length;
-                let method_counts_after_chunk = &method_counts + &succ_chunk.method_counts;
-
-                // Contiguous run of duffers would be too long
-                if let Some(duffer_limit) = search.query.max_contiguous_duffer {
-                    if succ_chunk.duffer {
-                        let min_contiguous_duffer = contiguous_duffer
-                            + succ_chunk.per_part_length
-                            + succ_chunk.min_dist_to_non_duffer;
-                        if min_contiguous_duffer > duffer_limit {
-                            continue; // Chunk would force there to be too much duffer
-                        }
+
+        // Every splice happens once in each part
+        let changes_of_method = match link.is_splice {
+            true => changes_of_method + search.query.num_parts(),
+            false => *changes_of_method,
+        };
+        if search
+            .query
+            .com_range
+            .max
+            .is_some_and(|max| changes_of_method > max)
+        {
+            return None; // Too many changes of method
+        }
+        // Every call also happens once in each part
+        let mut call_counts = call_counts.clone();
+        if let Some(call_idx) = link.call {
+            call_counts[call_idx.index()] += search.query.num_parts();
+            let call_range = search.query.calls[call_idx].count_range;
+            if call_range
+                .max
+                .is_some_and(|max| call_counts[call_idx.index()] > max)
+            {
+                return None; // Too many of this call
+            }
+            let total_range = search.query.call_count_range;
+            if total_range
+                .max
+                .is_some_and(|max| call_counts.iter().sum::<usize>() > max)
+            {
+                return None; // Too many calls in total
+            }
+        }
+
+        // If this `link` would add a new `Chunk`, check if that `Chunk` would make the comps
+        // obviously impossible to complete
+        if let LinkSide::Chunk(succ_idx) = link.next {
+            let succ_chunk = &search.graph.chunks[succ_idx];
+            let length_after_succ = length + succ_chunk.total_length;
+            let method_counts_after_chunk = method_counts + &succ_chunk.method_counts;
+
+            // Contiguous run of duffers would be too long
+            if let Some(duffer_limit) = search.query.max_contiguous_duffer {
+                if succ_chunk.duffer {
+                    let min_contiguous_duffer = *contiguous_duffer
+                        + succ_chunk.per_part_length
+                        + succ_chunk.min_dist_to_non_duffer;
+                    if min_contiguous_duffer > duffer_limit {
+                        return None; // Chunk would force there to be too much duffer
                     }
                 }
-                // Total duffers would be too much
-                if let Some(max_total_duffer) = search.query.max_total_duffer {
-                    let succ_duffer_len = match succ_chunk.duffer {
-                        false => TotalLength::ZERO,
-                        true => succ_chunk.total_length,
-                    };
-                    let total_duffer_including_succ = total_duffer
-                        + succ_duffer_len
-                        + succ_chunk
-                            .min_dist_to_non_duffer
-                            .as_total(&search.query.part_head_group);
-                    if total_duffer_including_succ > max_total_duffer {
-                        continue; // Chunk would force us to ring too much duffer
-                    }
+            }
+            // Total duffers would be too much
+            if let Some(max_total_duffer) = search.query.max_total_duffer {
+                let succ_duffer_len = match succ_chunk.duffer {
+                    false => TotalLength::ZERO,
+                    true => succ_chunk.total_length,
+                };
+                let total_duffer_including_succ = *total_duffer
+                    + succ_duffer_len
+                    + succ_chunk
+                        .min_dist_to_non_duffer
+                        .as_total(&search.query.part_head_group);
+                if total_duffer_including_succ > max_total_duffer {
+                    return None; // Chunk would force us to ring too much duffer
                 }
+            }
 
-                if length_after_succ + succ_chunk.min_len_to_rounds > max_length {
-                    continue; // Chunk would make comp too long
+            if length_after_succ + succ_chunk.min_len_to_rounds > max_length {
+                return None; // Chunk would make comp too long
+            }
+            if let Some(extent_length) = search.query.extent_length() {
+                if length_after_succ + succ_chunk.min_len_to_rounds > *extent_start + extent_length
+                {
+                    return None; // Chunk would make this extent too long
                 }
-                if unringable_chunks.get(succ_idx.index()).unwrap() {
-                    continue; // Something already in the comp has made this unringable (i.e. false)
+            }
+            if unringable_chunks.get(succ_idx.index()).unwrap() {
+                return None; // Something already in the comp has made this unringable (i.e. false)
+            }
+            if !succ_chunk.row_ids.is_empty() {
+                // Every row of `succ_chunk` which has already been rung is at least one more false
+                // row (this ignores repeats within `succ_chunk`, so is a lower bound)
+                let mut falseness_after_succ = Falseness {
+                    false_rows: *false_rows,
+                    max_row_occurrences: *max_row_occurrences,
+                };
+                for &row_id in &succ_chunk.row_ids {
+                    let count = row_counts[row_id as usize] as usize;
+                    if count > 0 {
+                        falseness_after_succ.false_rows += 1;
+                        falseness_after_succ.max_row_occurrences =
+                            falseness_after_succ.max_row_occurrences.max(count + 1);
+                    }
                 }
-                if !method_counts_after_chunk.is_feasible(
-                    (max_length - length_after_succ).as_usize(),
-                    search.refined_ranges.method_counts.as_raw_slice(),
-                ) {
-                    continue; // Can't recover the method balance before running out of rows
+                if !search.query.falseness_limit.allows(falseness_after_succ) {
+                    return None; // Chunk would make the composition too false
                 }
             }
+            if !method_counts_after_chunk.is_feasible(
+                (max_length - length_after_succ).as_usize(),
+                search.refined_ranges.method_counts.as_raw_slice(),
+            ) {
+                return None; // Can't recover the method balance before running out of rows
+            }
+        }
 
-            frontier.push(CompPrefix {
-                inner: Box::new(PrefixInner {
-                    path: paths.add(path, succ_idx),
-                    next_link_side: link.next,
-                    unringable_chunks: unringable_chunks.clone(),
-                    part_head,
-                    contiguous_duffer,
-                    total_duffer,
-                    method_counts: method_counts.clone(),
-                    atw_bitmap: atw_bitmap.clone(),
-                }),
-                score,
-                length,
-            });
+        // Every extent of a multi-extent composition must be exactly one extent long
+        if link.next.is_start_or_end() {
+            if let Some(extent_length) = search.query.extent_length() {
+                if length != *extent_start + extent_length {
+                    return None;
+                }
+            }
         }
 
-        None
+        // A call is positioned at the last row of the chunk before it
+        let position = length.as_usize() / search.query.num_parts() - 1;
+        let call_score = match link.call {
+            Some(call) => {
+                let weight = search.query.scheduled_call_weight(call, position);
+                weight * search.query.num_parts() as f32
+            }
+            None => 0.0,
+        };
+        // Wraps out of the chunk are also positioned at its last row
+        let mut wrap_score = 0.0;
+        for (idx, &count) in link.wrap_counts.iter().enumerate() {
+            if count > 0 {
+                let music_type = MusicTypeIdx::new(idx);
+                wrap_score +=
+                    search.query.scheduled_music_weight(music_type, position) * count as f32;
+            }
+        }
+        let mut music_counts = music_counts.clone();
+        if !music_counts.is_empty() && !link.wrap_counts.is_empty() {
+            wrap_score -= search.query.count_score(&music_counts);
+            music_counts += &link.wrap_counts;
+            wrap_score += search.query.count_score(&music_counts);
+        }
+
+        Some(CompPrefix {
+            inner: Box::new(PrefixInner {
+                path: *path,
+                next_link_side: link.next,
+                unringable_chunks: unringable_chunks.clone(),
+                part_head: *part_head * link.ph_rotation,
+                extent_start: *extent_start,
+                contiguous_duffer: *contiguous_duffer,
+                total_duffer: *total_duffer,
+                method_counts: method_counts.clone(),
+                required_course_leads: required_course_leads.clone(),
+                changes_of_method,
+                call_counts,
+                atw_bitmap: atw_bitmap.clone(),
+                row_counts: row_counts.clone(),
+                false_rows: *false_rows,
+                max_row_occurrences: *max_row_occurrences,
+                music_counts,
+            }),
+            score: self.score + link.score + call_score + wrap_score,
+            length,
+        })
+    }
+}
+
+////////////
+// BOUNDS //
+////////////
+
+impl CompPrefix {
+    /// Computes an upper bound on the [`average_score`](Composition::average_score) of any
+    /// [`Composition`] which starts with this prefix.  `max_rate_from[c]` must be an upper bound
+    /// on the score per row (including the score of the following link) of any chunk reachable
+    /// from chunk `c`.
+    pub(super) fn avg_score_bound(
+        &self,
+        search: &Search,
+        max_rate_from: &index_vec::IndexVec<ChunkIdx, f32>,
+    ) -> f32 {
+        let length = self.length.as_usize() as f32;
+        let mut max_extra_score = 0.0;
+        // Splices over the part head can only add score if the splice weight is positive
+        if search.query.is_multipart() {
+            let num_part_head_splices = (search.query.num_parts() - 1) as f32;
+            max_extra_score += search.query.max_part_head_splice_score * num_part_head_splices;
+        }
+        // Deferred weights and method balance are only added once the composition is complete
+        max_extra_score += search.query.max_deferred_score();
+        max_extra_score += search.query.max_method_balance_score();
+        if !self.music_counts.is_empty() {
+            max_extra_score += search.query.base_count_score();
+            max_extra_score += search.query.max_count_score_gain(&self.music_counts);
+        }
+        let (rate, min_rows_to_rounds) = match self.next_link_side {
+            LinkSide::Chunk(chunk_idx) => {
+                let chunk = &search.graph.chunks[chunk_idx];
+                let min_rows_to_rounds = chunk.total_length + chunk.min_len_to_rounds;
+                (max_rate_from[chunk_idx], min_rows_to_rounds.as_usize())
+            }
+            // The next extent of a multi-extent composition could start with any chunk
+            LinkSide::StartOrEnd if self.can_start_new_extent(search) => {
+                let max_rate = max_rate_from
+                    .iter()
+                    .copied()
+                    .fold(f32::NEG_INFINITY, f32::max);
+                (max_rate, 0)
+            }
+            LinkSide::StartOrEnd => return (self.score + max_extra_score) / length,
+        };
+        max_extra_score += search.atw_table.max_atw_score_gain(&self.atw_bitmap);
+
+        // Determine the range of rows which could be added before the composition comes round
+        let length_range = &search.refined_ranges.length;
+        let min_rows_left = min_rows_to_rounds.max(
+            length_range
+                .start()
+                .as_usize()
+                .saturating_sub(self.length.as_usize()),
+        );
+        let max_rows_left = length_range
+            .end()
+            .as_usize()
+            .saturating_sub(self.length.as_usize());
+        if min_rows_left > max_rows_left {
+            return f32::NEG_INFINITY; // Prefix can't be completed
+        }
+
+        // Adding `x` rows to the composition gives an average score of at most
+        // `(score + extra + rate * x) / (length + x)`.  This is monotonic in `x`, so its maximum
+        // is at one of the ends of the range of `x`
+        let avg_score_with_rows_left =
+            |x: usize| (self.score + max_extra_score + rate * x as f32) / (length + x as f32);
+        avg_score_with_rows_left(min_rows_left).max(avg_score_with_rows_left(max_rows_left))
     }
 }
 
@@ -316,17 +647,44 @@ impl CompPrefix {
         {
             return None; // Comp doesn't have the required method balance
         }
+        let has_required_courses = search
+            .query
+            .required_courses
+            .iter()
+            .zip_eq(self.required_course_leads.iter())
+            .all(|(required, &leads)| leads >= required.min_leads);
+        if !has_required_courses {
+            return None; // Comp doesn't ring enough of some required course
+        }
+        let has_call_counts = search
+            .query
+            .calls
+            .iter()
+            .zip_eq(self.call_counts.iter())
+            .all(|(call, &count)| call.count_range.contains(count));
+        let total_calls = self.call_counts.iter().sum();
+        if !has_call_counts || !search.query.call_count_range.contains(total_calls) {
+            return None; // Comp has the wrong number of some call
+        }
         if !search.query.part_head_group.is_generator(self.part_head) {
             return None; // The part head reached wouldn't generate all the parts
         }
         if search.query.require_atw && search.atw_table.atw_factor(&self.atw_bitmap) < 0.99999 {
             return None; // The composition is not atw, but we were required to make it atw
         }
+        let falseness = Falseness {
+            false_rows: self.false_rows,
+            max_row_occurrences: self.max_row_occurrences,
+        };
+        if search.query.is_falseness_limited() && !search.query.falseness_limit.allows(falseness) {
+            return None; // The composition is too false
+        }
 
         /* At this point, all checks on the composition have passed and we know it satisfies the
          * user's query */
 
-        let (path, music_counts, contiguous_duffer_lengths) = self.flattened_path(search, paths);
+        let (path, extent_starts, music_counts, contiguous_duffer_lengths) =
+            self.flattened_path(search, paths);
         let first_elem = path.first().expect("Must have at least one chunk");
         let last_elem = path.last().expect("Must have at least one chunk");
 
@@ -337,36 +695,42 @@ impl CompPrefix {
         let splice_over_part_head = search.query.is_multipart() && is_splice;
         if splice_over_part_head {
             // Check if this splice is actually allowed under the composition (i.e. there must be a
-            // common label between the start and end of the composition for a splice to be
-            // allowed)
-            let start_labels = search.query.methods[first_elem.method]
-                .first_lead()
-                .get_annot(first_elem.start_sub_lead_idx)
-                .unwrap();
-            let end_labels = search.query.methods[last_elem.method]
-                .first_lead()
-                .get_annot(last_elem.end_sub_lead_idx(&search.query))
-                .unwrap();
-            let is_valid_splice = start_labels.iter().any(|label| end_labels.contains(label));
-            if !is_valid_splice {
-                return None;
-            }
-            // Don't generate comp if it would violate the splice style over the part head
-            if search.query.splice_style == SpliceStyle::Calls && last_elem.ends_with_plain() {
+            // common label between the start and end of the composition, and the splice must fit
+            // the splice style)
+            if !last_elem.is_valid_splice_to(first_elem, &search.query) {
                 return None;
             }
             // Add/subtract weights from the splices over the part head
-            score += search.query.splice_weight * (search.query.num_parts() - 1) as f32;
+            let splice_score = search.query.transition_score(
+                None,
+                true,
+                last_elem.method,
+                Some(first_elem.method),
+            );
+            score += splice_score * (search.query.num_parts() - 1) as f32;
         }
+        let changes_of_method = match splice_over_part_head {
+            true => self.changes_of_method + search.query.num_parts() - 1,
+            false => self.changes_of_method,
+        };
+        if !search.query.com_range.contains(changes_of_method) {
+            return None; // Comp has too many or too few changes of method
+        }
+        score += search.query.method_balance_score(&self.method_counts);
+        score += search.query.base_count_score();
 
         // Now we know the composition is valid, construct it and return
-        let comp = Composition {
+        let canonical_form = Box::new(CanonicalForm::new(&path, &extent_starts, &search.query));
+        let mut comp = Composition {
             generation_number: num_comps_so_far,
             path,
+            extent_starts,
+            canonical_form,
 
             part_head: self.part_head,
             length: self.length,
             method_counts: self.method_counts.clone(),
+            call_counts: self.call_counts.clone(),
             atw_bitmap: self.atw_bitmap.clone(),
             music_counts: search
                 .query
@@ -383,12 +747,21 @@ impl CompPrefix {
             query: search.query.clone(),
             atw_table: search.atw_table.clone(),
         };
-        // Sanity check that the composition is true
+        // Weights which depend on the composition's length or part can only be added now that the
+        // composition is complete
+        comp.total_score += comp.scheduled_score(false);
+        // Sanity check that the composition is true (or, for multi-extent compositions, that
+        // every extent is true)
         if search.query.require_truth {
-            let mut rows_so_far = HashSet::<&Row>::with_capacity(comp.length());
-            for row in comp.rows().rows() {
-                if !rows_so_far.insert(row) {
-                    panic!("Generated false composition ({})", comp.call_string());
+            let rows = comp.rows();
+            let rows_per_block =
+                (search.query.extent_length()).map_or(rows.len(), |l| l.as_usize());
+            for block in &rows.rows().chunks(rows_per_block) {
+                let mut rows_so_far = HashSet::<&Row>::with_capacity(rows_per_block);
+                for row in block {
+                    if !rows_so_far.insert(row) {
+                        panic!("Generated false composition ({})", comp.call_string());
+                    }
                 }
             }
         }
@@ -397,16 +770,18 @@ impl CompPrefix {
     }
 
     /// Create a sequence of [`ChunkId`]/[`LinkId`]s by traversing the [`Graph`] following the
-    /// reversed-linked-list path.  Whilst traversing, this also totals up the music counts.
+    /// reversed-linked-list path.  Whilst traversing, this also totals up the music counts and
+    /// records the index of the first [`PathElem`] of every extent.
     fn flattened_path(
         &self,
         search: &Search,
         paths: &Paths,
-    ) -> (Vec<PathElem>, Counts, Vec<PerPartLength>) {
+    ) -> (Vec<PathElem>, Vec<usize>, Counts, Vec<PerPartLength>) {
         // Flatten the reversed-linked-list path into a flat `Vec` that we can iterate over
         let (start_idx, succ_idxs) = paths.flatten(self.path);
 
         let mut path = Vec::<PathElem>::new();
+        let mut extent_starts = vec![0];
         let mut music_counts = Counts::zeros(search.query.music_types.len());
         let mut duffer_lengths = Vec::<PerPartLength>::new();
 
@@ -419,12 +794,31 @@ impl CompPrefix {
         for succ_idx in succ_idxs {
             let next_chunk_idx = match next_link_side {
                 LinkSide::Chunk(idx) => idx,
-                LinkSide::StartOrEnd => unreachable!(),
+                // Coming round part-way through the path starts the next extent of a multi-extent
+                // composition, and the 'successor' is the start of that extent
+                LinkSide::StartOrEnd => {
+                    let start_idx = StartIdx::from_raw(succ_idx.raw());
+                    let (start_chunk_idx, _start_link, start_part_head) =
+                        search.graph.starts[start_idx];
+                    if was_last_chunk_duffer {
+                        duffer_lengths.push(consecutive_duffer);
+                    }
+                    extent_starts.push(path.len());
+                    next_link_side = LinkSide::Chunk(start_chunk_idx);
+                    was_last_chunk_duffer = false;
+                    part_head_elem = start_part_head;
+                    continue;
+                }
             };
+            // `PathElem`s from previous extents can't be extended
+            let extent_path = &mut path[*extent_starts.last().unwrap()..];
             // Load the chunk at the end of the previous link
             let chunk = &search.graph.chunks[next_chunk_idx];
             let succ_link = &chunk.succs[succ_idx];
             music_counts += &chunk.music_counts;
+            if !succ_link.wrap_counts.is_empty() {
+                music_counts += &succ_link.wrap_counts;
+            }
             // Check for duffer lengths
             match (was_last_chunk_duffer, chunk.duffer) {
                 (false, true) => consecutive_duffer = chunk.per_part_length, // Starting duffers
@@ -437,24 +831,686 @@ impl CompPrefix {
                     }
                 }
             }
-            // Convert this chunk into a `PathElem`
+            // Convert this chunk into a `PathElem`.  `PathElem`s include the rows covered by the
+            // call at their end, whereas chunks include the rows covered by the call at their
+            // start.
             let method_idx = chunk.id.row_idx.method;
             let sub_lead_idx = chunk.id.row_idx.sub_lead_idx;
-            path.push(PathElem {
-                start_row: search.query.part_head_group.get_row(part_head_elem)
-                    * chunk.id.lead_head.as_ref()
-                    * search.query.methods[method_idx].row_in_plain_lead(sub_lead_idx),
-                method: method_idx,
-                start_sub_lead_idx: sub_lead_idx,
-                length: chunk.per_part_length,
-                call_to_end: succ_link.call,
-            });
+            let num_covered_rows = search.query.num_rows_covered_by(chunk.id.covering_call);
+            if let Some(last_elem) = extent_path.last_mut() {
+                last_elem.length += PerPartLength::new(num_covered_rows);
+            }
+            let length = chunk.per_part_length.as_usize() - num_covered_rows;
+            // Chunks which only contain covered rows don't need their own `PathElem`
+            if length > 0 {
+                let elem = PathElem {
+                    start_row: search.query.part_head_group.get_row(part_head_elem)
+                        * chunk.id.lead_head.as_ref()
+                        * search.query.methods[method_idx].row_in_plain_lead(sub_lead_idx),
+                    method: method_idx,
+                    start_sub_lead_idx: sub_lead_idx,
+                    length: PerPartLength::new(length),
+                    call_to_end: succ_link.call,
+                };
+                // Plain continuations of the same method are merged into one `PathElem`, exactly
+                // like when evaluating a calling
+                match extent_path.last_mut() {
+                    Some(last_elem)
+                        if last_elem.ends_with_plain()
+                            && !last_elem.is_splice_to(&elem, &search.query) =>
+                    {
+                        last_elem.length += elem.length;
+                        last_elem.call_to_end = elem.call_to_end;
+                    }
+                    _ => path.push(elem),
+                }
+            }
             // Follow the link to the next chunk in the path
             next_link_side = succ_link.next;
             was_last_chunk_duffer = chunk.duffer;
             part_head_elem = part_head_elem * succ_link.ph_rotation;
         }
         assert!(next_link_side.is_start_or_end());
-        (path, music_counts, duffer_lengths)
+        (path, extent_starts, music_counts, duffer_lengths)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{BinaryHeap, HashMap, HashSet};
+
+    use bellframe::{method::LABEL_HALF_LEAD, music::Pattern, Mask, PlaceNot, Stage};
+    use index_vec::IndexVec;
+    use itertools::Itertools;
+
+    use crate::{
+        parameters::{
+            CallDisplayStyle, CourseSet, FalsenessLimit, MusicType, MusicTypeId,
+            OptionalRangeInclusive, Parameters, RequiredCourses, RowPosition, ScheduledWeight,
+            SpliceStyle, StrokeSet,
+        },
+        test_utils::{
+            grandsire_doubles, plain_bob_doubles, plain_bob_minor, search, spliced_minor,
+        },
+        utils::lengths::TotalLength,
+        Composition, Config, Error, Falseness,
+    };
+
+    use super::{
+        super::{exhaustive::max_rate_from, graph::ChunkIdx, path::Paths, Search},
+        CompPrefix,
+    };
+
+    /// Expand every prefix of `params`' search, returning every composition found.  This also
+    /// checks that the score bound of every prefix is admissible, i.e. that no composition starting
+    /// with a prefix has a higher average score than that prefix's bound.
+    fn all_comps(params: Parameters) -> Vec<Composition> {
+        let search = search(params);
+        let mut paths = Paths::new();
+        let max_rate_from = max_rate_from(&search);
+        let mut comps = Vec::new();
+        for prefix in CompPrefix::starts(&search, &mut paths) {
+            expand_all(&search, &mut paths, &max_rate_from, prefix, &mut comps);
+        }
+        comps
+    }
+
+    /// Recursively expand `prefix`, returning the best average score of any composition starting
+    /// with it
+    fn expand_all(
+        search: &Search,
+        paths: &mut Paths,
+        max_rate_from: &IndexVec<ChunkIdx, f32>,
+        prefix: CompPrefix,
+        comps: &mut Vec<Composition>,
+    ) -> f32 {
+        let bound = prefix.avg_score_bound(search, max_rate_from);
+        let mut succs = BinaryHeap::new();
+        let mut best_score = f32::NEG_INFINITY;
+        if let Some(comp) = prefix.expand(search, paths, &mut succs, comps.len()) {
+            best_score = comp.average_score();
+            comps.push(comp);
+        }
+        for succ in succs {
+            let succ_score = expand_all(search, paths, max_rate_from, succ, comps);
+            best_score = best_score.max(succ_score);
+        }
+        assert!(
+            best_score <= bound + 1e-4,
+            "Prefix bound {bound} is lower than score {best_score}"
+        );
+        best_score
+    }
+
+    /// Check that adding a constraint to `params` removes some (but not all) of the compositions,
+    /// and that every composition removed fails evaluation with an error matching `is_expected`
+    fn check_constraint(
+        params: Parameters,
+        constrained_params: Parameters,
+        is_expected: impl Fn(&Error) -> bool,
+    ) {
+        let call_strings = |params: Parameters| -> HashSet<String> {
+            all_comps(params)
+                .iter()
+                .map(Composition::call_string)
+                .collect()
+        };
+        let unconstrained = call_strings(params);
+        let constrained = call_strings(constrained_params.clone());
+        assert!(!constrained.is_empty(), "Constraint removed every comp");
+        assert!(
+            constrained.len() < unconstrained.len(),
+            "Constraint had no effect"
+        );
+
+        let search = search(constrained_params);
+        for calling in &unconstrained {
+            match search.evaluate(calling) {
+                Ok(_) => assert!(constrained.contains(calling), "{calling} wasn't generated"),
+                Err(e) => {
+                    assert!(
+                        !constrained.contains(calling),
+                        "{calling} was generated: {e:?}"
+                    );
+                    assert!(is_expected(&e), "{calling} gave unexpected error {e:?}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn bounds_are_admissible() {
+        let comps = all_comps(plain_bob_minor());
+        assert!(!comps.is_empty());
+    }
+
+    #[test]
+    fn required_courses() {
+        let params = plain_bob_minor();
+        let mut constrained_params = params.clone();
+        constrained_params.required_courses = vec![RequiredCourses {
+            courses: CourseSet::from(Mask::parse_with_stage("1x3x56", Stage::MINOR).unwrap()),
+            min_leads: 3,
+        }];
+        check_constraint(params, constrained_params, |e| {
+            matches!(e, Error::MissingRequiredCourse { .. })
+        });
+    }
+
+    #[test]
+    fn unachievable_required_courses() {
+        let search = |min_leads| {
+            let mut params = plain_bob_minor();
+            params.required_courses = vec![RequiredCourses {
+                courses: CourseSet::from(Mask::parse_with_stage("1x3x56", Stage::MINOR).unwrap()),
+                min_leads,
+            }];
+            let config = Config {
+                thread_limit: Some(1),
+                ..Default::default()
+            };
+            Search::new(params, config)
+        };
+        // `1x3x56` matches two courses, each of which has 5 leads
+        assert!(search(10).is_ok());
+        assert!(matches!(
+            search(11),
+            Err(Error::UnachievableRequiredCourse {
+                min_leads: 11,
+                max_leads: 10,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn scheduled_weights() {
+        use RowPosition::*;
+        let schedule = |start, end, weight| {
+            vec![ScheduledWeight {
+                start,
+                end,
+                parts: Vec::new(),
+                weight,
+            }]
+        };
+        let scores = |params: Parameters| -> HashMap<String, f32> {
+            let comps = all_comps(params);
+            (comps.iter())
+                .map(|comp| (comp.call_string(), comp.total_score))
+                .collect()
+        };
+        let params = plain_bob_minor();
+        let base_scores = scores(params.clone());
+
+        // Weights covering the whole of each part are the same as increasing the base weights
+        let mut whole_part = params.clone();
+        whole_part.maybe_unused_music_types[0].weight_schedule =
+            schedule(FromStart(0), FromEnd(0), 1.0);
+        whole_part.maybe_unused_calls[0].weight_schedule = schedule(FromStart(0), FromEnd(0), 2.0);
+        let mut heavier = params.clone();
+        heavier.maybe_unused_music_types[0].weight += 1.0;
+        heavier.maybe_unused_calls[0].weight += 2.0;
+        let heavier_scores = scores(heavier);
+        let whole_part_scores = scores(whole_part);
+        assert_eq!(whole_part_scores.len(), heavier_scores.len());
+        for (calling, score) in &whole_part_scores {
+            assert!((score - heavier_scores[calling]).abs() < 1e-4, "{calling}");
+        }
+
+        // Weights on only part of each part change the scores, and the search gives the same
+        // scores as evaluating each calling.  Heavily weighting music near the end checks that the
+        // bounds account for scheduled weights which haven't been reached yet.
+        let mut partial = params;
+        partial.maybe_unused_music_types[0].weight_schedule =
+            schedule(FromStart(84), FromStart(120), 20.0);
+        partial.maybe_unused_calls[0].weight_schedule = schedule(FromEnd(36), FromEnd(0), 2.0);
+        let search = search(partial.clone());
+        let comps = all_comps(partial);
+        let mut num_changed = 0;
+        for comp in &comps {
+            let calling = comp.call_string();
+            let evaluated = search.evaluate(&calling).unwrap();
+            assert!(
+                (evaluated.total_score - comp.total_score).abs() < 1e-4,
+                "{calling}"
+            );
+            if (comp.total_score - base_scores[&calling]).abs() > 1e-4 {
+                num_changed += 1;
+            }
+        }
+        assert!(num_changed > 0);
+    }
+
+    #[test]
+    fn com_range() {
+        let params = spliced_minor();
+        let mut constrained_params = params.clone();
+        constrained_params.com_range = OptionalRangeInclusive {
+            min: Some(2),
+            max: Some(3),
+        };
+        check_constraint(params, constrained_params, |e| {
+            matches!(e, Error::WrongComCount { .. })
+        });
+    }
+
+    #[test]
+    fn method_balance() {
+        let scores = |params: Parameters| -> HashMap<String, f32> {
+            let comps = all_comps(params);
+            (comps.iter())
+                .map(|comp| (comp.call_string(), comp.total_score))
+                .collect()
+        };
+        let params = spliced_minor();
+        let base_scores = scores(params.clone());
+        // Both positive and negative weights should be reflected in the scores (and positive
+        // weights must be included in the bounds)
+        for weight in [-0.1, 0.05] {
+            let mut balanced = params.clone();
+            balanced.method_balance_weight = weight;
+            for comp in all_comps(balanced) {
+                let calling = comp.call_string();
+                let counts = comp.method_counts();
+                let mean = counts.iter().sum::<usize>() as f32 / counts.len() as f32;
+                let deviation = counts.iter().map(|&c| (c as f32 - mean).abs()).sum::<f32>();
+                let expected_score = base_scores[&calling] + weight * deviation;
+                assert!(
+                    (comp.total_score - expected_score).abs() < 1e-3,
+                    "{calling}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn call_count_ranges() {
+        let params = plain_bob_minor();
+        // Range on a single call
+        let mut constrained_params = params.clone();
+        constrained_params.maybe_unused_calls[0].count_range = OptionalRangeInclusive {
+            min: Some(1),
+            max: Some(2),
+        };
+        check_constraint(
+            params.clone(),
+            constrained_params,
+            |e| matches!(e, Error::WrongCallCount { symbol, .. } if symbol == "-"),
+        );
+        // Range on the total number of calls
+        let mut constrained_params = params.clone();
+        constrained_params.call_count_range = OptionalRangeInclusive {
+            min: Some(2),
+            max: Some(3),
+        };
+        check_constraint(params, constrained_params, |e| {
+            matches!(e, Error::WrongTotalCallCount { .. })
+        });
+    }
+
+    #[test]
+    fn calling_positions() {
+        let params = plain_bob_minor();
+        let is_expected = |e: &Error| matches!(e, Error::CallingPositionNotAllowed { .. });
+        // Bobs can only be called at some positions
+        let mut constrained_params = params.clone();
+        constrained_params.maybe_unused_calls[0].allowed_calling_positions =
+            vec!["W".to_owned(), "H".to_owned()];
+        check_constraint(params.clone(), constrained_params, is_expected);
+        // Singles can't be called at some positions
+        let mut constrained_params = params.clone();
+        constrained_params.maybe_unused_calls[1].forbidden_calling_positions = vec!["H".to_owned()];
+        check_constraint(params, constrained_params, is_expected);
+    }
+
+    #[test]
+    fn falseness_limits() {
+        let mut true_params = plain_bob_minor();
+        // False touches can pass a calling position without a call and then make the call when
+        // they get there again, so their calling positions don't say where the calls are.
+        // Lead-by-lead callings always do.
+        true_params.call_display_style = CallDisplayStyle::Positional;
+        let mut params = true_params.clone();
+        params.require_truth = false;
+
+        // Comps with no false rows are exactly the true comps
+        let comps = all_comps(params.clone());
+        let true_comps = (comps.iter())
+            .filter(|comp| comp.falseness().false_rows == 0)
+            .map(Composition::call_string)
+            .collect::<HashSet<_>>();
+        for comp in &comps {
+            let Falseness {
+                false_rows,
+                max_row_occurrences,
+            } = comp.falseness();
+            assert_eq!(false_rows == 0, max_row_occurrences == 1);
+        }
+        let expected_true_comps = (all_comps(true_params).iter())
+            .map(Composition::call_string)
+            .collect::<HashSet<_>>();
+        assert!(true_comps.len() < comps.len());
+        assert_eq!(true_comps, expected_true_comps);
+
+        // Limit the number of false rows
+        let mut constrained_params = params.clone();
+        constrained_params.falseness_limit = FalsenessLimit {
+            max_row_occurrences: None,
+            max_false_rows: Some(12),
+        };
+        check_constraint(params.clone(), constrained_params, |e| {
+            matches!(e, Error::TooManyFalseRows { limit: 12, .. })
+        });
+        // Limit the number of times each row can be rung
+        let mut constrained_params = params.clone();
+        constrained_params.falseness_limit = FalsenessLimit {
+            max_row_occurrences: Some(2),
+            max_false_rows: None,
+        };
+        check_constraint(params, constrained_params, |e| {
+            matches!(e, Error::RowRepeatedTooOften { limit: 2, .. })
+        });
+    }
+
+    #[test]
+    fn covering_calls() {
+        let true_params = grandsire_doubles();
+        let stage = true_params.stage;
+        let mut params = true_params.clone();
+        // Touches of Grandsire Doubles with bobs are all false
+        params.require_truth = false;
+        let method_pns = ["1", "5", "1", "5", "1", "5", "1", "5"];
+        let lead_ends = [["1", "3"], ["3", "1"], ["3", "123"]];
+
+        let comps = all_comps(params);
+        let mut lead_ends_used = HashSet::new();
+        for comp in &comps {
+            // Every change is either the method's or a call's, and calls replace both of the last
+            // two changes of a lead
+            let rows = comp.rows();
+            let changes = (rows.all_rows().tuple_windows())
+                .map(|(r1, r2)| PlaceNot::pn_between(r1, r2).unwrap())
+                .collect_vec();
+            for (idx, (&(_, sub_lead_idx), change)) in rows.annots().zip_eq(&changes).enumerate() {
+                if let Some(pn) = method_pns.get(sub_lead_idx) {
+                    assert_eq!(change, &PlaceNot::parse(pn, stage).unwrap());
+                } else if sub_lead_idx == 8 && idx + 1 < changes.len() {
+                    let lead_end = (lead_ends.iter())
+                        .position(|pns| {
+                            pns.iter()
+                                .map(|pn| PlaceNot::parse(pn, stage).unwrap())
+                                .eq(changes[idx..=idx + 1].iter().cloned())
+                        })
+                        .unwrap();
+                    lead_ends_used.insert(lead_end);
+                }
+            }
+        }
+        assert_eq!(lead_ends_used.len(), lead_ends.len());
+
+        // Falseness is still measured correctly when calls cover several rows
+        let true_comps = (comps.iter())
+            .filter(|comp| comp.falseness().false_rows == 0)
+            .map(Composition::call_string)
+            .collect::<HashSet<_>>();
+        let expected_true_comps = (all_comps(true_params).iter())
+            .map(Composition::call_string)
+            .collect::<HashSet<_>>();
+        assert!(true_comps.len() < comps.len());
+        assert_eq!(true_comps, expected_true_comps);
+    }
+
+    /// Check that restricting `params` to `splice_style` removes exactly the compositions with a
+    /// plain splice which [`PathElem::is_valid_splice_to`] rejects
+    ///
+    /// [`PathElem::is_valid_splice_to`]: crate::composition::PathElem::is_valid_splice_to
+    fn check_splice_style(params: Parameters, splice_style: SpliceStyle) -> Vec<Composition> {
+        let mut constrained_params = params.clone();
+        constrained_params.splice_style = splice_style;
+        check_constraint(params.clone(), constrained_params.clone(), |e| {
+            matches!(e, Error::InvalidSplice { .. })
+        });
+
+        let constrained_search = search(constrained_params.clone());
+        let query = &constrained_search.query;
+        let constrained_comps = all_comps(constrained_params);
+        let constrained = (constrained_comps.iter())
+            .map(Composition::call_string)
+            .collect::<HashSet<_>>();
+        for comp in all_comps(params) {
+            let splices_are_valid = comp.path.iter().tuple_windows().all(|(elem, next_elem)| {
+                !elem.is_splice_to(next_elem, query) || elem.is_valid_splice_to(next_elem, query)
+            });
+            let calling = comp.call_string();
+            assert_eq!(
+                splices_are_valid,
+                constrained.contains(&calling),
+                "{calling}"
+            );
+        }
+        constrained_comps
+    }
+
+    /// The number of plain splices in `comp`, and how many of them happen part-way through a lead
+    fn plain_splices(comp: &Composition) -> (usize, usize) {
+        let splices = (comp.path.iter().tuple_windows())
+            .filter(|(elem, next_elem)| {
+                elem.ends_with_plain() && elem.is_splice_to(next_elem, &comp.query)
+            })
+            .map(|(_, next_elem)| next_elem)
+            .collect_vec();
+        let mid_lead = splices.iter().filter(|e| e.start_sub_lead_idx != 0).count();
+        (splices.len(), mid_lead)
+    }
+
+    #[test]
+    fn half_lead_splices() {
+        let mut params = spliced_minor();
+        for method in &mut params.maybe_unused_methods {
+            let half_lead_idx = method.inner.half_lead_idx().unwrap();
+            method
+                .inner
+                .add_label(half_lead_idx, LABEL_HALF_LEAD.to_owned());
+        }
+        let comps = check_splice_style(params.clone(), SpliceStyle::HalfLeads);
+
+        params.splice_style = SpliceStyle::HalfLeads;
+        let search = search(params);
+        let mut num_mid_lead_splices = 0;
+        for comp in &comps {
+            // Every plain splice is at a half lead
+            let (splices, mid_lead) = plain_splices(comp);
+            assert_eq!(splices, mid_lead);
+            num_mid_lead_splices += mid_lead;
+            // Call strings of mid-lead splices (containing `/`) can be read back
+            let calling = comp.call_string();
+            let evaluated = search.evaluate(&calling).unwrap();
+            assert_eq!(evaluated.call_string(), calling);
+            assert_eq!(evaluated.rows(), comp.rows());
+        }
+        assert!(num_mid_lead_splices > 0);
+        assert!(comps.iter().any(|comp| comp.call_string().contains('/')));
+    }
+
+    #[test]
+    fn course_head_splices() {
+        let mut params = spliced_minor();
+        let tenor_home = Mask::parse_with_stage("xxxxx6", Stage::MINOR).unwrap();
+        for method in &mut params.maybe_unused_methods {
+            method.allowed_courses = vec![CourseSet::from(tenor_home.clone())];
+        }
+        let comps = check_splice_style(params, SpliceStyle::CourseHeads);
+
+        let mut num_splices = 0;
+        for comp in &comps {
+            // Every plain splice starts a new course
+            for (elem, next_elem) in comp.path.iter().tuple_windows() {
+                if elem.ends_with_plain() && elem.is_splice_to(next_elem, &comp.query) {
+                    assert_eq!(next_elem.start_sub_lead_idx, 0);
+                    assert!(tenor_home.matches(&next_elem.start_row));
+                }
+            }
+            num_splices += plain_splices(comp).0;
+        }
+        assert!(num_splices > 0);
+    }
+
+    #[test]
+    fn wraps() {
+        let mut params = plain_bob_minor();
+        let stage = params.stage;
+        for (id, strokes) in [(1, StrokeSet::Both), (2, StrokeSet::Hand)] {
+            params.maybe_unused_music_types.push(MusicType {
+                id: MusicTypeId(id),
+                patterns: Pattern::run_wraps(stage, 4),
+                wraps: true,
+                strokes,
+                ..params.maybe_unused_music_types[0].clone()
+            });
+        }
+
+        let comps = all_comps(params.clone());
+        let search = search(params);
+        assert!(comps.iter().any(|comp| comp.num_calls() > 0));
+        let mut total_wraps = 0;
+        for comp in &comps {
+            // Count the wraps from every row into the next, including the wraps between chunks
+            // and over calls
+            let rows = comp.rows();
+            let mut wraps = 0;
+            let mut handstroke_wraps = 0;
+            for (idx, (row, next_row)) in rows.all_rows().tuple_windows().enumerate() {
+                let num_wraps = (Pattern::run_wraps(stage, 4).iter())
+                    .map(|pattern| pattern.num_wraps(row, next_row).unwrap())
+                    .sum::<usize>();
+                wraps += num_wraps;
+                // `start_stroke` is the stroke of the first row after the start row
+                if idx % 2 == 1 {
+                    handstroke_wraps += num_wraps;
+                }
+            }
+            let calling = comp.call_string();
+            assert_eq!(comp.music_counts()[&MusicTypeId(1)], wraps, "{calling}");
+            assert_eq!(
+                comp.music_counts()[&MusicTypeId(2)],
+                handstroke_wraps,
+                "{calling}"
+            );
+            // Evaluating the composition counts the same wraps
+            let evaluated = search.evaluate(&calling).unwrap();
+            assert_eq!(evaluated.music_counts(), comp.music_counts(), "{calling}");
+            total_wraps += wraps;
+        }
+        assert!(total_wraps > 0);
+    }
+
+    #[test]
+    fn new_extents() {
+        let search = search(plain_bob_doubles());
+        let mut paths = Paths::new();
+        // Find a prefix which has just come round at the end of its first extent
+        let mut stack = CompPrefix::starts(&search, &mut paths).into_vec();
+        let end_of_extent = loop {
+            let prefix = stack.pop().unwrap();
+            if prefix.is_finished() {
+                break prefix;
+            }
+            let mut succs = BinaryHeap::new();
+            assert!(prefix.expand(&search, &mut paths, &mut succs, 0).is_none());
+            stack.extend(succs);
+        };
+        assert_eq!(end_of_extent.length(), TotalLength::new(120));
+        assert!(end_of_extent.can_start_new_extent(&search));
+
+        // The next extent starts afresh, carrying on from the length of the first
+        for start_idx in search.graph.starts.indices() {
+            let next_extent = end_of_extent.clone().start_new_extent(&search, start_idx);
+            assert!(!next_extent.is_finished());
+            assert_eq!(next_extent.length(), TotalLength::new(120));
+            assert_eq!(next_extent.extent_start, TotalLength::new(120));
+            assert!(next_extent.row_counts.iter().all(|&count| count == 0));
+            assert!(next_extent.unringable_chunks.none());
+        }
+
+        // Expanding the end of the extent gives both the composition and the next extents
+        let mut succs = BinaryHeap::new();
+        let comp = end_of_extent
+            .clone()
+            .expand(&search, &mut paths, &mut succs, 0);
+        assert!(comp.is_none()); // 120 rows is too short for a composition
+        assert_eq!(succs.len(), search.graph.starts.len());
+
+        // There's no room for another extent if the composition can't be any longer
+        let mut short_params = plain_bob_doubles();
+        short_params.length = TotalLength::new(120)..=TotalLength::new(120);
+        let short_search = self::search(short_params);
+        assert!(!end_of_extent.can_start_new_extent(&short_search));
+        // And non-multi-extent compositions only have one extent
+        let mut one_extent_params = plain_bob_doubles();
+        one_extent_params.multi_extent = false;
+        one_extent_params.length = TotalLength::new(120)..=TotalLength::new(240);
+        let one_extent_search = self::search(one_extent_params);
+        assert!(!end_of_extent.can_start_new_extent(&one_extent_search));
+    }
+
+    #[test]
+    fn multi_extent() {
+        let mut params = plain_bob_doubles();
+        params.length = TotalLength::new(120)..=TotalLength::new(240);
+        let extent = TotalLength::new(120).as_usize();
+
+        let comps = all_comps(params.clone());
+        let mut num_multi_extent_comps = 0;
+        for comp in &comps {
+            let calling = comp.call_string();
+            assert_eq!(comp.length() % extent, 0, "{calling}");
+            assert_eq!(comp.num_extents(), comp.length() / extent, "{calling}");
+            // Every extent comes round and is true, though rows repeat between extents
+            let rows = comp.rows();
+            for extent_rows in &rows.rows().chunks(extent) {
+                let extent_rows = extent_rows.collect_vec();
+                assert!(extent_rows[0].is_rounds(), "{calling}");
+                assert_eq!(extent_rows.iter().unique().count(), extent, "{calling}");
+            }
+            assert!(rows.leftover_row().is_rounds());
+            assert_eq!(comp.falseness().false_rows, 0, "{calling}");
+            if comp.num_extents() > 1 {
+                num_multi_extent_comps += 1;
+                assert_eq!(rows.rows().unique().count(), extent, "{calling}");
+            }
+        }
+        assert!(num_multi_extent_comps > 0);
+
+        // Each extent must come round
+        let search = search(params.clone());
+        let extent_calling = "#PPPP[-]PPPP[-]PPPP[-]";
+        let error = |calling: &str| search.evaluate(calling).unwrap_err();
+        assert!(matches!(
+            error(&format!("{extent_calling} + #PPPP[-]")),
+            Error::DoesntComeRound
+        ));
+        assert!(matches!(
+            error(&format!("#PPPP[-]PPPP[-] + {extent_calling}")),
+            Error::DoesntComeRound
+        ));
+        assert!(matches!(
+            error(&format!("#PPPPPPPPPP + {extent_calling}")),
+            Error::ComesRoundEarly { .. }
+        ));
+
+        // Each extent must be true on its own
+        let false_extent = "#P[s]P[-]P[-]P[-]P[-]P[-]P[-]P[-]P[-]P[-]P[-]P[s]";
+        let mut false_params = params;
+        false_params.require_truth = false;
+        let false_search = self::search(false_params);
+        let false_comp = false_search
+            .evaluate(&format!("{extent_calling} + {false_extent}"))
+            .unwrap();
+        assert!(false_comp.falseness().false_rows > 0);
+        assert!(matches!(
+            error(&format!("{extent_calling} + {false_extent}")),
+            Error::FalseComposition { .. }
+        ));
     }
 }
@@ -0,0 +1,568 @@
+//! Code for moving the lowest-scoring part of a best-first search's frontier onto disk, instead of
+//! throwing it away when the search runs out of memory.
+//!
+//! Like [checkpoints](super::checkpoint), spilled prefixes are stored as the paths they take
+//! through the graph, and are rebuilt (with [`CompPrefix::follow`]) when they're loaded back into
+//! memory.  Every spill writes a new 'run' file, which contains prefixes sorted best first.  The
+//! runs are merged as they're read back, so prefixes are always reloaded in the order that the
+//! in-memory frontier would expand them.
+
+use std::{
+    collections::BinaryHeap,
+    fs::File,
+    io::{BufReader, BufWriter, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use ordered_float::OrderedFloat;
+
+use super::{
+    checkpoint::{read_u32, PathTree},
+    graph::{StartIdx, SuccIdx},
+    path::Paths,
+    prefix::CompPrefix,
+    Search,
+};
+
+/// Once this many runs have been written, they are merged into one file.  This stops a long
+/// search from holding huge numbers of files open.
+const MAX_RUNS: usize = 32;
+
+/// Used to give every run file a unique name, even if several searches share a directory
+static NEXT_RUN_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// The prefixes which one search thread has written to disk
+#[derive(Debug)]
+pub(super) struct SpilledFrontier {
+    dir: PathBuf,
+    runs: Vec<SpillRun>,
+}
+
+impl SpilledFrontier {
+    pub fn new(dir: &Path) -> Self {
+        Self {
+            dir: dir.to_owned(),
+            runs: Vec::new(),
+        }
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// The number of prefixes which are currently on disk
+    pub fn len(&self) -> usize {
+        self.runs.iter().map(|run| run.len).sum()
+    }
+
+    /// The sum of the lengths of every prefix which is currently on disk
+    pub fn total_len(&self) -> u64 {
+        self.runs.iter().map(|run| run.total_len).sum()
+    }
+
+    /// An upper bound on the length of the prefixes which are currently on disk
+    pub fn max_length(&self) -> usize {
+        self.runs
+            .iter()
+            .map(|run| run.max_length)
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// The [`avg_score`](CompPrefix::avg_score) of the best prefix on disk, or `None` if there
+    /// aren't any
+    pub fn best_score(&self) -> Option<OrderedFloat<f32>> {
+        self.runs
+            .iter()
+            .filter_map(|run| run.head.as_ref())
+            .map(|prefix| prefix.avg_score)
+            .max()
+    }
+
+    /// Write some prefixes (which must be sorted best first) to a new run file.  If this returns
+    /// an error, then none of `prefixes` have been saved (but every prefix which was already on
+    /// disk is kept).
+    pub fn spill(&mut self, prefixes: &[CompPrefix], paths: &Paths) -> std::io::Result<()> {
+        if prefixes.is_empty() {
+            return Ok(());
+        }
+        if self.runs.len() >= MAX_RUNS {
+            self.merge_runs()?;
+        }
+        let prefixes = prefixes.iter().map(|prefix| {
+            let (start_idx, succs) = paths.flatten(prefix.path_head());
+            Ok(SpilledPrefix {
+                avg_score: prefix.avg_score(),
+                length: prefix.length().as_usize() as u32,
+                start_idx,
+                succs,
+            })
+        });
+        let run = write_run(&self.dir, prefixes)?;
+        self.runs.push(run);
+        Ok(())
+    }
+
+    /// Load up to `max_prefixes` of the best prefixes from disk, adding them to `frontier`
+    pub fn reload(
+        &mut self,
+        search: &Search,
+        paths: &mut Paths,
+        frontier: &mut BinaryHeap<CompPrefix>,
+        max_prefixes: usize,
+    ) {
+        for _ in 0..max_prefixes {
+            let Some(SpilledPrefix {
+                start_idx, succs, ..
+            }) = pop_best(&mut self.runs)
+            else {
+                break; // Every prefix has been reloaded
+            };
+            if search.graph.starts.get(start_idx).is_none() {
+                log::warn!("Ignoring invalid prefix in {:?}", self.dir);
+                continue;
+            }
+            // Rebuild the prefix by following its path from the start
+            let mut prefix = Some(CompPrefix::start(
+                search,
+                start_idx,
+                paths.start_path(start_idx),
+            ));
+            for &succ_idx in &succs {
+                prefix = prefix.and_then(|p| p.follow(search, succ_idx));
+            }
+            match prefix {
+                Some(mut prefix) => {
+                    prefix.set_path_head(paths.add_flattened(start_idx, &succs));
+                    frontier.push(prefix);
+                }
+                None => log::warn!("Ignoring invalid prefix in {:?}", self.dir),
+            }
+        }
+    }
+
+    /// Add the path of every prefix on disk to a [`PathTree`], so that they're included in
+    /// checkpoints
+    pub fn add_to_tree(&self, tree: &mut PathTree) {
+        for run in &self.runs {
+            if let Err(e) = run.add_to_tree(tree) {
+                log::warn!(
+                    "Couldn't read {:?}, so it won't be checkpointed: {e}",
+                    run.path
+                );
+            }
+        }
+    }
+
+    /// Combine every run into one, in order to close their files.  The old runs are read with
+    /// separate readers and only deleted once the merged run has been written, so if this returns
+    /// an error then `self.runs` is left untouched.
+    fn merge_runs(&mut self) -> std::io::Result<()> {
+        let mut readers = self
+            .runs
+            .iter()
+            .map(SpillRun::read_remaining)
+            .collect::<std::io::Result<Vec<_>>>()?;
+        let merged_run = write_run(
+            &self.dir,
+            std::iter::from_fn(|| {
+                let (reader_idx, _) = readers
+                    .iter()
+                    .enumerate()
+                    .filter_map(|(idx, r)| Some((idx, r.head.as_ref()?.avg_score)))
+                    .max_by_key(|(_, avg_score)| *avg_score)?;
+                readers[reader_idx].pop().transpose()
+            }),
+        )?;
+        for run in self.runs.drain(..) {
+            run.delete();
+        }
+        self.runs.push(merged_run);
+        Ok(())
+    }
+}
+
+impl Drop for SpilledFrontier {
+    fn drop(&mut self) {
+        for run in self.runs.drain(..) {
+            run.delete();
+        }
+    }
+}
+
+/// Remove the best prefix from any of some [`SpillRun`]s, deleting the run if it becomes empty
+fn pop_best(runs: &mut Vec<SpillRun>) -> Option<SpilledPrefix> {
+    let (run_idx, _) = runs
+        .iter()
+        .enumerate()
+        .filter_map(|(idx, run)| Some((idx, run.head.as_ref()?.avg_score)))
+        .max_by_key(|(_, avg_score)| *avg_score)?;
+    let run = &mut runs[run_idx];
+    let prefix = run.pop();
+    if run.head.is_none() {
+        runs.swap_remove(run_idx).delete();
+    }
+    prefix
+}
+
+/// Write some prefixes (sorted best first) to a new file in `dir`, and open that file as a
+/// [`SpillRun`]
+fn write_run(
+    dir: &Path,
+    prefixes: impl Iterator<Item = std::io::Result<SpilledPrefix>>,
+) -> std::io::Result<SpillRun> {
+    std::fs::create_dir_all(dir)?;
+    let run_id = NEXT_RUN_ID.fetch_add(1, Ordering::Relaxed);
+    let path = dir.join(format!("monument-{}-{run_id}.spill", std::process::id()));
+
+    let mut len = 0;
+    let mut total_len = 0;
+    let mut max_length = 0;
+    let result = (|| -> std::io::Result<SpillRun> {
+        let mut w = BufWriter::new(File::create(&path)?);
+        for prefix in prefixes {
+            let prefix = prefix?;
+            prefix.write(&mut w)?;
+            len += 1;
+            total_len += prefix.length as u64;
+            max_length = max_length.max(prefix.length as usize);
+        }
+        w.flush()?;
+        drop(w);
+        SpillRun::open(path.clone(), len, total_len, max_length)
+    })();
+    if result.is_err() {
+        // Don't leave half-written runs lying around
+        let _ = std::fs::remove_file(&path);
+    }
+    result
+}
+
+/// A file containing some spilled prefixes, sorted best first
+#[derive(Debug)]
+struct SpillRun {
+    path: PathBuf,
+    reader: BufReader<File>,
+    /// The best prefix in this run which hasn't been reloaded, or `None` if the run is empty
+    head: Option<SpilledPrefix>,
+    /// The position in the file of the prefix after `head`
+    next_offset: u64,
+    /// The number of prefixes in this run which haven't been reloaded (including `head`)
+    len: usize,
+    /// The sum of the lengths of the prefixes which haven't been reloaded
+    total_len: u64,
+    /// The length of the longest prefix which was written to this run
+    max_length: usize,
+}
+
+impl SpillRun {
+    fn open(path: PathBuf, len: usize, total_len: u64, max_length: usize) -> std::io::Result<Self> {
+        let mut run = Self {
+            reader: BufReader::new(File::open(&path)?),
+            path,
+            head: None,
+            next_offset: 0,
+            len,
+            total_len,
+            max_length,
+        };
+        if len > 0 {
+            run.head = Some(run.read_next()?);
+        }
+        Ok(run)
+    }
+
+    /// Remove `head`, replacing it with the next prefix in the file
+    fn pop(&mut self) -> Option<SpilledPrefix> {
+        let prefix = self.head.take()?;
+        self.len -= 1;
+        self.total_len -= prefix.length as u64;
+        if self.len > 0 {
+            match self.read_next() {
+                Ok(next) => self.head = Some(next),
+                Err(e) => {
+                    log::warn!(
+                        "Couldn't read {:?}, so dropping its prefixes: {e}",
+                        self.path
+                    );
+                    self.len = 0;
+                    self.total_len = 0;
+                }
+            }
+        }
+        Some(prefix)
+    }
+
+    fn read_next(&mut self) -> std::io::Result<SpilledPrefix> {
+        let prefix = SpilledPrefix::read(&mut self.reader)?;
+        self.next_offset += prefix.size_on_disk();
+        Ok(prefix)
+    }
+
+    /// Open a separate reader over the prefixes in this run which haven't been reloaded, so that
+    /// they can be read without moving `self.reader`
+    fn read_remaining(&self) -> std::io::Result<RunReader> {
+        let mut file = File::open(&self.path)?;
+        file.seek(SeekFrom::Start(self.next_offset))?;
+        Ok(RunReader {
+            head: self.head.clone(),
+            num_unread: self.len.saturating_sub(1),
+            reader: BufReader::new(file),
+        })
+    }
+
+    fn add_to_tree(&self, tree: &mut PathTree) -> std::io::Result<()> {
+        let mut r = self.read_remaining()?;
+        while let Some(prefix) = r.pop()? {
+            tree.add_flat_path(prefix.start_idx, &prefix.succs);
+        }
+        Ok(())
+    }
+
+    /// Close and delete this run's file
+    fn delete(self) {
+        let Self { path, reader, .. } = self;
+        drop(reader); // Some OSes won't delete files which are still open
+        if let Err(e) = std::fs::remove_file(&path) {
+            log::warn!("Couldn't delete {path:?}: {e}");
+        }
+    }
+}
+
+/// Reads the prefixes of a [`SpillRun`] which haven't been reloaded, without changing the run
+struct RunReader {
+    /// The next prefix to be returned, or `None` if every prefix has been read
+    head: Option<SpilledPrefix>,
+    /// The number of prefixes after `head` which haven't been read from the file
+    num_unread: usize,
+    reader: BufReader<File>,
+}
+
+impl RunReader {
+    /// Remove `head`, replacing it with the next prefix in the file
+    fn pop(&mut self) -> std::io::Result<Option<SpilledPrefix>> {
+        let Some(prefix) = self.head.take() else {
+            return Ok(None);
+        };
+        if self.num_unread > 0 {
+            self.head = Some(SpilledPrefix::read(&mut self.reader)?);
+            self.num_unread -= 1;
+        }
+        Ok(Some(prefix))
+    }
+}
+
+/// A [`CompPrefix`] as it's stored on disk
+#[derive(Debug, Clone)]
+struct SpilledPrefix {
+    avg_score: OrderedFloat<f32>,
+    length: u32,
+    start_idx: StartIdx,
+    succs: Vec<SuccIdx>,
+}
+
+impl SpilledPrefix {
+    fn write(&self, w: &mut impl Write) -> std::io::Result<()> {
+        w.write_all(&self.avg_score.0.to_bits().to_le_bytes())?;
+        w.write_all(&self.length.to_le_bytes())?;
+        w.write_all(&self.start_idx.raw().to_le_bytes())?;
+        w.write_all(&(self.succs.len() as u32).to_le_bytes())?;
+        for succ in &self.succs {
+            w.write_all(&succ.raw().to_le_bytes())?;
+        }
+        Ok(())
+    }
+
+    fn read(r: &mut impl std::io::Read) -> std::io::Result<Self> {
+        let avg_score = OrderedFloat(f32::from_bits(read_u32(r)?));
+        let length = read_u32(r)?;
+        let start_idx = StartIdx::from_raw(read_u32(r)?);
+        let num_succs = read_u32(r)?;
+        let mut succs = Vec::with_capacity(num_succs as usize);
+        for _ in 0..num_succs {
+            succs.push(SuccIdx::from_raw(read_u32(r)?));
+        }
+        Ok(Self {
+            avg_score,
+            length,
+            start_idx,
+            succs,
+        })
+    }
+
+    /// The number of bytes taken by [`SpilledPrefix::write`]
+    fn size_on_disk(&self) -> u64 {
+        4 * (4 + self.succs.len() as u64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::BinaryHeap, path::PathBuf};
+
+    use crate::test_utils;
+
+    use super::{
+        super::{
+            graph::{StartIdx, SuccIdx},
+            path::Paths,
+            prefix::CompPrefix,
+            Search,
+        },
+        SpilledFrontier, MAX_RUNS,
+    };
+
+    fn search() -> Search {
+        test_utils::search(test_utils::plain_bob_minor())
+    }
+
+    fn spill_dir(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("monument-test-{}-{name}", std::process::id()))
+    }
+
+    /// Expand prefixes (shortest first) until there are at least `num_prefixes`, returning them
+    /// sorted best first
+    fn prefixes(search: &Search, paths: &mut Paths, num_prefixes: usize) -> Vec<CompPrefix> {
+        let mut prefixes = Vec::new();
+        let mut to_expand = CompPrefix::starts(search, paths).into_vec();
+        while prefixes.len() < num_prefixes {
+            let mut succs = BinaryHeap::new();
+            for prefix in to_expand.drain(..) {
+                prefixes.push(prefix.clone());
+                prefix.expand(search, paths, &mut succs, 0);
+            }
+            assert!(!succs.is_empty(), "Ran out of prefixes");
+            to_expand = succs.into_vec();
+        }
+        prefixes.sort_by(|a, b| b.cmp(a));
+        prefixes
+    }
+
+    /// Spill `prefixes` into `num_runs` runs, each of which gets every `num_runs`th prefix (so
+    /// the runs overlap and have to be interleaved when they're merged)
+    fn spill_runs(
+        spilled: &mut SpilledFrontier,
+        prefixes: &[CompPrefix],
+        paths: &Paths,
+        num_runs: usize,
+    ) {
+        for run_idx in 0..num_runs {
+            let run = prefixes
+                .iter()
+                .skip(run_idx)
+                .step_by(num_runs)
+                .cloned()
+                .collect::<Vec<_>>();
+            spilled.spill(&run, paths).unwrap();
+        }
+    }
+
+    /// Reload every prefix from `spilled` one at a time, checking that they come back best
+    /// first.  Returns the paths of the prefixes, in the order they were reloaded.
+    fn reload_all(
+        spilled: &mut SpilledFrontier,
+        search: &Search,
+        paths: &mut Paths,
+    ) -> Vec<(StartIdx, Vec<SuccIdx>)> {
+        let mut reloaded = Vec::new();
+        let mut last_score = None;
+        while spilled.len() > 0 {
+            let mut frontier = BinaryHeap::new();
+            spilled.reload(search, paths, &mut frontier, 1);
+            let prefix = frontier.pop().unwrap();
+            assert!(frontier.is_empty());
+            if let Some(last_score) = last_score {
+                assert!(
+                    prefix.avg_score() <= last_score,
+                    "Prefixes reloaded out of order"
+                );
+            }
+            last_score = Some(prefix.avg_score());
+            reloaded.push(paths.flatten(prefix.path_head()));
+        }
+        reloaded
+    }
+
+    fn sorted_paths(prefixes: &[CompPrefix], paths: &Paths) -> Vec<(StartIdx, Vec<SuccIdx>)> {
+        let mut flat_paths = prefixes
+            .iter()
+            .map(|prefix| paths.flatten(prefix.path_head()))
+            .collect::<Vec<_>>();
+        flat_paths.sort();
+        flat_paths
+    }
+
+    #[test]
+    fn merged_runs_keep_every_prefix() {
+        let dir = spill_dir("merge");
+        let search = search();
+        let mut paths = Paths::new();
+        let prefixes = prefixes(&search, &mut paths, 500);
+
+        // Write enough runs that they get merged
+        let mut spilled = SpilledFrontier::new(&dir);
+        spill_runs(&mut spilled, &prefixes, &paths, MAX_RUNS + 8);
+        assert!(spilled.runs.len() < MAX_RUNS);
+        assert_eq!(spilled.len(), prefixes.len());
+        assert_eq!(spilled.best_score(), Some(prefixes[0].avg_score()));
+
+        let mut reloaded = reload_all(&mut spilled, &search, &mut paths);
+        reloaded.sort();
+        assert_eq!(reloaded, sorted_paths(&prefixes, &paths));
+        // Every run should be deleted once it's been reloaded
+        assert!(spilled.runs.is_empty());
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+
+        std::fs::remove_dir(&dir).unwrap();
+    }
+
+    #[test]
+    fn failed_merge_keeps_runs() {
+        let dir = spill_dir("failed-merge");
+        let search = search();
+        let mut paths = Paths::new();
+        let prefixes = prefixes(&search, &mut paths, 500);
+        let (first_prefixes, last_prefixes) = prefixes.split_at(400);
+
+        let mut spilled = SpilledFrontier::new(&dir);
+        spill_runs(&mut spilled, first_prefixes, &paths, MAX_RUNS);
+        assert_eq!(spilled.runs.len(), MAX_RUNS);
+
+        // Truncate one of the runs, so that the merge fails after part of the merged run has been
+        // written
+        let truncated_run = &spilled.runs[MAX_RUNS - 1];
+        let file_len = std::fs::metadata(&truncated_run.path).unwrap().len();
+        let truncated_bytes = std::fs::read(&truncated_run.path).unwrap();
+        std::fs::write(
+            &truncated_run.path,
+            &truncated_bytes[..file_len as usize - 1],
+        )
+        .unwrap();
+        assert!(spilled.spill(last_prefixes, &paths).is_err());
+        // The old runs are kept, and the half-written run is removed
+        assert_eq!(spilled.runs.len(), MAX_RUNS);
+        assert_eq!(spilled.len(), first_prefixes.len());
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), MAX_RUNS);
+        std::fs::write(&spilled.runs[MAX_RUNS - 1].path, &truncated_bytes).unwrap();
+
+        // The merged run can't be written if `dir` isn't a directory
+        let not_a_dir = spill_dir("not-a-dir");
+        std::fs::write(&not_a_dir, []).unwrap();
+        spilled.dir = not_a_dir.clone();
+        assert!(spilled.spill(last_prefixes, &paths).is_err());
+        assert_eq!(spilled.runs.len(), MAX_RUNS);
+        assert_eq!(spilled.len(), first_prefixes.len());
+        std::fs::remove_file(&not_a_dir).unwrap();
+
+        // Once the runs can be merged, nothing should have been lost
+        spilled.dir = dir.clone();
+        spilled.spill(last_prefixes, &paths).unwrap();
+        assert_eq!(spilled.runs.len(), 2);
+        let mut reloaded = reload_all(&mut spilled, &search, &mut paths);
+        reloaded.sort();
+        assert_eq!(reloaded, sorted_paths(&prefixes, &paths));
+
+        std::fs::remove_dir(&dir).unwrap();
+    }
+}
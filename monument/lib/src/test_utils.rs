@@ -0,0 +1,225 @@
+//! Fixtures shared by the unit tests
+
+use std::sync::Arc;
+
+use bellframe::{
+    method::{LABEL_LEAD_END, LABEL_SIX_END},
+    music::Pattern,
+    Mask, PlaceNot, PnBlock, RowBuf, Stage, Stroke,
+};
+
+use crate::{
+    group::PartHeadGroup,
+    parameters::{
+        base_calls, BaseCallType, Call, CallDisplayStyle, CourseSet, FalsenessLimit, IdGenerator,
+        Method, MethodId, MusicType, MusicTypeId, OptionalRangeInclusive, Parameters, SpliceStyle,
+        StrokeSet,
+    },
+    scoring::DefaultScorer,
+    utils::lengths::TotalLength,
+    Config, Search,
+};
+
+/// Touches of Plain Bob Minor, up to two courses long
+pub(crate) fn plain_bob_minor() -> Parameters {
+    let stage = Stage::MINOR;
+    let mut method =
+        bellframe::Method::from_place_not_string("Plain".to_owned(), stage, "x16x16x16,12")
+            .unwrap();
+    method.add_label(0, LABEL_LEAD_END.to_owned());
+    Parameters {
+        length: TotalLength::new(60)..=TotalLength::new(120),
+        stage,
+        num_comps: 10_000,
+        min_comp_distance: 0,
+        require_truth: true,
+        multi_extent: false,
+        falseness_limit: FalsenessLimit::default(),
+        maybe_unused_methods: vec![Method {
+            id: MethodId(0),
+            used: true,
+            inner: method,
+            custom_shorthand: String::new(),
+            count_range: OptionalRangeInclusive::OPEN,
+            start_indices: vec![0],
+            end_indices: (0..12).collect(),
+            allowed_courses: vec![CourseSet::from(Mask::any(stage))],
+            non_duffer_courses: vec![CourseSet::from(Mask::any(stage))],
+        }],
+        splice_style: SpliceStyle::LeadLabels,
+        splice_weight: 0.0,
+        com_range: OptionalRangeInclusive::OPEN,
+        method_balance_weight: 0.0,
+        maybe_unused_calls: base_calls(
+            &mut IdGenerator::starting_at_zero(),
+            BaseCallType::Near,
+            Some(-0.3),
+            Some(-0.5),
+            stage,
+        ),
+        call_count_range: OptionalRangeInclusive::OPEN,
+        call_display_style: CallDisplayStyle::CallingPositions(stage.tenor()),
+        atw_weight: None,
+        require_atw: false,
+        start_row: RowBuf::rounds(stage),
+        end_row: RowBuf::rounds(stage),
+        part_head_group: PartHeadGroup::one_part(stage),
+        course_weights: Vec::new(),
+        required_courses: Vec::new(),
+        max_contiguous_duffer: None,
+        max_total_duffer: None,
+        maybe_unused_music_types: vec![MusicType {
+            id: MusicTypeId(0),
+            used: true,
+            patterns: Pattern::runs_front_or_back(stage, 4),
+            wraps: false,
+            strokes: StrokeSet::Both,
+            weight: 1.0,
+            weight_schedule: Vec::new(),
+            count_score: None,
+            count_range: OptionalRangeInclusive::OPEN,
+        }],
+        start_stroke: Stroke::Hand,
+        scorer: Arc::new(DefaultScorer),
+    }
+}
+
+/// Spliced touches of Plain Bob and Cambridge Surprise Minor, up to 96 rows long
+pub(crate) fn spliced_minor() -> Parameters {
+    let mut params = plain_bob_minor();
+    let mut method = bellframe::Method::from_place_not_string(
+        "Cambridge".to_owned(),
+        Stage::MINOR,
+        "x36x14x12x36x14x56,12",
+    )
+    .unwrap();
+    method.add_label(0, LABEL_LEAD_END.to_owned());
+    params.maybe_unused_methods.push(Method {
+        id: MethodId(1),
+        inner: method,
+        end_indices: (0..24).collect(),
+        ..params.maybe_unused_methods[0].clone()
+    });
+    params.length = TotalLength::new(48)..=TotalLength::new(96);
+    params
+}
+
+/// Two extents of Plain Bob Doubles, rung as one [multi-extent](Parameters::multi_extent)
+/// composition
+pub(crate) fn plain_bob_doubles() -> Parameters {
+    let stage = Stage::DOUBLES;
+    let mut method =
+        bellframe::Method::from_place_not_string("Plain".to_owned(), stage, "5.1.5.1.5,125")
+            .unwrap();
+    method.add_label(0, LABEL_LEAD_END.to_owned());
+
+    let mut params = plain_bob_minor();
+    params.stage = stage;
+    params.length = TotalLength::new(240)..=TotalLength::new(240);
+    params.multi_extent = true;
+    params.maybe_unused_methods = vec![Method {
+        inner: method,
+        end_indices: vec![0],
+        allowed_courses: vec![CourseSet::from(Mask::any(stage))],
+        non_duffer_courses: vec![CourseSet::from(Mask::any(stage))],
+        ..params.maybe_unused_methods[0].clone()
+    }];
+    params.maybe_unused_calls = base_calls(
+        &mut IdGenerator::starting_at_zero(),
+        BaseCallType::Near,
+        Some(-0.3),
+        Some(-0.5),
+        stage,
+    );
+    params.start_row = RowBuf::rounds(stage);
+    params.end_row = RowBuf::rounds(stage);
+    params.call_display_style = CallDisplayStyle::Positional;
+    params.part_head_group = PartHeadGroup::one_part(stage);
+    params.maybe_unused_music_types[0].patterns = Pattern::runs_front_or_back(stage, 4);
+    params
+}
+
+/// Touches of Grandsire Doubles, whose bobs (`3.1`) and singles (`3.123`) both replace the last
+/// two changes of the lead
+pub(crate) fn grandsire_doubles() -> Parameters {
+    let stage = Stage::DOUBLES;
+    let mut method = bellframe::Method::from_place_not_string(
+        "Grandsire".to_owned(),
+        stage,
+        "1.5.1.5.1.5.1.5.1.3",
+    )
+    .unwrap();
+    method.add_label(0, LABEL_LEAD_END.to_owned());
+    let call = |id: u16, pn: &str, symbol: &str, weight: f32| Call {
+        place_notation: PnBlock::parse(pn, stage).unwrap(),
+        ..Call::lead_end_call(
+            id.into(),
+            PlaceNot::parse("3", stage).unwrap(),
+            symbol,
+            weight,
+        )
+    };
+
+    let mut params = plain_bob_minor();
+    params.stage = stage;
+    params.length = TotalLength::new(30)..=TotalLength::new(120);
+    params.maybe_unused_methods = vec![Method {
+        inner: method,
+        end_indices: (0..10).collect(),
+        allowed_courses: vec![CourseSet::from(Mask::any(stage))],
+        non_duffer_courses: vec![CourseSet::from(Mask::any(stage))],
+        ..params.maybe_unused_methods[0].clone()
+    }];
+    params.maybe_unused_calls = vec![call(0, "3.1", "-", -0.3), call(1, "3.123", "s", -0.5)];
+    params.call_display_style = CallDisplayStyle::Positional;
+    params.start_row = RowBuf::rounds(stage);
+    params.end_row = RowBuf::rounds(stage);
+    params.part_head_group = PartHeadGroup::one_part(stage);
+    params.maybe_unused_music_types[0].patterns = Pattern::runs_front_or_back(stage, 4);
+    params
+}
+
+/// Touches of Stedman Triples, up to 84 rows long, with `5` bobs and `567` singles at every six end
+pub(crate) fn stedman_triples() -> Parameters {
+    let stage = Stage::TRIPLES;
+    let mut method =
+        bellframe::Method::from_place_not_string("Stedman".to_owned(), stage, "3.1.7.3.1.3,1")
+            .unwrap();
+    for (idx, six_type) in method.six_ends().unwrap() {
+        method.add_label(idx, LABEL_SIX_END.to_owned());
+        method.add_label(idx, six_type.label().to_owned());
+    }
+
+    let mut params = plain_bob_minor();
+    params.stage = stage;
+    params.length = TotalLength::new(24)..=TotalLength::new(84);
+    params.maybe_unused_methods = vec![Method {
+        inner: method,
+        end_indices: vec![0],
+        allowed_courses: vec![CourseSet::from(Mask::any(stage))],
+        non_duffer_courses: vec![CourseSet::from(Mask::any(stage))],
+        ..params.maybe_unused_methods[0].clone()
+    }];
+    params.maybe_unused_calls = base_calls(
+        &mut IdGenerator::starting_at_zero(),
+        BaseCallType::SixEnd,
+        Some(-0.3),
+        Some(-0.5),
+        stage,
+    );
+    params.call_display_style = CallDisplayStyle::Sixes;
+    params.start_row = RowBuf::rounds(stage);
+    params.end_row = RowBuf::rounds(stage);
+    params.part_head_group = PartHeadGroup::one_part(stage);
+    params.maybe_unused_music_types[0].patterns = Pattern::runs_front_or_back(stage, 4);
+    params
+}
+
+/// A single-threaded [`Search`] for `params`
+pub(crate) fn search(params: Parameters) -> Search {
+    let config = Config {
+        thread_limit: Some(1),
+        ..Default::default()
+    };
+    Search::new(params, config).unwrap()
+}
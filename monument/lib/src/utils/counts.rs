@@ -23,11 +23,14 @@ impl Counts {
         cnts
     }
 
-    #[allow(clippy::len_without_is_empty)]
     pub fn len(&self) -> usize {
         self.0.len()
     }
 
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
     /// Returns the underlying count of slices
     pub fn as_slice(&self) -> &[usize] {
         &self.0
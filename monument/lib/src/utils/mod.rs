@@ -3,11 +3,6 @@ use std::{
     ops::{Add, AddAssign},
 };
 
-use bellframe::{Row, RowBuf, Stage, Stroke};
-use itertools::Itertools;
-
-use crate::parameters::MusicType;
-
 use self::counts::Counts;
 
 pub(crate) mod counts;
@@ -62,8 +57,9 @@ pub(crate) fn div_rounding_up(lhs: usize, rhs: usize) -> usize {
 /// A breakdown of the music generated by a composition
 #[derive(Debug, Clone)]
 pub(crate) struct MusicBreakdown {
+    /// The score given to the rows by the [`Scorer`](crate::scoring::Scorer)
     pub score: f32,
-    /// The number of occurrences of each [`MusicType`] specified in the current
+    /// The number of occurrences of each [`MusicType`](crate::parameters::MusicType) specified in the current
     /// [`Query`](crate::Query)
     pub counts: Counts,
 }
@@ -77,47 +73,9 @@ impl MusicBreakdown {
         }
     }
 
-    /// Returns the `Score` generated by a sequence of [`Row`]s, (pre-)transposed by some course
-    /// head.
-    pub fn from_rows<'r>(
-        rows: impl IntoIterator<Item = &'r Row>,
-        pre_transposition: &Row,
-        music_types: &[MusicType],
-        start_stroke: Stroke,
-    ) -> Self {
-        let mut temp_row = RowBuf::rounds(Stage::ONE);
-        let mut occurences = vec![0; music_types.len()];
-        // For every (transposed) row ...
-        for (idx, r) in rows.into_iter().enumerate() {
-            pre_transposition.mul_into(r, &mut temp_row).unwrap();
-            // ... for every music type ...
-            for (num_instances, ty) in occurences.iter_mut().zip_eq(music_types) {
-                if ty.strokes.contains(start_stroke.offset(idx)) {
-                    // ... count the number of instances of that type of music
-                    for pattern in &ty.patterns {
-                        // Unwrap is safe because `pattern` must have the same `Stage` as the rest
-                        // of the rows
-                        if pattern.matches(&temp_row).unwrap() {
-                            *num_instances += 1;
-                        }
-                    }
-                }
-            }
-        }
-
-        Self {
-            score: occurences
-                .iter()
-                .zip_eq(music_types)
-                .map(|(&num_instances, ty)| ty.weight * num_instances as f32)
-                .sum(),
-            counts: occurences.into(),
-        }
-    }
-
     /// # Panics
     ///
-    /// Panics if the number of [`MusicType`]s in `rhs` is different to that of `self`.
+    /// Panics if the number of [`MusicType`](crate::parameters::MusicType)s in `rhs` is different to that of `self`.
     pub fn saturating_sub(&self, rhs: &Self) -> Self {
         MusicBreakdown {
             score: self.score - rhs.score,
@@ -127,7 +85,7 @@ impl MusicBreakdown {
 
     /// # Panics
     ///
-    /// Panics if the number of [`MusicType`]s in `rhs` is different to that of `self`.
+    /// Panics if the number of [`MusicType`](crate::parameters::MusicType)s in `rhs` is different to that of `self`.
     pub fn saturating_sub_assign(&mut self, rhs: &Self) {
         self.score -= rhs.score;
         self.counts.saturating_sub_assign(&rhs.counts);
@@ -141,7 +99,7 @@ impl Add for &MusicBreakdown {
     ///
     /// # Panics
     ///
-    /// Panics if the number of [`MusicType`]s in `rhs` is different to that of `self`.
+    /// Panics if the number of [`MusicType`](crate::parameters::MusicType)s in `rhs` is different to that of `self`.
     fn add(self, rhs: &MusicBreakdown) -> Self::Output {
         MusicBreakdown {
             score: self.score + rhs.score,
@@ -156,7 +114,7 @@ impl AddAssign<&MusicBreakdown> for MusicBreakdown {
     ///
     /// # Panics
     ///
-    /// Panics if the number of [`MusicType`]s in `rhs` is different to that of `self`.
+    /// Panics if the number of [`MusicType`](crate::parameters::MusicType)s in `rhs` is different to that of `self`.
     fn add_assign(&mut self, rhs: &MusicBreakdown) {
         self.score += rhs.score;
         self.counts += &rhs.counts;
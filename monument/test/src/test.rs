@@ -128,6 +128,7 @@ fn run_test(case: UnrunTestCase<CaseData>) -> RunTestCase<CaseData> {
     let mut args = vec![
         "-q",                // Info messages might change often
         "--no-comp-numbers", // We only want to test *which* comps are outputted, not their order
+        "--num-threads=1",   // Multi-threaded output isn't repeatable (see `--num-threads`)
     ];
     if no_search {
         args.extend(["-D", "no-search"]);